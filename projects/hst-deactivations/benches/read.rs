@@ -0,0 +1,49 @@
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use hst_deactivations::DeactivationLog;
+use hst_tw_utils::{
+    splitmix::splitmix64,
+    testkit::{record_count, SEED},
+};
+
+const DEFAULT_RECORD_COUNT: usize = 100_000;
+
+/// A log where about a fifth of deactivations have been reversed.
+fn generate_log(count: usize) -> Vec<u8> {
+    let mut state = SEED;
+    let mut output = String::new();
+
+    for _ in 0..count {
+        let user_id = splitmix64(&mut state) % 1_500_000_000_000_000_000;
+        let status = if splitmix64(&mut state).is_multiple_of(4) {
+            63
+        } else {
+            50
+        };
+        let observed = 1_600_000_000 + (splitmix64(&mut state) % 50_000_000) as i64;
+
+        output.push_str(&format!("{},{},{},", user_id, status, observed));
+
+        if splitmix64(&mut state).is_multiple_of(5) {
+            output.push_str(&(observed + 86_400).to_string());
+        }
+
+        output.push('\n');
+    }
+
+    output.into_bytes()
+}
+
+fn read(c: &mut Criterion) {
+    let count = record_count(DEFAULT_RECORD_COUNT);
+    let log = generate_log(count);
+
+    let mut group = c.benchmark_group("deactivations");
+    group.throughput(Throughput::Elements(count as u64));
+    group.bench_function("read", |b| {
+        b.iter(|| DeactivationLog::read(&log[..]).unwrap())
+    });
+    group.finish();
+}
+
+criterion_group!(benches, read);
+criterion_main!(benches);
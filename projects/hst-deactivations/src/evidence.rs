@@ -0,0 +1,399 @@
+//! Append-only sidecar recording the evidence behind each applied reversal.
+//!
+//! Rows have the form `user_id,reversal,kind,applied_at,tool_version,detail` (with timestamps in
+//! epoch seconds, and the detail last since it may contain commas). Rows are keyed by user ID,
+//! reversal, kind, and detail, so applying the same evidence again doesn't add a row.
+
+use super::{DeactivationLog, Error};
+use chrono::{DateTime, TimeZone, Utc};
+use std::collections::HashSet;
+use std::fmt::Formatter;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum EvidenceKind {
+    /// A profile snapshot observed after the deactivation.
+    SnapshotAfter,
+    /// A tweet posted within the deactivation window.
+    TweetInWindow,
+    ExternalList,
+}
+
+impl std::fmt::Display for EvidenceKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let value = match self {
+            Self::SnapshotAfter => "snapshot-after",
+            Self::TweetInWindow => "tweet-in-window",
+            Self::ExternalList => "external-list",
+        };
+        write!(f, "{}", value)
+    }
+}
+
+impl FromStr for EvidenceKind {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "snapshot-after" => Ok(Self::SnapshotAfter),
+            "tweet-in-window" => Ok(Self::TweetInWindow),
+            "external-list" => Ok(Self::ExternalList),
+            _ => Err(Error::InvalidEvidenceKind(s.to_string())),
+        }
+    }
+}
+
+/// A reversal together with the evidence for it.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ReversalEvidence {
+    pub user_id: u64,
+    pub reversal: DateTime<Utc>,
+    pub kind: EvidenceKind,
+    /// E.g. the snapshot time or tweet ID.
+    pub detail: String,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct EvidenceRow {
+    pub evidence: ReversalEvidence,
+    pub applied_at: DateTime<Utc>,
+    pub tool_version: String,
+}
+
+impl std::fmt::Display for EvidenceRow {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{},{},{},{},{},{}",
+            self.evidence.user_id,
+            self.evidence.reversal.timestamp(),
+            self.evidence.kind,
+            self.applied_at.timestamp(),
+            self.tool_version,
+            self.evidence.detail
+        )
+    }
+}
+
+impl FromStr for EvidenceRow {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields = s.splitn(6, ',').collect::<Vec<_>>();
+        let invalid = || Error::InvalidEvidenceLine(s.to_string());
+
+        if fields.len() != 6 {
+            return Err(invalid());
+        }
+
+        let timestamp = |value: &str| {
+            value
+                .parse::<i64>()
+                .ok()
+                .and_then(|value| Utc.timestamp_opt(value, 0).single())
+                .ok_or_else(invalid)
+        };
+
+        Ok(Self {
+            evidence: ReversalEvidence {
+                user_id: fields[0].parse().map_err(|_| invalid())?,
+                reversal: timestamp(fields[1])?,
+                kind: fields[2].parse()?,
+                detail: fields[5].to_string(),
+            },
+            applied_at: timestamp(fields[3])?,
+            tool_version: fields[4].to_string(),
+        })
+    }
+}
+
+type EvidenceKey = (u64, i64, EvidenceKind, String);
+
+pub struct EvidenceLog {
+    path: PathBuf,
+    tool_version: String,
+    rows: Vec<EvidenceRow>,
+    keys: HashSet<EvidenceKey>,
+}
+
+impl EvidenceLog {
+    /// Open the sidecar (which does not need to exist yet), stamping new rows with the version.
+    pub fn open<P: AsRef<Path>>(path: P, tool_version: &str) -> Result<Self, Error> {
+        let mut rows = vec![];
+
+        if path.as_ref().exists() {
+            for line in BufReader::new(File::open(&path)?).lines() {
+                let line = line?;
+
+                if !line.is_empty() {
+                    rows.push(line.parse::<EvidenceRow>()?);
+                }
+            }
+        }
+
+        Ok(Self {
+            path: path.as_ref().to_path_buf(),
+            tool_version: tool_version.to_string(),
+            keys: rows.iter().map(|row| key(&row.evidence)).collect(),
+            rows,
+        })
+    }
+
+    pub fn rows(&self) -> &[EvidenceRow] {
+        &self.rows
+    }
+
+    /// All evidence recorded for the user, in the order in which it was added.
+    pub fn for_user(&self, user_id: u64) -> Vec<&EvidenceRow> {
+        self.rows
+            .iter()
+            .filter(|row| row.evidence.user_id == user_id)
+            .collect()
+    }
+
+    /// Append the evidence unless an identical row already exists, returning `true` if added.
+    pub fn append(&mut self, evidence: ReversalEvidence) -> Result<bool, Error> {
+        let evidence = ReversalEvidence {
+            detail: evidence.detail.replace(['\r', '\n'], " "),
+            ..evidence
+        };
+
+        let key = key(&evidence);
+
+        if self.keys.contains(&key) {
+            return Ok(false);
+        }
+
+        let row = EvidenceRow {
+            evidence,
+            applied_at: Utc::now(),
+            tool_version: self.tool_version.clone(),
+        };
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(file, "{}", row)?;
+
+        self.keys.insert(key);
+        self.rows.push(row);
+
+        Ok(true)
+    }
+}
+
+impl DeactivationLog {
+    /// Apply reversals, recording the evidence for each in the sidecar.
+    ///
+    /// Evidence for a reversal that has already been applied (with the same timestamp) is also
+    /// recorded. Returns the evidence that could not be applied (because there is no unreversed
+    /// deactivation for the user, it was reversed at a different time, or the reversal isn't
+    /// after the observation).
+    ///
+    /// The evidence is written to the sidecar before the log is changed, so if writing fails the
+    /// reversal hasn't been applied.
+    pub fn update_with_reversal_evidence<I: IntoIterator<Item = ReversalEvidence>>(
+        &mut self,
+        evidence: I,
+        sidecar: &mut EvidenceLog,
+    ) -> Result<Vec<ReversalEvidence>, Error> {
+        let mut rejected = vec![];
+
+        for evidence in evidence {
+            let last = self
+                .entries
+                .get(&evidence.user_id)
+                .and_then(|entries| entries.last())
+                .copied();
+
+            match last {
+                Some(last) if last.reversal.is_none() && last.observed < evidence.reversal => {
+                    let (user_id, reversal) = (evidence.user_id, evidence.reversal);
+                    sidecar.append(evidence)?;
+                    self.reverse(user_id, reversal)?;
+                }
+                Some(last) if last.reversal == Some(evidence.reversal) => {
+                    sidecar.append(evidence)?;
+                }
+                _ => rejected.push(evidence),
+            }
+        }
+
+        Ok(rejected)
+    }
+
+    /// Return the recorded evidence for the user's current reversals.
+    pub fn reversal_evidence<'a>(
+        &self,
+        sidecar: &'a EvidenceLog,
+        user_id: u64,
+    ) -> Vec<&'a EvidenceRow> {
+        let reversals = self
+            .entries
+            .get(&user_id)
+            .map(|entries| {
+                entries
+                    .iter()
+                    .filter_map(|entry| entry.reversal)
+                    .collect::<HashSet<_>>()
+            })
+            .unwrap_or_default();
+
+        sidecar
+            .for_user(user_id)
+            .into_iter()
+            .filter(|row| reversals.contains(&row.evidence.reversal))
+            .collect()
+    }
+}
+
+fn key(evidence: &ReversalEvidence) -> EvidenceKey {
+    (
+        evidence.user_id,
+        evidence.reversal.timestamp(),
+        evidence.kind,
+        evidence.detail.clone(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Entry;
+
+    fn timestamp(value: i64) -> DateTime<Utc> {
+        Utc.timestamp_opt(value, 0).single().unwrap()
+    }
+
+    fn log() -> DeactivationLog {
+        let mut log = DeactivationLog {
+            entries: Default::default(),
+        };
+        log.add_entry(1, 50, timestamp(1000)).unwrap();
+        log
+    }
+
+    fn evidence(user_id: u64, reversal: i64, detail: &str) -> ReversalEvidence {
+        ReversalEvidence {
+            user_id,
+            reversal: timestamp(reversal),
+            kind: EvidenceKind::SnapshotAfter,
+            detail: detail.to_string(),
+        }
+    }
+
+    #[test]
+    fn applies_and_records_reversals() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("evidence.csv");
+        let mut sidecar = EvidenceLog::open(&path, "0.1.0").unwrap();
+        let mut log = log();
+
+        let rejected = log
+            .update_with_reversal_evidence(
+                vec![evidence(1, 2000, "a,b"), evidence(2, 2000, "missing")],
+                &mut sidecar,
+            )
+            .unwrap();
+
+        assert_eq!(rejected, vec![evidence(2, 2000, "missing")]);
+        assert_eq!(log.lookup(1).unwrap()[0].reversal, Some(timestamp(2000)));
+
+        let reopened = EvidenceLog::open(&path, "0.1.0").unwrap();
+        assert_eq!(reopened.rows().len(), 1);
+        assert_eq!(reopened.rows()[0].evidence, evidence(1, 2000, "a,b"));
+        assert_eq!(log.reversal_evidence(&reopened, 1).len(), 1);
+    }
+
+    #[test]
+    fn records_repeated_evidence_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut sidecar = EvidenceLog::open(dir.path().join("evidence.csv"), "0.1.0").unwrap();
+        let mut log = log();
+
+        let rejected = log
+            .update_with_reversal_evidence(
+                vec![
+                    evidence(1, 2000, "first"),
+                    evidence(1, 2000, "first"),
+                    evidence(1, 2000, "second"),
+                    evidence(1, 3000, "different"),
+                ],
+                &mut sidecar,
+            )
+            .unwrap();
+
+        assert_eq!(rejected, vec![evidence(1, 3000, "different")]);
+        assert_eq!(sidecar.rows().len(), 2);
+    }
+
+    #[test]
+    fn rejects_reversals_before_the_observation() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut sidecar = EvidenceLog::open(dir.path().join("evidence.csv"), "0.1.0").unwrap();
+        let mut log = log();
+
+        let rejected = log
+            .update_with_reversal_evidence(
+                vec![evidence(1, 500, "early"), evidence(1, 1000, "same")],
+                &mut sidecar,
+            )
+            .unwrap();
+
+        assert_eq!(rejected.len(), 2);
+        assert!(sidecar.rows().is_empty());
+        assert_eq!(log.lookup(1).unwrap()[0].reversal, None);
+        assert!(log.validate().is_ok());
+    }
+
+    #[test]
+    fn leaves_log_unchanged_if_sidecar_write_fails() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("missing").join("evidence.csv");
+        let mut sidecar = EvidenceLog::open(&path, "0.1.0").unwrap();
+        let mut log = log();
+
+        assert!(log
+            .update_with_reversal_evidence(vec![evidence(1, 2000, "a")], &mut sidecar)
+            .is_err());
+
+        assert_eq!(
+            log.lookup(1),
+            Some(vec![Entry {
+                status: 50,
+                observed: timestamp(1000),
+                reversal: None,
+            }])
+        );
+        assert!(sidecar.rows().is_empty());
+
+        // The evidence can still be applied once the sidecar is writable.
+        std::fs::create_dir(dir.path().join("missing")).unwrap();
+        assert!(log
+            .update_with_reversal_evidence(vec![evidence(1, 2000, "a")], &mut sidecar)
+            .unwrap()
+            .is_empty());
+        assert_eq!(sidecar.rows().len(), 1);
+    }
+
+    #[test]
+    fn parses_rows_with_commas_in_detail() {
+        let row = "1,2000,tweet-in-window,3000,0.1.0,status 123, quoted"
+            .parse::<EvidenceRow>()
+            .unwrap();
+
+        assert_eq!(row.evidence.kind, EvidenceKind::TweetInWindow);
+        assert_eq!(row.evidence.detail, "status 123, quoted");
+        assert_eq!(
+            row.to_string(),
+            "1,2000,tweet-in-window,3000,0.1.0,status 123, quoted"
+        );
+        assert!("1,2000,unknown,3000,0.1.0,x"
+            .parse::<EvidenceRow>()
+            .is_err());
+    }
+}
@@ -0,0 +1,400 @@
+//! Lookups in a CSV log without loading it into memory.
+//!
+//! The index is a sidecar file (the log path with `.idx` appended) containing a header and the
+//! byte offset of every line, sorted by user ID. Lookups binary search the sidecar and then read
+//! the matching lines from the log, so neither file is held in memory.
+//!
+//! The header records the length and modification time of the log, and the index is rejected
+//! as stale if either has changed.
+
+use super::{parse_csv_line, Entry, Error};
+use hst_tw_utils::extsort::ExternalSorter;
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// The first bytes of a sidecar (including the format version).
+pub const MAGIC: &[u8; 8] = b"HSTDIDX1";
+/// Magic bytes, log length, modification time (seconds and nanoseconds), and record count.
+pub const HEADER_SIZE: u64 = 8 + 8 + 8 + 4 + 8;
+/// User ID and byte offset.
+const RECORD_SIZE: u64 = 16;
+
+/// Both files share a cursor, so an index can't be shared between threads.
+pub struct DeactivationIndex {
+    log: RefCell<File>,
+    index: RefCell<File>,
+    count: u64,
+}
+
+impl DeactivationIndex {
+    /// The path of the sidecar for a log.
+    pub fn index_path<P: AsRef<Path>>(log_path: P) -> PathBuf {
+        let mut path = log_path.as_ref().as_os_str().to_os_string();
+        path.push(".idx");
+        PathBuf::from(path)
+    }
+
+    /// Build the sidecar for a CSV log (in one pass over the log), replacing any existing one.
+    ///
+    /// The offsets are sorted externally (with any spill files in the log's directory), and the
+    /// sidecar is written to a temporary file that is only moved into place once it's complete.
+    pub fn build<P: AsRef<Path>>(log_path: P) -> Result<Self, Error> {
+        let log = File::open(&log_path)?;
+        let (length, seconds, nanoseconds) = file_stamp(&log)?;
+        let mut reader = BufReader::new(&log);
+        let mut line = String::new();
+        let mut offset = 0;
+        let mut record_count: u64 = 0;
+        let mut read_error = None;
+
+        let records = std::iter::from_fn(|| loop {
+            line.clear();
+
+            let count = match reader.read_line(&mut line) {
+                Ok(0) => return None,
+                Ok(count) => count,
+                Err(error) => {
+                    read_error = Some(Error::from(error));
+                    return None;
+                }
+            };
+            let line_offset = offset;
+            offset += count as u64;
+
+            // Blank lines are skipped, as in `DeactivationLog::read`.
+            if !line.trim().is_empty() {
+                match parse_csv_line(line.trim_end_matches(['\r', '\n'])) {
+                    Ok((user_id, _)) => {
+                        record_count += 1;
+                        return Some((user_id, line_offset));
+                    }
+                    Err(error) => {
+                        read_error = Some(error);
+                        return None;
+                    }
+                }
+            }
+        });
+
+        let temp_dir = log_path
+            .as_ref()
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+            .unwrap_or_else(|| Path::new("."));
+        let sorted = ExternalSorter::default().temp_dir(temp_dir).sort(records)?;
+
+        if let Some(error) = read_error {
+            return Err(error);
+        }
+
+        let index_path = Self::index_path(&log_path);
+        let mut temp_path = index_path.clone().into_os_string();
+        temp_path.push(".tmp");
+        let temp_path = PathBuf::from(temp_path);
+
+        let result = write_index(
+            &temp_path,
+            (length, seconds, nanoseconds),
+            record_count,
+            sorted,
+        )
+        .and_then(|()| Ok(std::fs::rename(&temp_path, &index_path)?));
+
+        if result.is_err() {
+            let _ = std::fs::remove_file(&temp_path);
+        }
+
+        result?;
+
+        Self::open(log_path)
+    }
+
+    /// Open an existing sidecar, checking that it matches the log.
+    pub fn open<P: AsRef<Path>>(log_path: P) -> Result<Self, Error> {
+        let log = File::open(&log_path)?;
+        let index_path = Self::index_path(&log_path);
+        let mut index = File::open(&index_path)?;
+        let mut header = [0; HEADER_SIZE as usize];
+        let stale = || Error::StaleIndex(index_path.display().to_string());
+
+        index.read_exact(&mut header).map_err(|_| stale())?;
+
+        if &header[0..8] != MAGIC {
+            return Err(stale());
+        }
+
+        let length = u64::from_be_bytes(header[8..16].try_into().unwrap_or_default());
+        let seconds = u64::from_be_bytes(header[16..24].try_into().unwrap_or_default());
+        let nanoseconds = u32::from_be_bytes(header[24..28].try_into().unwrap_or_default());
+        let count = u64::from_be_bytes(header[28..36].try_into().unwrap_or_default());
+
+        if file_stamp(&log)? != (length, seconds, nanoseconds)
+            || index.metadata()?.len() != HEADER_SIZE + count * RECORD_SIZE
+        {
+            return Err(stale());
+        }
+
+        Ok(Self {
+            log: RefCell::new(log),
+            index: RefCell::new(index),
+            count,
+        })
+    }
+
+    /// Open the sidecar, building it if it is missing or stale.
+    pub fn open_or_build<P: AsRef<Path>>(log_path: P) -> Result<Self, Error> {
+        match Self::open(&log_path) {
+            Ok(index) => Ok(index),
+            Err(Error::StaleIndex(_)) => Self::build(log_path),
+            Err(Error::Io(error)) if error.kind() == std::io::ErrorKind::NotFound => {
+                Self::build(log_path)
+            }
+            Err(error) => Err(error),
+        }
+    }
+
+    /// The number of entries in the log.
+    pub fn len(&self) -> u64 {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Entries for the user, in the order in which they appear in the log.
+    pub fn lookup(&self, user_id: u64) -> Result<Option<Vec<Entry>>, Error> {
+        let mut position = self.lower_bound(user_id)?;
+        let mut entries = vec![];
+
+        while position < self.count {
+            let (record_user_id, offset) = self.record(position)?;
+
+            if record_user_id != user_id {
+                break;
+            }
+
+            entries.push(self.entry(offset)?.1);
+            position += 1;
+        }
+
+        Ok(if entries.is_empty() {
+            None
+        } else {
+            Some(entries)
+        })
+    }
+
+    pub fn status(&self, user_id: u64) -> Result<Option<u32>, Error> {
+        Ok(self.current(user_id)?.map(|entry| entry.status))
+    }
+
+    pub fn status_timestamp(
+        &self,
+        user_id: u64,
+    ) -> Result<Option<chrono::DateTime<chrono::Utc>>, Error> {
+        Ok(self.current(user_id)?.map(|entry| entry.observed))
+    }
+
+    /// All entries (sorted by user ID), optionally filtered by status.
+    pub fn deactivations(
+        &self,
+        status_filter: Option<u32>,
+    ) -> impl Iterator<Item = Result<(u64, Entry), Error>> + '_ {
+        (0..self.count)
+            .map(move |position| {
+                let (_, offset) = self.record(position)?;
+                self.entry(offset)
+            })
+            .filter(move |result| match result {
+                Ok((_, entry)) => status_filter.is_none_or(|status| entry.status == status),
+                Err(_) => true,
+            })
+    }
+
+    fn current(&self, user_id: u64) -> Result<Option<Entry>, Error> {
+        Ok(self
+            .lookup(user_id)?
+            .and_then(|entries| entries.into_iter().find(|entry| entry.reversal.is_none())))
+    }
+
+    /// The position of the first record with a user ID not less than the given one.
+    fn lower_bound(&self, user_id: u64) -> Result<u64, Error> {
+        let mut low = 0;
+        let mut high = self.count;
+
+        while low < high {
+            let middle = low + (high - low) / 2;
+
+            if self.record(middle)?.0 < user_id {
+                low = middle + 1;
+            } else {
+                high = middle;
+            }
+        }
+
+        Ok(low)
+    }
+
+    fn record(&self, position: u64) -> Result<(u64, u64), Error> {
+        let mut index = self.index.borrow_mut();
+        let mut bytes = [0; RECORD_SIZE as usize];
+
+        index.seek(SeekFrom::Start(HEADER_SIZE + position * RECORD_SIZE))?;
+        index.read_exact(&mut bytes)?;
+
+        Ok((
+            u64::from_be_bytes(bytes[0..8].try_into().unwrap_or_default()),
+            u64::from_be_bytes(bytes[8..16].try_into().unwrap_or_default()),
+        ))
+    }
+
+    fn entry(&self, offset: u64) -> Result<(u64, Entry), Error> {
+        let mut log = self.log.borrow_mut();
+        log.seek(SeekFrom::Start(offset))?;
+
+        let mut line = String::new();
+        BufReader::new(&mut *log).read_line(&mut line)?;
+
+        parse_csv_line(line.trim_end_matches(['\r', '\n']))
+    }
+}
+
+/// Write a sidecar header and the sorted records to a new file.
+fn write_index<I: Iterator<Item = Result<(u64, u64), hst_tw_utils::extsort::Error>>>(
+    path: &Path,
+    (length, seconds, nanoseconds): (u64, u64, u32),
+    count: u64,
+    records: I,
+) -> Result<(), Error> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    writer.write_all(MAGIC)?;
+    writer.write_all(&length.to_be_bytes())?;
+    writer.write_all(&seconds.to_be_bytes())?;
+    writer.write_all(&nanoseconds.to_be_bytes())?;
+    writer.write_all(&count.to_be_bytes())?;
+
+    for record in records {
+        let (user_id, offset) = record?;
+        writer.write_all(&user_id.to_be_bytes())?;
+        writer.write_all(&offset.to_be_bytes())?;
+    }
+
+    writer.flush()?;
+
+    Ok(writer.get_ref().sync_all()?)
+}
+
+/// The length and modification time of a file.
+fn file_stamp(file: &File) -> Result<(u64, u64, u32), Error> {
+    let metadata = file.metadata()?;
+    let modified = metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+
+    Ok((metadata.len(), modified.as_secs(), modified.subsec_nanos()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DeactivationLog;
+
+    const LOG: &str = "3,50,300,\n1,50,100,150\n\n2,63,200,\r\n1,63,400,\n   \n";
+
+    fn write_log(contents: &str) -> (tempfile::TempDir, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("log.csv");
+        std::fs::write(&path, contents).unwrap();
+
+        (dir, path)
+    }
+
+    #[test]
+    fn build_matches_read() {
+        let (_dir, path) = write_log(LOG);
+        let index = DeactivationIndex::build(&path).unwrap();
+        let log = DeactivationLog::read(LOG.as_bytes()).unwrap();
+
+        assert_eq!(index.len(), 4);
+
+        for user_id in 0..=4 {
+            assert_eq!(index.lookup(user_id).unwrap(), log.lookup(user_id));
+            assert_eq!(index.status(user_id).unwrap(), log.status(user_id));
+        }
+
+        let user_ids = index
+            .deactivations(None)
+            .map(|result| result.map(|(user_id, _)| user_id))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        assert_eq!(user_ids, vec![1, 1, 2, 3]);
+        assert_eq!(index.deactivations(Some(63)).count(), 2);
+    }
+
+    #[test]
+    fn build_leaves_only_sidecar() {
+        let (dir, path) = write_log(LOG);
+
+        DeactivationIndex::build(&path).unwrap();
+        // Rebuilding replaces the existing sidecar.
+        DeactivationIndex::build(&path).unwrap();
+
+        let mut file_names = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        file_names.sort();
+
+        assert_eq!(file_names, vec!["log.csv", "log.csv.idx"]);
+    }
+
+    #[test]
+    fn invalid_lines_are_rejected() {
+        let contents = "1,50,100,\nnot a line\n";
+        let (_dir, path) = write_log(contents);
+
+        assert!(DeactivationLog::read(contents.as_bytes()).is_err());
+        assert!(DeactivationIndex::build(&path).is_err());
+        assert!(!DeactivationIndex::index_path(&path).exists());
+    }
+
+    #[test]
+    fn stale_index() {
+        let (_dir, path) = write_log(LOG);
+
+        assert!(matches!(
+            DeactivationIndex::open(&path),
+            Err(Error::Io(error)) if error.kind() == std::io::ErrorKind::NotFound
+        ));
+
+        DeactivationIndex::build(&path).unwrap();
+        assert!(DeactivationIndex::open(&path).is_ok());
+
+        std::fs::write(&path, format!("{}4,50,500,\n", LOG)).unwrap();
+
+        assert!(matches!(
+            DeactivationIndex::open(&path),
+            Err(Error::StaleIndex(_))
+        ));
+
+        let index = DeactivationIndex::open_or_build(&path).unwrap();
+
+        assert_eq!(index.len(), 5);
+        assert_eq!(index.status(4).unwrap(), Some(50));
+    }
+
+    #[test]
+    fn empty_log() {
+        let (_dir, path) = write_log("\n");
+        let index = DeactivationIndex::build(&path).unwrap();
+
+        assert!(index.is_empty());
+        assert_eq!(index.lookup(1).unwrap(), None);
+    }
+}
@@ -10,22 +10,79 @@
 //!   63 for suspension).
 //! * A deactivation has a time at which it was first observed and (optionally) another at which it
 //!   was reversed.
+//!
+//! The [`prelude`] module re-exports the public types.
 
 use chrono::{DateTime, TimeZone, Utc};
 use std::collections::{HashMap, HashSet};
+use std::fs::File;
 use std::io::{BufRead, BufReader, BufWriter, Read, Write};
 use std::ops::Add;
+use std::path::Path;
+
+pub mod evidence;
+pub mod index;
+pub mod merge;
+mod ndjson;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+pub mod summary;
+
+/// Public types (with the error type renamed to avoid collisions).
+///
+/// ```rust
+/// use hst_deactivations::prelude::*;
+///
+/// let log = DeactivationLog::read("1,50,1600000000,\n".as_bytes())?;
+/// assert_eq!(log.status(1), Some(50));
+/// # Ok::<(), DeactivationsError>(())
+/// ```
+pub mod prelude {
+    pub use super::evidence::{EvidenceKind, EvidenceLog, ReversalEvidence};
+    pub use super::index::DeactivationIndex;
+    pub use super::merge::MergeConflict;
+    pub use super::summary::{LogSummary, StatusCounts};
+    pub use super::{DeactivationLog, Entry, Error as DeactivationsError};
+}
 
 #[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
 pub enum Error {
     #[error("I/O error")]
     Io(#[from] std::io::Error),
+    #[cfg(feature = "sqlite")]
+    #[error("SQLite error")]
+    Sqlite(#[from] rusqlite::Error),
+    #[error("JSON error")]
+    Json(#[from] serde_json::Error),
+    #[error("Unsupported file extension")]
+    UnsupportedExtension(String),
     #[error("Invalid user ID")]
     InvalidUserId(Option<String>),
     #[error("Invalid timestamp")]
     InvalidTimestamp(Option<String>),
     #[error("Invalid status code")]
     InvalidStatus(Option<String>),
+    #[error("Out-of-order entry")]
+    OutOfOrderEntry {
+        user_id: u64,
+        observed: DateTime<Utc>,
+    },
+    #[error("Invalid reversal")]
+    InvalidReversal {
+        user_id: u64,
+        reversal: DateTime<Utc>,
+    },
+    #[error("External sort error")]
+    Sort(#[from] hst_tw_utils::extsort::Error),
+    #[error("Missing or stale index")]
+    StaleIndex(String),
+    #[error("Invalid entries")]
+    InvalidEntries(Vec<u64>),
+    #[error("Invalid evidence kind")]
+    InvalidEvidenceKind(String),
+    #[error("Invalid evidence line")]
+    InvalidEvidenceLine(String),
 }
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
@@ -45,6 +102,26 @@ impl DeactivationLog {
         self.entries.get(&user_id).cloned()
     }
 
+    /// Look up entries for several IDs belonging to the same account (e.g. legacy aliases).
+    ///
+    /// Each entry is labeled with the ID it was recorded under, and results are sorted by
+    /// observation time.
+    pub fn lookup_all<I: IntoIterator<Item = u64>>(&self, user_ids: I) -> Vec<(u64, Entry)> {
+        let mut entries = user_ids
+            .into_iter()
+            .flat_map(|user_id| {
+                self.entries
+                    .get(&user_id)
+                    .into_iter()
+                    .flatten()
+                    .map(move |entry| (user_id, *entry))
+            })
+            .collect::<Vec<_>>();
+
+        entries.sort_by_key(|(user_id, entry)| (entry.observed, *user_id));
+        entries
+    }
+
     pub fn status(&self, user_id: u64) -> Option<u32> {
         self.entries.get(&user_id).and_then(|entries| {
             entries.iter().find_map(|entry| {
@@ -90,6 +167,58 @@ impl DeactivationLog {
             .collect()
     }
 
+    /// Deactivations observed in the window (inclusive of the start but not the end), sorted by
+    /// observation time and then user ID.
+    pub fn deactivated_between(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        status_filter: Option<u32>,
+    ) -> Vec<(u64, Entry)> {
+        let mut entries = self.filter_entries(status_filter, |entry| {
+            entry.observed >= start && entry.observed < end
+        });
+
+        entries.sort_by_key(|(user_id, entry)| (entry.observed, *user_id));
+        entries
+    }
+
+    /// Deactivations reversed in the window (regardless of when they were observed), sorted by
+    /// reversal time and then user ID.
+    pub fn reversed_between(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        status_filter: Option<u32>,
+    ) -> Vec<(u64, Entry)> {
+        let mut entries = self.filter_entries(status_filter, |entry| {
+            entry
+                .reversal
+                .is_some_and(|reversal| reversal >= start && reversal < end)
+        });
+
+        entries.sort_by_key(|(user_id, entry)| (entry.reversal, *user_id));
+        entries
+    }
+
+    fn filter_entries<F: Fn(&Entry) -> bool>(
+        &self,
+        status_filter: Option<u32>,
+        f: F,
+    ) -> Vec<(u64, Entry)> {
+        self.entries
+            .iter()
+            .flat_map(|(user_id, entries)| {
+                entries
+                    .iter()
+                    .filter(|entry| {
+                        status_filter.is_none_or(|status| entry.status == status) && f(entry)
+                    })
+                    .map(move |entry| (*user_id, *entry))
+            })
+            .collect()
+    }
+
     pub fn ever_deactivated(&self, status_filter: Option<u32>) -> HashSet<u64> {
         self.entries
             .iter()
@@ -129,6 +258,76 @@ impl DeactivationLog {
             .collect()
     }
 
+    /// Add a new unreversed deactivation for the user.
+    ///
+    /// The user's previous deactivation (if any) must have been reversed and observed earlier.
+    ///
+    /// ```rust
+    /// use chrono::{TimeZone, Utc};
+    /// use hst_deactivations::DeactivationLog;
+    ///
+    /// let mut log = DeactivationLog::read("1,50,1600000000,\n".as_bytes())?;
+    /// let timestamp = |value| Utc.timestamp_opt(value, 0).unwrap();
+    ///
+    /// assert!(log.add_entry(1, 63, timestamp(1600000200)).is_err());
+    /// log.reverse(1, timestamp(1600000100))?;
+    /// log.add_entry(1, 63, timestamp(1600000200))?;
+    ///
+    /// assert_eq!(log.status(1), Some(63));
+    /// assert!(log.validate().is_ok());
+    /// # Ok::<(), hst_deactivations::Error>(())
+    /// ```
+    pub fn add_entry(
+        &mut self,
+        user_id: u64,
+        status: u32,
+        observed: DateTime<Utc>,
+    ) -> Result<(), Error> {
+        let entries = self.entries.entry(user_id).or_default();
+
+        if let Some(last) = entries.last() {
+            if last.reversal.is_none() || last.observed >= observed {
+                return Err(Error::OutOfOrderEntry { user_id, observed });
+            }
+        }
+
+        entries.push(Entry {
+            status,
+            observed,
+            reversal: None,
+        });
+
+        Ok(())
+    }
+
+    /// Remove the user's most recent deactivation.
+    pub fn remove_last(&mut self, user_id: u64) -> Option<Entry> {
+        let entries = self.entries.get_mut(&user_id)?;
+        let last = entries.pop();
+
+        // Users with no entries are considered invalid.
+        if entries.is_empty() {
+            self.entries.remove(&user_id);
+        }
+
+        last
+    }
+
+    /// Reverse the user's current deactivation (which must have been observed earlier).
+    pub fn reverse(&mut self, user_id: u64, reversal: DateTime<Utc>) -> Result<(), Error> {
+        match self
+            .entries
+            .get_mut(&user_id)
+            .and_then(|entries| entries.last_mut())
+        {
+            Some(last) if last.reversal.is_none() && last.observed < reversal => {
+                last.reversal = Some(reversal);
+                Ok(())
+            }
+            _ => Err(Error::InvalidReversal { user_id, reversal }),
+        }
+    }
+
     pub fn update_with_reversals<I: Iterator<Item = (u64, DateTime<Utc>)>>(
         &mut self,
         reversals: I,
@@ -201,57 +400,31 @@ impl DeactivationLog {
             }
     }
 
+    /// Read a log in either format, choosing by extension (`.csv`, or `.ndjson` or `.jsonl`).
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref();
+
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("csv") => Self::read(File::open(path)?),
+            Some("ndjson" | "jsonl") => Self::read_ndjson(File::open(path)?),
+            _ => Err(Error::UnsupportedExtension(path.display().to_string())),
+        }
+    }
+
+    /// Read a CSV log (blank lines are skipped).
     pub fn read<R: Read>(reader: R) -> Result<Self, Error> {
         let mut entries: HashMap<u64, Vec<Entry>> = HashMap::new();
 
         for line in BufReader::new(reader).lines() {
             let line = line?;
-            let fields = line.split(',').collect::<Vec<_>>();
-
-            let user_id = fields
-                .first()
-                .and_then(|value| value.parse::<u64>().ok())
-                .ok_or_else(|| {
-                    Error::InvalidUserId(fields.first().map(|value| value.to_string()))
-                })?;
-
-            let status = fields
-                .get(1)
-                .and_then(|value| value.parse::<u32>().ok())
-                .ok_or_else(|| {
-                    Error::InvalidStatus(fields.get(1).map(|value| value.to_string()))
-                })?;
-
-            let observed = fields
-                .get(2)
-                .and_then(|value| value.parse::<i64>().ok())
-                .map(|value| Utc.timestamp(value, 0))
-                .ok_or_else(|| {
-                    Error::InvalidTimestamp(fields.get(2).map(|value| value.to_string()))
-                })?;
-
-            let reversal = fields
-                .get(3)
-                .and_then(|value| {
-                    if value.is_empty() {
-                        Some(None)
-                    } else {
-                        value
-                            .parse::<i64>()
-                            .ok()
-                            .map(|value| Some(Utc.timestamp(value, 0)))
-                    }
-                })
-                .ok_or_else(|| {
-                    Error::InvalidTimestamp(fields.get(3).map(|value| value.to_string()))
-                })?;
-
-            let seen = entries.entry(user_id).or_default();
-            seen.push(Entry {
-                status,
-                observed,
-                reversal,
-            });
+
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let (user_id, entry) = parse_csv_line(&line)?;
+
+            entries.entry(user_id).or_default().push(entry);
         }
 
         Ok(Self { entries })
@@ -283,6 +456,52 @@ impl DeactivationLog {
     }
 }
 
+/// Parse a CSV line of the form `user_id,status,observed,reversal`.
+fn parse_csv_line(line: &str) -> Result<(u64, Entry), Error> {
+    let fields = line.split(',').collect::<Vec<_>>();
+
+    let user_id = fields
+        .first()
+        .and_then(|value| value.parse::<u64>().ok())
+        .ok_or_else(|| Error::InvalidUserId(fields.first().map(|value| value.to_string())))?;
+
+    let status = fields
+        .get(1)
+        .and_then(|value| value.parse::<u32>().ok())
+        .ok_or_else(|| Error::InvalidStatus(fields.get(1).map(|value| value.to_string())))?;
+
+    let observed = fields
+        .get(2)
+        .and_then(|value| value.parse::<i64>().ok())
+        .map(|value| Utc.timestamp(value, 0))
+        .ok_or_else(|| Error::InvalidTimestamp(fields.get(2).map(|value| value.to_string())))?;
+
+    let reversal = fields
+        .get(3)
+        .and_then(|value| {
+            if value.is_empty() {
+                Some(None)
+            } else {
+                value
+                    .parse::<i64>()
+                    .ok()
+                    .map(|value| Some(Utc.timestamp(value, 0)))
+            }
+        })
+        .ok_or_else(|| Error::InvalidTimestamp(fields.get(3).map(|value| value.to_string())))?;
+
+    Ok((
+        user_id,
+        Entry {
+            status,
+            observed,
+            reversal,
+        },
+    ))
+}
+
+/// Combine all entries, dropping a duplicate open entry at the end of a user's entries (see
+/// [`DeactivationLog::merge`] for collapsing near-duplicates and reporting conflicts).
 impl Add for &DeactivationLog {
     type Output = DeactivationLog;
 
@@ -313,3 +532,260 @@ impl Add for &DeactivationLog {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn timestamp(value: i64) -> DateTime<Utc> {
+        Utc.timestamp_opt(value, 0).single().unwrap()
+    }
+
+    fn entry(status: u32, observed: i64, reversal: Option<i64>) -> Entry {
+        Entry {
+            status,
+            observed: timestamp(observed),
+            reversal: reversal.map(timestamp),
+        }
+    }
+
+    fn log(entries: Vec<(u64, Vec<Entry>)>) -> DeactivationLog {
+        DeactivationLog {
+            entries: entries.into_iter().collect(),
+        }
+    }
+
+    #[test]
+    fn add_keeps_earliest_duplicate_open_entry() {
+        // A status 50 entry that was reversed, followed by an open status 63 entry that the two
+        // collectors observed at different times.
+        let a = entry(50, 100, Some(150));
+        let b_t1 = entry(63, 200, None);
+        let b_t2 = entry(63, 300, None);
+
+        let left = log(vec![(1, vec![b_t2])]);
+        let right = log(vec![(1, vec![a, b_t1])]);
+
+        // The result doesn't depend on the order of the operands.
+        for combined in [&left + &right, &right + &left] {
+            assert_eq!(combined.lookup(1), Some(vec![a, b_t1]));
+            assert!(combined.validate().is_ok());
+        }
+    }
+
+    #[test]
+    fn add_keeps_identical_entries_once() {
+        let left = log(vec![(
+            1,
+            vec![entry(50, 100, Some(150)), entry(63, 200, None)],
+        )]);
+        let right = log(vec![(1, vec![entry(50, 100, Some(150))])]);
+
+        let combined = &left + &right;
+
+        assert_eq!(
+            combined.lookup(1),
+            Some(vec![entry(50, 100, Some(150)), entry(63, 200, None)])
+        );
+    }
+
+    #[test]
+    fn lookup_all_labels_and_sorts_entries() {
+        let log = log(vec![
+            (1, vec![entry(50, 300, None)]),
+            (10, vec![entry(63, 100, Some(200)), entry(50, 400, None)]),
+            (11, vec![entry(50, 300, Some(350))]),
+            (2, vec![entry(63, 150, None)]),
+        ]);
+
+        assert_eq!(
+            log.lookup_all([1, 10, 11, 12]),
+            vec![
+                (10, entry(63, 100, Some(200))),
+                (1, entry(50, 300, None)),
+                (11, entry(50, 300, Some(350))),
+                (10, entry(50, 400, None)),
+            ]
+        );
+        assert!(log.lookup_all([3]).is_empty());
+    }
+
+    #[test]
+    fn add_entry_rejects_out_of_order_entries() {
+        let mut log = log(vec![(1, vec![entry(50, 100, None)])]);
+
+        // The last entry is still open.
+        assert!(matches!(
+            log.add_entry(1, 63, timestamp(200)),
+            Err(Error::OutOfOrderEntry { user_id: 1, observed }) if observed == timestamp(200)
+        ));
+
+        log.reverse(1, timestamp(150)).unwrap();
+        assert!(log.validate().is_ok());
+
+        // The last entry was observed later (or at the same time).
+        for observed in [50, 100] {
+            assert!(matches!(
+                log.add_entry(1, 63, timestamp(observed)),
+                Err(Error::OutOfOrderEntry { user_id: 1, .. })
+            ));
+        }
+
+        assert_eq!(log.lookup(1), Some(vec![entry(50, 100, Some(150))]));
+
+        log.add_entry(1, 63, timestamp(200)).unwrap();
+        assert!(log.validate().is_ok());
+        log.add_entry(2, 50, timestamp(100)).unwrap();
+        assert!(log.validate().is_ok());
+
+        assert_eq!(
+            log.lookup(1),
+            Some(vec![entry(50, 100, Some(150)), entry(63, 200, None)])
+        );
+        assert_eq!(log.lookup(2), Some(vec![entry(50, 100, None)]));
+    }
+
+    #[test]
+    fn remove_last_drops_empty_users() {
+        let mut log = log(vec![(
+            1,
+            vec![entry(50, 100, Some(150)), entry(63, 200, None)],
+        )]);
+
+        assert_eq!(log.remove_last(1), Some(entry(63, 200, None)));
+        assert!(log.validate().is_ok());
+        assert_eq!(log.lookup(1), Some(vec![entry(50, 100, Some(150))]));
+
+        assert_eq!(log.remove_last(1), Some(entry(50, 100, Some(150))));
+        assert!(log.validate().is_ok());
+        assert_eq!(log.lookup(1), None);
+        assert!(log.ever_deactivated(None).is_empty());
+
+        assert_eq!(log.remove_last(1), None);
+        assert_eq!(log.remove_last(2), None);
+    }
+
+    #[test]
+    fn reverse_requires_later_reversal() {
+        let mut log = log(vec![(1, vec![entry(50, 100, None)])]);
+
+        for reversal in [50, 100] {
+            assert!(matches!(
+                log.reverse(1, timestamp(reversal)),
+                Err(Error::InvalidReversal { user_id: 1, .. })
+            ));
+        }
+
+        assert_eq!(log.lookup(1), Some(vec![entry(50, 100, None)]));
+
+        log.reverse(1, timestamp(101)).unwrap();
+        assert!(log.validate().is_ok());
+        assert_eq!(log.lookup(1), Some(vec![entry(50, 100, Some(101))]));
+
+        // The deactivation has already been reversed, and there's nothing to reverse for user 2.
+        assert!(log.reverse(1, timestamp(200)).is_err());
+        assert!(log.reverse(2, timestamp(200)).is_err());
+    }
+
+    fn window_log() -> DeactivationLog {
+        log(vec![
+            (1, vec![entry(50, 100, Some(250)), entry(63, 300, None)]),
+            (2, vec![entry(63, 200, None)]),
+            (3, vec![entry(50, 200, Some(300))]),
+            (4, vec![entry(63, 50, Some(150))]),
+        ])
+    }
+
+    #[test]
+    fn deactivated_between_boundaries() {
+        let log = window_log();
+
+        assert_eq!(
+            log.deactivated_between(timestamp(100), timestamp(300), None),
+            vec![
+                (1, entry(50, 100, Some(250))),
+                (2, entry(63, 200, None)),
+                (3, entry(50, 200, Some(300))),
+            ]
+        );
+        assert_eq!(
+            log.deactivated_between(timestamp(101), timestamp(301), None),
+            vec![
+                (2, entry(63, 200, None)),
+                (3, entry(50, 200, Some(300))),
+                (1, entry(63, 300, None)),
+            ]
+        );
+        assert!(log
+            .deactivated_between(timestamp(200), timestamp(200), None)
+            .is_empty());
+    }
+
+    #[test]
+    fn deactivated_between_status_filter() {
+        let log = window_log();
+
+        assert_eq!(
+            log.deactivated_between(timestamp(0), timestamp(1000), Some(63)),
+            vec![
+                (4, entry(63, 50, Some(150))),
+                (2, entry(63, 200, None)),
+                (1, entry(63, 300, None)),
+            ]
+        );
+        assert!(log
+            .deactivated_between(timestamp(0), timestamp(1000), Some(64))
+            .is_empty());
+    }
+
+    #[test]
+    fn reversed_between_boundaries() {
+        let log = window_log();
+
+        assert_eq!(
+            log.reversed_between(timestamp(150), timestamp(300), None),
+            vec![
+                (4, entry(63, 50, Some(150))),
+                (1, entry(50, 100, Some(250)))
+            ]
+        );
+        assert_eq!(
+            log.reversed_between(timestamp(151), timestamp(301), None),
+            vec![
+                (1, entry(50, 100, Some(250))),
+                (3, entry(50, 200, Some(300)))
+            ]
+        );
+        assert_eq!(
+            log.reversed_between(timestamp(0), timestamp(1000), Some(63)),
+            vec![(4, entry(63, 50, Some(150)))]
+        );
+    }
+
+    #[test]
+    fn reversal_of_earlier_deactivation() {
+        let log = window_log();
+
+        // User 4 was deactivated before the window and reinstated in it.
+        assert!(log
+            .deactivated_between(timestamp(100), timestamp(200), None)
+            .iter()
+            .all(|(user_id, _)| *user_id != 4));
+        assert_eq!(
+            log.reversed_between(timestamp(100), timestamp(200), None),
+            vec![(4, entry(63, 50, Some(150)))]
+        );
+    }
+
+    #[test]
+    fn add_includes_users_from_both_logs() {
+        let left = log(vec![(1, vec![entry(50, 100, None)])]);
+        let right = log(vec![(2, vec![entry(63, 200, None)])]);
+
+        let combined = &left + &right;
+
+        assert_eq!(combined.lookup(1), Some(vec![entry(50, 100, None)]));
+        assert_eq!(combined.lookup(2), Some(vec![entry(63, 200, None)]));
+    }
+}
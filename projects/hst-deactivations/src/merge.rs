@@ -0,0 +1,210 @@
+//! Merging logs from several collectors.
+
+use super::{DeactivationLog, Entry};
+use chrono::Duration;
+use std::collections::HashMap;
+
+/// Entries for a user that could not be combined into a valid sequence.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MergeConflict {
+    pub user_id: u64,
+    /// The combined entries (after collapsing duplicates), sorted by observation time.
+    pub entries: Vec<Entry>,
+}
+
+impl DeactivationLog {
+    /// Merge two logs, collapsing entries with the same status that were observed within the
+    /// given tolerance of each other (keeping the earliest observation and latest reversal).
+    ///
+    /// If a user's combined entries still aren't valid (see [`Self::validate`]), they are
+    /// returned as a conflict, and only the entries from this log are kept for that user.
+    pub fn merge(&self, other: &Self, tolerance: Duration) -> (Self, Vec<MergeConflict>) {
+        let mut entries: HashMap<u64, Vec<Entry>> = self.entries.clone();
+        let mut conflicts = vec![];
+
+        for (user_id, other_entries) in &other.entries {
+            let existing = match entries.get(user_id) {
+                Some(existing) => existing,
+                None => {
+                    entries.insert(*user_id, other_entries.clone());
+                    continue;
+                }
+            };
+
+            let mut combined = existing
+                .iter()
+                .chain(other_entries)
+                .copied()
+                .collect::<Vec<_>>();
+            combined.sort_by_key(|entry| (entry.observed, entry.status, entry.reversal));
+
+            let collapsed = collapse(combined, tolerance);
+
+            if Self::validate_entries(&collapsed) {
+                entries.insert(*user_id, collapsed);
+            } else {
+                conflicts.push(MergeConflict {
+                    user_id: *user_id,
+                    entries: collapsed,
+                });
+            }
+        }
+
+        conflicts.sort_by_key(|conflict| conflict.user_id);
+
+        (Self { entries }, conflicts)
+    }
+}
+
+/// Collapse sorted entries that have the same status and were observed within the tolerance,
+/// keeping the earliest observation and the latest reversal.
+///
+/// An entry observed after the previous entry's reversal is a separate deactivation (e.g. a
+/// re-suspension soon after a reversal), so it is kept even if it is within the tolerance.
+fn collapse(entries: Vec<Entry>, tolerance: Duration) -> Vec<Entry> {
+    let mut collapsed: Vec<Entry> = Vec::with_capacity(entries.len());
+
+    for entry in entries {
+        match collapsed.last_mut() {
+            Some(last)
+                if last.status == entry.status
+                    && entry.observed - last.observed <= tolerance
+                    && last
+                        .reversal
+                        .is_none_or(|reversal| entry.observed < reversal) =>
+            {
+                last.reversal = last.reversal.max(entry.reversal);
+            }
+            _ => collapsed.push(entry),
+        }
+    }
+
+    collapsed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{DateTime, TimeZone, Utc};
+
+    fn timestamp(value: i64) -> DateTime<Utc> {
+        Utc.timestamp_opt(value, 0).single().unwrap()
+    }
+
+    fn entry(status: u32, observed: i64, reversal: Option<i64>) -> Entry {
+        Entry {
+            status,
+            observed: timestamp(observed),
+            reversal: reversal.map(timestamp),
+        }
+    }
+
+    fn log(entries: Vec<(u64, Vec<Entry>)>) -> DeactivationLog {
+        DeactivationLog {
+            entries: entries.into_iter().collect(),
+        }
+    }
+
+    #[test]
+    fn merge_collapses_open_entries_within_tolerance() {
+        let left = log(vec![(1, vec![entry(50, 100, None)])]);
+        let right = log(vec![(1, vec![entry(50, 130, None)])]);
+
+        let (merged, conflicts) = left.merge(&right, Duration::seconds(60));
+
+        assert_eq!(merged.lookup(1), Some(vec![entry(50, 100, None)]));
+        assert!(conflicts.is_empty());
+    }
+
+    #[test]
+    fn merge_collapses_entries_with_the_same_reversal() {
+        let left = log(vec![(1, vec![entry(50, 100, Some(500))])]);
+        let right = log(vec![(1, vec![entry(50, 120, Some(500))])]);
+
+        let (merged, conflicts) = left.merge(&right, Duration::seconds(60));
+
+        assert_eq!(merged.lookup(1), Some(vec![entry(50, 100, Some(500))]));
+        assert!(conflicts.is_empty());
+    }
+
+    #[test]
+    fn merge_keeps_latest_reversal() {
+        // Only one collector saw the reversal.
+        let left = log(vec![(1, vec![entry(50, 100, None)])]);
+        let right = log(vec![(1, vec![entry(50, 120, Some(500))])]);
+
+        let (merged, conflicts) = left.merge(&right, Duration::seconds(60));
+
+        assert_eq!(merged.lookup(1), Some(vec![entry(50, 100, Some(500))]));
+        assert!(conflicts.is_empty());
+
+        let (merged, conflicts) = right.merge(&left, Duration::seconds(60));
+
+        assert_eq!(merged.lookup(1), Some(vec![entry(50, 100, Some(500))]));
+        assert!(conflicts.is_empty());
+
+        let left = log(vec![(1, vec![entry(50, 100, Some(400))])]);
+        let (merged, conflicts) = left.merge(&right, Duration::seconds(60));
+
+        assert_eq!(merged.lookup(1), Some(vec![entry(50, 100, Some(500))]));
+        assert!(conflicts.is_empty());
+    }
+
+    #[test]
+    fn merge_keeps_resuspension_within_tolerance() {
+        // Suspended, reinstated, and suspended again within a minute.
+        let left = log(vec![(1, vec![entry(50, 100, Some(120))])]);
+        let right = log(vec![(1, vec![entry(50, 140, None)])]);
+
+        let (merged, conflicts) = left.merge(&right, Duration::seconds(60));
+
+        assert_eq!(
+            merged.lookup(1),
+            Some(vec![entry(50, 100, Some(120)), entry(50, 140, None)])
+        );
+        assert!(conflicts.is_empty());
+    }
+
+    #[test]
+    fn merge_keeps_entries_outside_tolerance() {
+        let left = log(vec![(1, vec![entry(50, 100, Some(200))])]);
+        let right = log(vec![(1, vec![entry(63, 300, None)])]);
+
+        let (merged, conflicts) = left.merge(&right, Duration::zero());
+
+        assert_eq!(
+            merged.lookup(1),
+            Some(vec![entry(50, 100, Some(200)), entry(63, 300, None)])
+        );
+        assert!(conflicts.is_empty());
+    }
+
+    #[test]
+    fn merge_reports_conflicts_and_keeps_own_entries() {
+        let left = log(vec![
+            (1, vec![entry(50, 300, None)]),
+            (2, vec![entry(63, 100, None)]),
+        ]);
+        let right = log(vec![
+            (1, vec![entry(50, 100, Some(150)), entry(63, 200, None)]),
+            (3, vec![entry(63, 100, None)]),
+        ]);
+
+        let (merged, conflicts) = left.merge(&right, Duration::zero());
+
+        assert_eq!(merged.lookup(1), Some(vec![entry(50, 300, None)]));
+        assert_eq!(merged.lookup(2), Some(vec![entry(63, 100, None)]));
+        assert_eq!(merged.lookup(3), Some(vec![entry(63, 100, None)]));
+        assert_eq!(
+            conflicts,
+            vec![MergeConflict {
+                user_id: 1,
+                entries: vec![
+                    entry(50, 100, Some(150)),
+                    entry(63, 200, None),
+                    entry(50, 300, None)
+                ],
+            }]
+        );
+    }
+}
@@ -0,0 +1,200 @@
+//! JSON Lines serialization, with one object per entry.
+//!
+//! Objects have the form `{"user_id":1,"status":50,"observed":"2020-09-13T12:26:40Z",
+//! "reversal":null}`, with timestamps as RFC 3339 strings.
+
+use super::{DeactivationLog, Entry, Error};
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+
+impl DeactivationLog {
+    /// Read a log, rejecting it if any user's entries are out of order (see [`Self::validate`]).
+    pub fn read_ndjson<R: Read>(reader: R) -> Result<Self, Error> {
+        let mut entries: HashMap<u64, Vec<Entry>> = HashMap::new();
+
+        for line in BufReader::new(reader).lines() {
+            let line = line?;
+
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let value = serde_json::from_str::<Value>(&line)?;
+
+            let user_id = value
+                .get("user_id")
+                .and_then(|value| value.as_u64())
+                .ok_or_else(|| Error::InvalidUserId(field_string(&value, "user_id")))?;
+
+            let status = value
+                .get("status")
+                .and_then(|value| value.as_u64())
+                .and_then(|value| u32::try_from(value).ok())
+                .ok_or_else(|| Error::InvalidStatus(field_string(&value, "status")))?;
+
+            let observed = value
+                .get("observed")
+                .and_then(|value| value.as_str())
+                .and_then(parse_timestamp)
+                .ok_or_else(|| Error::InvalidTimestamp(field_string(&value, "observed")))?;
+
+            let reversal = match value.get("reversal") {
+                None | Some(Value::Null) => None,
+                Some(reversal) => Some(
+                    reversal
+                        .as_str()
+                        .and_then(parse_timestamp)
+                        .ok_or_else(|| Error::InvalidTimestamp(Some(reversal.to_string())))?,
+                ),
+            };
+
+            entries.entry(user_id).or_default().push(Entry {
+                status,
+                observed,
+                reversal,
+            });
+        }
+
+        let log = Self { entries };
+        log.validate().map_err(Error::InvalidEntries)?;
+
+        Ok(log)
+    }
+
+    /// Write the log sorted by user ID (in the same order as the CSV format).
+    pub fn write_ndjson<W: Write>(&self, writer: W) -> Result<(), std::io::Error> {
+        let mut entries = self.entries.iter().collect::<Vec<_>>();
+        entries.sort_by_key(|(user_id, _)| *user_id);
+
+        let mut writer = BufWriter::new(writer);
+
+        for (user_id, entries) in entries {
+            for entry in entries {
+                let value = serde_json::json!({
+                    "user_id": user_id,
+                    "status": entry.status,
+                    "observed": format_timestamp(entry.observed),
+                    "reversal": entry.reversal.map(format_timestamp),
+                });
+
+                writeln!(writer, "{}", value)?;
+            }
+        }
+
+        writer.flush()
+    }
+}
+
+fn field_string(value: &Value, name: &str) -> Option<String> {
+    value.get(name).map(|value| value.to_string())
+}
+
+fn parse_timestamp(value: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(value)
+        .ok()
+        .map(|timestamp| timestamp.with_timezone(&Utc))
+}
+
+fn format_timestamp(timestamp: DateTime<Utc>) -> String {
+    timestamp.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn timestamp(value: i64) -> DateTime<Utc> {
+        Utc.timestamp_opt(value, 0).single().unwrap()
+    }
+
+    fn entry(status: u32, observed: i64, reversal: Option<i64>) -> Entry {
+        Entry {
+            status,
+            observed: timestamp(observed),
+            reversal: reversal.map(timestamp),
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let log = DeactivationLog {
+            entries: vec![
+                (10, vec![entry(63, 100, Some(200)), entry(50, 300, None)]),
+                (2, vec![entry(50, 1_600_000_000, None)]),
+            ]
+            .into_iter()
+            .collect(),
+        };
+
+        let mut bytes = vec![];
+        log.write_ndjson(&mut bytes).unwrap();
+        let lines = std::str::from_utf8(&bytes)
+            .unwrap()
+            .lines()
+            .collect::<Vec<_>>();
+
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].contains(r#""user_id":2"#));
+        assert!(lines[0].contains(r#""observed":"2020-09-13T12:26:40Z""#));
+        assert!(lines[0].contains(r#""reversal":null"#));
+        assert_eq!(DeactivationLog::read_ndjson(&bytes[..]).unwrap(), log);
+    }
+
+    #[test]
+    fn read_skips_blank_lines() {
+        let input = r#"
+{"user_id":1,"status":50,"observed":"2020-09-13T12:26:40Z"}
+
+{"user_id":1,"status":63,"observed":"2020-09-14T00:00:00+02:00","reversal":null}
+   
+"#;
+        let log = DeactivationLog::read_ndjson(input.as_bytes());
+
+        // The first entry has no reversal, so the second is out of order.
+        assert!(matches!(log, Err(Error::InvalidEntries(ids)) if ids == vec![1]));
+
+        let input = "\n{\"user_id\":1,\"status\":50,\"observed\":\"2020-09-13T12:26:40Z\",\"reversal\":\"2020-09-14T00:00:00+02:00\"}\n\n";
+        let log = DeactivationLog::read_ndjson(input.as_bytes()).unwrap();
+
+        assert_eq!(
+            log.lookup(1),
+            Some(vec![entry(50, 1_600_000_000, Some(1_600_034_400))])
+        );
+    }
+
+    #[test]
+    fn read_rejects_invalid_entries() {
+        let read = |line: &str| DeactivationLog::read_ndjson(line.as_bytes());
+
+        assert!(matches!(read("{\"user_id\":1,"), Err(Error::Json(_))));
+
+        // Reversal before the observation.
+        assert!(matches!(
+            read(r#"{"user_id":1,"status":50,"observed":"2020-09-13T12:26:40Z","reversal":"2020-09-13T00:00:00Z"}"#),
+            Err(Error::InvalidEntries(ids)) if ids == vec![1]
+        ));
+        assert!(matches!(
+            read(r#"{"user_id":"1","status":50,"observed":"2020-09-13T12:26:40Z"}"#),
+            Err(Error::InvalidUserId(Some(value))) if value == r#""1""#
+        ));
+        assert!(matches!(
+            read(r#"{"user_id":1,"status":-1,"observed":"2020-09-13T12:26:40Z"}"#),
+            Err(Error::InvalidStatus(_))
+        ));
+        assert!(matches!(
+            read(r#"{"user_id":1,"status":50,"observed":1600000000}"#),
+            Err(Error::InvalidTimestamp(_))
+        ));
+        assert!(matches!(
+            read(r#"{"user_id":1,"status":50}"#),
+            Err(Error::InvalidTimestamp(None))
+        ));
+        assert!(matches!(
+            read(r#"{"user_id":1,"status":50,"observed":"2020-09-13T12:26:40Z","reversal":1}"#),
+            Err(Error::InvalidTimestamp(Some(value))) if value == "1"
+        ));
+    }
+}
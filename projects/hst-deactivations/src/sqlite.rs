@@ -0,0 +1,191 @@
+use super::{DeactivationLog, Error};
+use chrono::Utc;
+use rusqlite::{params, Connection};
+use std::path::Path;
+
+const SCHEMA: &str = "
+    PRAGMA user_version = 1;
+    CREATE TABLE deactivations (
+        user_id INTEGER NOT NULL,
+        status INTEGER NOT NULL,
+        observed INTEGER NOT NULL,
+        reversal INTEGER,
+        source TEXT
+    );
+    CREATE INDEX deactivations_user_id ON deactivations (user_id);
+    CREATE INDEX deactivations_status_observed ON deactivations (status, observed);
+    CREATE TABLE metadata (
+        name TEXT PRIMARY KEY,
+        value TEXT NOT NULL
+    );
+";
+
+impl DeactivationLog {
+    /// Export the log to a new SQLite database.
+    ///
+    /// Timestamps are stored as integral Unix epoch seconds (as in the CSV format), and an
+    /// unreversed entry has a null `reversal`. The source (for example the name of the input
+    /// file) is recorded for every entry, and the given metadata (for example a digest of the
+    /// source file) is stored alongside the generation time. Fails if the tables already exist
+    /// at the path.
+    pub fn export_sqlite<P: AsRef<Path>>(
+        &self,
+        path: P,
+        source: Option<&str>,
+        metadata: &[(&str, String)],
+    ) -> Result<(), Error> {
+        let mut connection = Connection::open(path)?;
+        let transaction = connection.transaction()?;
+
+        transaction.execute_batch(SCHEMA)?;
+
+        {
+            let mut insert = transaction.prepare(
+                "INSERT INTO deactivations (user_id, status, observed, reversal, source)
+                    VALUES (?1, ?2, ?3, ?4, ?5)",
+            )?;
+
+            for (user_id, entry) in self.deactivations(None) {
+                insert.execute(params![
+                    user_id as i64,
+                    entry.status,
+                    entry.observed.timestamp(),
+                    entry.reversal.map(|reversal| reversal.timestamp()),
+                    source
+                ])?;
+            }
+        }
+
+        {
+            let generated_at = [("generated_at", Utc::now().to_rfc3339())];
+            let mut insert = transaction
+                .prepare("INSERT OR REPLACE INTO metadata (name, value) VALUES (?1, ?2)")?;
+
+            for (name, value) in generated_at.iter().chain(metadata) {
+                insert.execute(params![name, value])?;
+            }
+        }
+
+        Ok(transaction.commit()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Entry;
+    use chrono::{DateTime, TimeZone};
+
+    fn timestamp(value: i64) -> DateTime<Utc> {
+        Utc.timestamp_opt(value, 0).single().unwrap()
+    }
+
+    fn log() -> DeactivationLog {
+        let csv = "1,50,1600000000,1600000100\n1,63,1600000200,\n2,63,1600000300,\n3,50,1600000400,1600000500\n";
+
+        DeactivationLog::read(csv.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn export_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("deactivations.db");
+        let log = log();
+
+        log.export_sqlite(
+            &path,
+            Some("deactivations.csv"),
+            &[("input_sha256", "abc123".to_string())],
+        )
+        .unwrap();
+
+        let connection = Connection::open(&path).unwrap();
+
+        let count: usize = connection
+            .query_row("SELECT COUNT(*) FROM deactivations", [], |row| row.get(0))
+            .unwrap();
+        let user_count: usize = connection
+            .query_row(
+                "SELECT COUNT(DISTINCT user_id) FROM deactivations",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        let reversal_count: usize = connection
+            .query_row(
+                "SELECT COUNT(*) FROM deactivations WHERE reversal IS NOT NULL",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+
+        assert_eq!(count, 4);
+        assert_eq!(user_count, 3);
+        assert_eq!(reversal_count, 2);
+
+        let sources = connection
+            .prepare("SELECT DISTINCT source FROM deactivations")
+            .unwrap()
+            .query_map([], |row| row.get::<_, Option<String>>(0))
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        assert_eq!(sources, vec![Some("deactivations.csv".to_string())]);
+
+        let mut select = connection
+            .prepare(
+                "SELECT user_id, status, observed, reversal FROM deactivations
+                    ORDER BY user_id, observed",
+            )
+            .unwrap();
+        let rows = select
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, i64>(0)? as u64,
+                    Entry {
+                        status: row.get(1)?,
+                        observed: timestamp(row.get(2)?),
+                        reversal: row.get::<_, Option<i64>>(3)?.map(timestamp),
+                    },
+                ))
+            })
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        let mut expected = log.deactivations(None);
+        expected.sort_by_key(|(user_id, entry)| (*user_id, entry.observed));
+
+        assert_eq!(rows, expected);
+
+        let input_sha256: String = connection
+            .query_row(
+                "SELECT value FROM metadata WHERE name = 'input_sha256'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        let generated_at: String = connection
+            .query_row(
+                "SELECT value FROM metadata WHERE name = 'generated_at'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+
+        assert_eq!(input_sha256, "abc123");
+        assert!(DateTime::parse_from_rfc3339(&generated_at).is_ok());
+    }
+
+    #[test]
+    fn export_fails_for_existing_tables() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("deactivations.db");
+        let log = log();
+
+        log.export_sqlite(&path, None, &[]).unwrap();
+
+        assert!(log.export_sqlite(&path, None, &[]).is_err());
+    }
+}
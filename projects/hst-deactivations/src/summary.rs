@@ -0,0 +1,195 @@
+//! Summary statistics for a log.
+
+use super::DeactivationLog;
+use chrono::{DateTime, Utc};
+use std::collections::BTreeMap;
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, serde::Serialize)]
+pub struct StatusCounts {
+    /// Users whose most recent deactivation has this status and hasn't been reversed.
+    pub current: usize,
+    /// Users who have ever had a deactivation with this status.
+    pub ever: usize,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, serde::Serialize)]
+pub struct LogSummary {
+    pub statuses: BTreeMap<u32, StatusCounts>,
+    pub user_count: usize,
+    pub entry_count: usize,
+    pub reversal_count: usize,
+    pub first_observed: Option<DateTime<Utc>>,
+    pub last_observed: Option<DateTime<Utc>>,
+    /// Users with more than one entry.
+    pub repeated_user_count: usize,
+}
+
+impl std::fmt::Display for LogSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let timestamp = |value: Option<DateTime<Utc>>| {
+            value
+                .map(|value| value.to_rfc3339())
+                .unwrap_or_else(|| "-".to_string())
+        };
+
+        writeln!(f, "{:<20}{:>12}", "Users", self.user_count)?;
+        writeln!(f, "{:<20}{:>12}", "Entries", self.entry_count)?;
+        writeln!(f, "{:<20}{:>12}", "Reversals", self.reversal_count)?;
+        writeln!(
+            f,
+            "{:<20}{:>12}",
+            "Repeated users", self.repeated_user_count
+        )?;
+        writeln!(
+            f,
+            "{:<20}{}",
+            "First observed",
+            timestamp(self.first_observed)
+        )?;
+        writeln!(
+            f,
+            "{:<20}{}",
+            "Last observed",
+            timestamp(self.last_observed)
+        )?;
+        writeln!(f)?;
+        writeln!(f, "{:<20}{:>12}{:>12}", "Status", "Current", "Ever")?;
+
+        for (status, counts) in &self.statuses {
+            writeln!(f, "{:<20}{:>12}{:>12}", status, counts.current, counts.ever)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl DeactivationLog {
+    pub fn summary(&self) -> LogSummary {
+        let mut summary = LogSummary {
+            user_count: self.entries.len(),
+            ..LogSummary::default()
+        };
+
+        for entries in self.entries.values() {
+            let mut statuses = entries.iter().map(|entry| entry.status).collect::<Vec<_>>();
+            statuses.sort_unstable();
+            statuses.dedup();
+
+            for status in statuses {
+                summary.statuses.entry(status).or_default().ever += 1;
+            }
+
+            if let Some(last) = entries.last().filter(|entry| entry.reversal.is_none()) {
+                summary.statuses.entry(last.status).or_default().current += 1;
+            }
+
+            for entry in entries {
+                summary.entry_count += 1;
+
+                if entry.reversal.is_some() {
+                    summary.reversal_count += 1;
+                }
+
+                summary.first_observed = Some(
+                    summary
+                        .first_observed
+                        .map_or(entry.observed, |first| first.min(entry.observed)),
+                );
+                summary.last_observed = Some(
+                    summary
+                        .last_observed
+                        .map_or(entry.observed, |last| last.max(entry.observed)),
+                );
+            }
+
+            if entries.len() > 1 {
+                summary.repeated_user_count += 1;
+            }
+        }
+
+        summary
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    const LOG: &str = "1,50,100,150\n1,63,200,\n2,63,300,\n3,50,50,60\n3,50,400,\n4,99,250,\n";
+
+    fn timestamp(value: i64) -> DateTime<Utc> {
+        Utc.timestamp_opt(value, 0).single().unwrap()
+    }
+
+    #[test]
+    fn summary_counts() {
+        let summary = DeactivationLog::read(LOG.as_bytes()).unwrap().summary();
+
+        assert_eq!(
+            summary.statuses.into_iter().collect::<Vec<_>>(),
+            vec![
+                (
+                    50,
+                    StatusCounts {
+                        current: 1,
+                        ever: 2
+                    }
+                ),
+                (
+                    63,
+                    StatusCounts {
+                        current: 2,
+                        ever: 2
+                    }
+                ),
+                // An unknown status code is counted like any other.
+                (
+                    99,
+                    StatusCounts {
+                        current: 1,
+                        ever: 1
+                    }
+                ),
+            ]
+        );
+        assert_eq!(summary.user_count, 4);
+        assert_eq!(summary.entry_count, 6);
+        assert_eq!(summary.reversal_count, 2);
+        assert_eq!(summary.first_observed, Some(timestamp(50)));
+        assert_eq!(summary.last_observed, Some(timestamp(400)));
+        assert_eq!(summary.repeated_user_count, 2);
+    }
+
+    #[test]
+    fn summary_of_empty_log() {
+        let summary = DeactivationLog::read("".as_bytes()).unwrap().summary();
+
+        assert_eq!(summary, LogSummary::default());
+    }
+
+    #[test]
+    fn summary_display() {
+        let summary = DeactivationLog::read(LOG.as_bytes()).unwrap().summary();
+
+        let expected = [
+            "Users                          4",
+            "Entries                        6",
+            "Reversals                      2",
+            "Repeated users                 2",
+            "First observed      1970-01-01T00:00:50+00:00",
+            "Last observed       1970-01-01T00:06:40+00:00",
+            "",
+            "Status                   Current        Ever",
+            "50                             1           2",
+            "63                             2           2",
+            "99                             1           1",
+            "",
+        ];
+
+        assert_eq!(summary.to_string(), expected.join("\n"));
+        assert!(LogSummary::default()
+            .to_string()
+            .contains("First observed      -\n"));
+    }
+}
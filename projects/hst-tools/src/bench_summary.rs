@@ -0,0 +1,213 @@
+//! Summaries of Criterion benchmark results, for tracking throughput between releases.
+//!
+//! Criterion writes the latest results for each benchmark to `new/benchmark.json` and
+//! `new/estimates.json` under its output directory (usually `target/criterion`).
+//!
+//! The summary isn't checked in, since it's only meaningful for a complete run on a known
+//! machine. To produce one, run every benchmark and then the summary tool from the workspace
+//! root:
+//!
+//! ```bash
+//! cargo bench --workspace
+//! cargo run --release --bin hst-bench-summary
+//! ```
+
+use std::fs::File;
+use std::io::{BufReader, Write};
+use std::path::{Path, PathBuf};
+
+const REPORT_DIR_NAME: &str = "report";
+const LATEST_DIR_NAME: &str = "new";
+
+#[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
+pub enum Error {
+    #[error("I/O error")]
+    Io(#[from] std::io::Error),
+    #[error("JSON error")]
+    Json(#[from] serde_json::Error),
+    #[error("Invalid benchmark result")]
+    InvalidResult(PathBuf),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct BenchResult {
+    pub id: String,
+    /// Number of records processed per iteration (if the benchmark reports throughput).
+    pub elements: Option<u64>,
+    /// Mean iteration time in nanoseconds.
+    pub mean_ns: f64,
+}
+
+impl BenchResult {
+    pub fn records_per_second(&self) -> Option<f64> {
+        self.elements
+            .filter(|_| self.mean_ns > 0.0)
+            .map(|elements| elements as f64 * 1e9 / self.mean_ns)
+    }
+}
+
+/// Read the latest result for every benchmark, sorted by ID.
+pub fn read_results<P: AsRef<Path>>(criterion_dir: P) -> Result<Vec<BenchResult>, Error> {
+    let mut results = vec![];
+    visit(criterion_dir.as_ref(), &mut results)?;
+    results.sort_by(|a, b| a.id.cmp(&b.id));
+    Ok(results)
+}
+
+fn visit(path: &Path, results: &mut Vec<BenchResult>) -> Result<(), Error> {
+    let latest = path.join(LATEST_DIR_NAME);
+
+    if latest.join("benchmark.json").is_file() {
+        results.push(read_result(&latest)?);
+    }
+
+    for entry in std::fs::read_dir(path)? {
+        let entry = entry?;
+
+        if entry.file_type()?.is_dir()
+            && entry.file_name() != REPORT_DIR_NAME
+            && entry.file_name() != LATEST_DIR_NAME
+        {
+            visit(&entry.path(), results)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn read_result(path: &Path) -> Result<BenchResult, Error> {
+    let invalid = || Error::InvalidResult(path.to_path_buf());
+    let benchmark: serde_json::Value =
+        serde_json::from_reader(BufReader::new(File::open(path.join("benchmark.json"))?))?;
+    let estimates: serde_json::Value =
+        serde_json::from_reader(BufReader::new(File::open(path.join("estimates.json"))?))?;
+
+    Ok(BenchResult {
+        id: benchmark["full_id"]
+            .as_str()
+            .ok_or_else(invalid)?
+            .to_string(),
+        elements: benchmark["throughput"]["Elements"].as_u64(),
+        mean_ns: estimates["mean"]["point_estimate"]
+            .as_f64()
+            .ok_or_else(invalid)?,
+    })
+}
+
+/// Write the results as a Markdown table.
+pub fn write_markdown<W: Write>(
+    results: &[BenchResult],
+    generated_at: chrono::NaiveDate,
+    mut writer: W,
+) -> Result<(), std::io::Error> {
+    writeln!(writer, "# Benchmark summary")?;
+    writeln!(writer)?;
+    writeln!(
+        writer,
+        "Generated by `hst-bench-summary` on {} from the latest `cargo bench` results.",
+        generated_at
+    )?;
+    writeln!(
+        writer,
+        "Absolute numbers depend on the machine, so compare them only with runs on the same one."
+    )?;
+    writeln!(writer)?;
+    writeln!(writer, "| Benchmark | Records | Mean time | Records/s |")?;
+    writeln!(writer, "|---|---:|---:|---:|")?;
+
+    for result in results {
+        writeln!(
+            writer,
+            "| {} | {} | {:.2} ms | {} |",
+            result.id,
+            result
+                .elements
+                .map(|elements| elements.to_string())
+                .unwrap_or_else(|| "-".to_string()),
+            result.mean_ns / 1e6,
+            result
+                .records_per_second()
+                .map(|rate| format!("{:.0}", rate))
+                .unwrap_or_else(|| "-".to_string())
+        )?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_result(base: &Path, id: &str, elements: Option<u64>, mean_ns: f64) {
+        let path = base.join(id).join(LATEST_DIR_NAME);
+        std::fs::create_dir_all(&path).unwrap();
+
+        let throughput = elements.map(|elements| serde_json::json!({ "Elements": elements }));
+
+        std::fs::write(
+            path.join("benchmark.json"),
+            serde_json::json!({ "full_id": id, "throughput": throughput }).to_string(),
+        )
+        .unwrap();
+        std::fs::write(
+            path.join("estimates.json"),
+            serde_json::json!({ "mean": { "point_estimate": mean_ns } }).to_string(),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn read_and_summarize() {
+        let dir = tempfile::tempdir().unwrap();
+        write_result(dir.path(), "stream/parse", Some(1_000), 2_000_000.0);
+        write_result(dir.path(), "avro/write", Some(10_000), 50_000_000.0);
+        write_result(dir.path(), "lookup", None, 1_500.0);
+        // Criterion's HTML reports and earlier baselines are ignored.
+        std::fs::create_dir_all(dir.path().join(REPORT_DIR_NAME)).unwrap();
+        std::fs::create_dir_all(dir.path().join("avro/write/base")).unwrap();
+
+        let results = read_results(dir.path()).unwrap();
+
+        assert_eq!(
+            results
+                .iter()
+                .map(|result| (result.id.as_str(), result.records_per_second()))
+                .collect::<Vec<_>>(),
+            vec![
+                ("avro/write", Some(200_000.0)),
+                ("lookup", None),
+                ("stream/parse", Some(500_000.0)),
+            ]
+        );
+
+        let mut output = vec![];
+        write_markdown(
+            &results,
+            chrono::NaiveDate::from_ymd_opt(2022, 1, 1).unwrap(),
+            &mut output,
+        )
+        .unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        assert!(output.contains("on 2022-01-01 from"));
+        assert!(output.contains("| avro/write | 10000 | 50.00 ms | 200000 |\n"));
+        assert!(output.contains("| lookup | - | 0.00 ms | - |\n"));
+        assert!(output.contains("| stream/parse | 1000 | 2.00 ms | 500000 |\n"));
+    }
+
+    #[test]
+    fn invalid_results() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("broken").join(LATEST_DIR_NAME);
+        std::fs::create_dir_all(&path).unwrap();
+        std::fs::write(path.join("benchmark.json"), "{}").unwrap();
+        std::fs::write(path.join("estimates.json"), "{}").unwrap();
+
+        assert!(matches!(
+            read_results(dir.path()),
+            Err(Error::InvalidResult(_))
+        ));
+    }
+}
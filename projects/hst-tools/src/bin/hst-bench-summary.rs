@@ -0,0 +1,43 @@
+use hst_cli::prelude::*;
+use hst_tw_tools::bench_summary;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+fn main() -> Result<(), Error> {
+    let opts: Opts = Opts::parse();
+    opts.verbose.init_logging()?;
+
+    let results = bench_summary::read_results(&opts.criterion)?;
+    let mut writer = BufWriter::new(File::create(&opts.output)?);
+
+    bench_summary::write_markdown(&results, chrono::Utc::now().date_naive(), &mut writer)?;
+    writer.flush()?;
+
+    log::info!("Summarized {} benchmarks in {}", results.len(), opts.output);
+
+    Ok(())
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("Benchmark summary error")]
+    BenchSummary(#[from] bench_summary::Error),
+    #[error("I/O error")]
+    Io(#[from] std::io::Error),
+    #[error("Log initialization error")]
+    LogInitialization(#[from] log::SetLoggerError),
+}
+
+/// Collate the latest Criterion results into a Markdown summary
+#[derive(Debug, Parser)]
+#[clap(name = "hst-bench-summary", version, author)]
+struct Opts {
+    #[clap(flatten)]
+    verbose: Verbosity,
+    /// Criterion output directory
+    #[clap(long, default_value = "target/criterion")]
+    criterion: String,
+    /// Summary output path
+    #[clap(long, default_value = "BENCHMARKS.md")]
+    output: String,
+}
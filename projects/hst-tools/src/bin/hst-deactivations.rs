@@ -0,0 +1,93 @@
+use hst_cli::prelude::*;
+use hst_deactivations::{index::DeactivationIndex, DeactivationLog};
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::path::Path;
+
+fn main() -> Result<(), Error> {
+    let opts: Opts = Opts::parse();
+    opts.verbose.init_logging()?;
+
+    match opts.command {
+        Command::Summary { json } => {
+            let summary = DeactivationLog::open(opts.deactivations)?.summary();
+
+            if json {
+                println!("{}", serde_json::to_string_pretty(&summary)?);
+            } else {
+                print!("{}", summary);
+            }
+        }
+        Command::BuildIndex => {
+            let index = DeactivationIndex::build(&opts.deactivations)?;
+
+            log::info!(
+                "Indexed {} entries in {}",
+                index.len(),
+                DeactivationIndex::index_path(&opts.deactivations).display()
+            );
+        }
+        Command::ExportSqlite { output } => {
+            let mut hasher = Sha256::new();
+            std::io::copy(&mut File::open(&opts.deactivations)?, &mut hasher)?;
+            let input_digest = hst_tw_utils::hex(&hasher.finalize());
+
+            let source = Path::new(&opts.deactivations)
+                .file_name()
+                .and_then(|name| name.to_str());
+
+            DeactivationLog::open(&opts.deactivations)?.export_sqlite(
+                output,
+                source,
+                &[
+                    ("input_sha256", input_digest),
+                    ("tool_version", env!("CARGO_PKG_VERSION").to_string()),
+                ],
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("Deactivation log error")]
+    Deactivations(#[from] hst_deactivations::Error),
+    #[error("I/O error")]
+    Io(#[from] std::io::Error),
+    #[error("JSON encoding error")]
+    Json(#[from] serde_json::Error),
+    #[error("Log initialization error")]
+    LogInitialization(#[from] log::SetLoggerError),
+}
+
+#[derive(Debug, Parser)]
+#[clap(name = "hst-deactivations", version, author)]
+struct Opts {
+    #[clap(flatten)]
+    verbose: Verbosity,
+    /// Deactivation log path (CSV or NDJSON)
+    #[clap(long)]
+    deactivations: String,
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Parser)]
+enum Command {
+    /// Print counts of users, entries, and reversals (by status)
+    Summary {
+        /// Print JSON instead of a table
+        #[clap(long)]
+        json: bool,
+    },
+    /// Build the index sidecar for a CSV log
+    BuildIndex,
+    /// Export the log to a new SQLite database
+    ExportSqlite {
+        /// Output database path
+        #[clap(long)]
+        output: String,
+    },
+}
@@ -0,0 +1,63 @@
+use hst_cli::prelude::*;
+use hst_tw_tools::doctor;
+
+fn main() -> Result<(), Error> {
+    let opts: Opts = Opts::parse();
+    opts.verbose.init_logging()?;
+
+    let sniffers = doctor::default_sniffers();
+
+    for path in opts.paths {
+        for entry in doctor::inventory(path, &sniffers)? {
+            match entry.detection {
+                Some(detection) => println!(
+                    "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                    entry.path.display(),
+                    detection.format,
+                    detection.version.unwrap_or_else(|| "-".to_string()),
+                    entry.size,
+                    detection
+                        .record_count
+                        .map(|count| count.to_string())
+                        .unwrap_or_else(|| "-".to_string()),
+                    if detection.readable {
+                        "readable"
+                    } else {
+                        "unreadable"
+                    },
+                    if detection.writable {
+                        "writable"
+                    } else {
+                        "read-only"
+                    }
+                ),
+                None => println!(
+                    "{}\tunknown\t-\t{}\t-\t-\t-",
+                    entry.path.display(),
+                    entry.size
+                ),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("I/O error")]
+    Io(#[from] std::io::Error),
+    #[error("Log initialization error")]
+    LogInitialization(#[from] log::SetLoggerError),
+}
+
+/// Report the format, version, size, and record count of files and directories
+#[derive(Debug, Parser)]
+#[clap(name = "hst-doctor", version, author)]
+struct Opts {
+    #[clap(flatten)]
+    verbose: Verbosity,
+    /// Files or directories to inspect
+    #[clap(required = true)]
+    paths: Vec<String>,
+}
@@ -0,0 +1,51 @@
+use hst_cli::prelude::*;
+use hst_tw_profiles::coverage::{self, compute_coverage};
+
+fn main() -> Result<(), Error> {
+    let opts: Opts = Opts::parse();
+    opts.verbose.init_logging()?;
+
+    let output = opts
+        .output
+        .map(Into::into)
+        .unwrap_or_else(|| coverage::coverage_path(&opts.archives[0]));
+
+    let coverage = compute_coverage(&opts.archives)?;
+    coverage.save(&output)?;
+
+    if let (Some(first), Some(last)) = (coverage.days.first(), coverage.days.last()) {
+        log::info!(
+            "Wrote coverage for {} to {} to {} ({} days missing)",
+            first.date,
+            last.date,
+            output.display(),
+            coverage.missing_days(first.date, last.date).len()
+        );
+    }
+
+    Ok(())
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("Archive error")]
+    Archive(#[from] hst_tw_profiles::archive::Error),
+    #[error("Coverage error")]
+    Coverage(#[from] coverage::Error),
+    #[error("Log initialization error")]
+    LogInitialization(#[from] log::SetLoggerError),
+}
+
+/// Compute per-day collection coverage for tar or ZIP profile archives
+#[derive(Debug, Parser)]
+#[clap(name = "hst-tw-coverage", version, author)]
+struct Opts {
+    #[clap(flatten)]
+    verbose: Verbosity,
+    /// Output path (defaults to coverage.json next to the first archive)
+    #[clap(long)]
+    output: Option<String>,
+    /// Archive paths
+    #[clap(required = true)]
+    archives: Vec<String>,
+}
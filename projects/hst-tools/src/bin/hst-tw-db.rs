@@ -1,91 +1,661 @@
+use chrono::{TimeZone, Utc};
 use hst_cli::prelude::*;
+use hst_deactivations::DeactivationLog;
 use hst_tw_db::{
-    table::{ReadOnly, Table, Writeable},
-    ProfileDb,
+    alias::AliasDb,
+    collision::ImportStats,
+    export::ExportFormat,
+    identity::{Identity, IdentityDb},
+    pack::ResearchPack,
+    table::{Mode, ReadOnly, Table, Writeable},
+    unprofiled, ProfileDb,
 };
-use hst_tw_profiles::model::User;
-use std::collections::HashSet;
+use hst_tw_profiles::{
+    avro::chunked::ChunkLimits,
+    coverage::Coverage,
+    model::User,
+    names::normalize_screen_name,
+    similarity::{MinHasher, SimilarityIndex, SimilarityWeights},
+};
+use hst_tw_utils::preflight;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeSet, HashSet};
 use std::fs::File;
+use std::io::{BufRead, BufWriter};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Upper bound on database growth relative to the Avro input size (allowing for compaction).
+const IMPORT_SPACE_RATIO: f64 = 2.0;
+/// Number of stored values used to estimate the average snapshot size.
+const SPACE_SAMPLE_SIZE: usize = 10_000;
 
 fn main() -> Result<(), Error> {
     let opts: Opts = Opts::parse();
     opts.verbose.init_logging()?;
 
+    let slow_threshold = opts.slow_threshold.map(Duration::from_millis);
+
     match opts.command {
-        Command::Import { input } => {
-            let db = ProfileDb::<Writeable>::open(opts.db, false)?;
+        Command::Import {
+            input,
+            skip_space_check,
+            batch_size,
+            disable_wal,
+        } => {
+            hst_tw_profiles::avro::verify_schema_compatibility()?;
+
+            let probe = (!skip_space_check).then_some(preflight::SystemProbe);
+            let db = import(
+                probe.as_ref(),
+                &opts.db,
+                &input,
+                batch_size,
+                disable_wal,
+                slow_threshold,
+            )?;
+
+            log_timing_report(&db);
+        }
+        Command::Lookup { id, aliases } => {
+            let db = open_db::<ReadOnly>(&opts.db, true, slow_threshold)?;
+
+            match aliases {
+                Some(aliases) => {
+                    let aliases = AliasDb::open(aliases)?;
+
+                    for (_, _, user) in db.lookup_with_aliases(&aliases, id)? {
+                        println!("{}", serde_json::to_value(user)?);
+                    }
+                }
+                None => {
+                    for (_, user) in db.lookup(id)? {
+                        println!("{}", serde_json::to_value(user)?);
+                    }
+                }
+            }
+        }
+        Command::Diff { id, from, to, json } => {
+            let db = open_db::<ReadOnly>(&opts.db, true, slow_threshold)?;
+
+            // Only the snapshots on either side of each timestamp are decoded.
+            let nearest = |timestamp: i64| -> Result<User, Error> {
+                let timestamp = Utc
+                    .timestamp_opt(timestamp, 0)
+                    .single()
+                    .ok_or(Error::InvalidTimestamp(timestamp))?;
 
-            let file = File::open(input)?;
-            let reader = hst_tw_profiles::avro::reader(file)?;
+                db.lookup_latest_before(id, timestamp)?
+                    .into_iter()
+                    .chain(db.lookup_earliest_after(id, timestamp)?)
+                    .map(|(_, user)| user)
+                    .min_by_key(|user| (user.snapshot - timestamp.timestamp()).abs())
+                    .ok_or(Error::NoSnapshots(id))
+            };
 
-            for value in reader {
-                let user = apache_avro::from_value::<User>(&value?)?;
-                db.update(&user)?;
+            let diff = hst_tw_profiles::model::diff(&nearest(from)?, &nearest(to)?);
+
+            if json {
+                println!("{}", serde_json::to_string_pretty(&diff)?);
+            } else {
+                print!("{}", diff.to_markdown());
             }
         }
-        Command::Lookup { id } => {
-            let db = ProfileDb::<ReadOnly>::open(opts.db, true)?;
-            let users = db.lookup(id)?;
+        Command::Changes { id, json } => {
+            let db = open_db::<ReadOnly>(&opts.db, true, slow_threshold)?;
 
-            for (_, user) in users {
-                println!("{}", serde_json::to_value(user)?);
+            for diff in db.changes(id)? {
+                if json {
+                    println!("{}", serde_json::to_string(&diff)?);
+                } else {
+                    println!("## {} to {}\n", diff.from_snapshot, diff.to_snapshot);
+                    println!("{}", diff.to_markdown());
+                }
             }
         }
-        Command::Count => {
-            let db = ProfileDb::<ReadOnly>::open(opts.db, true)?;
-            let mut user_count = 0;
-            let mut screen_name_count = 0;
-            let mut verified = 0;
-            let mut protected = 0;
-            for result in db.iter() {
-                let (_, users) = result?;
-                let mut screen_names = HashSet::new();
+        Command::ResearchPack {
+            ids,
+            out,
+            deactivations,
+            coverage,
+            json,
+        } => {
+            let db = open_db::<ReadOnly>(&opts.db, false, slow_threshold)?;
+            let deactivations = deactivations
+                .map(|path| Ok::<_, Error>(DeactivationLog::read(File::open(path)?)?))
+                .transpose()?;
+
+            let ids_bytes = std::fs::read(&ids)?;
+            let ids_digest = hst_tw_utils::hex(&Sha256::digest(&ids_bytes));
+
+            let user_ids = read_user_ids(&ids_bytes)?;
+            let mut pack = ResearchPack::create(out, json)?;
+            let mut missing = 0;
+
+            for id in user_ids {
+                let snapshots = db.lookup(id)?;
+
+                if snapshots.is_empty() {
+                    missing += 1;
+                }
+
+                pack.add_user(id, &snapshots)?;
+
+                if let Some(entries) = deactivations.as_ref().and_then(|log| log.lookup(id)) {
+                    pack.add_deactivations(
+                        id,
+                        entries
+                            .into_iter()
+                            .map(|entry| (entry.status, entry.observed, entry.reversal)),
+                    )?;
+                }
+            }
+
+            let mut metadata = vec![
+                ("ids_sha256", ids_digest),
+                ("missing_user_count", missing.to_string()),
+                ("tool_version", env!("CARGO_PKG_VERSION").to_string()),
+            ];
+
+            if let Some(coverage) = coverage {
+                let coverage = Coverage::load(coverage)?;
+
+                if let (Some(first), Some(last)) = (coverage.days.first(), coverage.days.last()) {
+                    metadata.push(("coverage_first_day", first.date.to_string()));
+                    metadata.push(("coverage_last_day", last.date.to_string()));
+                    metadata.push((
+                        "coverage_missing_days",
+                        coverage
+                            .missing_days(first.date, last.date)
+                            .len()
+                            .to_string(),
+                    ));
+                }
+            }
+
+            let counts = pack.finish(&metadata)?;
+
+            log::info!(
+                "Exported {} snapshots for {} users ({} not found)",
+                counts.snapshot_count,
+                counts.user_count,
+                missing
+            );
+            log_timing_report(&db);
+        }
+        Command::Unprofiled {
+            deactivations,
+            status,
+        } => {
+            let db = open_db::<ReadOnly>(&opts.db, false, slow_threshold)?;
+            let log = DeactivationLog::read(File::open(deactivations)?)?;
+            let accounts = unprofiled::unprofiled_deactivations(&log, &db, status)?;
+
+            unprofiled::write_csv(&accounts, std::io::stdout().lock())?;
+            log::info!("{} unprofiled accounts", accounts.len());
+        }
+        Command::Similar {
+            ids,
+            min_followers,
+            min_score,
+        } => {
+            let db = open_db::<ReadOnly>(&opts.db, true, slow_threshold)?;
+            let mut index = SimilarityIndex::new(MinHasher::new(4, 128, 0), 32);
+
+            match ids {
+                Some(ids) => {
+                    for line in std::io::BufReader::new(File::open(ids)?).lines() {
+                        let line = line?;
+                        let id = line
+                            .trim()
+                            .parse::<u64>()
+                            .map_err(|_| Error::InvalidUserId(line.clone()))?;
+
+                        if let Some((_, user)) = db.lookup(id)?.last() {
+                            index.add(user);
+                        }
+                    }
+                }
+                None => {
+                    for result in db.iter() {
+                        let (_, users) = result?;
+
+                        if let Some((_, user)) = users.last() {
+                            if user.followers_count >= min_followers {
+                                index.add(user);
+                            }
+                        }
+                    }
+                }
+            }
+
+            for pair in index.pairs(&SimilarityWeights::default(), min_score) {
+                println!(
+                    "{},{},{},{},{:.4},{:.4},{},{}",
+                    pair.user_id_a,
+                    pair.screen_name_a,
+                    pair.user_id_b,
+                    pair.screen_name_b,
+                    pair.score,
+                    pair.bio_similarity,
+                    pair.creation_delta
+                        .map(|delta| delta.num_seconds().to_string())
+                        .unwrap_or_default(),
+                    pair.examples.join(";")
+                );
+            }
+
+            log_timing_report(&db);
+        }
+        Command::Identity { file, command } => {
+            let mut identities = IdentityDb::open(file)?;
 
-                user_count += 1;
+            match command {
+                IdentityCommand::Add {
+                    twitter_id,
+                    platform,
+                    handle,
+                    confidence,
+                    source,
+                } => {
+                    identities.add(&Identity::new(
+                        twitter_id,
+                        platform.parse()?,
+                        &handle,
+                        confidence,
+                        &source,
+                    )?)?;
+                }
+                IdentityCommand::Import { input } => {
+                    let report = identities.import_csv(File::open(input)?)?;
+
+                    for (line_number, error) in &report.rejected {
+                        log::warn!("Rejected line {}: {:?}", line_number, error);
+                    }
 
-                for (_, user) in &users {
-                    screen_names.insert(user.screen_name.clone());
+                    log::info!(
+                        "Imported {} mappings ({} rejected)",
+                        report.imported,
+                        report.rejected.len()
+                    );
                 }
+                IdentityCommand::Lookup { twitter_id } => {
+                    for identity in identities.lookup(twitter_id)? {
+                        println!(
+                            "{},{},{},{},{}",
+                            identity.twitter_id,
+                            identity.platform,
+                            identity.handle,
+                            identity.confidence,
+                            identity.source
+                        );
+                    }
+                }
+            }
+        }
+        Command::Alias { file, command } => {
+            let aliases = AliasDb::open(file)?;
 
-                if let Some((_, user)) = users.last() {
-                    if user.verified {
-                        verified += 1;
+            match command {
+                AliasCommand::Add {
+                    canonical_id,
+                    alias_id,
+                    note,
+                } => {
+                    if !aliases.add(canonical_id, alias_id, note.as_deref())? {
+                        log::warn!("Alias {} already exists", alias_id);
                     }
-                    if user.protected {
-                        protected += 1;
+                }
+                AliasCommand::Remove { alias_id } => {
+                    if !aliases.remove(alias_id)? {
+                        log::warn!("Alias {} not found", alias_id);
+                    }
+                }
+                AliasCommand::List => {
+                    for alias in aliases.list()? {
+                        println!(
+                            "{},{},{},{}",
+                            alias.canonical_id,
+                            alias.alias_id,
+                            alias.added_at.timestamp(),
+                            alias.note.unwrap_or_default()
+                        );
                     }
                 }
+            }
+        }
+        Command::Count => {
+            let db = open_db::<ReadOnly>(&opts.db, true, slow_threshold)?;
+            let mut counts = UserCounts::default();
 
-                screen_name_count += screen_names.len();
+            for result in db.iter() {
+                let (_, users) = result?;
+                counts.add(&users);
             }
 
-            println!("{} users, {} screen names", user_count, screen_name_count);
-            println!("{} verified, {} protected", verified, protected);
+            println!(
+                "{} users, {} screen names",
+                counts.user_count, counts.screen_name_count
+            );
+            println!(
+                "{} verified, {} protected",
+                counts.verified, counts.protected
+            );
         }
         Command::Stats => {
-            let db = ProfileDb::<ReadOnly>::open(opts.db, true)?;
+            let db = open_db::<ReadOnly>(&opts.db, true, slow_threshold)?;
             if let Some(count) = db.get_estimated_key_count()? {
                 println!("Estimated number of keys: {}", count);
             }
             println!("{:?}", db.statistics());
         }
+        Command::ScreenName {
+            screen_name,
+            prefix,
+        } => {
+            let db = open_db::<ReadOnly>(&opts.db, false, slow_threshold)?;
+            let user_ids = if prefix {
+                db.lookup_screen_name_prefix(&screen_name)?
+            } else {
+                db.lookup_screen_name(&screen_name)?
+            };
+
+            for user_id in user_ids {
+                println!("{}", user_id);
+            }
+        }
+        Command::RebuildScreenNameIndex => {
+            let db = open_db::<Writeable>(&opts.db, false, slow_threshold)?;
+            let count = db.rebuild_screen_name_index()?;
+
+            log::info!("Indexed {} snapshots", count);
+        }
+        Command::Merge { source } => {
+            let source = open_db::<ReadOnly>(&source, false, slow_threshold)?;
+            let target = open_db::<Writeable>(&opts.db, false, slow_threshold)?;
+            let stats = hst_tw_db::merge(&source, &target)?;
+
+            for (user_id, snapshot) in &stats.conflicts {
+                log::warn!("Conflicting snapshot for {} at {}", user_id, snapshot);
+            }
+
+            log::info!(
+                "Copied {} snapshots ({} skipped, {} conflicts)",
+                stats.copied,
+                stats.skipped,
+                stats.conflicts.len()
+            );
+        }
+        Command::Prune { ignore, dry_run } => {
+            let db = open_db::<Writeable>(&opts.db, false, slow_threshold)?;
+            let ignored_fields = ignore.iter().map(String::as_str).collect::<Vec<_>>();
+            let count = db.prune_unchanged(&ignored_fields, dry_run)?;
+
+            if dry_run {
+                log::info!("Would delete {} snapshots", count);
+            } else {
+                log::info!("Deleted {} snapshots", count);
+            }
+        }
+        Command::Export {
+            output,
+            start,
+            end,
+            by_time,
+            temp_dir,
+            chunked,
+            max_part_bytes,
+            skip_space_check,
+        } => {
+            let timestamp = |value: i64| {
+                Utc.timestamp_opt(value, 0)
+                    .single()
+                    .ok_or(Error::InvalidTimestamp(value))
+            };
+            let start = start.map(timestamp).transpose()?;
+            let end = end.map(timestamp).transpose()?;
+
+            let db = open_db::<ReadOnly>(&opts.db, false, slow_threshold)?;
+            let format = if chunked {
+                ExportFormat::Avro
+            } else {
+                ExportFormat::from_path(&output)
+                    .ok_or_else(|| Error::InvalidExportPath(output.clone()))?
+            };
+            let temp_dir = temp_dir.map_or_else(std::env::temp_dir, PathBuf::from);
+
+            if !skip_space_check {
+                check_export_space(
+                    &preflight::SystemProbe,
+                    &db,
+                    &output,
+                    format,
+                    by_time.then_some(temp_dir.as_path()),
+                )?;
+            }
+
+            if chunked {
+                let limits = ChunkLimits {
+                    max_bytes: max_part_bytes.unwrap_or(ChunkLimits::default().max_bytes),
+                    ..ChunkLimits::default()
+                };
+                let manifest = db.export_chunked(&output, limits, start, end)?;
+
+                log::info!(
+                    "Exported {} snapshots to {} parts in {}",
+                    manifest.record_count(),
+                    manifest.parts.len(),
+                    output
+                );
+            } else {
+                let writer = BufWriter::new(File::create(&output)?);
+
+                let count = if by_time {
+                    db.export_by_time(writer, format, start, end, temp_dir)?
+                } else {
+                    db.export(writer, format, start, end)?
+                };
+
+                log::info!("Exported {} snapshots to {}", count, output);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Import profiles from an Avro file, checking for available disk space first (if a probe is
+/// given) so that we fail before opening or writing to the database.
+fn import<F: preflight::FsProbe>(
+    probe: Option<&F>,
+    db_path: &str,
+    input: &str,
+    batch_size: usize,
+    disable_wal: bool,
+    slow_threshold: Option<Duration>,
+) -> Result<ProfileDb<Writeable>, Error> {
+    if let Some(probe) = probe {
+        let required = preflight::estimate_from_input(input, IMPORT_SPACE_RATIO)?;
+        preflight::check_space_with(probe, db_path, required, 0)?;
+    }
+
+    let db = open_db::<Writeable>(db_path, false, slow_threshold)?.with_wal(!disable_wal);
+
+    let file = File::open(input)?;
+    let mut reader = hst_tw_profiles::avro::reader(file)?.peekable();
+    let mut stats = ImportStats::default();
+
+    while reader.peek().is_some() {
+        let users = reader
+            .by_ref()
+            .take(batch_size.max(1))
+            .map(|value| Ok(apache_avro::from_value::<User>(&value?)?))
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        for (user, outcome) in users.iter().zip(db.update_batch(&users)?) {
+            if outcome.is_collision() {
+                log::warn!(
+                    "Snapshot collision for {} at {}: {:?}",
+                    user.id(),
+                    user.snapshot,
+                    outcome
+                );
+            }
+
+            stats.record(outcome);
+        }
+    }
+
+    log::info!("{:?}", stats);
+
+    Ok(db)
+}
+
+/// Check that there's space for an export of the entire database (and for the temporary files
+/// used when ordering by time), estimated from the stored snapshots.
+fn check_export_space<F: preflight::FsProbe, M>(
+    probe: &F,
+    db: &ProfileDb<M>,
+    output: &str,
+    format: ExportFormat,
+    temp_dir: Option<&Path>,
+) -> Result<(), Error> {
+    let count = db.estimate_snapshot_count()?;
+    let average_size = db.average_value_size(SPACE_SAMPLE_SIZE)?;
+
+    preflight::check_space_with(
+        probe,
+        output,
+        preflight::estimate_from_records(count, average_size, format.space_ratio()),
+        0,
+    )?;
+
+    if let Some(temp_dir) = temp_dir {
+        preflight::check_space_with(
+            probe,
+            temp_dir,
+            preflight::estimate_from_records(count, average_size, ExportFormat::Avro.space_ratio()),
+            0,
+        )?;
     }
 
     Ok(())
 }
 
+/// Totals for the `count` command.
+#[derive(Debug, Default, Eq, PartialEq)]
+struct UserCounts {
+    user_count: usize,
+    /// Distinct screen names per user (after normalization, so differences in case or invisible
+    /// characters aren't counted).
+    screen_name_count: usize,
+    /// Users whose latest snapshot is verified.
+    verified: usize,
+    /// Users whose latest snapshot is protected.
+    protected: usize,
+}
+
+impl UserCounts {
+    /// Add every snapshot for a user (in snapshot order).
+    fn add<T>(&mut self, users: &[(T, User)]) {
+        let screen_names = users
+            .iter()
+            .map(|(_, user)| normalize_screen_name(&user.screen_name))
+            .collect::<HashSet<_>>();
+
+        self.user_count += 1;
+        self.screen_name_count += screen_names.len();
+
+        if let Some((_, user)) = users.last() {
+            if user.verified {
+                self.verified += 1;
+            }
+            if user.protected {
+                self.protected += 1;
+            }
+        }
+    }
+}
+
+/// Read a file with one user ID per line, skipping blank lines and duplicates.
+fn read_user_ids(bytes: &[u8]) -> Result<BTreeSet<u64>, Error> {
+    let mut user_ids = BTreeSet::new();
+
+    for line in bytes.lines() {
+        let line = line?;
+        let trimmed = line.trim();
+
+        if !trimmed.is_empty() {
+            user_ids.insert(
+                trimmed
+                    .parse::<u64>()
+                    .map_err(|_| Error::InvalidUserId(line.clone()))?,
+            );
+        }
+    }
+
+    Ok(user_ids)
+}
+
+/// Open the profile database, enabling operation timing if a slow threshold was given.
+fn open_db<M: Mode>(
+    path: &str,
+    enable_statistics: bool,
+    slow_threshold: Option<Duration>,
+) -> Result<ProfileDb<M>, Error> {
+    let db = ProfileDb::open(path, enable_statistics)?;
+
+    Ok(match slow_threshold {
+        Some(slow_threshold) => db.with_timing(slow_threshold),
+        None => db,
+    })
+}
+
+fn log_timing_report<M>(db: &ProfileDb<M>) {
+    for timing in db.timing_report().unwrap_or_default() {
+        if timing.count > 0 {
+            log::info!(
+                "{}: {} operations ({} slow), p50 {:?}, p90 {:?}, p99 {:?}, max {:?}",
+                timing.op,
+                timing.count,
+                timing.slow_count,
+                timing.p50,
+                timing.p90,
+                timing.p99,
+                timing.max
+            );
+        }
+    }
+}
+
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
     #[error("ProfileDb error")]
     ProfileDb(#[from] hst_tw_db::Error),
     #[error("Profile Avro error")]
     ProfileAvro(#[from] hst_tw_profiles::avro::Error),
+    #[error("Profile Avro schema mismatch")]
+    SchemaMismatch(#[from] hst_tw_profiles::avro::SchemaMismatch),
+    #[error("Deactivation log error")]
+    Deactivations(#[from] hst_deactivations::Error),
+    #[error("Coverage error")]
+    Coverage(#[from] hst_tw_profiles::coverage::Error),
+    #[error("Preflight check error")]
+    Preflight(#[from] preflight::Error),
     #[error("Avro decoding error")]
     Avro(#[from] apache_avro::Error),
     #[error("JSON encoding error")]
     Json(#[from] serde_json::Error),
     #[error("I/O error")]
     Io(#[from] std::io::Error),
+    #[error("Invalid user ID")]
+    InvalidUserId(String),
+    #[error("No snapshots for user")]
+    NoSnapshots(u64),
+    #[error("Invalid timestamp")]
+    InvalidTimestamp(i64),
+    #[error("Invalid export path")]
+    InvalidExportPath(String),
     #[error("Log initialization error")]
     LogInitialization(#[from] log::SetLoggerError),
 }
@@ -98,6 +668,10 @@ struct Opts {
     /// Database directory path
     #[clap(long)]
     db: String,
+    /// Warn about database operations slower than this many milliseconds (and report operation
+    /// timings for imports, research packs, and similarity searches)
+    #[clap(long)]
+    slow_threshold: Option<u64>,
     #[clap(subcommand)]
     command: Command,
 }
@@ -108,11 +682,372 @@ enum Command {
         /// Avro input path
         #[clap(short, long)]
         input: String,
+        /// Skip checking for available disk space
+        #[clap(long)]
+        skip_space_check: bool,
+        /// Number of profiles written per batch
+        #[clap(long, default_value = "10000")]
+        batch_size: usize,
+        /// Disable the write-ahead log (only for bulk loads that can be rerun)
+        #[clap(long)]
+        disable_wal: bool,
     },
     Lookup {
         /// Twitter user ID
         id: u64,
+        /// Alias database path (includes snapshots for aliases of the user)
+        #[clap(long)]
+        aliases: Option<String>,
     },
     Count,
     Stats,
+    /// Print the IDs of users who have had a screen name
+    ScreenName {
+        screen_name: String,
+        /// Match every screen name starting with the given one
+        #[clap(long)]
+        prefix: bool,
+    },
+    /// Build the screen name index for a database created without it
+    RebuildScreenNameIndex,
+    /// Copy snapshots that are missing from this database from another one
+    Merge {
+        /// Source database directory path
+        #[clap(long)]
+        source: String,
+    },
+    /// Delete snapshots that are unchanged from the previous snapshot for the user
+    Prune {
+        /// Additional fields to ignore when comparing (e.g. statuses_count)
+        #[clap(long)]
+        ignore: Vec<String>,
+        /// Only count the snapshots that would be deleted
+        #[clap(long)]
+        dry_run: bool,
+    },
+    /// Write snapshots to an Avro or NDJSON file (chosen by extension)
+    Export {
+        /// Output path (ending in .avro or .ndjson, or a directory with --chunked)
+        #[clap(short, long)]
+        output: String,
+        /// Epoch second (inclusive)
+        #[clap(long)]
+        start: Option<i64>,
+        /// Epoch second (exclusive)
+        #[clap(long)]
+        end: Option<i64>,
+        /// Order by snapshot timestamp instead of by user ID
+        #[clap(long)]
+        by_time: bool,
+        /// Directory for temporary files when ordering by time
+        #[clap(long)]
+        temp_dir: Option<String>,
+        /// Write a directory of Avro parts with a manifest
+        #[clap(long, conflicts_with = "by-time")]
+        chunked: bool,
+        /// Target size of each part in bytes (with --chunked)
+        #[clap(long, requires = "chunked")]
+        max_part_bytes: Option<u64>,
+        /// Skip checking for available disk space
+        #[clap(long)]
+        skip_space_check: bool,
+    },
+    /// Print the changes between the snapshots nearest the given timestamps
+    Diff {
+        /// Twitter user ID
+        id: u64,
+        /// Epoch second
+        #[clap(long)]
+        from: i64,
+        /// Epoch second
+        #[clap(long)]
+        to: i64,
+        /// Print JSON instead of Markdown
+        #[clap(long)]
+        json: bool,
+    },
+    /// Print the changes between each pair of consecutive snapshots
+    Changes {
+        /// Twitter user ID
+        id: u64,
+        /// Print JSON (one diff per line) instead of Markdown
+        #[clap(long)]
+        json: bool,
+    },
+    /// Export the snapshots for a list of users to a new SQLite file
+    ResearchPack {
+        /// File with one Twitter user ID per line
+        #[clap(long)]
+        ids: String,
+        /// SQLite output path
+        #[clap(long)]
+        out: String,
+        /// Deactivation log CSV path
+        #[clap(long)]
+        deactivations: Option<String>,
+        /// Collection coverage file path (summarized in the metadata)
+        #[clap(long)]
+        coverage: Option<String>,
+        /// Include the full JSON for each snapshot
+        #[clap(long)]
+        json: bool,
+    },
+    /// Print deactivation log entries for accounts without profile snapshots
+    Unprofiled {
+        /// Deactivation log CSV path
+        #[clap(long)]
+        deactivations: String,
+        /// Only include entries with this status code
+        #[clap(long)]
+        status: Option<u32>,
+    },
+    /// Print candidate near-duplicate accounts as CSV
+    Similar {
+        /// File with one Twitter user ID per line (defaults to all users)
+        #[clap(long)]
+        ids: Option<String>,
+        /// Minimum follower count when scanning all users
+        #[clap(long, default_value = "1000")]
+        min_followers: i64,
+        /// Minimum pair score
+        #[clap(long, default_value = "0.5")]
+        min_score: f64,
+    },
+    Identity {
+        /// Identity mapping database path
+        #[clap(long)]
+        file: String,
+        #[clap(subcommand)]
+        command: IdentityCommand,
+    },
+    Alias {
+        /// Alias database path
+        #[clap(long)]
+        file: String,
+        #[clap(subcommand)]
+        command: AliasCommand,
+    },
+}
+
+#[derive(Debug, Parser)]
+enum IdentityCommand {
+    Add {
+        /// Twitter user ID
+        twitter_id: u64,
+        /// Platform name (gab, gettr, mastodon, telegram, or truthsocial)
+        platform: String,
+        /// Handle or profile URL
+        handle: String,
+        #[clap(long, default_value = "1.0")]
+        confidence: f64,
+        #[clap(long)]
+        source: String,
+    },
+    /// Import CSV lines of the form twitter_id,platform,handle,confidence,source
+    Import {
+        #[clap(short, long)]
+        input: String,
+    },
+    Lookup {
+        /// Twitter user ID
+        twitter_id: u64,
+    },
+}
+
+#[derive(Debug, Parser)]
+enum AliasCommand {
+    Add {
+        /// Canonical Twitter user ID
+        canonical_id: u64,
+        /// Legacy Twitter user ID
+        alias_id: u64,
+        #[clap(long)]
+        note: Option<String>,
+    },
+    Remove {
+        /// Legacy Twitter user ID
+        alias_id: u64,
+    },
+    List,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use preflight::{FsProbe, SpaceInfo};
+
+    /// Probe for a file system with a fixed amount of available space.
+    struct FixedProbe(u64);
+
+    impl FsProbe for FixedProbe {
+        fn space(&self, _path: &Path) -> std::io::Result<Option<SpaceInfo>> {
+            Ok(Some(SpaceInfo {
+                available_bytes: self.0,
+                available_inodes: None,
+            }))
+        }
+    }
+
+    fn user(id: i64) -> User {
+        User {
+            id,
+            id_str: id.to_string(),
+            screen_name: format!("user_{}", id),
+            snapshot: 1_600_000_000,
+            ..User::default()
+        }
+    }
+
+    fn write_avro(path: &Path, users: &[User]) {
+        let mut writer = hst_tw_profiles::avro::writer(File::create(path).unwrap());
+
+        for user in users {
+            writer.append_ser(user).unwrap();
+        }
+
+        writer.into_inner().unwrap();
+    }
+
+    #[test]
+    fn count_normalized_screen_names() {
+        let snapshot = |screen_name: &str, verified: bool| User {
+            screen_name: screen_name.to_string(),
+            verified,
+            ..user(1)
+        };
+        let mut counts = UserCounts::default();
+
+        // Differences in case and zero-width characters are the same screen name.
+        counts.add(&[
+            ((), snapshot("Foo", true)),
+            ((), snapshot("foo", true)),
+            ((), snapshot("fo\u{200b}o", true)),
+            ((), snapshot("bar", false)),
+        ]);
+        counts.add(&[((), snapshot("Bar", true))]);
+
+        assert_eq!(
+            counts,
+            UserCounts {
+                user_count: 2,
+                screen_name_count: 3,
+                verified: 1,
+                protected: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn read_user_ids_skips_blanks_and_duplicates() {
+        let user_ids = read_user_ids(b"3\n 1 \n\n3\r\n  \n2\n").unwrap();
+
+        assert_eq!(user_ids.into_iter().collect::<Vec<_>>(), vec![1, 2, 3]);
+        assert!(matches!(
+            read_user_ids(b"1\nabc\n"),
+            Err(Error::InvalidUserId(line)) if line == "abc"
+        ));
+    }
+
+    #[test]
+    fn import_aborts_before_writing_when_space_is_low() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("input.avro");
+        let db_path = dir.path().join("db");
+        let db_path = db_path.to_str().unwrap();
+        write_avro(&input, &[user(1), user(2)]);
+
+        let result = import(
+            Some(&FixedProbe(0)),
+            db_path,
+            input.to_str().unwrap(),
+            10,
+            false,
+            None,
+        );
+
+        assert!(matches!(
+            result,
+            Err(Error::Preflight(preflight::Error::InsufficientSpace { .. }))
+        ));
+        assert!(!Path::new(db_path).exists());
+
+        let db = import(
+            Some(&FixedProbe(u64::MAX)),
+            db_path,
+            input.to_str().unwrap(),
+            10,
+            false,
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(db.lookup(2).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn import_without_space_check() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("input.avro");
+        let db_path = dir.path().join("db");
+        write_avro(&input, &[user(1)]);
+
+        let db = import::<FixedProbe>(
+            None,
+            db_path.to_str().unwrap(),
+            input.to_str().unwrap(),
+            10,
+            false,
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(db.lookup(1).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn export_space_check() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = ProfileDb::<Writeable>::open(dir.path().join("db"), false).unwrap();
+        let output = dir.path().join("output.ndjson");
+        let output = output.to_str().unwrap();
+
+        // An empty database needs no space.
+        check_export_space(&FixedProbe(0), &db, output, ExportFormat::Ndjson, None).unwrap();
+
+        db.update_batch(&[user(1), user(2), user(3)]).unwrap();
+
+        let average_size = db.average_value_size(SPACE_SAMPLE_SIZE).unwrap();
+        let required = preflight::estimate_from_records(3, average_size, 4.0);
+
+        assert!(matches!(
+            check_export_space(
+                &FixedProbe(required - 1),
+                &db,
+                output,
+                ExportFormat::Ndjson,
+                None
+            ),
+            Err(Error::Preflight(preflight::Error::InsufficientSpace { required: value, .. }))
+                if value == required
+        ));
+        check_export_space(
+            &FixedProbe(required),
+            &db,
+            output,
+            ExportFormat::Ndjson,
+            None,
+        )
+        .unwrap();
+
+        // The temporary files for ordering by time are checked separately.
+        assert!(check_export_space(
+            &FixedProbe(required),
+            &db,
+            output,
+            ExportFormat::Ndjson,
+            Some(dir.path())
+        )
+        .is_ok());
+        assert!(!Path::new(output).exists());
+    }
 }
@@ -1,9 +1,15 @@
+use futures::StreamExt;
 use hst_cli::prelude::*;
-use hst_tw_images::{Image, Store};
+use hst_tw_db::{table::ReadOnly, ProfileDb};
+use hst_tw_images::{
+    liveness::{self, LivenessChecker, ReqwestHeadClient, Summary},
+    Image, Store,
+};
 use reqwest::Url;
-use std::fs::File;
+use std::fs::{File, OpenOptions};
 use std::io::Write;
 use std::path::Path;
+use std::time::Duration;
 
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
@@ -11,6 +17,12 @@ pub enum Error {
     TwitterImage(#[from] hst_tw_images::Error),
     #[error("Twitter image store error")]
     TwitterImageStore(#[from] hst_tw_images::store::Error),
+    #[error("Liveness check error")]
+    Liveness(#[from] liveness::Error),
+    #[error("ProfileDb error")]
+    ProfileDb(#[from] hst_tw_db::Error),
+    #[error("Deactivation log error")]
+    Deactivations(#[from] hst_deactivations::Error),
     #[error("HTTP client error")]
     HttpClient(#[from] reqwest::Error),
     #[error("I/O error")]
@@ -61,11 +73,73 @@ async fn main() -> Result<(), Error> {
             }
         }
         Command::Scrape => todo!(),
+        Command::Liveness {
+            db,
+            deactivations,
+            status,
+            log,
+            concurrency,
+            retries,
+            delay,
+            timeout,
+        } => {
+            let db = ProfileDb::<ReadOnly>::open(db, true)?;
+            let deactivations =
+                hst_deactivations::DeactivationLog::read(File::open(deactivations)?)?;
+
+            let mut user_ids = deactivations
+                .current_deactivated(status)
+                .into_iter()
+                .collect::<Vec<_>>();
+            user_ids.sort_unstable();
+
+            let mut targets = Vec::with_capacity(user_ids.len());
+
+            for user_id in user_ids {
+                if let Some((_, user)) = db.lookup(user_id)?.pop() {
+                    targets.push((user_id, user.profile_image_url_https));
+                }
+            }
+
+            let checker = LivenessChecker::new(
+                ReqwestHeadClient::new(Duration::from_secs(timeout))?,
+                concurrency,
+                retries,
+                Duration::from_millis(delay),
+            );
+
+            let mut writer = OpenOptions::new().create(true).append(true).open(log)?;
+            let mut checks = Vec::with_capacity(targets.len());
+            let mut results = Box::pin(checker.check_stream(targets));
+
+            // Each result is logged as soon as it completes, so an interrupted run keeps them.
+            while let Some(check) = results.next().await {
+                check.write(&mut writer)?;
+                writer.flush()?;
+                checks.push(check);
+            }
+
+            print_summary(&Summary::from_checks(&checks));
+        }
+        Command::LivenessSummary { log } => {
+            let checks = liveness::read_log(File::open(log)?)?;
+
+            print_summary(&Summary::from_checks(&checks));
+        }
     }
 
     Ok(())
 }
 
+fn print_summary(summary: &Summary) {
+    println!("Alive: {}", summary.alive);
+    println!("Gone: {}", summary.gone);
+    println!("Redirected to default: {}", summary.default_avatar);
+    println!("Redirected elsewhere: {}", summary.redirected);
+    println!("Other status: {}", summary.other);
+    println!("Unreachable: {}", summary.unreachable);
+}
+
 #[derive(Parser)]
 #[clap(name = "hst-tw-images", about, version, author)]
 struct Opts {
@@ -81,4 +155,31 @@ enum Command {
     Scrape,
     /// Dump a list of URLs (arbitrarily ordered) from a store as text
     StoreUrls { base: String },
+    /// Check whether the latest profile image URLs of deactivated accounts still resolve
+    Liveness {
+        /// Profile database directory path
+        #[clap(long)]
+        db: String,
+        /// Deactivation log CSV path
+        #[clap(long)]
+        deactivations: String,
+        /// Only check accounts with this deactivation status
+        #[clap(long)]
+        status: Option<u32>,
+        /// CSV log path (results are appended)
+        #[clap(long)]
+        log: String,
+        #[clap(long, default_value = "4")]
+        concurrency: usize,
+        #[clap(long, default_value = "2")]
+        retries: usize,
+        /// Pause after each request in milliseconds
+        #[clap(long, default_value = "250")]
+        delay: u64,
+        /// Request timeout in seconds
+        #[clap(long, default_value = "10")]
+        timeout: u64,
+    },
+    /// Summarize the most recent check for each URL in a liveness log
+    LivenessSummary { log: String },
 }
@@ -0,0 +1,828 @@
+//! Detection of the on-disk formats produced by the Hassreden-Tracker tools.
+//!
+//! Each format is recognized by a `Sniffer`, which looks at a file's first bytes (or a
+//! directory's contents). New formats plug in by adding a sniffer to `default_sniffers`.
+
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+
+const HEADER_LENGTH: usize = 64;
+/// Files larger than this are not read in full to count records.
+const MAX_COUNTED_SIZE: u64 = 64 * 1024 * 1024;
+/// Lines longer than this are not read when checking the first line of a file.
+const MAX_LINE_LENGTH: u64 = 64 * 1024;
+/// The `user_version` of the SQLite databases created by this build.
+const SQLITE_USER_VERSION: i64 = 1;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Detection {
+    pub format: &'static str,
+    pub version: Option<String>,
+    pub record_count: Option<u64>,
+    /// Indicates whether this build can read the contents.
+    pub readable: bool,
+    /// Indicates whether this build writes this version of the format (and so can update or
+    /// append to it without converting it first).
+    pub writable: bool,
+}
+
+impl Detection {
+    fn new(format: &'static str) -> Self {
+        Self {
+            format,
+            version: None,
+            record_count: None,
+            readable: true,
+            writable: true,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct InventoryEntry {
+    pub path: PathBuf,
+    pub size: u64,
+    pub detection: Option<Detection>,
+}
+
+pub trait Sniffer {
+    /// Inspect a file, given its first bytes.
+    fn sniff_file(&self, _path: &Path, _header: &[u8], _size: u64) -> Option<Detection> {
+        None
+    }
+
+    /// Inspect a directory (which will not be recursed into if detected).
+    fn sniff_dir(&self, _path: &Path) -> Option<Detection> {
+        None
+    }
+}
+
+pub fn default_sniffers() -> Vec<Box<dyn Sniffer>> {
+    vec![
+        Box::new(RocksDbSniffer),
+        Box::new(ChunkedAvroSniffer),
+        Box::new(AvroSniffer),
+        Box::new(SqliteSniffer),
+        Box::new(CoverageSniffer),
+        Box::new(DeactivationIndexSniffer),
+        Box::new(LivenessLogSniffer),
+        Box::new(EvidenceLogSniffer),
+        Box::new(DeactivationsSniffer),
+    ]
+}
+
+/// Inspect a file or (recursively) a directory, returning entries in path order.
+///
+/// The given path is followed if it's a symbolic link, but links found inside directories are
+/// listed without being followed (so that link cycles can't cause infinite recursion).
+pub fn inventory<P: AsRef<Path>>(
+    path: P,
+    sniffers: &[Box<dyn Sniffer>],
+) -> Result<Vec<InventoryEntry>, std::io::Error> {
+    let path = path.as_ref();
+    let mut entries = vec![];
+    visit(path, std::fs::metadata(path)?, sniffers, &mut entries)?;
+    Ok(entries)
+}
+
+fn visit(
+    path: &Path,
+    metadata: std::fs::Metadata,
+    sniffers: &[Box<dyn Sniffer>],
+    entries: &mut Vec<InventoryEntry>,
+) -> Result<(), std::io::Error> {
+    if metadata.file_type().is_symlink() {
+        entries.push(InventoryEntry {
+            path: path.to_path_buf(),
+            size: metadata.len(),
+            detection: Some(Detection::new("Symbolic link")),
+        });
+    } else if metadata.is_dir() {
+        if let Some(detection) = sniffers.iter().find_map(|sniffer| sniffer.sniff_dir(path)) {
+            entries.push(InventoryEntry {
+                path: path.to_path_buf(),
+                size: dir_size(path)?,
+                detection: Some(detection),
+            });
+        } else {
+            let mut children = std::fs::read_dir(path)?
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<Result<Vec<_>, _>>()?;
+            children.sort();
+
+            for child in children {
+                let metadata = std::fs::symlink_metadata(&child)?;
+                visit(&child, metadata, sniffers, entries)?;
+            }
+        }
+    } else {
+        let mut header = Vec::with_capacity(HEADER_LENGTH);
+        File::open(path)?
+            .take(HEADER_LENGTH as u64)
+            .read_to_end(&mut header)?;
+
+        entries.push(InventoryEntry {
+            path: path.to_path_buf(),
+            size: metadata.len(),
+            detection: sniffers
+                .iter()
+                .find_map(|sniffer| sniffer.sniff_file(path, &header, metadata.len())),
+        });
+    }
+
+    Ok(())
+}
+
+fn dir_size(path: &Path) -> Result<u64, std::io::Error> {
+    let mut size = 0;
+
+    for entry in std::fs::read_dir(path)? {
+        let entry = entry?;
+        // This doesn't follow symbolic links.
+        let metadata = entry.metadata()?;
+
+        size += if metadata.is_dir() {
+            dir_size(&entry.path())?
+        } else {
+            metadata.len()
+        };
+    }
+
+    Ok(size)
+}
+
+fn first_line(header: &[u8]) -> Option<&str> {
+    let end = header.iter().position(|byte| *byte == b'\n')?;
+    std::str::from_utf8(&header[..end]).ok()
+}
+
+/// The first line of a file, even if it's longer than the header.
+fn read_first_line(path: &Path) -> Option<String> {
+    BufReader::new(File::open(path).ok()?.take(MAX_LINE_LENGTH))
+        .lines()
+        .next()?
+        .ok()
+}
+
+fn count_lines(path: &Path, size: u64) -> Option<u64> {
+    if size <= MAX_COUNTED_SIZE {
+        let reader = BufReader::new(File::open(path).ok()?);
+        Some(reader.split(b'\n').count() as u64)
+    } else {
+        None
+    }
+}
+
+/// Profile databases (identified by the RocksDB version recorded in the options file).
+pub struct RocksDbSniffer;
+
+impl Sniffer for RocksDbSniffer {
+    fn sniff_dir(&self, path: &Path) -> Option<Detection> {
+        if !path.join("CURRENT").is_file() || !path.join("IDENTITY").is_file() {
+            return None;
+        }
+
+        let options_path = std::fs::read_dir(path)
+            .ok()?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.file_name()
+                    .and_then(|name| name.to_str())
+                    .is_some_and(|name| name.starts_with("OPTIONS-"))
+            })
+            .max();
+
+        let version = options_path.and_then(|options_path| {
+            BufReader::new(File::open(options_path).ok()?)
+                .lines()
+                .map_while(Result::ok)
+                .find_map(|line| {
+                    line.trim()
+                        .strip_prefix("rocksdb_version=")
+                        .map(|value| value.to_string())
+                })
+        });
+
+        Some(Detection {
+            version,
+            ..Detection::new("RocksDB database")
+        })
+    }
+}
+
+/// Archives written by `ChunkedAvroWriter`.
+pub struct ChunkedAvroSniffer;
+
+impl Sniffer for ChunkedAvroSniffer {
+    fn sniff_dir(&self, path: &Path) -> Option<Detection> {
+        let manifest_path = path.join(hst_tw_profiles::avro::chunked::MANIFEST_FILE_NAME);
+
+        if manifest_path.is_file() {
+            let manifest = File::open(manifest_path).ok().and_then(|file| {
+                serde_json::from_reader::<_, hst_tw_profiles::avro::chunked::Manifest>(
+                    BufReader::new(file),
+                )
+                .ok()
+            });
+
+            Some(Detection {
+                record_count: manifest
+                    .as_ref()
+                    .map(|manifest| manifest.record_count() as u64),
+                readable: manifest.is_some(),
+                writable: manifest.is_some(),
+                ..Detection::new("Chunked Avro profile archive")
+            })
+        } else {
+            None
+        }
+    }
+}
+
+/// Avro object container files (checked against the current user schema, with the version of the
+/// writer schema identified by its fields). Only files written with the current schema can be
+/// appended to.
+pub struct AvroSniffer;
+
+impl Sniffer for AvroSniffer {
+    fn sniff_file(&self, path: &Path, header: &[u8], size: u64) -> Option<Detection> {
+        if !header.starts_with(b"Obj\x01") {
+            return None;
+        }
+
+        let reader = File::open(path)
+            .ok()
+            .and_then(|file| hst_tw_profiles::avro::reader(BufReader::new(file)).ok());
+
+        match reader {
+            Some(mut reader) => {
+                let schema_version =
+                    hst_tw_profiles::avro::schema::user_schema_version(reader.writer_schema());
+                let current_version = hst_tw_profiles::avro::schema::user_schema_version(
+                    &hst_tw_profiles::avro::USER_SCHEMA,
+                );
+                let version = schema_version.map(|version| format!("v{}", version));
+
+                // Reading the first record checks that the writer schema resolves.
+                let readable = reader.next().is_none_or(|value| value.is_ok());
+
+                Some(Detection {
+                    version,
+                    record_count: if size <= MAX_COUNTED_SIZE && readable {
+                        hst_tw_profiles::avro::reader(BufReader::new(File::open(path).ok()?))
+                            .ok()
+                            .map(|reader| reader.count() as u64)
+                    } else {
+                        None
+                    },
+                    readable,
+                    writable: readable && schema_version == current_version,
+                    ..Detection::new("Avro profiles")
+                })
+            }
+            None => Some(Detection {
+                readable: false,
+                writable: false,
+                ..Detection::new("Avro")
+            }),
+        }
+    }
+}
+
+/// SQLite databases (alias and identity tables, research packs, and deactivation log exports).
+///
+/// Databases with a different `user_version` from the one this build creates are reported as
+/// read-only.
+pub struct SqliteSniffer;
+
+impl Sniffer for SqliteSniffer {
+    fn sniff_file(&self, path: &Path, header: &[u8], _size: u64) -> Option<Detection> {
+        if !header.starts_with(b"SQLite format 3\0") {
+            return None;
+        }
+
+        let flags = rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY;
+        let connection = match rusqlite::Connection::open_with_flags(path, flags) {
+            Ok(connection) => connection,
+            Err(_) => {
+                return Some(Detection {
+                    readable: false,
+                    writable: false,
+                    ..Detection::new("SQLite database")
+                })
+            }
+        };
+
+        let tables = connection
+            .prepare("SELECT name FROM sqlite_master WHERE type = 'table' ORDER BY name")
+            .and_then(|mut select| {
+                select
+                    .query_map([], |row| row.get::<_, String>(0))?
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .unwrap_or_default();
+
+        // Research packs also have a deactivations table, so they're checked first.
+        let (format, table) = if tables.iter().any(|table| table == "profiles") {
+            ("Research pack", Some("profiles"))
+        } else if tables.iter().any(|table| table == "aliases") {
+            ("User ID aliases", Some("aliases"))
+        } else if tables.iter().any(|table| table == "identities") {
+            ("Identity mappings", Some("identities"))
+        } else if tables.iter().any(|table| table == "deactivations") {
+            ("Deactivation log export", Some("deactivations"))
+        } else {
+            ("SQLite database", None)
+        };
+
+        let record_count = table.and_then(|table| {
+            connection
+                .query_row(&format!("SELECT COUNT(*) FROM {}", table), [], |row| {
+                    row.get::<_, i64>(0)
+                })
+                .ok()
+                .map(|count| count as u64)
+        });
+
+        let user_version = connection
+            .query_row("PRAGMA user_version", [], |row| row.get::<_, i64>(0))
+            .ok();
+
+        Some(Detection {
+            version: user_version.map(|version| version.to_string()),
+            record_count,
+            writable: table.is_some() && user_version == Some(SQLITE_USER_VERSION),
+            ..Detection::new(format)
+        })
+    }
+}
+
+/// Collection coverage files.
+pub struct CoverageSniffer;
+
+impl Sniffer for CoverageSniffer {
+    fn sniff_file(&self, path: &Path, _header: &[u8], _size: u64) -> Option<Detection> {
+        if path.file_name()? != hst_tw_profiles::coverage::COVERAGE_FILE_NAME {
+            return None;
+        }
+
+        let coverage = hst_tw_profiles::coverage::Coverage::load(path).ok();
+
+        Some(Detection {
+            record_count: coverage.as_ref().map(|coverage| coverage.days.len() as u64),
+            readable: coverage.is_some(),
+            writable: coverage.is_some(),
+            ..Detection::new("Collection coverage")
+        })
+    }
+}
+
+/// Deactivation log index sidecars (identified by the magic bytes, with the last giving the
+/// version). Only the current version can be read or rebuilt in place, and only if the log it
+/// indexes is present and unchanged.
+pub struct DeactivationIndexSniffer;
+
+impl Sniffer for DeactivationIndexSniffer {
+    fn sniff_file(&self, path: &Path, header: &[u8], _size: u64) -> Option<Detection> {
+        use hst_deactivations::index::{DeactivationIndex, HEADER_SIZE, MAGIC};
+
+        let version = header
+            .strip_prefix(&MAGIC[..MAGIC.len() - 1])?
+            .first()
+            .map(|version| char::from(*version).to_string())?;
+        let current = header.starts_with(&MAGIC[..]);
+
+        let record_count = header
+            .get(28..HEADER_SIZE as usize)
+            .filter(|_| current)
+            .and_then(|bytes| bytes.try_into().ok())
+            .map(u64::from_be_bytes);
+        let readable = current
+            && path
+                .to_str()
+                .and_then(|path| path.strip_suffix(".idx"))
+                .is_some_and(|log_path| DeactivationIndex::open(log_path).is_ok());
+
+        Some(Detection {
+            version: Some(version),
+            record_count,
+            readable,
+            writable: current,
+            ..Detection::new("Deactivation log index")
+        })
+    }
+}
+
+/// Profile image liveness logs.
+pub struct LivenessLogSniffer;
+
+impl Sniffer for LivenessLogSniffer {
+    fn sniff_file(&self, path: &Path, header: &[u8], size: u64) -> Option<Detection> {
+        first_line(header)?
+            .parse::<hst_tw_images::liveness::Check>()
+            .ok()?;
+
+        Some(Detection {
+            record_count: count_lines(path, size),
+            ..Detection::new("Image liveness log")
+        })
+    }
+}
+
+/// Reversal evidence sidecars for deactivation logs.
+pub struct EvidenceLogSniffer;
+
+impl Sniffer for EvidenceLogSniffer {
+    fn sniff_file(&self, path: &Path, header: &[u8], size: u64) -> Option<Detection> {
+        // Rows start with a user ID, and may be longer than the header.
+        if !header.first().is_some_and(u8::is_ascii_digit) {
+            return None;
+        }
+
+        read_first_line(path)?
+            .parse::<hst_deactivations::evidence::EvidenceRow>()
+            .ok()?;
+
+        let readable = size > MAX_COUNTED_SIZE
+            || hst_deactivations::evidence::EvidenceLog::open(path, "").is_ok();
+
+        Some(Detection {
+            record_count: count_lines(path, size),
+            readable,
+            writable: readable,
+            ..Detection::new("Reversal evidence log")
+        })
+    }
+}
+
+/// Deactivation log CSV files.
+pub struct DeactivationsSniffer;
+
+impl Sniffer for DeactivationsSniffer {
+    fn sniff_file(&self, path: &Path, header: &[u8], size: u64) -> Option<Detection> {
+        let fields = first_line(header)?.split(',').collect::<Vec<_>>();
+
+        let valid = fields.len() == 4
+            && fields[0].parse::<u64>().is_ok()
+            && fields[1].parse::<u32>().is_ok()
+            && fields[2].parse::<i64>().is_ok()
+            && (fields[3].is_empty() || fields[3].parse::<i64>().is_ok());
+
+        if valid {
+            Some(Detection {
+                record_count: count_lines(path, size),
+                ..Detection::new("Deactivation log")
+            })
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use apache_avro::{types::Value, Writer};
+    use hst_tw_profiles::{avro::schema::USER_SCHEMA_V1, model::User};
+
+    fn detect(path: &Path) -> Vec<(String, Option<Detection>)> {
+        inventory(path, &default_sniffers())
+            .unwrap()
+            .into_iter()
+            .map(|entry| {
+                (
+                    entry.path.strip_prefix(path).unwrap().display().to_string(),
+                    entry.detection,
+                )
+            })
+            .collect()
+    }
+
+    fn format_and_version(detection: &Option<Detection>) -> Option<(&str, Option<&str>)> {
+        detection
+            .as_ref()
+            .map(|detection| (detection.format, detection.version.as_deref()))
+    }
+
+    fn write_v1_avro(path: &Path) {
+        let mut value = apache_avro::to_value(User::default()).unwrap();
+        if let Value::Record(fields) = &mut value {
+            fields.retain(|(name, _)| !name.starts_with("ext_"));
+        }
+
+        let mut writer = Writer::new(&USER_SCHEMA_V1, File::create(path).unwrap());
+        writer
+            .append(value.resolve(&USER_SCHEMA_V1).unwrap())
+            .unwrap();
+        writer.into_inner().unwrap();
+    }
+
+    fn write_current_avro(path: &Path) {
+        let mut writer = hst_tw_profiles::avro::writer(File::create(path).unwrap());
+        writer.append_ser(User::default()).unwrap();
+        writer.append_ser(User::default()).unwrap();
+        writer.into_inner().unwrap();
+    }
+
+    #[test]
+    fn avro_versions() {
+        let dir = tempfile::tempdir().unwrap();
+        write_v1_avro(&dir.path().join("v1.avro"));
+        write_current_avro(&dir.path().join("v3.avro"));
+
+        let detections = detect(dir.path());
+
+        assert_eq!(detections.len(), 2);
+        assert_eq!(
+            format_and_version(&detections[0].1),
+            Some(("Avro profiles", Some("v1")))
+        );
+        assert_eq!(
+            format_and_version(&detections[1].1),
+            Some(("Avro profiles", Some("v3")))
+        );
+        assert_eq!(detections[0].1.as_ref().unwrap().record_count, Some(1));
+        assert_eq!(detections[1].1.as_ref().unwrap().record_count, Some(2));
+    }
+
+    #[test]
+    fn sqlite_user_version() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = hst_deactivations::DeactivationLog::read(&b"1,50,1600000000,\n"[..]).unwrap();
+        log.export_sqlite(dir.path().join("deactivations.db"), None, &[])
+            .unwrap();
+        hst_tw_db::alias::AliasDb::open(dir.path().join("aliases.db")).unwrap();
+        hst_tw_db::identity::IdentityDb::open(dir.path().join("identities.db")).unwrap();
+        hst_tw_db::pack::ResearchPack::create(dir.path().join("pack.db"), false)
+            .unwrap()
+            .finish(&[])
+            .unwrap();
+
+        let detections = detect(dir.path())
+            .iter()
+            .map(|(path, detection)| {
+                let detection = detection.as_ref().unwrap();
+                (
+                    path.clone(),
+                    detection.format,
+                    detection.version.clone(),
+                    detection.record_count,
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let version = Some("1".to_string());
+
+        assert_eq!(
+            detections,
+            vec![
+                (
+                    "aliases.db".to_string(),
+                    "User ID aliases",
+                    version.clone(),
+                    Some(0)
+                ),
+                (
+                    "deactivations.db".to_string(),
+                    "Deactivation log export",
+                    version.clone(),
+                    Some(1)
+                ),
+                (
+                    "identities.db".to_string(),
+                    "Identity mappings",
+                    version.clone(),
+                    Some(0)
+                ),
+                ("pack.db".to_string(), "Research pack", version, Some(0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn text_formats() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("deactivations.csv"),
+            "1,50,1600000000,\n2,63,1600000100,1600000200\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.path().join("liveness.csv"),
+            "1,https://example.com/a.png,200,1600000000,alive\n",
+        )
+        .unwrap();
+        hst_tw_profiles::coverage::Coverage::default()
+            .save(
+                dir.path()
+                    .join(hst_tw_profiles::coverage::COVERAGE_FILE_NAME),
+            )
+            .unwrap();
+        std::fs::write(
+            dir.path().join("evidence.csv"),
+            format!(
+                "2,1600000200,snapshot-after,1700000000,0.1.0,{}\n",
+                "a".repeat(100)
+            ),
+        )
+        .unwrap();
+        std::fs::write(dir.path().join("notes.txt"), "hello\n").unwrap();
+
+        let detections = detect(dir.path())
+            .into_iter()
+            .map(|(path, detection)| {
+                (
+                    path,
+                    detection.map(|detection| (detection.format, detection.record_count)),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            detections,
+            vec![
+                (
+                    "coverage.json".to_string(),
+                    Some(("Collection coverage", Some(0)))
+                ),
+                (
+                    "deactivations.csv".to_string(),
+                    Some(("Deactivation log", Some(2)))
+                ),
+                (
+                    "evidence.csv".to_string(),
+                    Some(("Reversal evidence log", Some(1)))
+                ),
+                (
+                    "liveness.csv".to_string(),
+                    Some(("Image liveness log", Some(1)))
+                ),
+                ("notes.txt".to_string(), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn deactivation_index() {
+        let dir = tempfile::tempdir().unwrap();
+        let log_path = dir.path().join("deactivations.csv");
+        let index_path = hst_deactivations::index::DeactivationIndex::index_path(&log_path);
+
+        std::fs::write(&log_path, "1,50,1600000000,\n2,63,1600000100,\n").unwrap();
+        hst_deactivations::index::DeactivationIndex::build(&log_path).unwrap();
+
+        let sniff = || {
+            inventory(&index_path, &default_sniffers())
+                .unwrap()
+                .remove(0)
+                .detection
+                .unwrap()
+        };
+
+        assert_eq!(
+            sniff(),
+            Detection {
+                version: Some("1".to_string()),
+                record_count: Some(2),
+                ..Detection::new("Deactivation log index")
+            }
+        );
+
+        // The index is stale once the log changes.
+        std::fs::write(&log_path, "1,50,1600000000,\n").unwrap();
+
+        let detection = sniff();
+
+        assert!(!detection.readable);
+        assert!(detection.writable);
+
+        // A later version of the format.
+        let mut bytes = std::fs::read(&index_path).unwrap();
+        bytes[7] = b'2';
+        std::fs::write(&index_path, bytes).unwrap();
+
+        let detection = sniff();
+
+        assert_eq!(detection.version.as_deref(), Some("2"));
+        assert!(!detection.readable);
+        assert!(!detection.writable);
+    }
+
+    #[test]
+    fn read_and_write_compatibility() {
+        let dir = tempfile::tempdir().unwrap();
+        write_v1_avro(&dir.path().join("v1.avro"));
+        write_current_avro(&dir.path().join("v3.avro"));
+
+        let connection = rusqlite::Connection::open(dir.path().join("pack.db")).unwrap();
+        connection
+            .execute_batch("PRAGMA user_version = 2; CREATE TABLE profiles (user_id INTEGER);")
+            .unwrap();
+        drop(connection);
+
+        let compatibility = detect(dir.path())
+            .into_iter()
+            .map(|(path, detection)| {
+                let detection = detection.unwrap();
+                (path, detection.readable, detection.writable)
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            compatibility,
+            vec![
+                // Written by a later version.
+                ("pack.db".to_string(), true, false),
+                ("v1.avro".to_string(), true, false),
+                ("v3.avro".to_string(), true, true),
+            ]
+        );
+    }
+
+    #[test]
+    fn directories() {
+        let dir = tempfile::tempdir().unwrap();
+        let chunked = dir.path().join("chunked");
+        let rocksdb = dir.path().join("db");
+
+        let mut writer =
+            hst_tw_profiles::avro::chunked::ChunkedAvroWriter::new(&chunked, Default::default())
+                .unwrap();
+        writer.write(&User::default()).unwrap();
+        writer.finish().unwrap();
+
+        std::fs::create_dir(&rocksdb).unwrap();
+        std::fs::write(rocksdb.join("CURRENT"), "MANIFEST-000001\n").unwrap();
+        std::fs::write(rocksdb.join("IDENTITY"), "abc\n").unwrap();
+        std::fs::write(
+            rocksdb.join("OPTIONS-000007"),
+            "[Version]\n  rocksdb_version=7.4.4\n",
+        )
+        .unwrap();
+
+        let detections = detect(dir.path())
+            .into_iter()
+            .map(|(path, detection)| {
+                let detection = detection.unwrap();
+                (
+                    path,
+                    detection.format,
+                    detection.version,
+                    detection.record_count,
+                )
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            detections,
+            vec![
+                (
+                    "chunked".to_string(),
+                    "Chunked Avro profile archive",
+                    None,
+                    Some(1)
+                ),
+                (
+                    "db".to_string(),
+                    "RocksDB database",
+                    Some("7.4.4".to_string()),
+                    None
+                ),
+            ]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symbolic_links_are_not_followed() {
+        let dir = tempfile::tempdir().unwrap();
+        let nested = dir.path().join("nested");
+
+        std::fs::create_dir(&nested).unwrap();
+        std::fs::write(nested.join("notes.txt"), "hello\n").unwrap();
+        // A cycle back to the top directory, and a link to a file.
+        std::os::unix::fs::symlink(dir.path(), nested.join("loop")).unwrap();
+        std::os::unix::fs::symlink(nested.join("notes.txt"), dir.path().join("link.txt")).unwrap();
+
+        let detections = detect(dir.path())
+            .into_iter()
+            .map(|(path, detection)| (path, detection.map(|detection| detection.format)))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            detections,
+            vec![
+                ("link.txt".to_string(), Some("Symbolic link")),
+                ("nested/loop".to_string(), Some("Symbolic link")),
+                ("nested/notes.txt".to_string(), None),
+            ]
+        );
+
+        // The top-level path is followed.
+        let entries = inventory(dir.path().join("link.txt"), &default_sniffers()).unwrap();
+
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].detection, None);
+    }
+}
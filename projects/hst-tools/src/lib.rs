@@ -0,0 +1,4 @@
+//! Shared code for the Hassreden-Tracker command-line tools.
+
+pub mod bench_summary;
+pub mod doctor;
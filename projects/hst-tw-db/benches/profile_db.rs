@@ -0,0 +1,104 @@
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use hst_tw_db::{
+    table::{ReadOnly, Writeable},
+    ProfileDb,
+};
+use hst_tw_profiles::model::User;
+use hst_tw_utils::{
+    splitmix::splitmix64,
+    testkit::{record_count, SEED},
+};
+use std::path::PathBuf;
+
+const DEFAULT_RECORD_COUNT: usize = 10_000;
+
+/// Users in random ID order, with a few snapshots per user.
+fn generate_users(count: usize) -> Vec<User> {
+    let mut state = SEED;
+    let mut user_id = 0;
+
+    (0..count)
+        .map(|index| {
+            if index % 3 == 0 {
+                user_id = 1_000 + splitmix64(&mut state) % 1_500_000_000_000_000_000;
+            }
+
+            User {
+                id: user_id as i64,
+                id_str: user_id.to_string(),
+                name: format!("User {}", user_id),
+                screen_name: format!("user_{}", user_id),
+                followers_count: (splitmix64(&mut state) % 100_000) as i64,
+                created_at: "Mon Jan 04 12:00:00 +0000 2010".to_string(),
+                snapshot: 1_600_000_000 + index as i64,
+                ..User::default()
+            }
+        })
+        .collect()
+}
+
+fn temp_db_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("hst-bench-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&path);
+    path
+}
+
+fn import(c: &mut Criterion) {
+    let count = record_count(DEFAULT_RECORD_COUNT);
+    let users = generate_users(count);
+    let path = temp_db_path("import");
+
+    let mut group = c.benchmark_group("profile_db");
+    group.throughput(Throughput::Elements(count as u64));
+    group.sample_size(10);
+    group.bench_function("import", |b| {
+        b.iter_batched(
+            || {
+                let _ = std::fs::remove_dir_all(&path);
+                ProfileDb::<Writeable>::open(&path, false).unwrap()
+            },
+            |db| {
+                for user in &users {
+                    db.update(user).unwrap();
+                }
+            },
+            BatchSize::PerIteration,
+        )
+    });
+    group.finish();
+
+    let _ = std::fs::remove_dir_all(&path);
+}
+
+fn lookup(c: &mut Criterion) {
+    let count = record_count(DEFAULT_RECORD_COUNT);
+    let users = generate_users(count);
+    let path = temp_db_path("lookup");
+
+    {
+        let db = ProfileDb::<Writeable>::open(&path, false).unwrap();
+        for user in &users {
+            db.update(user).unwrap();
+        }
+    }
+
+    let db = ProfileDb::<ReadOnly>::open(&path, false).unwrap();
+    let ids = users.iter().map(|user| user.id()).collect::<Vec<_>>();
+
+    let mut group = c.benchmark_group("profile_db");
+    group.throughput(Throughput::Elements(ids.len() as u64));
+    group.bench_function("lookup", |b| {
+        b.iter(|| {
+            for id in &ids {
+                db.lookup(*id).unwrap();
+            }
+        })
+    });
+    group.finish();
+
+    drop(db);
+    let _ = std::fs::remove_dir_all(&path);
+}
+
+criterion_group!(benches, import, lookup);
+criterion_main!(benches);
@@ -0,0 +1,262 @@
+//! A small SQLite table mapping legacy user IDs to canonical IDs.
+//!
+//! Aliases form a flat mapping: a canonical ID cannot itself be an alias, and an alias cannot
+//! have aliases of its own, so chains and cycles are rejected when an alias is added.
+
+use super::Error;
+use chrono::{DateTime, TimeZone, Utc};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::path::Path;
+
+const SCHEMA: &str = "
+    PRAGMA user_version = 1;
+    CREATE TABLE IF NOT EXISTS aliases (
+        alias_id INTEGER PRIMARY KEY,
+        canonical_id INTEGER NOT NULL,
+        note TEXT,
+        added_at INTEGER NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS aliases_canonical_id ON aliases (canonical_id);
+";
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Alias {
+    pub canonical_id: u64,
+    pub alias_id: u64,
+    pub note: Option<String>,
+    pub added_at: DateTime<Utc>,
+}
+
+pub struct AliasDb {
+    connection: Connection,
+}
+
+impl AliasDb {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let connection = Connection::open(path)?;
+        connection.execute_batch(SCHEMA)?;
+
+        Ok(Self { connection })
+    }
+
+    /// Add an alias, returning `false` if this exact alias was already present.
+    pub fn add(&self, canonical_id: u64, alias_id: u64, note: Option<&str>) -> Result<bool, Error> {
+        if canonical_id == alias_id {
+            return Err(Error::InvalidAlias {
+                canonical_id,
+                alias_id,
+            });
+        }
+
+        if let Some(existing_canonical_id) = self.lookup_canonical_id(alias_id)? {
+            return if existing_canonical_id == canonical_id {
+                Ok(false)
+            } else {
+                Err(Error::ConflictingAlias {
+                    alias_id,
+                    canonical_id,
+                    existing_canonical_id,
+                })
+            };
+        }
+
+        // Neither side may already participate in the mapping in the other role.
+        if self.lookup_canonical_id(canonical_id)?.is_some() || !self.aliases(alias_id)?.is_empty()
+        {
+            return Err(Error::InvalidAlias {
+                canonical_id,
+                alias_id,
+            });
+        }
+
+        self.connection.execute(
+            "INSERT INTO aliases (alias_id, canonical_id, note, added_at) VALUES (?1, ?2, ?3, ?4)",
+            params![
+                alias_id as i64,
+                canonical_id as i64,
+                note,
+                Utc::now().timestamp()
+            ],
+        )?;
+
+        Ok(true)
+    }
+
+    /// Remove an alias, returning `false` if it did not exist.
+    pub fn remove(&self, alias_id: u64) -> Result<bool, Error> {
+        let count = self.connection.execute(
+            "DELETE FROM aliases WHERE alias_id = ?1",
+            params![alias_id as i64],
+        )?;
+
+        Ok(count > 0)
+    }
+
+    pub fn list(&self) -> Result<Vec<Alias>, Error> {
+        let mut select = self.connection.prepare(
+            "SELECT canonical_id, alias_id, note, added_at FROM aliases
+                ORDER BY canonical_id, alias_id",
+        )?;
+
+        let aliases = select
+            .query_map([], |row| {
+                Ok(Alias {
+                    canonical_id: row.get::<_, i64>(0)? as u64,
+                    alias_id: row.get::<_, i64>(1)? as u64,
+                    note: row.get(2)?,
+                    added_at: Utc
+                        .timestamp_opt(row.get(3)?, 0)
+                        .single()
+                        .unwrap_or_default(),
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(aliases)
+    }
+
+    /// Return the canonical ID for the given ID (which is the ID itself if it is not an alias).
+    pub fn canonical_id(&self, id: u64) -> Result<u64, Error> {
+        Ok(self.lookup_canonical_id(id)?.unwrap_or(id))
+    }
+
+    /// Return the aliases of the given canonical ID in ascending order.
+    pub fn aliases(&self, canonical_id: u64) -> Result<Vec<u64>, Error> {
+        let mut select = self
+            .connection
+            .prepare("SELECT alias_id FROM aliases WHERE canonical_id = ?1 ORDER BY alias_id")?;
+
+        let aliases = select
+            .query_map(params![canonical_id as i64], |row| {
+                row.get::<_, i64>(0).map(|id| id as u64)
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(aliases)
+    }
+
+    /// Return every ID for the account, with the canonical ID first.
+    pub fn ids(&self, id: u64) -> Result<Vec<u64>, Error> {
+        let canonical_id = self.canonical_id(id)?;
+        let mut ids = vec![canonical_id];
+        ids.extend(self.aliases(canonical_id)?);
+
+        Ok(ids)
+    }
+
+    fn lookup_canonical_id(&self, alias_id: u64) -> Result<Option<u64>, Error> {
+        Ok(self
+            .connection
+            .query_row(
+                "SELECT canonical_id FROM aliases WHERE alias_id = ?1",
+                params![alias_id as i64],
+                |row| row.get::<_, i64>(0),
+            )
+            .optional()?
+            .map(|id| id as u64))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn open(dir: &tempfile::TempDir) -> AliasDb {
+        AliasDb::open(dir.path().join("aliases.db")).unwrap()
+    }
+
+    #[test]
+    fn add_and_lookup() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open(&dir);
+
+        assert!(db.add(1, 10, Some("renumbered")).unwrap());
+        assert!(db.add(1, 11, None).unwrap());
+        assert!(db.add(2, 20, None).unwrap());
+        // Adding the same alias again is a no-op.
+        assert!(!db.add(1, 10, Some("other note")).unwrap());
+
+        assert_eq!(db.canonical_id(10).unwrap(), 1);
+        assert_eq!(db.canonical_id(1).unwrap(), 1);
+        assert_eq!(db.canonical_id(3).unwrap(), 3);
+        assert_eq!(db.aliases(1).unwrap(), vec![10, 11]);
+        assert_eq!(db.ids(11).unwrap(), vec![1, 10, 11]);
+        assert_eq!(db.ids(3).unwrap(), vec![3]);
+
+        let aliases = db.list().unwrap();
+        let pairs = aliases
+            .iter()
+            .map(|alias| (alias.canonical_id, alias.alias_id))
+            .collect::<Vec<_>>();
+
+        assert_eq!(pairs, vec![(1, 10), (1, 11), (2, 20)]);
+        assert_eq!(aliases[0].note.as_deref(), Some("renumbered"));
+        assert_eq!(aliases[1].note, None);
+    }
+
+    #[test]
+    fn invalid_aliases() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open(&dir);
+
+        db.add(1, 10, None).unwrap();
+
+        // Self-alias.
+        assert!(matches!(
+            db.add(2, 2, None),
+            Err(Error::InvalidAlias { .. })
+        ));
+        // Already an alias of another ID.
+        assert!(matches!(
+            db.add(2, 10, None),
+            Err(Error::ConflictingAlias {
+                alias_id: 10,
+                canonical_id: 2,
+                existing_canonical_id: 1
+            })
+        ));
+        // Chains in either direction.
+        assert!(matches!(
+            db.add(10, 100, None),
+            Err(Error::InvalidAlias {
+                canonical_id: 10,
+                alias_id: 100
+            })
+        ));
+        assert!(matches!(
+            db.add(2, 1, None),
+            Err(Error::InvalidAlias {
+                canonical_id: 2,
+                alias_id: 1
+            })
+        ));
+        // A cycle.
+        assert!(matches!(
+            db.add(10, 1, None),
+            Err(Error::InvalidAlias { .. })
+        ));
+
+        assert_eq!(db.list().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn remove_and_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open(&dir);
+
+        db.add(1, 10, None).unwrap();
+        db.add(1, 11, None).unwrap();
+
+        assert!(db.remove(10).unwrap());
+        assert!(!db.remove(10).unwrap());
+        // A removed alias can be reassigned.
+        assert!(db.add(2, 10, None).unwrap());
+
+        drop(db);
+
+        let db = open(&dir);
+
+        assert_eq!(db.ids(1).unwrap(), vec![1, 11]);
+        assert_eq!(db.ids(10).unwrap(), vec![2, 10]);
+    }
+}
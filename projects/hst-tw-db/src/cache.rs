@@ -0,0 +1,327 @@
+//! An in-memory LRU cache for repeated profile lookups.
+//!
+//! The cache is intended for read-only use (e.g. within a single report run). Writes through
+//! another handle to the same database are not reflected until the affected user is
+//! invalidated.
+
+use super::{Error, ProfileDb};
+use chrono::{DateTime, Utc};
+use hst_tw_profiles::model::User;
+use lru::LruCache;
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+type Snapshots = Vec<(DateTime<Utc>, User)>;
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    pub entry_count: usize,
+    pub memory_usage: usize,
+}
+
+struct CacheState {
+    entries: LruCache<u64, (Arc<Snapshots>, usize)>,
+    memory_usage: usize,
+}
+
+pub struct CachedProfileDb<M> {
+    db: ProfileDb<M>,
+    memory_budget: usize,
+    state: Mutex<CacheState>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+}
+
+impl<M> CachedProfileDb<M> {
+    /// Wrap a database with a cache holding at most `capacity` users and approximately
+    /// `memory_budget` bytes of profile data.
+    pub fn new(db: ProfileDb<M>, capacity: NonZeroUsize, memory_budget: usize) -> Self {
+        Self {
+            db,
+            memory_budget,
+            state: Mutex::new(CacheState {
+                entries: LruCache::new(capacity),
+                memory_usage: 0,
+            }),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+        }
+    }
+
+    pub fn underlying(&self) -> &ProfileDb<M> {
+        &self.db
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let state = self.state();
+
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            entry_count: state.entries.len(),
+            memory_usage: state.memory_usage,
+        }
+    }
+
+    pub fn invalidate(&self, user_id: u64) {
+        let mut state = self.state();
+
+        if let Some((_, size)) = state.entries.pop(&user_id) {
+            state.memory_usage -= size;
+        }
+    }
+
+    pub fn clear(&self) {
+        let mut state = self.state();
+        state.entries.clear();
+        state.memory_usage = 0;
+    }
+
+    /// Equivalent to `ProfileDb::lookup`.
+    pub fn lookup(&self, user_id: u64) -> Result<Snapshots, Error> {
+        Ok(self.get_snapshots(user_id)?.as_ref().clone())
+    }
+
+    /// Return the most recent snapshot for the user, if any.
+    pub fn lookup_latest(&self, user_id: u64) -> Result<Option<(DateTime<Utc>, User)>, Error> {
+        Ok(self.get_snapshots(user_id)?.last().cloned())
+    }
+
+    /// Return up to `n` of the most recent snapshots for the user, in snapshot order.
+    pub fn lookup_latest_n(&self, user_id: u64, n: usize) -> Result<Snapshots, Error> {
+        let snapshots = self.get_snapshots(user_id)?;
+
+        Ok(snapshots[snapshots.len().saturating_sub(n)..].to_vec())
+    }
+
+    fn get_snapshots(&self, user_id: u64) -> Result<Arc<Snapshots>, Error> {
+        if let Some((snapshots, _)) = self.state().entries.get(&user_id) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(snapshots.clone());
+        }
+
+        self.misses.fetch_add(1, Ordering::Relaxed);
+
+        // The lock isn't held during the database lookup, so concurrent misses for the same
+        // user may both hit the database, but they'll produce the same value.
+        let snapshots = Arc::new(self.db.lookup(user_id)?);
+        let size = approximate_size(&snapshots);
+
+        if size <= self.memory_budget {
+            let mut state = self.state();
+
+            // This returns either a replaced value for the same user or an evicted entry.
+            if let Some((old_user_id, (_, old_size))) =
+                state.entries.push(user_id, (snapshots.clone(), size))
+            {
+                state.memory_usage -= old_size;
+
+                if old_user_id != user_id {
+                    self.evictions.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            state.memory_usage += size;
+
+            while state.memory_usage > self.memory_budget {
+                match state.entries.pop_lru() {
+                    Some((_, (_, evicted_size))) => {
+                        state.memory_usage -= evicted_size;
+                        self.evictions.fetch_add(1, Ordering::Relaxed);
+                    }
+                    None => break,
+                }
+            }
+        }
+
+        Ok(snapshots)
+    }
+
+    fn state(&self) -> MutexGuard<'_, CacheState> {
+        // The cache state is always consistent between operations, so poisoning is harmless.
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Estimate the heap and inline size of a list of snapshots.
+///
+/// This only counts the string fields that are likely to be large, which is enough for keeping
+/// the cache within a budget.
+fn approximate_size(snapshots: &Snapshots) -> usize {
+    snapshots
+        .iter()
+        .map(|(_, user)| {
+            std::mem::size_of::<(DateTime<Utc>, User)>()
+                + user.id_str.len()
+                + user.name.len()
+                + user.screen_name.len()
+                + user.location.as_ref().map_or(0, |value| value.len())
+                + user.description.as_ref().map_or(0, |value| value.len())
+                + user.url.as_ref().map_or(0, |value| value.len())
+                + user.created_at.len()
+                + user.profile_image_url_https.len()
+                + user
+                    .profile_banner_url
+                    .as_ref()
+                    .map_or(0, |value| value.len())
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::table::Writeable;
+
+    fn user(id: i64, snapshot: i64, description: &str) -> User {
+        User {
+            id,
+            id_str: id.to_string(),
+            screen_name: format!("user_{}", id),
+            description: Some(description.to_string()),
+            snapshot,
+            ..User::default()
+        }
+    }
+
+    fn cached(
+        dir: &tempfile::TempDir,
+        capacity: usize,
+        memory_budget: usize,
+    ) -> CachedProfileDb<Writeable> {
+        let db = ProfileDb::<Writeable>::open(dir.path().join("db"), false).unwrap();
+        let users = (1..=4)
+            .flat_map(|id| (1..=3).map(move |snapshot| user(id, snapshot * 100, "")))
+            .collect::<Vec<_>>();
+
+        db.update_batch(&users).unwrap();
+
+        CachedProfileDb::new(db, NonZeroUsize::new(capacity).unwrap(), memory_budget)
+    }
+
+    fn snapshot_size() -> usize {
+        approximate_size(&vec![(DateTime::default(), user(1, 100, ""))])
+    }
+
+    #[test]
+    fn hits_and_misses() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = cached(&dir, 10, usize::MAX);
+
+        assert_eq!(db.lookup(1).unwrap(), db.underlying().lookup(1).unwrap());
+        assert_eq!(db.lookup_latest(1).unwrap().unwrap().1.snapshot, 300);
+        assert_eq!(
+            db.lookup_latest_n(1, 2)
+                .unwrap()
+                .iter()
+                .map(|(_, user)| user.snapshot)
+                .collect::<Vec<_>>(),
+            vec![200, 300]
+        );
+        assert_eq!(db.lookup_latest_n(1, 10).unwrap().len(), 3);
+        // Missing users are cached as empty.
+        assert_eq!(db.lookup_latest(5).unwrap(), None);
+        assert!(db.lookup(5).unwrap().is_empty());
+
+        let stats = db.stats();
+
+        assert_eq!(stats.hits, 4);
+        assert_eq!(stats.misses, 2);
+        assert_eq!(stats.evictions, 0);
+        assert_eq!(stats.entry_count, 2);
+        assert_eq!(stats.memory_usage, 3 * snapshot_size());
+    }
+
+    #[test]
+    fn capacity_eviction() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = cached(&dir, 2, usize::MAX);
+
+        db.lookup(1).unwrap();
+        db.lookup(2).unwrap();
+        // User 1 is now the most recently used.
+        db.lookup(1).unwrap();
+        db.lookup(3).unwrap();
+
+        let stats = db.stats();
+
+        assert_eq!(stats.evictions, 1);
+        assert_eq!(stats.entry_count, 2);
+        assert_eq!(stats.memory_usage, 6 * snapshot_size());
+
+        db.lookup(1).unwrap();
+        db.lookup(2).unwrap();
+
+        let stats = db.stats();
+
+        assert_eq!(stats.hits, 2);
+        assert_eq!(stats.misses, 4);
+    }
+
+    #[test]
+    fn memory_budget_eviction() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = cached(&dir, 10, 7 * snapshot_size());
+
+        db.lookup(1).unwrap();
+        db.lookup(2).unwrap();
+        db.lookup(3).unwrap();
+
+        let stats = db.stats();
+
+        assert_eq!(stats.evictions, 1);
+        assert_eq!(stats.entry_count, 2);
+        assert_eq!(stats.memory_usage, 6 * snapshot_size());
+
+        // Users larger than the budget are returned but not cached.
+        db.underlying()
+            .update(&user(4, 400, &"x".repeat(8 * snapshot_size())))
+            .unwrap();
+        db.invalidate(4);
+
+        assert_eq!(db.lookup(4).unwrap().len(), 4);
+
+        let stats = db.stats();
+
+        assert_eq!(stats.evictions, 1);
+        assert_eq!(stats.entry_count, 2);
+        assert_eq!(stats.memory_usage, 6 * snapshot_size());
+    }
+
+    #[test]
+    fn invalidate_and_clear() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = cached(&dir, 10, usize::MAX);
+
+        assert_eq!(db.lookup(1).unwrap().len(), 3);
+
+        db.underlying().update(&user(1, 400, "new")).unwrap();
+
+        // The cached value is stale until the user is invalidated.
+        assert_eq!(db.lookup(1).unwrap().len(), 3);
+
+        db.invalidate(1);
+
+        let latest = db.lookup_latest(1).unwrap().unwrap().1;
+
+        assert_eq!(latest.snapshot, 400);
+        assert_eq!(latest.description.as_deref(), Some("new"));
+        assert_eq!(db.stats().memory_usage, 4 * snapshot_size() + "new".len());
+
+        db.lookup(2).unwrap();
+        db.clear();
+
+        let stats = db.stats();
+
+        assert_eq!(stats.entry_count, 0);
+        assert_eq!(stats.memory_usage, 0);
+    }
+}
@@ -0,0 +1,291 @@
+//! Handling of distinct profile versions observed within the same second.
+//!
+//! Keys only have second resolution, so two different versions of a profile (e.g. from the
+//! stream and the downloader) can map to the same key. When a version is disambiguated, it's
+//! stored under a later key with its `snapshot` moved to match the key and the
+//! `ext_snapshot_adjusted` flag set, so exports stay in snapshot order and analysts can tell
+//! which snapshots were moved.
+//!
+//! Versions adjusted before the flag was added kept their original `snapshot`, so these are
+//! identified by comparing it with the key (see [`is_snapshot_adjusted`]), and are given the
+//! key's snapshot on export (see [`with_key_snapshot`]).
+
+use chrono::{DateTime, Utc};
+use hst_tw_profiles::model::User;
+
+/// Maximum number of seconds a snapshot will be moved forward to avoid a collision.
+pub const MAX_SNAPSHOT_ADJUSTMENT: i64 = 10;
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum CollisionPolicy {
+    KeepExisting,
+    Overwrite,
+    /// Store the new version under the next free second.
+    #[default]
+    Disambiguate,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum UpdateOutcome {
+    Inserted,
+    /// An identical version was already stored.
+    Unchanged,
+    KeptExisting,
+    Overwritten,
+    /// The version was stored under a later key (given here), with its snapshot moved to match
+    /// and flagged as adjusted.
+    Adjusted(DateTime<Utc>),
+}
+
+impl UpdateOutcome {
+    pub fn is_collision(&self) -> bool {
+        matches!(
+            self,
+            Self::KeptExisting | Self::Overwritten | Self::Adjusted(_)
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ImportStats {
+    pub inserted: usize,
+    pub unchanged: usize,
+    pub kept_existing: usize,
+    pub overwritten: usize,
+    pub adjusted: usize,
+}
+
+impl ImportStats {
+    pub fn record(&mut self, outcome: UpdateOutcome) {
+        match outcome {
+            UpdateOutcome::Inserted => self.inserted += 1,
+            UpdateOutcome::Unchanged => self.unchanged += 1,
+            UpdateOutcome::KeptExisting => self.kept_existing += 1,
+            UpdateOutcome::Overwritten => self.overwritten += 1,
+            UpdateOutcome::Adjusted(_) => self.adjusted += 1,
+        }
+    }
+
+    pub fn collisions(&self) -> usize {
+        self.kept_existing + self.overwritten + self.adjusted
+    }
+}
+
+/// Indicates whether a stored version was moved to a later key to avoid a collision.
+pub fn is_snapshot_adjusted(key_snapshot: DateTime<Utc>, user: &User) -> bool {
+    user.ext_snapshot_adjusted == Some(true) || key_snapshot.timestamp() != user.snapshot
+}
+
+/// The version as stored under a later key.
+pub fn adjust_snapshot(user: &User, key_snapshot: DateTime<Utc>) -> User {
+    User {
+        snapshot: key_snapshot.timestamp(),
+        ext_snapshot_adjusted: Some(true),
+        ..user.clone()
+    }
+}
+
+/// Give a stored version the snapshot from its key (for versions adjusted before the snapshot
+/// was moved on storage).
+pub fn with_key_snapshot(user: User, key_snapshot: DateTime<Utc>) -> User {
+    if user.snapshot == key_snapshot.timestamp() {
+        user
+    } else {
+        adjust_snapshot(&user, key_snapshot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{table::Writeable, Error, ProfileDb};
+    use chrono::TimeZone;
+
+    const SNAPSHOT: i64 = 1_600_000_000;
+
+    /// Distinct versions of the same profile at the same snapshot.
+    fn version(followers_count: i64) -> User {
+        User {
+            id: 1,
+            id_str: "1".to_string(),
+            screen_name: "user".to_string(),
+            followers_count,
+            snapshot: SNAPSHOT,
+            ..User::default()
+        }
+    }
+
+    fn timestamp(offset: i64) -> DateTime<Utc> {
+        Utc.timestamp_opt(SNAPSHOT + offset, 0).single().unwrap()
+    }
+
+    fn open(dir: &tempfile::TempDir, policy: CollisionPolicy) -> ProfileDb<Writeable> {
+        ProfileDb::<Writeable>::open(dir.path().join("db"), false)
+            .unwrap()
+            .with_collision_policy(policy)
+    }
+
+    fn stored(db: &ProfileDb<Writeable>) -> Vec<(DateTime<Utc>, i64, bool)> {
+        db.lookup(1)
+            .unwrap()
+            .into_iter()
+            .map(|(snapshot, user)| {
+                (
+                    snapshot,
+                    user.followers_count,
+                    is_snapshot_adjusted(snapshot, &user),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn disambiguate() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open(&dir, CollisionPolicy::Disambiguate);
+
+        assert_eq!(db.update(&version(1)).unwrap(), UpdateOutcome::Inserted);
+        assert_eq!(
+            db.update(&version(2)).unwrap(),
+            UpdateOutcome::Adjusted(timestamp(1))
+        );
+        assert_eq!(db.update(&version(1)).unwrap(), UpdateOutcome::Unchanged);
+        assert_eq!(db.update(&version(2)).unwrap(), UpdateOutcome::Unchanged);
+        assert_eq!(
+            db.update(&version(3)).unwrap(),
+            UpdateOutcome::Adjusted(timestamp(2))
+        );
+
+        assert_eq!(
+            stored(&db),
+            vec![
+                (timestamp(0), 1, false),
+                (timestamp(1), 2, true),
+                (timestamp(2), 3, true)
+            ]
+        );
+        assert!(db
+            .lookup(1)
+            .unwrap()
+            .iter()
+            .all(|(snapshot, user)| user.snapshot == snapshot.timestamp()));
+    }
+
+    #[test]
+    fn disambiguate_within_batch() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open(&dir, CollisionPolicy::Disambiguate);
+
+        db.update(&version(1)).unwrap();
+
+        let outcomes = db
+            .update_batch(&[version(2), version(1), version(3), version(2)])
+            .unwrap();
+
+        assert_eq!(
+            outcomes,
+            vec![
+                UpdateOutcome::Adjusted(timestamp(1)),
+                UpdateOutcome::Unchanged,
+                UpdateOutcome::Adjusted(timestamp(2)),
+                UpdateOutcome::Unchanged,
+            ]
+        );
+        assert_eq!(stored(&db).len(), 3);
+    }
+
+    #[test]
+    fn adjustment_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open(&dir, CollisionPolicy::Disambiguate);
+        let versions = (0..=MAX_SNAPSHOT_ADJUSTMENT)
+            .map(version)
+            .collect::<Vec<_>>();
+
+        let stats = db.update_all(&versions).unwrap();
+
+        assert_eq!(stats.inserted, 1);
+        assert_eq!(stats.adjusted, MAX_SNAPSHOT_ADJUSTMENT as usize);
+        assert!(matches!(
+            db.update(&version(-1)),
+            Err(Error::UnresolvedCollision {
+                user_id: 1,
+                snapshot: SNAPSHOT
+            })
+        ));
+        assert!(matches!(
+            db.update_batch(&[version(-1)]),
+            Err(Error::UnresolvedCollision { .. })
+        ));
+        assert_eq!(stored(&db).len(), versions.len());
+    }
+
+    #[test]
+    fn invalid_snapshot() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open(&dir, CollisionPolicy::Disambiguate);
+        let mut user = version(1);
+
+        for snapshot in [i64::MAX, i64::MIN] {
+            user.snapshot = snapshot;
+
+            assert!(matches!(
+                db.update(&user),
+                Err(Error::InvalidSnapshot(value)) if value == snapshot
+            ));
+            assert!(matches!(
+                db.update_batch(&[user.clone()]),
+                Err(Error::InvalidSnapshot(_))
+            ));
+        }
+
+        // Representable, but outside the range that keys can store.
+        user.snapshot = -1;
+        assert!(matches!(db.update(&user), Err(Error::InvalidTimestamp(_))));
+        assert!(stored(&db).is_empty());
+    }
+
+    #[test]
+    fn keep_existing() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open(&dir, CollisionPolicy::KeepExisting);
+
+        let stats = db
+            .update_all(&[version(1), version(2), version(1)])
+            .unwrap();
+
+        assert_eq!(
+            stats,
+            ImportStats {
+                inserted: 1,
+                unchanged: 1,
+                kept_existing: 1,
+                ..ImportStats::default()
+            }
+        );
+        assert_eq!(stats.collisions(), 1);
+        assert_eq!(stored(&db), vec![(timestamp(0), 1, false)]);
+    }
+
+    #[test]
+    fn overwrite() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open(&dir, CollisionPolicy::Overwrite);
+
+        let outcomes = db
+            .update_batch(&[version(1), version(2), version(3)])
+            .unwrap();
+
+        assert_eq!(
+            outcomes,
+            vec![
+                UpdateOutcome::Inserted,
+                UpdateOutcome::Overwritten,
+                UpdateOutcome::Overwritten
+            ]
+        );
+        assert!(outcomes[1].is_collision());
+        assert!(!outcomes[0].is_collision());
+        assert_eq!(stored(&db), vec![(timestamp(0), 3, false)]);
+    }
+}
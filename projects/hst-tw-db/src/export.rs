@@ -0,0 +1,460 @@
+//! Export of database contents to Avro or NDJSON files.
+
+use super::{collision::with_key_snapshot, key_to_pair, parse_value, seek_key, Error, ProfileDb};
+use apache_avro::Writer;
+use chrono::{DateTime, Utc};
+use hst_tw_profiles::avro::chunked::{ChunkLimits, ChunkedAvroWriter, Manifest};
+use hst_tw_profiles::model::User;
+use hst_tw_utils::extsort::ExternalSorter;
+use rocksdb::IteratorMode;
+use std::io::Write;
+use std::path::Path;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ExportFormat {
+    Avro,
+    Ndjson,
+}
+
+impl ExportFormat {
+    /// Choose the format from the file extension (`.avro`, or `.ndjson` or `.json`).
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<Self> {
+        match path.as_ref().extension()?.to_str()? {
+            "avro" => Some(Self::Avro),
+            "ndjson" | "json" => Some(Self::Ndjson),
+            _ => None,
+        }
+    }
+
+    /// Upper bound on the output size relative to the size of the stored values.
+    pub fn space_ratio(&self) -> f64 {
+        match self {
+            // Snappy-compressed blocks of the same encoding (with some allowance for headers).
+            Self::Avro => 1.1,
+            // Every value includes the field names.
+            Self::Ndjson => 4.0,
+        }
+    }
+}
+
+enum ExportWriter<W: Write> {
+    Avro(Writer<'static, W>),
+    Ndjson(W),
+}
+
+impl<W: Write> ExportWriter<W> {
+    fn new(writer: W, format: ExportFormat) -> Self {
+        match format {
+            ExportFormat::Avro => Self::Avro(hst_tw_profiles::avro::writer(writer)),
+            ExportFormat::Ndjson => Self::Ndjson(writer),
+        }
+    }
+
+    fn write(&mut self, user: &User) -> Result<(), Error> {
+        match self {
+            Self::Avro(writer) => {
+                writer.append_ser(user)?;
+            }
+            Self::Ndjson(writer) => {
+                serde_json::to_writer(&mut *writer, user)?;
+                writer.write_all(b"\n")?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn finish(self) -> Result<(), Error> {
+        let mut writer = match self {
+            Self::Avro(writer) => writer.into_inner()?,
+            Self::Ndjson(writer) => writer,
+        };
+
+        Ok(writer.flush()?)
+    }
+}
+
+impl<M> ProfileDb<M> {
+    /// Write every snapshot in the time range (inclusive of the start, exclusive of the end) in
+    /// key order (by user ID and then snapshot), returning the number written.
+    pub fn export<W: Write>(
+        &self,
+        writer: W,
+        format: ExportFormat,
+        start: Option<DateTime<Utc>>,
+        end: Option<DateTime<Utc>>,
+    ) -> Result<usize, Error> {
+        let mut writer = ExportWriter::new(writer, format);
+        let mut count = 0;
+
+        for result in self.db.iterator(IteratorMode::Start) {
+            let (key, value) = result?;
+            let (_, snapshot) = key_to_pair(&key)?;
+
+            if in_range(snapshot, start, end) {
+                writer.write(&with_key_snapshot(parse_value(value)?, snapshot))?;
+                count += 1;
+            }
+        }
+
+        writer.finish()?;
+
+        Ok(count)
+    }
+
+    /// Write every snapshot in the time range to a directory of Avro parts with a manifest.
+    ///
+    /// Snapshots are written in key order, so each user's snapshots end up in a single part.
+    pub fn export_chunked<P: AsRef<Path>>(
+        &self,
+        base: P,
+        limits: ChunkLimits,
+        start: Option<DateTime<Utc>>,
+        end: Option<DateTime<Utc>>,
+    ) -> Result<Manifest, Error> {
+        let mut writer = ChunkedAvroWriter::new(base, limits)?;
+
+        for result in self.db.iterator(IteratorMode::Start) {
+            let (key, value) = result?;
+            let (_, snapshot) = key_to_pair(&key)?;
+
+            if in_range(snapshot, start, end) {
+                writer.write(&with_key_snapshot(parse_value(value)?, snapshot))?;
+            }
+        }
+
+        Ok(writer.finish()?)
+    }
+
+    /// Write every snapshot in the time range ordered by snapshot timestamp (and then user ID).
+    ///
+    /// Keys are sorted externally (with spill files in the given directory), and each value is
+    /// then looked up individually.
+    pub fn export_by_time<W: Write, P: AsRef<Path>>(
+        &self,
+        writer: W,
+        format: ExportFormat,
+        start: Option<DateTime<Utc>>,
+        end: Option<DateTime<Utc>>,
+        temp_dir: P,
+    ) -> Result<usize, Error> {
+        let mut key_error = None;
+
+        let keys = self
+            .db
+            .iterator(IteratorMode::Start)
+            .map_while(|result| {
+                match result
+                    .map_err(Error::from)
+                    .and_then(|(key, _)| key_to_pair(&key))
+                {
+                    Ok(pair) => Some(pair),
+                    Err(error) => {
+                        key_error = Some(error);
+                        None
+                    }
+                }
+            })
+            .filter(|(_, snapshot)| in_range(*snapshot, start, end))
+            .map(|(user_id, snapshot)| (snapshot.timestamp() as u64, user_id));
+
+        let sorted = ExternalSorter::default().temp_dir(temp_dir).sort(keys)?;
+
+        if let Some(error) = key_error {
+            return Err(error);
+        }
+
+        let mut writer = ExportWriter::new(writer, format);
+        let mut count = 0;
+
+        for result in sorted {
+            let (timestamp, user_id) = result?;
+            let key = seek_key(user_id, timestamp as i64);
+            let value = self
+                .db
+                .get(key)?
+                .ok_or_else(|| Error::InvalidKeyBytes(key.to_vec()))?;
+            let (_, snapshot) = key_to_pair(&key)?;
+
+            writer.write(&with_key_snapshot(parse_value(value)?, snapshot))?;
+            count += 1;
+        }
+
+        writer.finish()?;
+
+        Ok(count)
+    }
+}
+
+fn in_range(
+    snapshot: DateTime<Utc>,
+    start: Option<DateTime<Utc>>,
+    end: Option<DateTime<Utc>>,
+) -> bool {
+    start.is_none_or(|start| snapshot >= start) && end.is_none_or(|end| snapshot < end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::table::Writeable;
+    use chrono::TimeZone;
+    use hst_tw_profiles::avro::chunked::ChunkedAvroReader;
+
+    fn user(id: i64, snapshot: i64) -> User {
+        User {
+            id,
+            id_str: id.to_string(),
+            screen_name: format!("user_{}", id),
+            snapshot,
+            ..User::default()
+        }
+    }
+
+    fn keys(users: &[User]) -> Vec<(u64, i64)> {
+        users
+            .iter()
+            .map(|user| (user.id(), user.snapshot))
+            .collect()
+    }
+
+    fn timestamp(value: i64) -> DateTime<Utc> {
+        Utc.timestamp_opt(value, 0).single().unwrap()
+    }
+
+    fn test_db(path: &Path) -> (ProfileDb<Writeable>, Vec<User>) {
+        let db = ProfileDb::<Writeable>::open(path, false).unwrap();
+        // Inserted out of order, with the later user having the earlier snapshot.
+        let users = vec![user(2, 100), user(1, 300), user(1, 200), user(2, 400)];
+
+        db.update_batch(&users).unwrap();
+
+        (db, users)
+    }
+
+    fn read_avro(bytes: &[u8]) -> Vec<User> {
+        hst_tw_profiles::avro::reader(bytes)
+            .unwrap()
+            .map(|value| apache_avro::from_value::<User>(&value.unwrap()).unwrap())
+            .collect()
+    }
+
+    fn read_ndjson(bytes: &[u8]) -> Vec<User> {
+        std::str::from_utf8(bytes)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn format_from_path() {
+        assert_eq!(
+            ExportFormat::from_path("a/b.avro"),
+            Some(ExportFormat::Avro)
+        );
+        assert_eq!(
+            ExportFormat::from_path("b.ndjson"),
+            Some(ExportFormat::Ndjson)
+        );
+        assert_eq!(
+            ExportFormat::from_path("b.json"),
+            Some(ExportFormat::Ndjson)
+        );
+        assert_eq!(ExportFormat::from_path("b.csv"), None);
+        assert_eq!(ExportFormat::from_path("avro"), None);
+    }
+
+    #[test]
+    fn export_in_key_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let (db, users) = test_db(&dir.path().join("db"));
+
+        let mut avro = vec![];
+        let mut ndjson = vec![];
+
+        assert_eq!(
+            db.export(&mut avro, ExportFormat::Avro, None, None)
+                .unwrap(),
+            4
+        );
+        assert_eq!(
+            db.export(&mut ndjson, ExportFormat::Ndjson, None, None)
+                .unwrap(),
+            4
+        );
+
+        let expected = vec![(1, 200), (1, 300), (2, 100), (2, 400)];
+
+        assert_eq!(keys(&read_avro(&avro)), expected);
+        assert_eq!(read_ndjson(&ndjson), read_avro(&avro));
+        assert!(read_avro(&avro)
+            .iter()
+            .all(|exported| users.contains(exported)));
+
+        let mut ndjson = vec![];
+
+        assert_eq!(
+            db.export(
+                &mut ndjson,
+                ExportFormat::Ndjson,
+                Some(timestamp(200)),
+                Some(timestamp(400))
+            )
+            .unwrap(),
+            2
+        );
+        assert_eq!(keys(&read_ndjson(&ndjson)), vec![(1, 200), (1, 300)]);
+    }
+
+    #[test]
+    fn export_by_time() {
+        let dir = tempfile::tempdir().unwrap();
+        let (db, _) = test_db(&dir.path().join("db"));
+
+        let mut avro = vec![];
+
+        assert_eq!(
+            db.export_by_time(&mut avro, ExportFormat::Avro, None, None, dir.path())
+                .unwrap(),
+            4
+        );
+        assert_eq!(
+            keys(&read_avro(&avro)),
+            vec![(2, 100), (1, 200), (1, 300), (2, 400)]
+        );
+
+        let mut ndjson = vec![];
+
+        assert_eq!(
+            db.export_by_time(
+                &mut ndjson,
+                ExportFormat::Ndjson,
+                Some(timestamp(150)),
+                None,
+                dir.path()
+            )
+            .unwrap(),
+            3
+        );
+        assert_eq!(
+            keys(&read_ndjson(&ndjson)),
+            vec![(1, 200), (1, 300), (2, 400)]
+        );
+    }
+
+    #[test]
+    fn export_chunked_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = ProfileDb::<Writeable>::open(dir.path().join("db"), false).unwrap();
+        let users = (1..=5)
+            .flat_map(|id| (1..=3).map(move |snapshot| user(id, 1_600_000_000 + snapshot * 100)))
+            .collect::<Vec<_>>();
+
+        db.update_batch(&users).unwrap();
+
+        let limits = ChunkLimits {
+            max_bytes: u64::MAX,
+            max_records: 4,
+        };
+        let manifest = db
+            .export_chunked(dir.path().join("all"), limits, None, None)
+            .unwrap();
+
+        assert_eq!(manifest.record_count(), 15);
+        assert_eq!(manifest.parts.len(), 3);
+
+        let reader = ChunkedAvroReader::open(dir.path().join("all")).unwrap();
+        let exported = reader.iter().collect::<Result<Vec<_>, _>>().unwrap();
+
+        assert_eq!(keys(&exported), keys(&users));
+        assert_eq!(reader.lookup(3).unwrap().len(), 3);
+
+        let manifest = db
+            .export_chunked(
+                dir.path().join("range"),
+                ChunkLimits::default(),
+                Some(timestamp(1_600_000_200)),
+                Some(timestamp(1_600_000_300)),
+            )
+            .unwrap();
+        let reader = ChunkedAvroReader::open(dir.path().join("range")).unwrap();
+        let exported = reader.iter().collect::<Result<Vec<_>, _>>().unwrap();
+
+        assert_eq!(manifest.record_count(), 5);
+        assert!(exported.iter().all(|user| user.snapshot == 1_600_000_200));
+    }
+
+    #[test]
+    fn export_adjusted_snapshots() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = ProfileDb::<Writeable>::open(dir.path().join("db"), false)
+            .unwrap()
+            .with_collision_policy(crate::collision::CollisionPolicy::Disambiguate);
+        let mut renamed = user(1, 100);
+        renamed.screen_name = "renamed".to_string();
+
+        // The second version at 100 is moved to 101.
+        db.update_batch(&[user(1, 100), user(1, 102), renamed])
+            .unwrap();
+
+        // Adjusted before the snapshot was moved on storage.
+        let mut legacy = user(1, 102);
+        legacy.screen_name = "legacy".to_string();
+        db.db
+            .put(
+                crate::pair_to_key(1, timestamp(103)).unwrap(),
+                crate::user_to_bytes(&legacy).unwrap(),
+            )
+            .unwrap();
+
+        let mut avro = vec![];
+
+        assert_eq!(
+            db.export(&mut avro, ExportFormat::Avro, None, None)
+                .unwrap(),
+            4
+        );
+        assert_eq!(
+            hst_tw_profiles::avro::validate(hst_tw_profiles::avro::reader(&avro[..]).unwrap())
+                .unwrap(),
+            4
+        );
+
+        let exported = read_avro(&avro);
+
+        assert_eq!(
+            keys(&exported),
+            vec![(1, 100), (1, 101), (1, 102), (1, 103)]
+        );
+        assert_eq!(
+            exported
+                .iter()
+                .map(|user| (user.screen_name.as_str(), user.ext_snapshot_adjusted))
+                .collect::<Vec<_>>(),
+            vec![
+                ("user_1", None),
+                ("renamed", Some(true)),
+                ("user_1", None),
+                ("legacy", Some(true))
+            ]
+        );
+
+        let manifest = db
+            .export_chunked(dir.path().join("all"), ChunkLimits::default(), None, None)
+            .unwrap();
+        let reader = ChunkedAvroReader::open(dir.path().join("all")).unwrap();
+
+        assert_eq!(manifest.record_count(), 4);
+        assert_eq!(
+            reader.iter().collect::<Result<Vec<_>, _>>().unwrap(),
+            exported
+        );
+
+        let mut ndjson = vec![];
+
+        db.export_by_time(&mut ndjson, ExportFormat::Ndjson, None, None, dir.path())
+            .unwrap();
+        assert_eq!(read_ndjson(&ndjson), exported);
+    }
+}
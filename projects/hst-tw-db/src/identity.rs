@@ -0,0 +1,563 @@
+//! A small SQLite table mapping Twitter user IDs to handles on other platforms.
+//!
+//! Mappings are curated by hand and may disagree, so conflicting handles for the same user and
+//! platform are all kept (distinguished by source), and lookups order them by confidence.
+
+use super::Error;
+use chrono::{DateTime, TimeZone, Utc};
+use rusqlite::{params, Connection};
+use std::fmt::Formatter;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+use std::str::FromStr;
+
+const SCHEMA: &str = "
+    PRAGMA user_version = 1;
+    CREATE TABLE IF NOT EXISTS identities (
+        twitter_id INTEGER NOT NULL,
+        platform TEXT NOT NULL,
+        handle TEXT NOT NULL,
+        confidence REAL NOT NULL,
+        source TEXT NOT NULL,
+        added_at INTEGER NOT NULL,
+        PRIMARY KEY (twitter_id, platform, handle, source)
+    );
+    CREATE INDEX IF NOT EXISTS identities_platform_handle ON identities (platform, handle);
+";
+
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Platform {
+    Gab,
+    Gettr,
+    Mastodon,
+    Telegram,
+    TruthSocial,
+}
+
+impl Platform {
+    /// Normalize a handle (possibly given as a profile URL or with a leading `@`).
+    pub fn normalize_handle(&self, input: &str) -> Option<String> {
+        let input = input.trim();
+        let input = strip_scheme(input);
+
+        let handle = match self {
+            Self::Gab => strip_domain(input, &["gab.com", "www.gab.com", "gab.ai"]),
+            Self::Gettr => strip_domain(input, &["gettr.com", "www.gettr.com"])
+                .map(|path| path.strip_prefix("user/").unwrap_or(path)),
+            Self::Telegram => strip_domain(input, &["t.me", "telegram.me", "telegram.dog"]),
+            Self::TruthSocial => strip_domain(input, &["truthsocial.com", "www.truthsocial.com"]),
+            // Mastodon handles include the instance, either as `user@instance` or as a profile
+            // URL of the form `instance/@user`.
+            Self::Mastodon => {
+                let input = input.strip_prefix('@').unwrap_or(input);
+
+                return match input.split_once("/@") {
+                    Some((instance, user)) => valid_mastodon(user, instance),
+                    None => input
+                        .split_once('@')
+                        .and_then(|(user, instance)| valid_mastodon(user, instance)),
+                };
+            }
+        }?;
+
+        let handle = handle.trim_end_matches('/');
+        let handle = handle.strip_prefix('@').unwrap_or(handle).to_lowercase();
+
+        let (min_length, max_length) = match self {
+            Self::Telegram => (5, 32),
+            _ => (1, 30),
+        };
+
+        if handle.len() >= min_length
+            && handle.len() <= max_length
+            && handle
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_')
+        {
+            Some(handle)
+        } else {
+            None
+        }
+    }
+}
+
+impl std::fmt::Display for Platform {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let value = match self {
+            Self::Gab => "gab",
+            Self::Gettr => "gettr",
+            Self::Mastodon => "mastodon",
+            Self::Telegram => "telegram",
+            Self::TruthSocial => "truthsocial",
+        };
+        write!(f, "{}", value)
+    }
+}
+
+impl FromStr for Platform {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "gab" => Ok(Self::Gab),
+            "gettr" => Ok(Self::Gettr),
+            "mastodon" => Ok(Self::Mastodon),
+            "telegram" => Ok(Self::Telegram),
+            "truthsocial" => Ok(Self::TruthSocial),
+            _ => Err(Error::InvalidPlatform(s.to_string())),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Identity {
+    pub twitter_id: u64,
+    pub platform: Platform,
+    /// Normalized handle.
+    pub handle: String,
+    /// Value between 0 and 1.
+    pub confidence: f64,
+    pub source: String,
+    pub added_at: DateTime<Utc>,
+}
+
+impl Identity {
+    /// Create a mapping, normalizing the handle.
+    pub fn new(
+        twitter_id: u64,
+        platform: Platform,
+        handle: &str,
+        confidence: f64,
+        source: &str,
+    ) -> Result<Self, Error> {
+        if !(0.0..=1.0).contains(&confidence) {
+            return Err(Error::InvalidConfidence(confidence));
+        }
+
+        Ok(Self {
+            twitter_id,
+            platform,
+            handle: platform
+                .normalize_handle(handle)
+                .ok_or_else(|| Error::InvalidHandle(handle.to_string()))?,
+            confidence,
+            source: source.to_string(),
+            added_at: Utc::now(),
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    pub imported: usize,
+    /// Line numbers (1-based) and errors for rejected lines.
+    pub rejected: Vec<(usize, Error)>,
+}
+
+pub struct IdentityDb {
+    connection: Connection,
+}
+
+impl IdentityDb {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let connection = Connection::open(path)?;
+        connection.execute_batch(SCHEMA)?;
+
+        Ok(Self { connection })
+    }
+
+    /// Add or replace a mapping (mappings are keyed by user ID, platform, handle, and source).
+    pub fn add(&self, identity: &Identity) -> Result<(), Error> {
+        self.connection.execute(
+            "INSERT OR REPLACE INTO identities
+                (twitter_id, platform, handle, confidence, source, added_at)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                identity.twitter_id as i64,
+                identity.platform.to_string(),
+                identity.handle,
+                identity.confidence,
+                identity.source,
+                identity.added_at.timestamp()
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    /// Remove all mappings of the user to the handle, returning the number removed.
+    pub fn remove(
+        &self,
+        twitter_id: u64,
+        platform: Platform,
+        handle: &str,
+    ) -> Result<usize, Error> {
+        let handle = platform
+            .normalize_handle(handle)
+            .ok_or_else(|| Error::InvalidHandle(handle.to_string()))?;
+
+        Ok(self.connection.execute(
+            "DELETE FROM identities WHERE twitter_id = ?1 AND platform = ?2 AND handle = ?3",
+            params![twitter_id as i64, platform.to_string(), handle],
+        )?)
+    }
+
+    /// Return all mappings for the user, in descending order of confidence.
+    pub fn lookup(&self, twitter_id: u64) -> Result<Vec<Identity>, Error> {
+        self.query(
+            "SELECT twitter_id, platform, handle, confidence, source, added_at FROM identities
+                WHERE twitter_id = ?1 ORDER BY confidence DESC, platform, handle, source",
+            params![twitter_id as i64],
+        )
+    }
+
+    /// Return all users mapped to the handle, in descending order of confidence.
+    pub fn lookup_handle(&self, platform: Platform, handle: &str) -> Result<Vec<Identity>, Error> {
+        let handle = platform
+            .normalize_handle(handle)
+            .ok_or_else(|| Error::InvalidHandle(handle.to_string()))?;
+
+        self.query(
+            "SELECT twitter_id, platform, handle, confidence, source, added_at FROM identities
+                WHERE platform = ?1 AND handle = ?2 ORDER BY confidence DESC, twitter_id, source",
+            params![platform.to_string(), handle],
+        )
+    }
+
+    /// Import CSV lines of the form `twitter_id,platform,handle,confidence,source`.
+    ///
+    /// Invalid lines are reported and skipped, and all valid lines are added in one transaction.
+    pub fn import_csv<R: Read>(&mut self, reader: R) -> Result<ImportReport, Error> {
+        let mut report = ImportReport::default();
+        let mut identities = vec![];
+
+        for (index, line) in BufReader::new(reader).lines().enumerate() {
+            let line = line?;
+
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            match parse_csv_line(&line) {
+                Ok(identity) => identities.push(identity),
+                Err(error) => report.rejected.push((index + 1, error)),
+            }
+        }
+
+        let transaction = self.connection.transaction()?;
+        {
+            let mut insert = transaction.prepare(
+                "INSERT OR REPLACE INTO identities
+                    (twitter_id, platform, handle, confidence, source, added_at)
+                    VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            )?;
+
+            for identity in &identities {
+                insert.execute(params![
+                    identity.twitter_id as i64,
+                    identity.platform.to_string(),
+                    identity.handle,
+                    identity.confidence,
+                    identity.source,
+                    identity.added_at.timestamp()
+                ])?;
+            }
+        }
+        transaction.commit()?;
+
+        report.imported = identities.len();
+
+        Ok(report)
+    }
+
+    fn query<P: rusqlite::Params>(&self, sql: &str, params: P) -> Result<Vec<Identity>, Error> {
+        let mut select = self.connection.prepare(sql)?;
+        let rows = select
+            .query_map(params, |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, f64>(3)?,
+                    row.get::<_, String>(4)?,
+                    row.get::<_, i64>(5)?,
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        rows.into_iter()
+            .map(
+                |(twitter_id, platform, handle, confidence, source, added_at)| {
+                    Ok(Identity {
+                        twitter_id: twitter_id as u64,
+                        platform: platform.parse()?,
+                        handle,
+                        confidence,
+                        source,
+                        added_at: Utc.timestamp_opt(added_at, 0).single().unwrap_or_default(),
+                    })
+                },
+            )
+            .collect()
+    }
+}
+
+fn parse_csv_line(line: &str) -> Result<Identity, Error> {
+    let fields = line.split(',').map(str::trim).collect::<Vec<_>>();
+
+    if fields.len() != 5 {
+        return Err(Error::InvalidIdentityLine(line.to_string()));
+    }
+
+    let twitter_id = fields[0]
+        .parse()
+        .map_err(|_| Error::InvalidIdentityLine(line.to_string()))?;
+    let confidence = fields[3]
+        .parse()
+        .map_err(|_| Error::InvalidIdentityLine(line.to_string()))?;
+
+    Identity::new(
+        twitter_id,
+        fields[1].parse()?,
+        fields[2],
+        confidence,
+        fields[4],
+    )
+}
+
+fn strip_scheme(input: &str) -> &str {
+    input
+        .strip_prefix("https://")
+        .or_else(|| input.strip_prefix("http://"))
+        .unwrap_or(input)
+}
+
+/// Remove a leading domain if present, returning `None` if the input is a URL on another domain.
+fn strip_domain<'a>(input: &'a str, domains: &[&str]) -> Option<&'a str> {
+    match input.split_once('/') {
+        Some((host, path)) if host.contains('.') => domains
+            .iter()
+            .any(|domain| host.eq_ignore_ascii_case(domain))
+            .then_some(path),
+        _ => Some(input),
+    }
+}
+
+fn valid_mastodon(user: &str, instance: &str) -> Option<String> {
+    let user = user.trim_end_matches('/').to_lowercase();
+    let instance = instance.trim_end_matches('/').to_lowercase();
+
+    if !user.is_empty()
+        && user.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && instance.contains('.')
+        && instance
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-')
+    {
+        Some(format!("{}@{}", user, instance))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PLATFORMS: [Platform; 5] = [
+        Platform::Gab,
+        Platform::Gettr,
+        Platform::Mastodon,
+        Platform::Telegram,
+        Platform::TruthSocial,
+    ];
+
+    #[test]
+    fn normalize_handles() {
+        let cases = [
+            (Platform::Gab, "@SomeUser", Some("someuser")),
+            (Platform::Gab, "https://gab.com/SomeUser/", Some("someuser")),
+            (Platform::Gab, "gab.ai/@some_user", Some("some_user")),
+            (Platform::Gab, "https://example.com/someuser", None),
+            (Platform::Gab, "some.user", None),
+            (Platform::Gab, "", None),
+            (
+                Platform::Gettr,
+                "https://gettr.com/user/SomeUser",
+                Some("someuser"),
+            ),
+            (Platform::Gettr, "www.gettr.com/SomeUser", Some("someuser")),
+            (
+                Platform::Telegram,
+                "https://t.me/channel_name",
+                Some("channel_name"),
+            ),
+            (Platform::Telegram, "@abcd", None),
+            (Platform::Telegram, &"a".repeat(33), None),
+            (
+                Platform::TruthSocial,
+                " truthsocial.com/@Someone ",
+                Some("someone"),
+            ),
+            (Platform::TruthSocial, &"a".repeat(31), None),
+            (
+                Platform::Mastodon,
+                "@SomeUser@Mastodon.Social",
+                Some("someuser@mastodon.social"),
+            ),
+            (
+                Platform::Mastodon,
+                "https://mastodon.social/@SomeUser/",
+                Some("someuser@mastodon.social"),
+            ),
+            (Platform::Mastodon, "someuser", None),
+            (Platform::Mastodon, "someuser@localhost", None),
+            (Platform::Mastodon, "some.user@mastodon.social", None),
+        ];
+
+        for (platform, input, expected) in cases {
+            assert_eq!(
+                platform.normalize_handle(input).as_deref(),
+                expected,
+                "{} {:?}",
+                platform,
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn platform_round_trip() {
+        for platform in PLATFORMS {
+            assert_eq!(platform.to_string().parse::<Platform>().unwrap(), platform);
+        }
+
+        assert_eq!(
+            " TruthSocial ".parse::<Platform>().unwrap(),
+            Platform::TruthSocial
+        );
+        assert!(matches!(
+            "myspace".parse::<Platform>(),
+            Err(Error::InvalidPlatform(value)) if value == "myspace"
+        ));
+    }
+
+    #[test]
+    fn identity_validation() {
+        let identity = Identity::new(1, Platform::Gab, "@User", 0.5, "manual").unwrap();
+
+        assert_eq!(identity.handle, "user");
+        assert!(matches!(
+            Identity::new(1, Platform::Gab, "user", 1.5, "manual"),
+            Err(Error::InvalidConfidence(_))
+        ));
+        assert!(matches!(
+            Identity::new(1, Platform::Gab, "user", f64::NAN, "manual"),
+            Err(Error::InvalidConfidence(_))
+        ));
+        assert!(matches!(
+            Identity::new(1, Platform::Gab, "not a handle", 0.5, "manual"),
+            Err(Error::InvalidHandle(_))
+        ));
+    }
+
+    #[test]
+    fn add_lookup_and_remove() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = IdentityDb::open(dir.path().join("identities.db")).unwrap();
+
+        for (twitter_id, platform, handle, confidence, source) in [
+            (1, Platform::Gab, "alice", 0.5, "a"),
+            (1, Platform::Gab, "alice", 0.9, "b"),
+            (1, Platform::Gab, "alice_2", 0.7, "a"),
+            (1, Platform::Telegram, "alice_channel", 0.6, "a"),
+            (2, Platform::Gab, "alice", 0.4, "a"),
+            // Replaces the first mapping.
+            (1, Platform::Gab, "ALICE", 0.8, "a"),
+        ] {
+            db.add(&Identity::new(twitter_id, platform, handle, confidence, source).unwrap())
+                .unwrap();
+        }
+
+        let summarize = |identities: Vec<Identity>| {
+            identities
+                .into_iter()
+                .map(|identity| {
+                    (
+                        identity.twitter_id,
+                        identity.handle,
+                        identity.confidence,
+                        identity.source,
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            summarize(db.lookup(1).unwrap()),
+            vec![
+                (1, "alice".to_string(), 0.9, "b".to_string()),
+                (1, "alice".to_string(), 0.8, "a".to_string()),
+                (1, "alice_2".to_string(), 0.7, "a".to_string()),
+                (1, "alice_channel".to_string(), 0.6, "a".to_string()),
+            ]
+        );
+        assert_eq!(
+            summarize(
+                db.lookup_handle(Platform::Gab, "https://gab.com/Alice")
+                    .unwrap()
+            ),
+            vec![
+                (1, "alice".to_string(), 0.9, "b".to_string()),
+                (1, "alice".to_string(), 0.8, "a".to_string()),
+                (2, "alice".to_string(), 0.4, "a".to_string()),
+            ]
+        );
+        assert!(db
+            .lookup_handle(Platform::Gettr, "alice")
+            .unwrap()
+            .is_empty());
+
+        assert_eq!(db.remove(1, Platform::Gab, "@Alice").unwrap(), 2);
+        assert_eq!(db.remove(1, Platform::Gab, "alice").unwrap(), 0);
+        assert!(matches!(
+            db.remove(1, Platform::Gab, "not a handle"),
+            Err(Error::InvalidHandle(_))
+        ));
+        assert_eq!(db.lookup(1).unwrap().len(), 2);
+        assert_eq!(db.lookup_handle(Platform::Gab, "alice").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn import_csv_reports_rejected_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut db = IdentityDb::open(dir.path().join("identities.db")).unwrap();
+        let input = "1,gab,alice,0.9,list\n\
+            \n\
+            2,myspace,bob,0.5,list\n\
+            3, telegram , @carol_channel , 0.5 , list\n\
+            x,gab,dave,0.5,list\n\
+            4,gab,erin,2.0,list\n\
+            5,gab,frank\n\
+            6,gab,not a handle,0.5,list\n";
+
+        let report = db.import_csv(input.as_bytes()).unwrap();
+        let rejected = report
+            .rejected
+            .iter()
+            .map(|(line_number, _)| *line_number)
+            .collect::<Vec<_>>();
+
+        assert_eq!(report.imported, 2);
+        assert_eq!(rejected, vec![3, 5, 6, 7, 8]);
+        assert!(matches!(report.rejected[0].1, Error::InvalidPlatform(_)));
+        assert!(matches!(
+            report.rejected[1].1,
+            Error::InvalidIdentityLine(_)
+        ));
+        assert!(matches!(report.rejected[2].1, Error::InvalidConfidence(_)));
+        assert!(matches!(report.rejected[4].1, Error::InvalidHandle(_)));
+        assert_eq!(db.lookup(3).unwrap()[0].handle, "carol_channel");
+        assert_eq!(db.lookup(1).unwrap()[0].source, "list");
+    }
+}
@@ -1,18 +1,49 @@
 //! A RocksDB database for storing user profiles from the Twitter API.
+//!
+//! The [`prelude`] module re-exports the database types and access modes.
 
-use apache_avro::{from_avro_datum, from_value, to_avro_datum, to_value};
+use apache_avro::{from_value, to_avro_datum, to_value};
 use chrono::{DateTime, TimeZone, Utc};
-use hst_tw_profiles::{avro::USER_SCHEMA, model::User};
-use rocksdb::{DBCompressionType, IteratorMode, Options, DB};
-use std::io::Cursor;
+use hst_tw_profiles::{
+    avro::{schema::read_user_datum, USER_SCHEMA},
+    model::{diff, ProfileDiff, User},
+};
+use rocksdb::{DBCompressionType, Direction, IteratorMode, Options, WriteBatch, WriteOptions, DB};
+use std::borrow::Cow;
+use std::collections::HashMap;
 use std::iter::Peekable;
 use std::marker::PhantomData;
 use std::path::Path;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+pub mod alias;
+pub mod cache;
+pub mod collision;
+pub mod export;
+pub mod identity;
+pub mod merge;
+pub mod pack;
+pub mod prune;
+pub mod screen_name_index;
 pub mod table;
+pub mod timing;
+pub mod unprofiled;
+
+pub use merge::{merge, MergeStats};
+
+/// Database types and access modes (with the error type renamed to avoid collisions).
+pub mod prelude {
+    pub use super::alias::AliasDb;
+    pub use super::cache::CachedProfileDb;
+    pub use super::collision::{CollisionPolicy, ImportStats, UpdateOutcome};
+    pub use super::identity::{Identity, IdentityDb, Platform};
+    pub use super::table::{Mode, ReadOnly, Table, Writeable};
+    pub use super::{Error as ProfileDbError, ProfileDb};
+}
 
 #[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
 pub enum Error {
     #[error("I/O error")]
     Io(#[from] std::io::Error),
@@ -20,14 +51,49 @@ pub enum Error {
     Utf8(#[from] std::str::Utf8Error),
     #[error("RocksDb error")]
     Db(#[from] rocksdb::Error),
+    #[error("SQLite error")]
+    Sqlite(#[from] rusqlite::Error),
     #[error("Avro decoding error")]
     Avro(#[from] apache_avro::Error),
+    #[error("JSON encoding error")]
+    Json(#[from] serde_json::Error),
+    #[error("Chunked Avro error")]
+    Chunked(#[from] hst_tw_profiles::avro::chunked::Error),
+    #[error("External sort error")]
+    Sort(#[from] hst_tw_utils::extsort::Error),
     #[error("Invalid key bytes")]
     InvalidKeyBytes(Vec<u8>),
     #[error("Invalid timestamp bytes")]
     InvalidTimestampBytes(Vec<u8>),
     #[error("Invalid timestamp")]
     InvalidTimestamp(DateTime<Utc>),
+    /// A snapshot value that can't be represented as a timestamp at all.
+    #[error("Invalid snapshot")]
+    InvalidSnapshot(i64),
+    #[error("Unknown field")]
+    UnknownField(String),
+    /// The database has no screen name index, or it hasn't been built for existing snapshots
+    /// (see [`ProfileDb::rebuild_screen_name_index`]).
+    #[error("Missing screen name index")]
+    MissingScreenNameIndex,
+    #[error("Unresolved snapshot collision")]
+    UnresolvedCollision { user_id: u64, snapshot: i64 },
+    #[error("Invalid alias")]
+    InvalidAlias { canonical_id: u64, alias_id: u64 },
+    #[error("Conflicting alias")]
+    ConflictingAlias {
+        alias_id: u64,
+        canonical_id: u64,
+        existing_canonical_id: u64,
+    },
+    #[error("Invalid platform")]
+    InvalidPlatform(String),
+    #[error("Invalid handle")]
+    InvalidHandle(String),
+    #[error("Invalid confidence")]
+    InvalidConfidence(f64),
+    #[error("Invalid identity line")]
+    InvalidIdentityLine(String),
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
@@ -36,10 +102,16 @@ pub struct ProfileDbCounts {
     pub pair_count: u64,
 }
 
+/// Operations recorded when timing is enabled.
+pub const TIMED_OPERATIONS: [&str; 4] = ["lookup", "lookup_range", "update", "update_batch"];
+
 #[derive(Clone)]
 pub struct ProfileDb<M> {
     db: Arc<DB>,
     options: Options,
+    timings: Option<Arc<timing::Timings>>,
+    collision_policy: collision::CollisionPolicy,
+    disable_wal: bool,
     mode: PhantomData<M>,
 }
 
@@ -79,21 +151,198 @@ impl<M> ProfileDb<M> {
         self.options.get_statistics()
     }
 
+    /// Enable operation timing, logging a warning (with RocksDB statistics, if enabled) for any
+    /// single operation that takes longer than the threshold.
+    pub fn with_timing(mut self, slow_threshold: Duration) -> Self {
+        self.timings = Some(Arc::new(timing::Timings::new(
+            &TIMED_OPERATIONS,
+            slow_threshold,
+        )));
+        self
+    }
+
+    /// Summarize and reset the operation timings (if enabled).
+    pub fn timing_report(&self) -> Option<Vec<timing::OpTiming>> {
+        self.timings.as_ref().map(|timings| timings.report())
+    }
+
     pub fn lookup(&self, target_user_id: u64) -> Result<Vec<(DateTime<Utc>, User)>, Error> {
-        let prefix = target_user_id.to_be_bytes();
-        let iter = self.db.prefix_iterator(prefix);
-        let mut users = vec![];
+        self.timed("lookup", || {
+            let prefix = target_user_id.to_be_bytes();
+            let iter = self.db.prefix_iterator(prefix);
+            let mut users = vec![];
 
-        for result in iter {
-            let (key, value) = result?;
-            let (user_id, snapshot) = key_to_pair(&key)?;
+            for result in iter {
+                let (key, value) = result?;
+                let (user_id, snapshot) = key_to_pair(&key)?;
+
+                if user_id == target_user_id {
+                    users.push((snapshot, parse_value(value)?));
+                } else {
+                    break;
+                }
+            }
+
+            Ok(users)
+        })
+    }
+
+    /// Look up the snapshots for a user in a time range (inclusive of the start, exclusive of
+    /// the end), without decoding any outside it.
+    pub fn lookup_range(
+        &self,
+        target_user_id: u64,
+        start: Option<DateTime<Utc>>,
+        end: Option<DateTime<Utc>>,
+    ) -> Result<Vec<(DateTime<Utc>, User)>, Error> {
+        self.timed("lookup_range", || {
+            let start_key = seek_key(target_user_id, start.map_or(0, |start| start.timestamp()));
+            let iter = self
+                .db
+                .iterator(IteratorMode::From(&start_key, Direction::Forward));
+            let mut users = vec![];
+
+            for result in iter {
+                let (key, value) = result?;
+                let (user_id, snapshot) = key_to_pair(&key)?;
+
+                if user_id != target_user_id || end.is_some_and(|end| snapshot >= end) {
+                    break;
+                }
 
-            if user_id == target_user_id {
                 users.push((snapshot, parse_value(value)?));
-            } else {
-                break;
             }
+
+            Ok(users)
+        })
+    }
+
+    /// Look up the most recent snapshot for a user at or before the given time.
+    pub fn lookup_latest_before(
+        &self,
+        target_user_id: u64,
+        timestamp: DateTime<Utc>,
+    ) -> Result<Option<(DateTime<Utc>, User)>, Error> {
+        if timestamp.timestamp() < 0 {
+            return Ok(None);
+        }
+
+        let key = seek_key(target_user_id, timestamp.timestamp());
+
+        match self
+            .db
+            .iterator(IteratorMode::From(&key, Direction::Reverse))
+            .next()
+        {
+            Some(result) => {
+                let (key, value) = result?;
+                let (user_id, snapshot) = key_to_pair(&key)?;
+
+                if user_id == target_user_id {
+                    Ok(Some((snapshot, parse_value(value)?)))
+                } else {
+                    Ok(None)
+                }
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Look up the earliest snapshot for a user at or after the given time.
+    pub fn lookup_earliest_after(
+        &self,
+        target_user_id: u64,
+        timestamp: DateTime<Utc>,
+    ) -> Result<Option<(DateTime<Utc>, User)>, Error> {
+        let key = seek_key(target_user_id, timestamp.timestamp());
+
+        match self
+            .db
+            .iterator(IteratorMode::From(&key, Direction::Forward))
+            .next()
+        {
+            Some(result) => {
+                let (key, value) = result?;
+                let (user_id, snapshot) = key_to_pair(&key)?;
+
+                if user_id == target_user_id && snapshot >= timestamp {
+                    Ok(Some((snapshot, parse_value(value)?)))
+                } else {
+                    Ok(None)
+                }
+            }
+            None => Ok(None),
         }
+    }
+
+    /// Differences between each pair of consecutive snapshots for a user.
+    ///
+    /// Pairs of snapshots with no differences in the compared fields (which don't include the
+    /// snapshot timestamp) are omitted, so consecutive results may not share a snapshot, and a
+    /// user whose profile never changed has no results.
+    pub fn changes(&self, target_user_id: u64) -> Result<Vec<ProfileDiff>, Error> {
+        let users = self.lookup(target_user_id)?;
+
+        Ok(users
+            .windows(2)
+            .map(|pair| diff(&pair[0].1, &pair[1].1))
+            .filter(|diff| !diff.is_empty())
+            .collect())
+    }
+
+    /// Estimate the number of snapshots (using RocksDB's key count estimate, which is cheap but
+    /// approximate).
+    pub fn estimate_snapshot_count(&self) -> Result<u64, Error> {
+        Ok(self
+            .db
+            .property_int_value(rocksdb::properties::ESTIMATE_NUM_KEYS)?
+            .unwrap_or(0))
+    }
+
+    /// Average size in bytes of the first `sample_size` stored values (zero if there are none).
+    pub fn average_value_size(&self, sample_size: usize) -> Result<u64, Error> {
+        let mut count = 0;
+        let mut total = 0;
+
+        for result in self.db.iterator(IteratorMode::Start).take(sample_size) {
+            let (_, value) = result?;
+            count += 1;
+            total += value.len() as u64;
+        }
+
+        Ok(if count == 0 { 0 } else { total.div_ceil(count) })
+    }
+
+    /// Check whether there are any snapshots for the user (without decoding them).
+    pub fn has_snapshots(&self, target_user_id: u64) -> Result<bool, Error> {
+        match self.db.prefix_iterator(target_user_id.to_be_bytes()).next() {
+            Some(result) => {
+                let (key, _) = result?;
+                Ok(key_to_pair(&key)?.0 == target_user_id)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Look up snapshots for every ID belonging to the account, labeled by the contributing ID.
+    ///
+    /// Results are sorted by snapshot timestamp.
+    pub fn lookup_with_aliases(
+        &self,
+        aliases: &alias::AliasDb,
+        user_id: u64,
+    ) -> Result<Vec<(u64, DateTime<Utc>, User)>, Error> {
+        let mut users = vec![];
+
+        for id in aliases.ids(user_id)? {
+            users.extend(
+                self.lookup(id)?
+                    .into_iter()
+                    .map(|(snapshot, user)| (id, snapshot, user)),
+            );
+        }
+
+        users.sort_by_key(|(_, snapshot, _)| *snapshot);
 
         Ok(users)
     }
@@ -116,6 +365,29 @@ impl<M> ProfileDb<M> {
             })
         })
     }
+
+    fn timed<T, F: FnOnce() -> T>(&self, op: &'static str, f: F) -> T {
+        match &self.timings {
+            None => f(),
+            Some(timings) => {
+                let start = Instant::now();
+                let result = f();
+                let elapsed = start.elapsed();
+
+                if timings.record(op, elapsed) {
+                    log::warn!(
+                        "Slow {} operation ({:?}): {}",
+                        op,
+                        elapsed,
+                        self.statistics()
+                            .unwrap_or_else(|| "statistics not enabled".to_string())
+                    );
+                }
+
+                result
+            }
+        }
+    }
 }
 
 impl<M: table::Mode> ProfileDb<M> {
@@ -128,26 +400,177 @@ impl<M: table::Mode> ProfileDb<M> {
             options.enable_statistics();
         }
 
+        // Databases created before the screen name index was added may not have it.
         let db = if M::is_read_only() {
-            DB::open_for_read_only(&options, path, true)?
+            let column_families = DB::list_cf(&options, &path)?;
+            DB::open_cf_for_read_only(&options, path, column_families, true)?
         } else {
-            DB::open(&options, path)?
+            options.create_missing_column_families(true);
+            DB::open_cf(
+                &options,
+                path,
+                [
+                    rocksdb::DEFAULT_COLUMN_FAMILY_NAME,
+                    screen_name_index::SCREEN_NAME_INDEX_CF,
+                ],
+            )?
         };
 
-        Ok(Self {
+        let db = Self {
             db: Arc::new(db),
             options,
+            timings: None,
+            collision_policy: collision::CollisionPolicy::default(),
+            disable_wal: false,
             mode: PhantomData,
-        })
+        };
+
+        if !M::is_read_only() {
+            db.mark_empty_screen_name_index()?;
+        }
+
+        Ok(db)
     }
 }
 
 impl ProfileDb<table::Writeable> {
-    pub fn update(&self, user: &User) -> Result<(), Error> {
-        let key = pair_to_key(user.id(), Utc.timestamp(user.snapshot, 0))?;
-        let avro_value = to_value(user)?;
-        let bytes = to_avro_datum(&USER_SCHEMA, avro_value)?;
-        Ok(self.db.put(key, bytes)?)
+    pub fn with_collision_policy(mut self, policy: collision::CollisionPolicy) -> Self {
+        self.collision_policy = policy;
+        self
+    }
+
+    /// Disable the write-ahead log for batch updates (which is faster for bulk loads, but means
+    /// that writes since the last flush may be lost on a crash).
+    pub fn with_wal(mut self, enabled: bool) -> Self {
+        self.disable_wal = !enabled;
+        self
+    }
+
+    /// Store a profile version, handling any different version with the same key according to
+    /// the collision policy.
+    pub fn update(&self, user: &User) -> Result<collision::UpdateOutcome, Error> {
+        self.timed("update", || {
+            let (entry, outcome) = self.resolve_key(user, |key| Ok(self.db.get(key)?))?;
+
+            if let Some((key, bytes)) = entry {
+                let mut batch = WriteBatch::default();
+                batch.put(key, bytes);
+                batch.put_cf(
+                    self.screen_name_index()?,
+                    screen_name_index::user_index_key(user),
+                    b"",
+                );
+                self.db.write(batch)?;
+            }
+
+            Ok(outcome)
+        })
+    }
+
+    /// Store profile versions (and their screen name index entries) in a single write batch,
+    /// returning the outcome for each.
+    ///
+    /// Collisions are resolved as in [`ProfileDb::update`], including between versions in the
+    /// same batch.
+    pub fn update_batch<'a, I: IntoIterator<Item = &'a User>>(
+        &self,
+        users: I,
+    ) -> Result<Vec<collision::UpdateOutcome>, Error> {
+        self.timed("update_batch", || {
+            let index = self.screen_name_index()?;
+            let mut batch = WriteBatch::default();
+            let mut pending: HashMap<[u8; 12], Vec<u8>> = HashMap::new();
+            let mut outcomes = vec![];
+
+            for user in users {
+                let (entry, outcome) = self.resolve_key(user, |key| match pending.get(key) {
+                    Some(value) => Ok(Some(value.clone())),
+                    None => Ok(self.db.get(key)?),
+                })?;
+
+                if let Some((key, bytes)) = entry {
+                    batch.put_cf(index, screen_name_index::user_index_key(user), b"");
+                    pending.insert(key, bytes);
+                }
+
+                outcomes.push(outcome);
+            }
+
+            for (key, value) in pending {
+                batch.put(key, value);
+            }
+
+            let mut write_options = WriteOptions::default();
+            write_options.disable_wal(self.disable_wal);
+            self.db.write_opt(batch, &write_options)?;
+
+            Ok(outcomes)
+        })
+    }
+
+    /// Determine the key and value to write for the version (if any) given a way to read
+    /// existing values.
+    ///
+    /// A version stored under a later key is adjusted to match it (see [`collision`]).
+    #[allow(clippy::type_complexity)]
+    fn resolve_key<G: Fn(&[u8; 12]) -> Result<Option<Vec<u8>>, Error>>(
+        &self,
+        user: &User,
+        get: G,
+    ) -> Result<(Option<([u8; 12], Vec<u8>)>, collision::UpdateOutcome), Error> {
+        use collision::{CollisionPolicy, UpdateOutcome};
+
+        let snapshot = Utc
+            .timestamp_opt(user.snapshot, 0)
+            .single()
+            .ok_or(Error::InvalidSnapshot(user.snapshot))?;
+
+        for adjustment in 0..=collision::MAX_SNAPSHOT_ADJUSTMENT {
+            let key_snapshot = snapshot + chrono::Duration::seconds(adjustment);
+            let key = pair_to_key(user.id(), key_snapshot)?;
+            let candidate = if adjustment == 0 {
+                Cow::Borrowed(user)
+            } else {
+                Cow::Owned(collision::adjust_snapshot(user, key_snapshot))
+            };
+            let bytes = user_to_bytes(&candidate)?;
+
+            let outcome = match get(&key)? {
+                None if adjustment == 0 => UpdateOutcome::Inserted,
+                None => UpdateOutcome::Adjusted(key_snapshot),
+                Some(existing) if same_value(&existing, &bytes, &candidate)? => {
+                    return Ok((None, UpdateOutcome::Unchanged))
+                }
+                Some(_) => match self.collision_policy {
+                    CollisionPolicy::KeepExisting => {
+                        return Ok((None, UpdateOutcome::KeptExisting))
+                    }
+                    CollisionPolicy::Overwrite => UpdateOutcome::Overwritten,
+                    CollisionPolicy::Disambiguate => continue,
+                },
+            };
+
+            return Ok((Some((key, bytes)), outcome));
+        }
+
+        Err(Error::UnresolvedCollision {
+            user_id: user.id(),
+            snapshot: user.snapshot,
+        })
+    }
+
+    /// Store every profile version, returning counts of outcomes (including collisions).
+    pub fn update_all<'a, I: IntoIterator<Item = &'a User>>(
+        &self,
+        users: I,
+    ) -> Result<collision::ImportStats, Error> {
+        let mut stats = collision::ImportStats::default();
+
+        for user in users {
+            stats.record(self.update(user)?);
+        }
+
+        Ok(stats)
     }
 }
 
@@ -164,6 +587,14 @@ fn pair_to_key(user_id: u64, snapshot: DateTime<Utc>) -> Result<[u8; 12], Error>
     Ok(key)
 }
 
+/// The key for a timestamp (which is clamped to the range we can store), for seeking.
+fn seek_key(user_id: u64, timestamp: i64) -> [u8; 12] {
+    let mut key = [0; 12];
+    key[0..8].copy_from_slice(&user_id.to_be_bytes());
+    key[8..12].copy_from_slice(&(timestamp.clamp(0, u32::MAX as i64) as u32).to_be_bytes());
+    key
+}
+
 fn key_to_pair(key: &[u8]) -> Result<(u64, DateTime<Utc>), Error> {
     let user_id = u64::from_be_bytes(
         key[0..8]
@@ -179,9 +610,20 @@ fn key_to_pair(key: &[u8]) -> Result<(u64, DateTime<Utc>), Error> {
     Ok((user_id, Utc.timestamp(snapshot as i64, 0)))
 }
 
+fn user_to_bytes(user: &User) -> Result<Vec<u8>, Error> {
+    Ok(to_avro_datum(&USER_SCHEMA, to_value(user)?)?)
+}
+
+/// Check whether a stored value is the same profile version as the given encoded user.
+///
+/// Values written with an earlier schema version have different bytes for the same profile, so
+/// if the bytes differ we compare the decoded users.
+fn same_value(existing: &[u8], bytes: &[u8], user: &User) -> Result<bool, Error> {
+    Ok(existing == bytes || parse_value(existing)? == *user)
+}
+
 fn parse_value<T: AsRef<[u8]>>(value: T) -> Result<User, Error> {
-    let mut cursor = Cursor::new(&value);
-    let avro_value = from_avro_datum(&USER_SCHEMA, &mut cursor, None)?;
+    let avro_value = read_user_datum(value.as_ref())?;
     Ok(from_value(&avro_value)?)
 }
 
@@ -217,3 +659,387 @@ impl<I: Iterator<Item = Result<(u64, DateTime<Utc>, User), Error>>> Iterator
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::table::Writeable;
+
+    fn user(id: i64, snapshot: i64) -> User {
+        User {
+            id,
+            id_str: id.to_string(),
+            screen_name: format!("user_{}", id),
+            snapshot,
+            ..User::default()
+        }
+    }
+
+    #[test]
+    fn timing_report() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = ProfileDb::<Writeable>::open(dir.path().join("db"), false).unwrap();
+
+        assert!(db.timing_report().is_none());
+
+        let db = db.with_timing(Duration::from_secs(60));
+
+        db.update_batch(&[user(1, 1_600_000_000), user(1, 1_600_000_100)])
+            .unwrap();
+        db.update(&user(2, 1_600_000_000)).unwrap();
+        db.lookup(1).unwrap();
+        db.lookup_range(1, None, None).unwrap();
+        db.lookup_range(2, None, None).unwrap();
+
+        let counts = db
+            .timing_report()
+            .unwrap()
+            .iter()
+            .map(|timing| (timing.op, timing.count))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            counts,
+            vec![
+                ("lookup", 1),
+                ("lookup_range", 2),
+                ("update", 1),
+                ("update_batch", 1)
+            ]
+        );
+        assert!(db
+            .timing_report()
+            .unwrap()
+            .iter()
+            .all(|timing| timing.count == 0));
+    }
+
+    #[test]
+    fn slow_operations() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = ProfileDb::<Writeable>::open(dir.path().join("db"), false)
+            .unwrap()
+            .with_timing(Duration::from_millis(1));
+
+        db.timed("lookup", || std::thread::sleep(Duration::from_millis(20)));
+        db.timed("lookup", || ());
+        db.timed("update", || ());
+
+        let slow_counts = db
+            .timing_report()
+            .unwrap()
+            .iter()
+            .map(|timing| (timing.op, timing.count, timing.slow_count))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            slow_counts,
+            vec![
+                ("lookup", 2, 1),
+                ("lookup_range", 0, 0),
+                ("update", 1, 0),
+                ("update_batch", 0, 0)
+            ]
+        );
+    }
+
+    #[test]
+    fn update_batch_matches_update() {
+        use collision::{CollisionPolicy, UpdateOutcome};
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut changed = user(1, 100);
+        changed.name = "changed".to_string();
+        let users = vec![
+            user(1, 100),
+            user(2, 100),
+            user(1, 100),
+            changed.clone(),
+            changed,
+        ];
+
+        for policy in [
+            CollisionPolicy::KeepExisting,
+            CollisionPolicy::Overwrite,
+            CollisionPolicy::Disambiguate,
+        ] {
+            let single =
+                ProfileDb::<Writeable>::open(dir.path().join(format!("{:?}", policy)), false)
+                    .unwrap()
+                    .with_collision_policy(policy);
+            let batched =
+                ProfileDb::<Writeable>::open(dir.path().join(format!("{:?}-batch", policy)), false)
+                    .unwrap()
+                    .with_collision_policy(policy);
+
+            let outcomes = users
+                .iter()
+                .map(|user| single.update(user).unwrap())
+                .collect::<Vec<_>>();
+
+            // Collisions within a batch are resolved against the earlier versions in the batch.
+            assert_eq!(batched.update_batch(&users).unwrap(), outcomes);
+            assert_eq!(
+                outcomes[..3],
+                [
+                    UpdateOutcome::Inserted,
+                    UpdateOutcome::Inserted,
+                    UpdateOutcome::Unchanged
+                ]
+            );
+
+            for id in [1, 2] {
+                assert_eq!(batched.lookup(id).unwrap(), single.lookup(id).unwrap());
+            }
+
+            assert_eq!(batched.lookup_screen_name("user_1").unwrap(), vec![1]);
+        }
+    }
+
+    #[test]
+    fn update_batch_without_wal() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = ProfileDb::<Writeable>::open(dir.path().join("db"), false)
+            .unwrap()
+            .with_wal(false);
+
+        let outcomes = db
+            .update_batch(&[user(1, 100), user(1, 200), user(2, 100)])
+            .unwrap();
+
+        assert_eq!(outcomes.len(), 3);
+        assert_eq!(db.lookup(1).unwrap().len(), 2);
+        assert_eq!(db.lookup(2).unwrap().len(), 1);
+        assert!(db.update_batch(&[]).unwrap().is_empty());
+    }
+
+    /// Encode a user with the original schema (as in databases created before the V2 fields).
+    pub(crate) fn user_to_v1_bytes(user: &User) -> Vec<u8> {
+        use apache_avro::types::Value;
+        use hst_tw_profiles::avro::USER_SCHEMA_V1;
+
+        let mut value = to_value(user).unwrap();
+        if let Value::Record(fields) = &mut value {
+            fields.retain(|(name, _)| !name.starts_with("ext_"));
+        }
+
+        to_avro_datum(&USER_SCHEMA_V1, value.resolve(&USER_SCHEMA_V1).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn update_unchanged_v1_value() {
+        use collision::UpdateOutcome;
+
+        let dir = tempfile::tempdir().unwrap();
+        let db = ProfileDb::<Writeable>::open(dir.path().join("db"), false).unwrap();
+        let user = user(1, 100);
+        let v1_bytes = user_to_v1_bytes(&user);
+
+        assert_ne!(v1_bytes, user_to_bytes(&user).unwrap());
+
+        db.db
+            .put(
+                pair_to_key(1, Utc.timestamp_opt(100, 0).single().unwrap()).unwrap(),
+                v1_bytes,
+            )
+            .unwrap();
+
+        assert_eq!(db.update(&user).unwrap(), UpdateOutcome::Unchanged);
+        assert_eq!(
+            db.update_batch(&[user.clone()]).unwrap(),
+            vec![UpdateOutcome::Unchanged]
+        );
+        assert_eq!(
+            db.lookup(1).unwrap(),
+            vec![(Utc.timestamp_opt(100, 0).single().unwrap(), user)]
+        );
+    }
+
+    #[test]
+    fn size_estimates() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = ProfileDb::<Writeable>::open(dir.path().join("db"), false).unwrap();
+
+        assert_eq!(db.estimate_snapshot_count().unwrap(), 0);
+        assert_eq!(db.average_value_size(100).unwrap(), 0);
+
+        let users = (1..=10).map(|id| user(id, 100)).collect::<Vec<_>>();
+        db.update_batch(&users).unwrap();
+
+        let sizes = users
+            .iter()
+            .map(|user| user_to_bytes(user).unwrap().len() as u64)
+            .collect::<Vec<_>>();
+
+        assert_eq!(db.estimate_snapshot_count().unwrap(), 10);
+        assert_eq!(
+            db.average_value_size(100).unwrap(),
+            sizes.iter().sum::<u64>().div_ceil(10)
+        );
+        assert_eq!(db.average_value_size(1).unwrap(), sizes[0]);
+    }
+
+    #[test]
+    fn changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = ProfileDb::<Writeable>::open(dir.path().join("db"), false).unwrap();
+
+        let mut renamed = user(1, 300);
+        renamed.name = "renamed".to_string();
+        let mut followed = renamed.clone();
+        followed.snapshot = 400;
+        followed.followers_count = 10;
+
+        db.update_batch(&[user(1, 100), user(1, 200), renamed, followed, user(2, 100)])
+            .unwrap();
+
+        let changes = db
+            .changes(1)
+            .unwrap()
+            .into_iter()
+            .map(|diff| {
+                assert_eq!(diff.user_id, 1);
+                (
+                    diff.from_snapshot,
+                    diff.to_snapshot,
+                    diff.changes
+                        .iter()
+                        .map(|change| change.field())
+                        .collect::<Vec<_>>(),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        // The unchanged pair (100 to 200) is omitted.
+        assert_eq!(
+            changes,
+            vec![
+                (200, 300, vec!["name"]),
+                (300, 400, vec!["followers_count"])
+            ]
+        );
+        assert!(db.changes(2).unwrap().is_empty());
+        assert!(db.changes(3).unwrap().is_empty());
+    }
+
+    #[test]
+    fn lookup_range() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = ProfileDb::<Writeable>::open(dir.path().join("db"), false).unwrap();
+
+        db.update_batch(&[
+            user(1, 100),
+            user(1, 200),
+            user(1, 300),
+            user(2, 150),
+            user(2, 200),
+        ])
+        .unwrap();
+
+        let snapshots = |id, start: Option<i64>, end: Option<i64>| {
+            db.lookup_range(
+                id,
+                start.map(|start| Utc.timestamp(start, 0)),
+                end.map(|end| Utc.timestamp(end, 0)),
+            )
+            .unwrap()
+            .into_iter()
+            .map(|(snapshot, user)| {
+                assert_eq!(user.id(), id);
+                snapshot.timestamp()
+            })
+            .collect::<Vec<_>>()
+        };
+
+        assert_eq!(snapshots(1, None, None), vec![100, 200, 300]);
+        assert_eq!(snapshots(1, Some(200), None), vec![200, 300]);
+        assert_eq!(snapshots(1, None, Some(300)), vec![100, 200]);
+        assert_eq!(snapshots(1, Some(150), Some(250)), vec![200]);
+        assert!(snapshots(1, Some(200), Some(200)).is_empty());
+        assert!(snapshots(1, Some(301), None).is_empty());
+        assert_eq!(snapshots(2, Some(100), Some(201)), vec![150, 200]);
+        assert!(snapshots(3, None, None).is_empty());
+        assert_eq!(
+            db.lookup_range(1, None, None).unwrap(),
+            db.lookup(1).unwrap()
+        );
+    }
+
+    #[test]
+    fn lookup_nearest() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = ProfileDb::<Writeable>::open(dir.path().join("db"), false).unwrap();
+
+        db.update_batch(&[user(1, 100), user(1, 200), user(2, 50), user(3, 300)])
+            .unwrap();
+
+        let snapshot = |result: Option<(DateTime<Utc>, User)>| {
+            result.map(|(snapshot, user)| {
+                assert_eq!(snapshot.timestamp(), user.snapshot);
+                user.snapshot
+            })
+        };
+        let before = |id, timestamp| {
+            snapshot(
+                db.lookup_latest_before(id, Utc.timestamp_opt(timestamp, 0).single().unwrap())
+                    .unwrap(),
+            )
+        };
+        let after = |id, timestamp| {
+            snapshot(
+                db.lookup_earliest_after(id, Utc.timestamp_opt(timestamp, 0).single().unwrap())
+                    .unwrap(),
+            )
+        };
+
+        assert_eq!(before(1, 150), Some(100));
+        assert_eq!(before(1, 200), Some(200));
+        assert_eq!(before(1, 1000), Some(200));
+        assert_eq!(before(1, 99), None);
+        assert_eq!(before(2, 49), None);
+        assert_eq!(before(3, 299), None);
+
+        assert_eq!(after(1, 150), Some(200));
+        assert_eq!(after(1, 100), Some(100));
+        assert_eq!(after(1, 0), Some(100));
+        assert_eq!(after(1, 201), None);
+        assert_eq!(after(2, 51), None);
+        assert_eq!(after(4, 0), None);
+    }
+
+    #[test]
+    fn lookup_with_aliases() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = ProfileDb::<Writeable>::open(dir.path().join("db"), false).unwrap();
+        let aliases = alias::AliasDb::open(dir.path().join("aliases.db")).unwrap();
+
+        db.update_batch(&[
+            user(1, 300),
+            user(10, 100),
+            user(10, 400),
+            user(11, 200),
+            user(2, 250),
+        ])
+        .unwrap();
+        aliases.add(1, 10, None).unwrap();
+        aliases.add(1, 11, None).unwrap();
+
+        let expected = vec![(10, 100), (11, 200), (1, 300), (10, 400)];
+
+        for id in [1, 10, 11] {
+            let results = db
+                .lookup_with_aliases(&aliases, id)
+                .unwrap()
+                .into_iter()
+                .map(|(id, snapshot, user)| {
+                    assert_eq!(snapshot.timestamp(), user.snapshot);
+                    (id, user.snapshot)
+                })
+                .collect::<Vec<_>>();
+
+            assert_eq!(results, expected);
+        }
+
+        assert_eq!(db.lookup_with_aliases(&aliases, 2).unwrap().len(), 1);
+    }
+}
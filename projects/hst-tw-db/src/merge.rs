@@ -0,0 +1,206 @@
+//! Copying snapshots from one database into another.
+
+use super::{
+    key_to_pair, parse_value, screen_name_index,
+    table::{ReadOnly, Writeable},
+    Error, ProfileDb,
+};
+use chrono::{DateTime, Utc};
+use rocksdb::{IteratorMode, WriteBatch};
+
+/// Number of snapshots written per batch.
+const MERGE_BATCH_SIZE: usize = 10_000;
+/// Number of source keys between progress log messages.
+const PROGRESS_INTERVAL: u64 = 1_000_000;
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct MergeStats {
+    pub copied: u64,
+    /// Keys that were already present in the target with the same value.
+    pub skipped: u64,
+    /// Keys that were already present in the target with a different value (which is kept).
+    pub conflicts: Vec<(u64, DateTime<Utc>)>,
+}
+
+/// Copy every snapshot in the source that isn't in the target.
+pub fn merge(
+    source: &ProfileDb<ReadOnly>,
+    target: &ProfileDb<Writeable>,
+) -> Result<MergeStats, Error> {
+    merge_batched(source, target, MERGE_BATCH_SIZE)
+}
+
+fn merge_batched(
+    source: &ProfileDb<ReadOnly>,
+    target: &ProfileDb<Writeable>,
+    batch_size: usize,
+) -> Result<MergeStats, Error> {
+    let index = target.screen_name_index()?;
+    let mut stats = MergeStats::default();
+    let mut batch = WriteBatch::default();
+    // Each snapshot adds two operations to the batch, so we count snapshots separately.
+    let mut batch_snapshot_count = 0;
+    let mut key_count: u64 = 0;
+
+    for result in source.db.iterator(IteratorMode::Start) {
+        let (key, value) = result?;
+
+        match target.db.get(&key)? {
+            // Values written with an earlier schema version may differ only in their encoding.
+            Some(existing)
+                if existing == value.as_ref()
+                    || parse_value(&existing)? == parse_value(&value)? =>
+            {
+                stats.skipped += 1;
+            }
+            Some(_) => {
+                stats.conflicts.push(key_to_pair(&key)?);
+            }
+            None => {
+                let user = parse_value(&value)?;
+
+                batch.put_cf(index, screen_name_index::user_index_key(&user), b"");
+                batch.put(&key, &value);
+                stats.copied += 1;
+                batch_snapshot_count += 1;
+
+                if batch_snapshot_count >= batch_size {
+                    target.db.write(std::mem::take(&mut batch))?;
+                    batch_snapshot_count = 0;
+                }
+            }
+        }
+
+        key_count += 1;
+
+        if key_count.is_multiple_of(PROGRESS_INTERVAL) {
+            log::info!(
+                "Merged {} keys ({} copied, {} skipped, {} conflicts)",
+                key_count,
+                stats.copied,
+                stats.skipped,
+                stats.conflicts.len()
+            );
+        }
+    }
+
+    target.db.write(batch)?;
+
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use hst_tw_profiles::model::User;
+    use std::path::Path;
+
+    fn user(id: i64, screen_name: &str, snapshot: i64) -> User {
+        User {
+            id,
+            id_str: id.to_string(),
+            screen_name: screen_name.to_string(),
+            snapshot,
+            ..User::default()
+        }
+    }
+
+    fn source_users() -> Vec<User> {
+        (1..=5)
+            .flat_map(|id| {
+                (1..=3).map(move |snapshot| user(id, &format!("user_{}", id), snapshot * 100))
+            })
+            .collect()
+    }
+
+    fn source<P: AsRef<Path>>(path: P) -> ProfileDb<ReadOnly> {
+        ProfileDb::<Writeable>::open(&path, false)
+            .unwrap()
+            .update_batch(&source_users())
+            .unwrap();
+
+        ProfileDb::open(&path, false).unwrap()
+    }
+
+    #[test]
+    fn merge_copies_missing_snapshots() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = source(dir.path().join("source"));
+
+        for batch_size in [1, 2, 4, 13, 100] {
+            let target =
+                ProfileDb::<Writeable>::open(dir.path().join(batch_size.to_string()), false)
+                    .unwrap();
+
+            // The same snapshot, a conflicting one, and one that isn't in the source.
+            target
+                .update_batch(&[
+                    user(1, "user_1", 100),
+                    user(2, "renamed", 200),
+                    user(6, "user_6", 100),
+                ])
+                .unwrap();
+
+            let stats = merge_batched(&source, &target, batch_size).unwrap();
+
+            assert_eq!(stats.copied, 13);
+            assert_eq!(stats.skipped, 1);
+            assert_eq!(
+                stats.conflicts,
+                vec![(2, Utc.timestamp_opt(200, 0).single().unwrap())]
+            );
+            assert_eq!(target.raw_iter().count(), 16);
+            assert_eq!(target.lookup(2).unwrap()[1].1.screen_name, "renamed");
+
+            for id in 1..=5 {
+                assert_eq!(
+                    target.lookup_screen_name(&format!("USER_{}", id)).unwrap(),
+                    vec![id]
+                );
+            }
+
+            assert_eq!(target.lookup_screen_name("renamed").unwrap(), vec![2]);
+        }
+    }
+
+    #[test]
+    fn merge_skips_v1_values() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = source(dir.path().join("source"));
+        let target = ProfileDb::<Writeable>::open(dir.path().join("target"), false).unwrap();
+        let user = user(1, "user_1", 100);
+
+        target
+            .db
+            .put(
+                crate::pair_to_key(1, Utc.timestamp_opt(100, 0).single().unwrap()).unwrap(),
+                crate::tests::user_to_v1_bytes(&user),
+            )
+            .unwrap();
+
+        let stats = merge(&source, &target).unwrap();
+
+        assert_eq!(stats.copied, 14);
+        assert_eq!(stats.skipped, 1);
+        assert!(stats.conflicts.is_empty());
+    }
+
+    #[test]
+    fn merge_into_empty_target() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = source(dir.path().join("source"));
+        let target = ProfileDb::<Writeable>::open(dir.path().join("target"), false).unwrap();
+
+        let stats = merge(&source, &target).unwrap();
+
+        assert_eq!(stats.copied, 15);
+        assert_eq!(stats.skipped, 0);
+        assert!(stats.conflicts.is_empty());
+
+        let stats = merge(&source, &target).unwrap();
+
+        assert_eq!(stats.copied, 0);
+        assert_eq!(stats.skipped, 15);
+    }
+}
@@ -0,0 +1,396 @@
+//! Self-contained SQLite exports of the snapshots for a set of accounts.
+//!
+//! Users are added one at a time, so memory use is bounded by the history of a single account.
+
+use super::Error;
+use chrono::{DateTime, Utc};
+use hst_tw_profiles::model::User;
+use rusqlite::{params, Connection};
+use std::collections::HashMap;
+use std::path::Path;
+
+const SCHEMA: &str = "
+    PRAGMA user_version = 1;
+    CREATE TABLE profiles (
+        user_id INTEGER NOT NULL,
+        snapshot INTEGER NOT NULL,
+        screen_name TEXT NOT NULL,
+        name TEXT NOT NULL,
+        description TEXT,
+        location TEXT,
+        url TEXT,
+        followers_count INTEGER NOT NULL,
+        friends_count INTEGER NOT NULL,
+        statuses_count INTEGER NOT NULL,
+        verified INTEGER NOT NULL,
+        protected INTEGER NOT NULL,
+        created_at TEXT NOT NULL,
+        profile_image_url TEXT NOT NULL,
+        json TEXT,
+        PRIMARY KEY (user_id, snapshot)
+    );
+    CREATE TABLE screen_names (
+        user_id INTEGER NOT NULL,
+        screen_name TEXT NOT NULL,
+        first_seen INTEGER NOT NULL,
+        last_seen INTEGER NOT NULL,
+        PRIMARY KEY (user_id, screen_name)
+    );
+    CREATE TABLE deactivations (
+        user_id INTEGER NOT NULL,
+        status INTEGER NOT NULL,
+        observed INTEGER NOT NULL,
+        reversal INTEGER
+    );
+    CREATE INDEX deactivations_user_id ON deactivations (user_id);
+    CREATE TABLE metadata (
+        name TEXT PRIMARY KEY,
+        value TEXT NOT NULL
+    );
+";
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct PackCounts {
+    pub user_count: usize,
+    pub snapshot_count: usize,
+    pub deactivation_count: usize,
+}
+
+pub struct ResearchPack {
+    connection: Connection,
+    include_json: bool,
+    counts: PackCounts,
+}
+
+impl ResearchPack {
+    /// Create a pack at a new path (fails if the tables already exist).
+    pub fn create<P: AsRef<Path>>(path: P, include_json: bool) -> Result<Self, Error> {
+        let connection = Connection::open(path)?;
+        connection.execute_batch(SCHEMA)?;
+
+        Ok(Self {
+            connection,
+            include_json,
+            counts: PackCounts::default(),
+        })
+    }
+
+    /// Add every snapshot for a user, along with their screen name history.
+    pub fn add_user(
+        &mut self,
+        user_id: u64,
+        snapshots: &[(DateTime<Utc>, User)],
+    ) -> Result<(), Error> {
+        let transaction = self.connection.transaction()?;
+        let mut screen_names: HashMap<&str, (i64, i64)> = HashMap::new();
+
+        {
+            let mut insert = transaction.prepare(
+                "INSERT OR REPLACE INTO profiles VALUES
+                    (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)",
+            )?;
+
+            for (snapshot, user) in snapshots {
+                let timestamp = snapshot.timestamp();
+                let json = if self.include_json {
+                    Some(serde_json::to_string(user)?)
+                } else {
+                    None
+                };
+
+                insert.execute(params![
+                    user_id as i64,
+                    timestamp,
+                    user.screen_name,
+                    user.name,
+                    user.description,
+                    user.location,
+                    user.expanded_url(),
+                    user.followers_count,
+                    user.friends_count,
+                    user.statuses_count,
+                    user.verified,
+                    user.protected,
+                    user.created_at,
+                    user.profile_image_url_https,
+                    json
+                ])?;
+
+                let seen = screen_names
+                    .entry(&user.screen_name)
+                    .or_insert((timestamp, timestamp));
+                seen.0 = seen.0.min(timestamp);
+                seen.1 = seen.1.max(timestamp);
+            }
+
+            let mut insert = transaction
+                .prepare("INSERT OR REPLACE INTO screen_names VALUES (?1, ?2, ?3, ?4)")?;
+
+            for (screen_name, (first_seen, last_seen)) in screen_names {
+                insert.execute(params![user_id as i64, screen_name, first_seen, last_seen])?;
+            }
+        }
+
+        transaction.commit()?;
+
+        if !snapshots.is_empty() {
+            self.counts.user_count += 1;
+        }
+        self.counts.snapshot_count += snapshots.len();
+
+        Ok(())
+    }
+
+    /// Add deactivation entries (status, observation time, and reversal time) for a user.
+    pub fn add_deactivations<
+        I: IntoIterator<Item = (u32, DateTime<Utc>, Option<DateTime<Utc>>)>,
+    >(
+        &mut self,
+        user_id: u64,
+        entries: I,
+    ) -> Result<(), Error> {
+        let transaction = self.connection.transaction()?;
+        let mut count = 0;
+
+        {
+            let mut insert =
+                transaction.prepare_cached("INSERT INTO deactivations VALUES (?1, ?2, ?3, ?4)")?;
+
+            for (status, observed, reversal) in entries {
+                insert.execute(params![
+                    user_id as i64,
+                    status,
+                    observed.timestamp(),
+                    reversal.map(|reversal| reversal.timestamp())
+                ])?;
+                count += 1;
+            }
+        }
+
+        transaction.commit()?;
+        self.counts.deactivation_count += count;
+
+        Ok(())
+    }
+
+    pub fn counts(&self) -> PackCounts {
+        self.counts
+    }
+
+    /// Write the metadata table (the counts and generation time are always included).
+    pub fn finish(self, metadata: &[(&str, String)]) -> Result<PackCounts, Error> {
+        let generated_at = Utc::now().to_rfc3339();
+        let counts = [
+            ("generated_at", generated_at),
+            ("user_count", self.counts.user_count.to_string()),
+            ("snapshot_count", self.counts.snapshot_count.to_string()),
+            (
+                "deactivation_count",
+                self.counts.deactivation_count.to_string(),
+            ),
+        ];
+
+        let mut insert = self
+            .connection
+            .prepare("INSERT OR REPLACE INTO metadata VALUES (?1, ?2)")?;
+
+        for (name, value) in counts.iter().chain(metadata) {
+            insert.execute(params![name, value])?;
+        }
+
+        Ok(self.counts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn timestamp(value: i64) -> DateTime<Utc> {
+        Utc.timestamp_opt(value, 0).single().unwrap()
+    }
+
+    fn snapshot(screen_name: &str, followers_count: i64, value: i64) -> (DateTime<Utc>, User) {
+        (
+            timestamp(value),
+            User {
+                id: 1,
+                id_str: "1".to_string(),
+                screen_name: screen_name.to_string(),
+                followers_count,
+                snapshot: value,
+                ..User::default()
+            },
+        )
+    }
+
+    fn pack(path: &Path, include_json: bool) -> PackCounts {
+        let mut pack = ResearchPack::create(path, include_json).unwrap();
+
+        pack.add_user(
+            1,
+            &[
+                snapshot("first", 10, 100),
+                snapshot("second", 20, 200),
+                snapshot("first", 30, 300),
+            ],
+        )
+        .unwrap();
+        // Users without snapshots aren't counted.
+        pack.add_user(2, &[]).unwrap();
+        pack.add_deactivations(1, vec![(50, timestamp(150), Some(timestamp(250)))])
+            .unwrap();
+        pack.add_deactivations(2, vec![(63, timestamp(400), None)])
+            .unwrap();
+
+        assert_eq!(pack.counts().snapshot_count, 3);
+
+        pack.finish(&[("source", "test".to_string())]).unwrap()
+    }
+
+    #[test]
+    fn create_and_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pack.db");
+        let counts = pack(&path, true);
+
+        assert_eq!(
+            counts,
+            PackCounts {
+                user_count: 1,
+                snapshot_count: 3,
+                deactivation_count: 2,
+            }
+        );
+
+        let connection = Connection::open(&path).unwrap();
+        let profiles = connection
+            .prepare("SELECT snapshot, screen_name, followers_count, json FROM profiles ORDER BY snapshot")
+            .unwrap()
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, i64>(2)?,
+                    row.get::<_, Option<String>>(3)?,
+                ))
+            })
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        assert_eq!(profiles.len(), 3);
+        assert_eq!(
+            (profiles[1].0, profiles[1].1.as_str(), profiles[1].2),
+            (200, "second", 20)
+        );
+
+        let json = serde_json::from_str::<User>(profiles[2].3.as_ref().unwrap()).unwrap();
+
+        assert_eq!(json, snapshot("first", 30, 300).1);
+
+        let screen_names = connection
+            .prepare(
+                "SELECT screen_name, first_seen, last_seen FROM screen_names ORDER BY first_seen",
+            )
+            .unwrap()
+            .query_map([], |row| {
+                Ok((row.get::<_, String>(0)?, row.get(1)?, row.get(2)?))
+            })
+            .unwrap()
+            .collect::<Result<Vec<(String, i64, i64)>, _>>()
+            .unwrap();
+
+        assert_eq!(
+            screen_names,
+            vec![
+                ("first".to_string(), 100, 300),
+                ("second".to_string(), 200, 200)
+            ]
+        );
+
+        let deactivations = connection
+            .prepare(
+                "SELECT user_id, status, observed, reversal FROM deactivations ORDER BY user_id",
+            )
+            .unwrap()
+            .query_map([], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+            })
+            .unwrap()
+            .collect::<Result<Vec<(i64, u32, i64, Option<i64>)>, _>>()
+            .unwrap();
+
+        assert_eq!(
+            deactivations,
+            vec![(1, 50, 150, Some(250)), (2, 63, 400, None)]
+        );
+
+        let metadata = connection
+            .prepare("SELECT name, value FROM metadata WHERE name != 'generated_at' ORDER BY name")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<Result<Vec<(String, String)>, _>>()
+            .unwrap();
+
+        assert_eq!(
+            metadata,
+            vec![
+                ("deactivation_count".to_string(), "2".to_string()),
+                ("snapshot_count".to_string(), "3".to_string()),
+                ("source".to_string(), "test".to_string()),
+                ("user_count".to_string(), "1".to_string()),
+            ]
+        );
+
+        let generated_at = connection
+            .query_row(
+                "SELECT value FROM metadata WHERE name = 'generated_at'",
+                [],
+                |row| row.get::<_, String>(0),
+            )
+            .unwrap();
+
+        assert!(DateTime::parse_from_rfc3339(&generated_at).is_ok());
+
+        let user_version = connection
+            .query_row("PRAGMA user_version", [], |row| row.get::<_, u32>(0))
+            .unwrap();
+
+        assert_eq!(user_version, 1);
+    }
+
+    #[test]
+    fn without_json() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pack.db");
+
+        pack(&path, false);
+
+        let json_count = Connection::open(&path)
+            .unwrap()
+            .query_row(
+                "SELECT COUNT(*) FROM profiles WHERE json IS NOT NULL",
+                [],
+                |row| row.get::<_, i64>(0),
+            )
+            .unwrap();
+
+        assert_eq!(json_count, 0);
+    }
+
+    #[test]
+    fn create_fails_for_existing_pack() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pack.db");
+
+        pack(&path, false);
+
+        assert!(matches!(
+            ResearchPack::create(&path, false),
+            Err(Error::Sqlite(_))
+        ));
+    }
+}
@@ -0,0 +1,151 @@
+//! Removal of snapshots that don't differ from the previous snapshot for the user.
+
+use super::{pair_to_key, table::Writeable, Error, ProfileDb};
+use hst_tw_profiles::model::User;
+use rocksdb::WriteBatch;
+use serde_json::{Map, Value};
+
+/// Fields that are always ignored when comparing snapshots.
+const ALWAYS_IGNORED_FIELDS: [&str; 1] = ["snapshot"];
+
+impl ProfileDb<Writeable> {
+    /// Delete every snapshot that is identical to the previous retained snapshot for the user
+    /// (ignoring the snapshot timestamp and the given fields), returning the number deleted.
+    ///
+    /// The first and last snapshots for each user are always retained, so the observed range is
+    /// preserved. Deletions are written in one batch per user, so interrupting this is safe. If
+    /// `dry_run` is set, nothing is deleted, but the count is still returned.
+    pub fn prune_unchanged(&self, ignored_fields: &[&str], dry_run: bool) -> Result<u64, Error> {
+        let known_fields = to_object(&User::default())?;
+
+        if let Some(field) = ignored_fields
+            .iter()
+            .find(|field| !known_fields.contains_key(**field))
+        {
+            return Err(Error::UnknownField(field.to_string()));
+        }
+
+        let mut count = 0;
+
+        for result in self.iter() {
+            let (user_id, users) = result?;
+
+            if users.len() < 3 {
+                continue;
+            }
+
+            let mut batch = WriteBatch::default();
+            let mut retained = comparable(&users[0].1, ignored_fields)?;
+
+            for (snapshot, user) in &users[1..users.len() - 1] {
+                let current = comparable(user, ignored_fields)?;
+
+                if current == retained {
+                    batch.delete(pair_to_key(user_id, *snapshot)?);
+                    count += 1;
+                } else {
+                    retained = current;
+                }
+            }
+
+            if !dry_run {
+                self.db.write(batch)?;
+            }
+        }
+
+        Ok(count)
+    }
+}
+
+fn to_object(user: &User) -> Result<Map<String, Value>, Error> {
+    match serde_json::to_value(user)? {
+        Value::Object(fields) => Ok(fields),
+        // Users are always serialized as objects.
+        _ => Ok(Map::new()),
+    }
+}
+
+fn comparable(user: &User, ignored_fields: &[&str]) -> Result<Map<String, Value>, Error> {
+    let mut fields = to_object(user)?;
+
+    for field in ALWAYS_IGNORED_FIELDS.iter().chain(ignored_fields) {
+        fields.remove(*field);
+    }
+
+    Ok(fields)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(id: i64, snapshot: i64, name: &str, followers_count: i64) -> User {
+        User {
+            id,
+            id_str: id.to_string(),
+            screen_name: format!("user_{}", id),
+            name: name.to_string(),
+            followers_count,
+            snapshot,
+            ..User::default()
+        }
+    }
+
+    fn snapshots(db: &ProfileDb<Writeable>, id: u64) -> Vec<i64> {
+        db.lookup(id)
+            .unwrap()
+            .into_iter()
+            .map(|(snapshot, _)| snapshot.timestamp())
+            .collect()
+    }
+
+    #[test]
+    fn prune_unchanged() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = ProfileDb::<Writeable>::open(dir.path().join("db"), false).unwrap();
+
+        db.update_batch(&[
+            // A single snapshot.
+            user(1, 100, "a", 0),
+            // Alternating versions.
+            user(2, 100, "a", 0),
+            user(2, 200, "b", 0),
+            user(2, 300, "a", 0),
+            user(2, 400, "b", 0),
+            user(2, 500, "a", 0),
+            // Runs of identical versions.
+            user(3, 100, "a", 0),
+            user(3, 200, "a", 1),
+            user(3, 300, "a", 2),
+            user(3, 400, "b", 2),
+            user(3, 500, "b", 2),
+            user(3, 600, "b", 2),
+        ])
+        .unwrap();
+
+        assert_eq!(db.prune_unchanged(&[], true).unwrap(), 1);
+        assert_eq!(snapshots(&db, 3).len(), 6);
+
+        assert_eq!(db.prune_unchanged(&["followers_count"], true).unwrap(), 3);
+        assert_eq!(db.prune_unchanged(&[], false).unwrap(), 1);
+        assert_eq!(snapshots(&db, 1), vec![100]);
+        assert_eq!(snapshots(&db, 2), vec![100, 200, 300, 400, 500]);
+        assert_eq!(snapshots(&db, 3), vec![100, 200, 300, 400, 600]);
+
+        // The first and last snapshots are kept even when the field is ignored.
+        assert_eq!(db.prune_unchanged(&["followers_count"], false).unwrap(), 2);
+        assert_eq!(snapshots(&db, 3), vec![100, 400, 600]);
+        assert_eq!(db.prune_unchanged(&["followers_count"], false).unwrap(), 0);
+    }
+
+    #[test]
+    fn prune_unknown_field() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = ProfileDb::<Writeable>::open(dir.path().join("db"), false).unwrap();
+
+        assert!(matches!(
+            db.prune_unchanged(&["followers"], false),
+            Err(Error::UnknownField(field)) if field == "followers"
+        ));
+    }
+}
@@ -0,0 +1,270 @@
+//! Secondary index from screen names to user IDs.
+//!
+//! The index is stored in a separate column family, with keys made up of the normalized screen
+//! name, a zero byte, and the big-endian user ID (and empty values). Every screen name that has
+//! been observed for a user is indexed, not only the most recent one.
+//!
+//! The column family also contains a marker recording that the index covers every snapshot in
+//! the database. It's set when an empty database is opened for writing and after a rebuild, and
+//! lookups fail without it (for example in databases created before the index was added).
+
+use super::{key_to_pair, parse_value, table::Writeable, Error, ProfileDb};
+use hst_tw_profiles::{model::User, names::normalize_screen_name};
+use rocksdb::{ColumnFamily, Direction, IteratorMode, WriteBatch};
+
+/// Name of the column family containing the index.
+pub const SCREEN_NAME_INDEX_CF: &str = "screen_names";
+
+/// Number of snapshots indexed per batch when rebuilding.
+const REBUILD_BATCH_SIZE: usize = 10_000;
+
+/// Key of the built marker (normalized screen names never start with a zero byte).
+const BUILT_MARKER_KEY: &[u8] = b"\0built";
+
+impl<M> ProfileDb<M> {
+    /// Indicates whether the index has been built for every snapshot in the database.
+    pub fn is_screen_name_index_built(&self) -> Result<bool, Error> {
+        let index = self.screen_name_index()?;
+
+        Ok(self.db.get_cf(index, BUILT_MARKER_KEY)?.is_some())
+    }
+
+    /// Find the IDs of every user who has had this screen name (ignoring case).
+    pub fn lookup_screen_name(&self, screen_name: &str) -> Result<Vec<u64>, Error> {
+        let mut prefix = normalize_screen_name(screen_name).into_bytes();
+        prefix.push(0);
+
+        self.scan_screen_names(&prefix)
+    }
+
+    /// Find the IDs of every user who has had a screen name starting with this prefix.
+    pub fn lookup_screen_name_prefix(&self, prefix: &str) -> Result<Vec<u64>, Error> {
+        self.scan_screen_names(normalize_screen_name(prefix).as_bytes())
+    }
+
+    /// Mark the index as built if the database is empty (so that it will be maintained for every
+    /// snapshot).
+    pub(crate) fn mark_empty_screen_name_index(&self) -> Result<(), Error> {
+        if self.db.iterator(IteratorMode::Start).next().is_none() {
+            self.db
+                .put_cf(self.screen_name_index()?, BUILT_MARKER_KEY, b"")?;
+        }
+
+        Ok(())
+    }
+
+    fn scan_screen_names(&self, prefix: &[u8]) -> Result<Vec<u64>, Error> {
+        if !self.is_screen_name_index_built()? {
+            return Err(Error::MissingScreenNameIndex);
+        }
+
+        let index = self.screen_name_index()?;
+        let mut user_ids = vec![];
+
+        for result in self
+            .db
+            .iterator_cf(index, IteratorMode::From(prefix, Direction::Forward))
+        {
+            let (key, _) = result?;
+
+            if !key.starts_with(prefix) {
+                break;
+            }
+
+            if &*key == BUILT_MARKER_KEY {
+                continue;
+            }
+
+            user_ids.push(index_key_user_id(&key)?);
+        }
+
+        user_ids.sort_unstable();
+        user_ids.dedup();
+
+        Ok(user_ids)
+    }
+
+    pub(crate) fn screen_name_index(&self) -> Result<&ColumnFamily, Error> {
+        self.db
+            .cf_handle(SCREEN_NAME_INDEX_CF)
+            .ok_or(Error::MissingScreenNameIndex)
+    }
+}
+
+impl ProfileDb<Writeable> {
+    /// Index every screen name in the database (for databases created without the index),
+    /// returning the number of snapshots indexed.
+    pub fn rebuild_screen_name_index(&self) -> Result<usize, Error> {
+        self.rebuild_screen_name_index_batched(REBUILD_BATCH_SIZE)
+    }
+
+    fn rebuild_screen_name_index_batched(&self, batch_size: usize) -> Result<usize, Error> {
+        let index = self.screen_name_index()?;
+        let mut batch = WriteBatch::default();
+        let mut batch_snapshot_count = 0;
+        let mut count = 0;
+
+        for result in self.db.iterator(IteratorMode::Start) {
+            let (key, value) = result?;
+            let (user_id, _) = key_to_pair(&key)?;
+            let user = parse_value(value)?;
+
+            batch.put_cf(index, index_key(&user.screen_name, user_id), b"");
+            batch_snapshot_count += 1;
+            count += 1;
+
+            if batch_snapshot_count >= batch_size {
+                self.db.write(std::mem::take(&mut batch))?;
+                batch_snapshot_count = 0;
+            }
+        }
+
+        batch.put_cf(index, BUILT_MARKER_KEY, b"");
+        self.db.write(batch)?;
+
+        Ok(count)
+    }
+}
+
+pub(crate) fn user_index_key(user: &User) -> Vec<u8> {
+    index_key(&user.screen_name, user.id())
+}
+
+fn index_key(screen_name: &str, user_id: u64) -> Vec<u8> {
+    let mut key = normalize_screen_name(screen_name).into_bytes();
+    key.push(0);
+    key.extend_from_slice(&user_id.to_be_bytes());
+    key
+}
+
+fn index_key_user_id(key: &[u8]) -> Result<u64, Error> {
+    key.len()
+        .checked_sub(8)
+        .and_then(|start| key[start..].try_into().ok())
+        .map(u64::from_be_bytes)
+        .ok_or_else(|| Error::InvalidKeyBytes(key.to_vec()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+
+    fn user(id: i64, screen_name: &str, snapshot: i64) -> User {
+        User {
+            id,
+            id_str: id.to_string(),
+            screen_name: screen_name.to_string(),
+            snapshot,
+            ..User::default()
+        }
+    }
+
+    #[test]
+    fn rebuild_screen_name_index() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = ProfileDb::<Writeable>::open(dir.path().join("db"), false).unwrap();
+        let users = (1..=7)
+            .flat_map(|id| {
+                (1..=3).map(move |snapshot| user(id, &format!("name_{}", id % 3), snapshot))
+            })
+            .collect::<Vec<_>>();
+
+        db.update_batch(&users).unwrap();
+
+        for batch_size in [1, 2, 5, 21, 100] {
+            let index = db.screen_name_index().unwrap();
+            let mut batch = WriteBatch::default();
+
+            for result in db.db.iterator_cf(index, IteratorMode::Start) {
+                batch.delete_cf(index, result.unwrap().0);
+            }
+
+            db.db.write(batch).unwrap();
+
+            assert!(!db.is_screen_name_index_built().unwrap());
+            assert!(matches!(
+                db.lookup_screen_name_prefix("name"),
+                Err(Error::MissingScreenNameIndex)
+            ));
+            assert_eq!(
+                db.rebuild_screen_name_index_batched(batch_size).unwrap(),
+                21
+            );
+            assert_eq!(db.lookup_screen_name("NAME_0").unwrap(), vec![3, 6]);
+            assert_eq!(db.lookup_screen_name("name_1").unwrap(), vec![1, 4, 7]);
+            assert_eq!(
+                db.lookup_screen_name_prefix("name_").unwrap(),
+                (1..=7).collect::<Vec<_>>()
+            );
+            assert_eq!(
+                db.lookup_screen_name_prefix("").unwrap(),
+                (1..=7).collect::<Vec<_>>()
+            );
+        }
+    }
+
+    #[test]
+    fn unbuilt_index() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db");
+
+        // A database created before the index was added.
+        {
+            let db = rocksdb::DB::open_default(&path).unwrap();
+            let snapshot = Utc.timestamp_opt(100, 0).single().unwrap();
+
+            db.put(
+                crate::pair_to_key(1, snapshot).unwrap(),
+                crate::user_to_bytes(&user(1, "a", 100)).unwrap(),
+            )
+            .unwrap();
+        }
+
+        let db = ProfileDb::<Writeable>::open(&path, false).unwrap();
+
+        assert!(!db.is_screen_name_index_built().unwrap());
+        assert!(matches!(
+            db.lookup_screen_name("a"),
+            Err(Error::MissingScreenNameIndex)
+        ));
+
+        assert_eq!(db.rebuild_screen_name_index().unwrap(), 1);
+        assert_eq!(db.lookup_screen_name("a").unwrap(), vec![1]);
+        drop(db);
+
+        // The marker is kept when the database is reopened.
+        let db = ProfileDb::<crate::table::ReadOnly>::open(&path, false).unwrap();
+
+        assert!(db.is_screen_name_index_built().unwrap());
+    }
+
+    #[test]
+    fn new_databases_are_built() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = ProfileDb::<Writeable>::open(dir.path().join("db"), false).unwrap();
+
+        assert!(db.is_screen_name_index_built().unwrap());
+        assert!(db.lookup_screen_name("a").unwrap().is_empty());
+    }
+
+    #[test]
+    fn updates_keep_old_screen_names() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = ProfileDb::<Writeable>::open(dir.path().join("db"), false).unwrap();
+
+        db.update(&user(1, "a", 100)).unwrap();
+        assert_eq!(db.lookup_screen_name("a").unwrap(), vec![1]);
+        assert!(db.lookup_screen_name("b").unwrap().is_empty());
+
+        db.update(&user(1, "b", 200)).unwrap();
+        assert_eq!(db.lookup_screen_name("a").unwrap(), vec![1]);
+        assert_eq!(db.lookup_screen_name("b").unwrap(), vec![1]);
+
+        db.update_batch(&[user(2, "c", 100), user(2, "D", 200), user(3, "c", 300)])
+            .unwrap();
+        assert_eq!(db.lookup_screen_name("c").unwrap(), vec![2, 3]);
+        assert_eq!(db.lookup_screen_name("d").unwrap(), vec![2]);
+        assert_eq!(db.lookup_screen_name("a").unwrap(), vec![1]);
+    }
+}
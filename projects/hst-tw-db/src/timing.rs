@@ -0,0 +1,248 @@
+//! Lightweight per-operation latency histograms.
+//!
+//! Durations are counted in fixed power-of-two microsecond buckets, so recording is a few atomic
+//! increments, and percentiles are estimated by interpolating within the containing bucket.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Bucket `i` (for `i > 0`) holds durations of at least `2^(i - 1)` and less than `2^i`
+/// microseconds, with the last bucket also holding everything longer.
+const BUCKET_COUNT: usize = 32;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct OpTiming {
+    pub op: &'static str,
+    pub count: u64,
+    pub total: Duration,
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+    /// Number of durations that exceeded the slow threshold (when recorded by [`Timings`]).
+    pub slow_count: u64,
+}
+
+pub struct Histogram {
+    buckets: [AtomicU64; BUCKET_COUNT],
+    total_micros: AtomicU64,
+    max_micros: AtomicU64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            buckets: std::array::from_fn(|_| AtomicU64::new(0)),
+            total_micros: AtomicU64::new(0),
+            max_micros: AtomicU64::new(0),
+        }
+    }
+}
+
+impl Histogram {
+    pub fn record(&self, duration: Duration) {
+        let micros = duration.as_micros().min(u64::MAX as u128) as u64;
+
+        self.buckets[bucket_index(micros)].fetch_add(1, Ordering::Relaxed);
+        self.total_micros.fetch_add(micros, Ordering::Relaxed);
+        self.max_micros.fetch_max(micros, Ordering::Relaxed);
+    }
+
+    /// Summarize the recorded durations and reset the histogram.
+    pub fn take(&self, op: &'static str) -> OpTiming {
+        let counts = self
+            .buckets
+            .iter()
+            .map(|bucket| bucket.swap(0, Ordering::Relaxed))
+            .collect::<Vec<_>>();
+        let total_micros = self.total_micros.swap(0, Ordering::Relaxed);
+        let max_micros = self.max_micros.swap(0, Ordering::Relaxed);
+
+        let estimate =
+            |quantile| Duration::from_micros(percentile(&counts, quantile).min(max_micros));
+
+        OpTiming {
+            op,
+            count: counts.iter().sum(),
+            total: Duration::from_micros(total_micros),
+            p50: estimate(0.5),
+            p90: estimate(0.9),
+            p99: estimate(0.99),
+            max: Duration::from_micros(max_micros),
+            slow_count: 0,
+        }
+    }
+}
+
+/// Histograms for a fixed set of operations, with a threshold for reporting slow operations.
+pub struct Timings {
+    ops: Vec<(&'static str, Histogram, AtomicU64)>,
+    slow_threshold: Duration,
+}
+
+impl Timings {
+    pub fn new(ops: &[&'static str], slow_threshold: Duration) -> Self {
+        Self {
+            ops: ops
+                .iter()
+                .map(|op| (*op, Histogram::default(), AtomicU64::new(0)))
+                .collect(),
+            slow_threshold,
+        }
+    }
+
+    /// Record a duration for the operation, returning `true` if it exceeds the slow threshold.
+    pub fn record(&self, op: &str, duration: Duration) -> bool {
+        let slow = duration > self.slow_threshold;
+
+        if let Some((_, histogram, slow_count)) = self.ops.iter().find(|(name, _, _)| *name == op) {
+            histogram.record(duration);
+
+            if slow {
+                slow_count.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        slow
+    }
+
+    /// Summarize and reset the histograms and slow counts for every operation.
+    pub fn report(&self) -> Vec<OpTiming> {
+        self.ops
+            .iter()
+            .map(|(op, histogram, slow_count)| OpTiming {
+                slow_count: slow_count.swap(0, Ordering::Relaxed),
+                ..histogram.take(op)
+            })
+            .collect()
+    }
+}
+
+fn bucket_index(micros: u64) -> usize {
+    ((u64::BITS - micros.leading_zeros()) as usize).min(BUCKET_COUNT - 1)
+}
+
+fn bucket_bounds(index: usize) -> (u64, u64) {
+    if index == 0 {
+        (0, 1)
+    } else {
+        (1 << (index - 1), 1 << index)
+    }
+}
+
+/// Estimate the given quantile (in microseconds) by linear interpolation within its bucket.
+fn percentile(counts: &[u64], quantile: f64) -> u64 {
+    let total = counts.iter().sum::<u64>();
+
+    if total == 0 {
+        return 0;
+    }
+
+    let rank = quantile * total as f64;
+    let mut seen = 0;
+
+    for (index, count) in counts.iter().enumerate() {
+        if *count > 0 && (seen + count) as f64 >= rank {
+            let (lower, upper) = bucket_bounds(index);
+            let fraction = (rank - seen as f64) / *count as f64;
+
+            return lower + (fraction * (upper - lower) as f64) as u64;
+        }
+
+        seen += count;
+    }
+
+    bucket_bounds(BUCKET_COUNT - 1).1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bucket_boundaries() {
+        assert_eq!(bucket_index(0), 0);
+        assert_eq!(bucket_index(1), 1);
+        assert_eq!(bucket_index(2), 2);
+        assert_eq!(bucket_index(3), 2);
+        assert_eq!(bucket_index(4), 3);
+        assert_eq!(bucket_index(1023), 10);
+        assert_eq!(bucket_index(1024), 11);
+        assert_eq!(bucket_index(u64::MAX), BUCKET_COUNT - 1);
+
+        for index in 0..BUCKET_COUNT - 1 {
+            let (lower, upper) = bucket_bounds(index);
+
+            assert_eq!(bucket_index(lower), index);
+            assert_eq!(bucket_index(upper - 1), index);
+            assert_eq!(bucket_index(upper), index + 1);
+        }
+    }
+
+    #[test]
+    fn percentile_interpolation() {
+        let mut counts = vec![0; BUCKET_COUNT];
+
+        assert_eq!(percentile(&counts, 0.5), 0);
+
+        // Ten durations in [64, 128) microseconds.
+        counts[7] = 10;
+
+        assert_eq!(percentile(&counts, 0.5), 96);
+        assert_eq!(percentile(&counts, 0.9), 121);
+        assert_eq!(percentile(&counts, 1.0), 128);
+
+        // Ninety more in [1, 2).
+        counts[1] = 90;
+
+        assert_eq!(percentile(&counts, 0.5), 1);
+        assert_eq!(percentile(&counts, 0.99), 121);
+    }
+
+    #[test]
+    fn take_resets() {
+        let histogram = Histogram::default();
+
+        for micros in [100, 100, 100, 5000] {
+            histogram.record(Duration::from_micros(micros));
+        }
+
+        let timing = histogram.take("lookup");
+
+        assert_eq!(timing.op, "lookup");
+        assert_eq!(timing.count, 4);
+        assert_eq!(timing.total, Duration::from_micros(5300));
+        assert_eq!(timing.max, Duration::from_micros(5000));
+        assert!(timing.p50 >= Duration::from_micros(64) && timing.p50 < Duration::from_micros(128));
+        // Estimates are capped at the maximum.
+        assert_eq!(timing.p99, Duration::from_micros(5000));
+
+        let empty = histogram.take("lookup");
+
+        assert_eq!(empty.count, 0);
+        assert_eq!(empty.total, Duration::ZERO);
+        assert_eq!(empty.max, Duration::ZERO);
+    }
+
+    #[test]
+    fn slow_threshold() {
+        let timings = Timings::new(&["lookup", "update"], Duration::from_millis(10));
+
+        assert!(!timings.record("lookup", Duration::from_millis(10)));
+        assert!(timings.record("lookup", Duration::from_millis(11)));
+        assert!(!timings.record("update", Duration::from_millis(1)));
+        // Unknown operations are not recorded, but are still checked against the threshold.
+        assert!(timings.record("unknown", Duration::from_millis(20)));
+
+        let report = timings.report();
+
+        assert_eq!(report.len(), 2);
+        assert_eq!(report[0].op, "lookup");
+        assert_eq!(report[0].count, 2);
+        assert_eq!(report[0].slow_count, 1);
+        assert_eq!(report[1].op, "update");
+        assert_eq!(report[1].count, 1);
+        assert_eq!(report[1].slow_count, 0);
+        assert_eq!(timings.report()[0].slow_count, 0);
+    }
+}
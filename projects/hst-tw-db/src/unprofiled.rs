@@ -0,0 +1,152 @@
+//! Deactivated accounts for which there are no profile snapshots.
+//!
+//! These accounts drop out of anything joined against the profile database, so they need to be
+//! listed separately to avoid undercounting.
+
+use super::{Error, ProfileDb};
+use hst_deactivations::{DeactivationLog, Entry};
+use std::io::Write;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct UnprofiledAccount {
+    pub id: u64,
+    /// Entries matching the status filter.
+    pub entries: Vec<Entry>,
+}
+
+/// List accounts in the log (optionally only with the given status) that have no snapshots, in
+/// order of user ID.
+pub fn unprofiled_deactivations<M>(
+    log: &DeactivationLog,
+    db: &ProfileDb<M>,
+    status: Option<u32>,
+) -> Result<Vec<UnprofiledAccount>, Error> {
+    let mut accounts: Vec<UnprofiledAccount> = vec![];
+    let mut last_profiled = None;
+
+    for (id, entry) in log.deactivations(status) {
+        if last_profiled == Some(id) {
+            continue;
+        }
+
+        match accounts.last_mut() {
+            Some(account) if account.id == id => account.entries.push(entry),
+            _ => {
+                if db.has_snapshots(id)? {
+                    last_profiled = Some(id);
+                } else {
+                    accounts.push(UnprofiledAccount {
+                        id,
+                        entries: vec![entry],
+                    });
+                }
+            }
+        }
+    }
+
+    Ok(accounts)
+}
+
+/// Write one line per entry in the deactivation log CSV format.
+pub fn write_csv<W: Write>(accounts: &[UnprofiledAccount], mut writer: W) -> Result<(), Error> {
+    for account in accounts {
+        for entry in &account.entries {
+            writeln!(
+                writer,
+                "{},{},{},{}",
+                account.id,
+                entry.status,
+                entry.observed.timestamp(),
+                entry
+                    .reversal
+                    .map(|reversal| reversal.timestamp().to_string())
+                    .unwrap_or_default()
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::table::Writeable;
+    use hst_tw_profiles::model::User;
+
+    const LOG: &str = "1,50,100,\n2,50,100,150\n2,63,200,\n3,63,300,\n5,50,500,\n";
+
+    fn user(id: i64) -> User {
+        User {
+            id,
+            id_str: id.to_string(),
+            screen_name: format!("user_{}", id),
+            snapshot: 1_600_000_000,
+            ..User::default()
+        }
+    }
+
+    fn db(dir: &tempfile::TempDir) -> ProfileDb<Writeable> {
+        let db = ProfileDb::<Writeable>::open(dir.path().join("db"), false).unwrap();
+        db.update_batch(&[user(1), user(4), user(6)]).unwrap();
+        db
+    }
+
+    fn ids(accounts: &[UnprofiledAccount]) -> Vec<(u64, usize)> {
+        accounts
+            .iter()
+            .map(|account| (account.id, account.entries.len()))
+            .collect()
+    }
+
+    #[test]
+    fn has_snapshots() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = db(&dir);
+
+        assert!(db.has_snapshots(1).unwrap());
+        assert!(db.has_snapshots(4).unwrap());
+        // The next key belongs to another user.
+        assert!(!db.has_snapshots(2).unwrap());
+        assert!(!db.has_snapshots(5).unwrap());
+        assert!(!db.has_snapshots(7).unwrap());
+    }
+
+    #[test]
+    fn unprofiled_accounts() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = db(&dir);
+        let log = DeactivationLog::read(LOG.as_bytes()).unwrap();
+
+        let accounts = unprofiled_deactivations(&log, &db, None).unwrap();
+
+        assert_eq!(ids(&accounts), vec![(2, 2), (3, 1), (5, 1)]);
+
+        let mut output = vec![];
+        write_csv(&accounts, &mut output).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "2,50,100,150\n2,63,200,\n3,63,300,\n5,50,500,\n"
+        );
+    }
+
+    #[test]
+    fn status_filter() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = db(&dir);
+        let log = DeactivationLog::read(LOG.as_bytes()).unwrap();
+
+        assert_eq!(
+            ids(&unprofiled_deactivations(&log, &db, Some(63)).unwrap()),
+            vec![(2, 1), (3, 1)]
+        );
+        assert_eq!(
+            ids(&unprofiled_deactivations(&log, &db, Some(50)).unwrap()),
+            vec![(2, 1), (5, 1)]
+        );
+        assert!(unprofiled_deactivations(&log, &db, Some(0))
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -1,9 +1,19 @@
 //! Library for working with Twitter profile images.
+//!
+//! The [`prelude`] module re-exports the public types.
 
 pub mod error;
+pub mod liveness;
 pub mod model;
 pub mod store;
 
 pub use error::Error;
 pub use model::{Domain, Image, ImageKey, Size};
 pub use store::Store;
+
+/// Public types (with error types renamed to avoid collisions).
+pub mod prelude {
+    pub use super::model::ParseError as ImageParseError;
+    pub use super::store::Error as ImageStoreError;
+    pub use super::{Domain, Error as ImageError, Image, ImageKey, Size, Store};
+}
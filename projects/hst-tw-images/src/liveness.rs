@@ -0,0 +1,620 @@
+//! Checks for whether profile image URLs are still served.
+//!
+//! Results are appended to a CSV log (one row per check), so that repeated runs produce a time
+//! series showing when each URL stopped resolving. URLs containing commas or quotes are quoted,
+//! and line breaks in URLs are percent-encoded so that each check stays on a single line.
+
+use chrono::{DateTime, TimeZone, Utc};
+use futures::stream::{self, Stream, StreamExt};
+use std::collections::HashMap;
+use std::fmt::Formatter;
+use std::future::Future;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::str::FromStr;
+use std::time::Duration;
+
+const DEFAULT_AVATAR_MARKER: &str = "default_profile_images";
+
+#[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
+pub enum Error {
+    #[error("HTTP client error")]
+    Reqwest(#[from] reqwest::Error),
+    #[error("I/O error")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid log line")]
+    InvalidLogLine(String),
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum LinkStatus {
+    Alive,
+    Gone,
+    /// Redirected to the default avatar (which Twitter does for some removed images).
+    DefaultAvatar,
+    Redirected,
+    Other,
+    /// No response was received (e.g. after repeated timeouts).
+    Unreachable,
+}
+
+impl LinkStatus {
+    pub fn classify(response: Option<&HeadResponse>) -> Self {
+        match response {
+            None => Self::Unreachable,
+            Some(response) => match response.status {
+                200..=299 => Self::Alive,
+                404 | 410 => Self::Gone,
+                300..=399 => {
+                    if response
+                        .location
+                        .as_ref()
+                        .is_some_and(|location| location.contains(DEFAULT_AVATAR_MARKER))
+                    {
+                        Self::DefaultAvatar
+                    } else {
+                        Self::Redirected
+                    }
+                }
+                _ => Self::Other,
+            },
+        }
+    }
+}
+
+impl FromStr for LinkStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "alive" => Ok(Self::Alive),
+            "gone" => Ok(Self::Gone),
+            "default" => Ok(Self::DefaultAvatar),
+            "redirected" => Ok(Self::Redirected),
+            "other" => Ok(Self::Other),
+            "unreachable" => Ok(Self::Unreachable),
+            _ => Err(s.to_string()),
+        }
+    }
+}
+
+impl std::fmt::Display for LinkStatus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let value = match self {
+            Self::Alive => "alive",
+            Self::Gone => "gone",
+            Self::DefaultAvatar => "default",
+            Self::Redirected => "redirected",
+            Self::Other => "other",
+            Self::Unreachable => "unreachable",
+        };
+        write!(f, "{}", value)
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct HeadResponse {
+    pub status: u16,
+    pub location: Option<String>,
+}
+
+/// HTTP client abstraction (allows checks to be run without network access).
+pub trait HeadClient {
+    fn head(&self, url: &str) -> impl Future<Output = Result<HeadResponse, Error>> + Send;
+}
+
+/// Client that doesn't follow redirects, so that redirects to the default avatar are visible.
+pub struct ReqwestHeadClient {
+    underlying: reqwest::Client,
+}
+
+impl ReqwestHeadClient {
+    pub fn new(timeout: Duration) -> Result<Self, Error> {
+        Ok(Self {
+            underlying: reqwest::Client::builder()
+                .redirect(reqwest::redirect::Policy::none())
+                .timeout(timeout)
+                .build()?,
+        })
+    }
+}
+
+impl HeadClient for ReqwestHeadClient {
+    fn head(&self, url: &str) -> impl Future<Output = Result<HeadResponse, Error>> + Send {
+        let request = self.underlying.head(url).send();
+
+        async move {
+            let response = request.await?;
+
+            Ok(HeadResponse {
+                status: response.status().as_u16(),
+                location: response
+                    .headers()
+                    .get(reqwest::header::LOCATION)
+                    .and_then(|value| value.to_str().ok())
+                    .map(|value| value.to_string()),
+            })
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Check {
+    pub user_id: u64,
+    pub url: String,
+    pub http_status: Option<u16>,
+    pub status: LinkStatus,
+    pub checked_at: DateTime<Utc>,
+}
+
+impl Check {
+    /// Append this check to a CSV log.
+    pub fn write<W: Write>(&self, writer: &mut W) -> Result<(), std::io::Error> {
+        writeln!(
+            writer,
+            "{},{},{},{},{}",
+            self.user_id,
+            escape_url(&self.url),
+            self.http_status
+                .map(|status| status.to_string())
+                .unwrap_or_default(),
+            self.checked_at.timestamp(),
+            self.status
+        )
+    }
+}
+
+impl FromStr for Check {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::InvalidLogLine(s.to_string());
+
+        // Only the URL may contain commas, so the other fields are split from either end.
+        let (user_id, rest) = s.split_once(',').ok_or_else(invalid)?;
+        let mut fields = rest.rsplitn(4, ',').collect::<Vec<_>>();
+
+        if fields.len() != 4 {
+            return Err(invalid());
+        }
+
+        fields.push(user_id);
+        fields.reverse();
+
+        Ok(Self {
+            user_id: fields[0].parse().map_err(|_| invalid())?,
+            url: unescape_url(fields[1]).ok_or_else(invalid)?,
+            http_status: if fields[2].is_empty() {
+                None
+            } else {
+                Some(fields[2].parse().map_err(|_| invalid())?)
+            },
+            checked_at: fields[3]
+                .parse()
+                .ok()
+                .and_then(|timestamp| Utc.timestamp_opt(timestamp, 0).single())
+                .ok_or_else(invalid)?,
+            // The redirect target isn't logged, so we use the recorded classification.
+            status: fields[4].parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+fn escape_url(url: &str) -> std::borrow::Cow<'_, str> {
+    let url = if url.contains(['\r', '\n']) {
+        url.replace('\r', "%0D").replace('\n', "%0A").into()
+    } else {
+        std::borrow::Cow::Borrowed(url)
+    };
+
+    if url.contains([',', '"']) {
+        format!("\"{}\"", url.replace('"', "\"\"")).into()
+    } else {
+        url
+    }
+}
+
+fn unescape_url(field: &str) -> Option<String> {
+    match field
+        .strip_prefix('"')
+        .and_then(|field| field.strip_suffix('"'))
+    {
+        Some(quoted) => Some(quoted.replace("\"\"", "\"")),
+        None if field.contains('"') => None,
+        None => Some(field.to_string()),
+    }
+}
+
+/// Read all checks from a CSV log.
+pub fn read_log<R: Read>(reader: R) -> Result<Vec<Check>, Error> {
+    BufReader::new(reader)
+        .lines()
+        .filter(|line| !matches!(line, Ok(line) if line.is_empty()))
+        .map(|line| line?.parse())
+        .collect()
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Summary {
+    pub alive: usize,
+    pub gone: usize,
+    pub default_avatar: usize,
+    pub redirected: usize,
+    pub other: usize,
+    pub unreachable: usize,
+}
+
+impl Summary {
+    /// Summarize the most recent check for each URL.
+    pub fn from_checks(checks: &[Check]) -> Self {
+        let mut latest: HashMap<&str, &Check> = HashMap::new();
+
+        for check in checks {
+            let entry = latest.entry(&check.url).or_insert(check);
+
+            if check.checked_at >= entry.checked_at {
+                *entry = check;
+            }
+        }
+
+        let mut summary = Self::default();
+
+        for check in latest.values() {
+            match check.status {
+                LinkStatus::Alive => summary.alive += 1,
+                LinkStatus::Gone => summary.gone += 1,
+                LinkStatus::DefaultAvatar => summary.default_avatar += 1,
+                LinkStatus::Redirected => summary.redirected += 1,
+                LinkStatus::Other => summary.other += 1,
+                LinkStatus::Unreachable => summary.unreachable += 1,
+            }
+        }
+
+        summary
+    }
+}
+
+pub struct LivenessChecker<C> {
+    client: C,
+    concurrency: usize,
+    retries: usize,
+    /// Pause after each request (per concurrent worker).
+    delay: Duration,
+}
+
+impl<C: HeadClient + Sync> LivenessChecker<C> {
+    pub fn new(client: C, concurrency: usize, retries: usize, delay: Duration) -> Self {
+        Self {
+            client,
+            concurrency: concurrency.max(1),
+            retries,
+            delay,
+        }
+    }
+
+    /// Check the given user ID and URL pairs, returning results in completion order.
+    pub async fn check_all<I: IntoIterator<Item = (u64, String)>>(&self, targets: I) -> Vec<Check> {
+        self.check_stream(targets).collect().await
+    }
+
+    /// Check the given user ID and URL pairs, yielding each result as soon as it completes.
+    pub fn check_stream<'a, I: IntoIterator<Item = (u64, String)>>(
+        &'a self,
+        targets: I,
+    ) -> impl Stream<Item = Check> + 'a
+    where
+        I::IntoIter: 'a,
+    {
+        stream::iter(targets)
+            .map(|(user_id, url)| self.check(user_id, url))
+            .buffer_unordered(self.concurrency)
+    }
+
+    pub async fn check(&self, user_id: u64, url: String) -> Check {
+        let mut response = None;
+
+        for attempt in 0..=self.retries {
+            match self.client.head(&url).await {
+                Ok(value) => {
+                    response = Some(value);
+                    break;
+                }
+                Err(error) => {
+                    log::warn!(
+                        "Request for {} failed (attempt {}): {}",
+                        url,
+                        attempt + 1,
+                        error
+                    );
+                }
+            }
+
+            if attempt < self.retries {
+                tokio::time::sleep(self.delay).await;
+            }
+        }
+
+        tokio::time::sleep(self.delay).await;
+
+        Check {
+            user_id,
+            http_status: response.as_ref().map(|response| response.status),
+            status: LinkStatus::classify(response.as_ref()),
+            url,
+            checked_at: Utc::now(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Responds from a fixed table, treating unknown URLs as timeouts.
+    struct StubClient {
+        responses: HashMap<String, HeadResponse>,
+        requests: AtomicUsize,
+    }
+
+    impl StubClient {
+        fn new(responses: &[(&str, u16, Option<&str>)]) -> Self {
+            Self {
+                responses: responses
+                    .iter()
+                    .map(|(url, status, location)| {
+                        (
+                            url.to_string(),
+                            HeadResponse {
+                                status: *status,
+                                location: location.map(|location| location.to_string()),
+                            },
+                        )
+                    })
+                    .collect(),
+                requests: AtomicUsize::new(0),
+            }
+        }
+    }
+
+    impl HeadClient for StubClient {
+        fn head(&self, url: &str) -> impl Future<Output = Result<HeadResponse, Error>> + Send {
+            self.requests.fetch_add(1, Ordering::SeqCst);
+
+            let result = self.responses.get(url).cloned().ok_or_else(|| {
+                Error::Io(std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    "timed out",
+                ))
+            });
+
+            async move { result }
+        }
+    }
+
+    fn checker(client: StubClient, retries: usize) -> LivenessChecker<StubClient> {
+        LivenessChecker::new(client, 2, retries, Duration::ZERO)
+    }
+
+    fn check(url: &str, http_status: Option<u16>, status: LinkStatus, checked_at: i64) -> Check {
+        Check {
+            user_id: 1,
+            url: url.to_string(),
+            http_status,
+            status,
+            checked_at: Utc.timestamp_opt(checked_at, 0).single().unwrap(),
+        }
+    }
+
+    #[tokio::test]
+    async fn check_all_classifies_responses() {
+        let client = StubClient::new(&[
+            ("https://example.com/alive", 200, None),
+            ("https://example.com/gone", 404, None),
+            ("https://example.com/removed", 410, None),
+            (
+                "https://example.com/default",
+                302,
+                Some("https://abs.twimg.com/sticky/default_profile_images/default.png"),
+            ),
+            (
+                "https://example.com/moved",
+                302,
+                Some("https://example.org/"),
+            ),
+            ("https://example.com/error", 500, None),
+        ]);
+        let targets = [
+            "alive", "gone", "removed", "default", "moved", "error", "timeout",
+        ]
+        .iter()
+        .enumerate()
+        .map(|(index, name)| (index as u64, format!("https://example.com/{}", name)));
+
+        let mut checks = checker(client, 0).check_all(targets).await;
+        checks.sort_by_key(|check| check.user_id);
+
+        let statuses = checks
+            .iter()
+            .map(|check| (check.http_status, check.status))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            statuses,
+            vec![
+                (Some(200), LinkStatus::Alive),
+                (Some(404), LinkStatus::Gone),
+                (Some(410), LinkStatus::Gone),
+                (Some(302), LinkStatus::DefaultAvatar),
+                (Some(302), LinkStatus::Redirected),
+                (Some(500), LinkStatus::Other),
+                (None, LinkStatus::Unreachable),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn check_retries_timeouts() {
+        let checker = checker(StubClient::new(&[]), 2);
+        let result = checker
+            .check(1, "https://example.com/timeout".to_string())
+            .await;
+
+        assert_eq!(result.status, LinkStatus::Unreachable);
+        assert_eq!(checker.client.requests.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn check_sleeps_between_retries() {
+        let delay = Duration::from_secs(1);
+        let client = StubClient::new(&[("https://example.com/alive", 200, None)]);
+        let checker = LivenessChecker::new(client, 1, 2, delay);
+
+        // Two pauses between the three attempts, and one after the last.
+        let start = tokio::time::Instant::now();
+        checker
+            .check(1, "https://example.com/timeout".to_string())
+            .await;
+        assert_eq!(start.elapsed(), delay * 3);
+
+        let start = tokio::time::Instant::now();
+        checker
+            .check(1, "https://example.com/alive".to_string())
+            .await;
+        assert_eq!(start.elapsed(), delay);
+    }
+
+    #[tokio::test]
+    async fn check_stream_yields_every_target() {
+        let client = StubClient::new(&[("https://example.com/alive", 200, None)]);
+        let checker = checker(client, 0);
+        let targets = (0..10).map(|user_id| (user_id, "https://example.com/alive".to_string()));
+
+        let checks = checker.check_stream(targets).collect::<Vec<_>>().await;
+
+        assert_eq!(checks.len(), 10);
+        assert!(checks.iter().all(|check| check.status == LinkStatus::Alive));
+    }
+
+    #[test]
+    fn log_round_trip() {
+        let checks = vec![
+            check(
+                "https://example.com/a.png",
+                Some(200),
+                LinkStatus::Alive,
+                100,
+            ),
+            check(
+                "https://example.com/a,b.png",
+                Some(404),
+                LinkStatus::Gone,
+                200,
+            ),
+            check(
+                "https://example.com/\"quoted\",.png",
+                None,
+                LinkStatus::Unreachable,
+                300,
+            ),
+            check(
+                "https://example.com/\"",
+                Some(302),
+                LinkStatus::Redirected,
+                400,
+            ),
+        ];
+
+        let mut bytes = vec![];
+
+        for check in &checks {
+            check.write(&mut bytes).unwrap();
+        }
+
+        // Blank lines are ignored.
+        bytes.push(b'\n');
+
+        assert_eq!(String::from_utf8_lossy(&bytes).lines().count(), 5);
+        assert_eq!(read_log(bytes.as_slice()).unwrap(), checks);
+    }
+
+    #[test]
+    fn log_line_breaks_are_encoded() {
+        let mut bytes = vec![];
+
+        check(
+            "https://example.com/a\r\nb",
+            Some(200),
+            LinkStatus::Alive,
+            100,
+        )
+        .write(&mut bytes)
+        .unwrap();
+
+        let checks = read_log(bytes.as_slice()).unwrap();
+
+        assert_eq!(checks.len(), 1);
+        assert_eq!(checks[0].url, "https://example.com/a%0D%0Ab");
+    }
+
+    #[test]
+    fn log_invalid_lines() {
+        for line in [
+            "",
+            "1,https://example.com/,200,100",
+            "x,https://example.com/,200,100,alive",
+            "1,https://example.com/,x,100,alive",
+            "1,https://example.com/,200,x,alive",
+            "1,https://example.com/,200,100,unknown",
+            "1,https://example.com/\",200,100,alive",
+            "1,\"https://example.com/,200,100,alive",
+        ] {
+            assert!(line.parse::<Check>().is_err(), "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn summary_uses_latest_check() {
+        let checks = vec![
+            check(
+                "https://example.com/a.png",
+                Some(200),
+                LinkStatus::Alive,
+                100,
+            ),
+            check(
+                "https://example.com/a.png",
+                Some(404),
+                LinkStatus::Gone,
+                200,
+            ),
+            check(
+                "https://example.com/b.png",
+                Some(404),
+                LinkStatus::Gone,
+                300,
+            ),
+            check(
+                "https://example.com/b.png",
+                Some(200),
+                LinkStatus::Alive,
+                250,
+            ),
+            check(
+                "https://example.com/c.png",
+                None,
+                LinkStatus::Unreachable,
+                100,
+            ),
+        ];
+
+        assert_eq!(
+            Summary::from_checks(&checks),
+            Summary {
+                gone: 2,
+                unreachable: 1,
+                ..Summary::default()
+            }
+        );
+    }
+}
@@ -6,6 +6,7 @@ use std::str::FromStr;
 const DEFAULT_PATH: &str = "profile_images/";
 
 #[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
 pub enum ParseError {
     #[error("Invalid size")]
     InvalidSize(String),
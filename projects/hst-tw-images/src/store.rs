@@ -7,6 +7,7 @@ const PREFIX_DIR_SIZE: usize = 100;
 const FILE_DIR_SIZE: usize = 1000;
 
 #[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
 pub enum Error {
     #[error("Parsing error")]
     Parse(#[from] ParseError),
@@ -0,0 +1,105 @@
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use hst_tw_profiles::model::User;
+use hst_tw_utils::{
+    splitmix::splitmix64,
+    testkit::{record_count, SEED},
+};
+
+const DEFAULT_RECORD_COUNT: usize = 10_000;
+
+/// Users sorted by ID and snapshot, with a few snapshots per user.
+fn generate_users(count: usize) -> Vec<User> {
+    let mut state = SEED;
+    let mut user_id = 1_000;
+
+    (0..count)
+        .map(|index| {
+            if index % 3 == 0 {
+                user_id += 1 + splitmix64(&mut state) % 1_000_000;
+            }
+
+            User {
+                id: user_id as i64,
+                id_str: user_id.to_string(),
+                name: format!("User {}", user_id),
+                screen_name: format!("user_{}", user_id),
+                description: Some(format!(
+                    "Description {} with some words",
+                    splitmix64(&mut state)
+                )),
+                followers_count: (splitmix64(&mut state) % 100_000) as i64,
+                friends_count: (splitmix64(&mut state) % 5_000) as i64,
+                statuses_count: (splitmix64(&mut state) % 50_000) as i64,
+                created_at: "Mon Jan 04 12:00:00 +0000 2010".to_string(),
+                profile_image_url_https: format!(
+                    "https://pbs.twimg.com/profile_images/{}/a_normal.jpg",
+                    splitmix64(&mut state)
+                ),
+                snapshot: 1_600_000_000 + index as i64,
+                ..User::default()
+            }
+        })
+        .collect()
+}
+
+fn write_avro(users: &[User]) -> Vec<u8> {
+    let mut writer = hst_tw_profiles::avro::writer(vec![]);
+
+    for user in users {
+        writer.append_ser(user).unwrap();
+    }
+
+    writer.into_inner().unwrap()
+}
+
+fn avro(c: &mut Criterion) {
+    let count = record_count(DEFAULT_RECORD_COUNT);
+    let users = generate_users(count);
+    let bytes = write_avro(&users);
+
+    let mut group = c.benchmark_group("avro");
+    group.throughput(Throughput::Elements(count as u64));
+    group.bench_function("write", |b| b.iter(|| write_avro(&users)));
+    group.bench_function("read", |b| {
+        b.iter(|| {
+            hst_tw_profiles::avro::reader(&bytes[..])
+                .unwrap()
+                .map(|value| apache_avro::from_value::<User>(&value.unwrap()).unwrap())
+                .collect::<Vec<_>>()
+        })
+    });
+    group.finish();
+}
+
+fn stream(c: &mut Criterion) {
+    let count = record_count(DEFAULT_RECORD_COUNT);
+    let lines = generate_users(count)
+        .into_iter()
+        .map(|user| {
+            serde_json::json!({
+                "id": user.snapshot,
+                "timestamp_ms": (user.snapshot * 1000).to_string(),
+                "user": user,
+            })
+            .to_string()
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let mut group = c.benchmark_group("stream");
+    group.throughput(Throughput::Elements(count as u64));
+    group.bench_function("parse_and_extract", |b| {
+        b.iter(|| {
+            hst_tw_profiles::ndjson::Lines::new(lines.as_bytes())
+                .map(|line| {
+                    let value = serde_json::from_slice(&line.unwrap().bytes).unwrap();
+                    hst_tw_profiles::stream::extract_user_info(&value, false).unwrap()
+                })
+                .collect::<Vec<_>>()
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, avro, stream);
+criterion_main!(benches);
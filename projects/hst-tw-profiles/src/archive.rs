@@ -1,3 +1,4 @@
+use crate::ndjson::{ErrorPolicy, ErrorSummary, Line, LineContext, Lines};
 use crate::stream::UserInfo;
 use bzip2::read::MultiBzDecoder;
 use std::ffi::OsStr;
@@ -8,6 +9,7 @@ use tar::Archive;
 use zip::ZipArchive;
 
 #[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
 pub enum Error {
     #[error("Profile stream error")]
     ProfileStream(#[from] crate::stream::Error),
@@ -19,67 +21,309 @@ pub enum Error {
     Zip(#[from] zip::result::ZipError),
     #[error("Other error")]
     Other(String),
+    #[error("Error at {context}")]
+    Line {
+        context: LineContext,
+        source: Box<Error>,
+    },
 }
 
-pub fn extract_tar<
-    P: AsRef<Path>,
-    F: FnMut(Result<Option<UserInfo>, Error>) -> Result<(), Error>,
->(
+impl Error {
+    /// The location of the failure, if it occurred while parsing a specific line.
+    pub fn context(&self) -> Option<&LineContext> {
+        match self {
+            Self::Line { context, .. } => Some(context),
+            _ => None,
+        }
+    }
+}
+
+/// Extract user information from every bzip2-compressed NDJSON entry in a tar archive.
+///
+/// Lines that fail to parse are handled according to the policy: under [`ErrorPolicy::Abort`]
+/// the first failure is returned as an [`Error::Line`], and under [`ErrorPolicy::Collect`] they
+/// are skipped and recorded in the returned summary. Read errors always end extraction.
+pub fn extract_tar<P: AsRef<Path>, F: FnMut(Option<UserInfo>) -> Result<(), Error>>(
     path: P,
+    policy: ErrorPolicy,
     mut f: F,
-) -> Result<(), Error> {
+) -> Result<ErrorSummary, Error> {
     let bz2_ext = OsStr::new("bz2");
 
+    let archive_path = path.as_ref().display().to_string();
     let file = File::open(path)?;
     let mut archive = Archive::new(file);
+    let mut summary = ErrorSummary::new(policy);
 
     for entry_res in archive.entries()? {
         let entry = entry_res?;
         let path = entry.path()?;
 
         if path.extension() == Some(bz2_ext) {
+            let entry_path = format!("{}/{}", archive_path, path.display());
             let reader = BufReader::new(MultiBzDecoder::new(entry));
-            for line in reader.lines() {
-                let result = line
-                    .map_err(Error::from)
-                    .and_then(|line| serde_json::from_str(&line).map_err(Error::from))
-                    .and_then(|value| {
-                        crate::stream::extract_user_info(&value, true).map_err(Error::from)
-                    });
-                f(result)?
-            }
+            extract_lines(reader, &entry_path, &mut summary, &mut f)?;
         }
     }
 
-    Ok(())
+    Ok(summary)
 }
 
-pub fn extract_zip<
-    P: AsRef<Path>,
-    F: FnMut(Result<Option<UserInfo>, Error>) -> Result<(), Error>,
->(
+/// Extract user information from every bzip2-compressed NDJSON entry in a ZIP archive.
+///
+/// Failures are handled as in [`extract_tar`].
+pub fn extract_zip<P: AsRef<Path>, F: FnMut(Option<UserInfo>) -> Result<(), Error>>(
     path: P,
+    policy: ErrorPolicy,
     mut f: F,
-) -> Result<(), Error> {
+) -> Result<ErrorSummary, Error> {
+    let archive_path = path.as_ref().display().to_string();
     let file = File::open(path)?;
     let mut archive = ZipArchive::new(file)?;
+    let mut summary = ErrorSummary::new(policy);
 
     for i in 0..archive.len() {
         let file = archive.by_index(i)?;
         let file_name = file.name();
         if file_name.ends_with("bz2") {
+            let entry_path = format!("{}/{}", archive_path, file_name);
             let reader = BufReader::new(MultiBzDecoder::new(file));
-            for line in reader.lines() {
-                let result = line
-                    .map_err(Error::from)
-                    .and_then(|line| serde_json::from_str(&line).map_err(Error::from))
-                    .and_then(|value| {
-                        crate::stream::extract_user_info(&value, true).map_err(Error::from)
+            extract_lines(reader, &entry_path, &mut summary, &mut f)?;
+        }
+    }
+
+    Ok(summary)
+}
+
+fn extract_lines<R: BufRead, F: FnMut(Option<UserInfo>) -> Result<(), Error>>(
+    reader: R,
+    path: &str,
+    summary: &mut ErrorSummary,
+    f: &mut F,
+) -> Result<(), Error> {
+    for line in Lines::new(reader) {
+        let line = line?;
+
+        match parse_line(&line) {
+            Ok(user_info) => f(user_info)?,
+            Err(error) => {
+                let context = line.context(path);
+
+                if !summary.record(context.clone(), &error) {
+                    return Err(Error::Line {
+                        context,
+                        source: Box::new(error),
                     });
-                f(result)?
+                }
             }
         }
     }
 
     Ok(())
 }
+
+fn parse_line(line: &Line) -> Result<Option<UserInfo>, Error> {
+    let value = serde_json::from_slice(&line.bytes)?;
+
+    Ok(crate::stream::extract_user_info(&value, true)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::User;
+    use bzip2::{write::BzEncoder, Compression};
+    use std::io::Write;
+
+    fn status_line(id: i64, screen_name: &str) -> Vec<u8> {
+        let user = User {
+            id,
+            id_str: id.to_string(),
+            screen_name: screen_name.to_string(),
+            ..User::default()
+        };
+        let status = serde_json::json!({
+            "timestamp_ms": "1600000000000",
+            "user": serde_json::to_value(user).unwrap(),
+        });
+
+        serde_json::to_vec(&status).unwrap()
+    }
+
+    /// Returns the contents and the byte offsets of the lines that don't parse.
+    fn contents() -> (Vec<u8>, Vec<(usize, u64)>) {
+        let lines = vec![
+            status_line(1, "a"),
+            b"not json".to_vec(),
+            br#"{"delete":{"status":{"id_str":"1","user_id_str":"1"}}}"#.to_vec(),
+            vec![b'{', 0xff, 0xfe, b'}'],
+            br#"{"timestamp_ms":"1600000000000"}"#.to_vec(),
+            status_line(2, "b"),
+        ];
+        let mut contents = vec![];
+        let mut offsets = vec![];
+
+        for line in lines {
+            offsets.push(contents.len() as u64);
+            contents.extend_from_slice(&line);
+            contents.push(b'\n');
+        }
+
+        let failures = [2, 4, 5]
+            .into_iter()
+            .map(|line_number| (line_number, offsets[line_number - 1]))
+            .collect();
+
+        (contents, failures)
+    }
+
+    fn compress(contents: &[u8]) -> Vec<u8> {
+        let mut encoder = BzEncoder::new(vec![], Compression::default());
+        encoder.write_all(contents).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn write_tar(path: &Path, contents: &[u8]) {
+        let mut builder = tar::Builder::new(File::create(path).unwrap());
+
+        for name in ["first.json.bz2", "ignored.txt", "second.json.bz2"] {
+            let data = if name.ends_with("bz2") {
+                compress(contents)
+            } else {
+                contents.to_vec()
+            };
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, name, &data[..]).unwrap();
+        }
+
+        builder.finish().unwrap();
+    }
+
+    fn write_zip(path: &Path, contents: &[u8]) {
+        let mut writer = zip::ZipWriter::new(File::create(path).unwrap());
+        let options =
+            zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+
+        writer.start_file("first.json.bz2", options).unwrap();
+        writer.write_all(&compress(contents)).unwrap();
+        writer.finish().unwrap();
+    }
+
+    #[test]
+    fn extract_tar_collects_failures() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("archive.tar");
+        let (contents, failures) = contents();
+        write_tar(&path, &contents);
+
+        let mut screen_names = vec![];
+        let mut none_count = 0;
+        let summary = extract_tar(&path, ErrorPolicy::Collect(4), |user_info| {
+            match user_info {
+                Some(user_info) => screen_names.push(user_info.users[0].screen_name.clone()),
+                None => none_count += 1,
+            }
+            Ok(())
+        })
+        .unwrap();
+
+        assert_eq!(screen_names, vec!["a", "b", "a", "b"]);
+        assert_eq!(none_count, 2);
+        assert_eq!(summary.error_count, 6);
+        assert_eq!(summary.failures.len(), 4);
+
+        let positions = summary
+            .failures
+            .iter()
+            .map(|failure| {
+                (
+                    failure.context.path.clone(),
+                    failure.context.line_number,
+                    failure.context.byte_offset,
+                )
+            })
+            .collect::<Vec<_>>();
+        let first = format!("{}/first.json.bz2", path.display());
+        let second = format!("{}/second.json.bz2", path.display());
+
+        assert_eq!(
+            positions,
+            vec![
+                (first.clone(), failures[0].0, failures[0].1),
+                (first.clone(), failures[1].0, failures[1].1),
+                (first, failures[2].0, failures[2].1),
+                (second, failures[0].0, failures[0].1),
+            ]
+        );
+        assert_eq!(summary.failures[0].context.snippet, "not json");
+        assert_eq!(summary.failures[1].context.snippet, "{\u{fffd}\u{fffd}}");
+        assert!(summary.failures[0].message.starts_with("JSON error: "));
+        assert_eq!(
+            summary.failures[2].message,
+            "Profile stream error: Missing user"
+        );
+    }
+
+    #[test]
+    fn extract_tar_aborts_on_first_failure() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("archive.tar");
+        let (contents, failures) = contents();
+        write_tar(&path, &contents);
+
+        let mut count = 0;
+        let error = extract_tar(&path, ErrorPolicy::Abort, |_| {
+            count += 1;
+            Ok(())
+        })
+        .unwrap_err();
+        let context = error.context().unwrap();
+
+        assert_eq!(count, 1);
+        assert!(matches!(
+            error,
+            Error::Line { ref source, .. } if matches!(**source, Error::Json(_))
+        ));
+        assert_eq!(context.path, format!("{}/first.json.bz2", path.display()));
+        assert_eq!(
+            (context.line_number, context.byte_offset),
+            (failures[0].0, failures[0].1)
+        );
+    }
+
+    #[test]
+    fn extract_zip_collects_failures() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("archive.zip");
+        let (contents, failures) = contents();
+        write_zip(&path, &contents);
+
+        let mut count = 0;
+        let summary = extract_zip(&path, ErrorPolicy::Collect(0), |_| {
+            count += 1;
+            Ok(())
+        })
+        .unwrap();
+
+        assert_eq!(count, 3);
+        assert_eq!(summary.error_count, failures.len());
+        assert!(summary.failures.is_empty());
+    }
+
+    #[test]
+    fn callback_errors_end_extraction() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("archive.tar");
+        let (contents, _) = contents();
+        write_tar(&path, &contents);
+
+        let result = extract_tar(&path, ErrorPolicy::Collect(10), |_| {
+            Err(Error::Other("stop".to_string()))
+        });
+
+        assert!(matches!(result, Err(Error::Other(message)) if message == "stop"));
+    }
+}
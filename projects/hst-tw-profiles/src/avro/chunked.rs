@@ -0,0 +1,480 @@
+//! Profile archives split across multiple Avro files, with a manifest for targeted reads.
+//!
+//! Input is expected to be sorted by user ID and snapshot (repeated snapshots are allowed), and a
+//! user's snapshots are never split across parts, so a lookup only ever needs to open a single
+//! part.
+
+use crate::model::User;
+use apache_avro::Writer;
+use hst_tw_utils::hex;
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+pub const MANIFEST_FILE_NAME: &str = "manifest.json";
+
+#[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
+pub enum Error {
+    #[error("I/O error")]
+    Io(#[from] std::io::Error),
+    #[error("Avro error")]
+    Avro(#[from] apache_avro::Error),
+    #[error("JSON error")]
+    Json(#[from] serde_json::Error),
+    #[error("Unsorted input")]
+    Unsorted {
+        previous: (u64, i64),
+        next: (u64, i64),
+    },
+    #[error("Checksum mismatch")]
+    ChecksumMismatch(String),
+}
+
+/// Thresholds for starting a new part (checked only between users).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ChunkLimits {
+    /// Target compressed size of each part in bytes.
+    pub max_bytes: u64,
+    pub max_records: usize,
+}
+
+impl Default for ChunkLimits {
+    fn default() -> Self {
+        Self {
+            max_bytes: 1 << 30,
+            max_records: usize::MAX,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct PartInfo {
+    pub file_name: String,
+    pub first_user_id: u64,
+    pub last_user_id: u64,
+    pub record_count: usize,
+    pub byte_count: u64,
+    /// Hex-encoded SHA-256 digest of the file.
+    pub sha256: String,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct Manifest {
+    pub parts: Vec<PartInfo>,
+}
+
+impl Manifest {
+    pub fn record_count(&self) -> usize {
+        self.parts.iter().map(|part| part.record_count).sum()
+    }
+
+    /// Find the part that contains snapshots for the given user, if any.
+    pub fn part_for(&self, user_id: u64) -> Option<&PartInfo> {
+        let index = self
+            .parts
+            .partition_point(|part| part.last_user_id < user_id);
+
+        self.parts
+            .get(index)
+            .filter(|part| part.first_user_id <= user_id)
+    }
+}
+
+/// Writer that computes the size and digest of everything written through it.
+struct DigestWriter<W> {
+    underlying: W,
+    hasher: Sha256,
+    byte_count: u64,
+}
+
+impl<W: Write> Write for DigestWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let count = self.underlying.write(buf)?;
+        self.hasher.update(&buf[..count]);
+        self.byte_count += count as u64;
+        Ok(count)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.underlying.flush()
+    }
+}
+
+struct OpenPart {
+    writer: Writer<'static, DigestWriter<BufWriter<File>>>,
+    file_name: String,
+    first_user_id: u64,
+    last_user_id: u64,
+    record_count: usize,
+    /// Compressed bytes written so far (excluding any buffered block).
+    byte_count: u64,
+}
+
+pub struct ChunkedAvroWriter {
+    base: PathBuf,
+    limits: ChunkLimits,
+    current: Option<OpenPart>,
+    last: Option<(u64, i64)>,
+    manifest: Manifest,
+}
+
+impl ChunkedAvroWriter {
+    /// Create a writer for the given directory (which will be created if necessary).
+    pub fn new<P: AsRef<Path>>(base: P, limits: ChunkLimits) -> Result<Self, Error> {
+        std::fs::create_dir_all(&base)?;
+
+        Ok(Self {
+            base: base.as_ref().to_path_buf(),
+            limits,
+            current: None,
+            last: None,
+            manifest: Manifest::default(),
+        })
+    }
+
+    pub fn write(&mut self, user: &User) -> Result<(), Error> {
+        let key = (user.id(), user.snapshot);
+
+        if let Some(previous) = self.last {
+            if key < previous {
+                return Err(Error::Unsorted {
+                    previous,
+                    next: key,
+                });
+            }
+        }
+
+        let new_user = self.last.is_none_or(|(user_id, _)| user_id != key.0);
+
+        if new_user {
+            let full = self.current.as_ref().is_some_and(|part| {
+                part.byte_count >= self.limits.max_bytes
+                    || part.record_count >= self.limits.max_records
+            });
+
+            if full {
+                self.close_part()?;
+            }
+        }
+
+        let part = match self.current.as_mut() {
+            Some(part) => part,
+            None => {
+                let file_name = format!("part-{:05}.avro", self.manifest.parts.len());
+                let file = File::create(self.base.join(&file_name))?;
+
+                self.current.insert(OpenPart {
+                    writer: super::writer(DigestWriter {
+                        underlying: BufWriter::new(file),
+                        hasher: Sha256::new(),
+                        byte_count: 0,
+                    }),
+                    file_name,
+                    first_user_id: key.0,
+                    last_user_id: key.0,
+                    record_count: 0,
+                    byte_count: 0,
+                })
+            }
+        };
+
+        part.byte_count += part.writer.append_ser(user)? as u64;
+        part.last_user_id = key.0;
+        part.record_count += 1;
+        self.last = Some(key);
+
+        Ok(())
+    }
+
+    /// Close the last part and write the manifest.
+    pub fn finish(mut self) -> Result<Manifest, Error> {
+        self.close_part()?;
+
+        let mut writer = BufWriter::new(File::create(self.base.join(MANIFEST_FILE_NAME))?);
+        serde_json::to_writer_pretty(&mut writer, &self.manifest)?;
+        writer.flush()?;
+
+        Ok(self.manifest)
+    }
+
+    fn close_part(&mut self) -> Result<(), Error> {
+        if let Some(part) = self.current.take() {
+            let mut digest_writer = part.writer.into_inner()?;
+            digest_writer.flush()?;
+
+            self.manifest.parts.push(PartInfo {
+                file_name: part.file_name,
+                first_user_id: part.first_user_id,
+                last_user_id: part.last_user_id,
+                record_count: part.record_count,
+                byte_count: digest_writer.byte_count,
+                sha256: hex(&digest_writer.hasher.finalize()),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+pub struct ChunkedAvroReader {
+    base: PathBuf,
+    manifest: Manifest,
+}
+
+impl ChunkedAvroReader {
+    pub fn open<P: AsRef<Path>>(base: P) -> Result<Self, Error> {
+        let file = File::open(base.as_ref().join(MANIFEST_FILE_NAME))?;
+        let manifest = serde_json::from_reader(BufReader::new(file))?;
+
+        Ok(Self {
+            base: base.as_ref().to_path_buf(),
+            manifest,
+        })
+    }
+
+    pub fn manifest(&self) -> &Manifest {
+        &self.manifest
+    }
+
+    /// Return all snapshots for the user, reading only the part that contains them.
+    pub fn lookup(&self, user_id: u64) -> Result<Vec<User>, Error> {
+        let mut users = vec![];
+
+        if let Some(part) = self.manifest.part_for(user_id) {
+            for result in self.read_part(part)? {
+                let user = result?;
+
+                match user.id().cmp(&user_id) {
+                    std::cmp::Ordering::Less => {}
+                    std::cmp::Ordering::Equal => users.push(user),
+                    std::cmp::Ordering::Greater => break,
+                }
+            }
+        }
+
+        Ok(users)
+    }
+
+    /// Iterate over every record in every part in order.
+    pub fn iter(&self) -> impl Iterator<Item = Result<User, Error>> + '_ {
+        self.manifest
+            .parts
+            .iter()
+            .flat_map(move |part| match self.read_part(part) {
+                Ok(iter) => Box::new(iter) as Box<dyn Iterator<Item = Result<User, Error>>>,
+                Err(error) => Box::new(std::iter::once(Err(error))),
+            })
+    }
+
+    /// Check every part against the size and digest recorded in the manifest.
+    pub fn verify(&self) -> Result<(), Error> {
+        for part in &self.manifest.parts {
+            let mut file = File::open(self.base.join(&part.file_name))?;
+            let mut hasher = Sha256::new();
+            let byte_count = std::io::copy(&mut file, &mut hasher)?;
+
+            if byte_count != part.byte_count || hex(&hasher.finalize()) != part.sha256 {
+                return Err(Error::ChecksumMismatch(part.file_name.clone()));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn read_part(
+        &self,
+        part: &PartInfo,
+    ) -> Result<impl Iterator<Item = Result<User, Error>>, Error> {
+        let file = File::open(self.base.join(&part.file_name))?;
+        let reader = apache_avro::Reader::with_schema(&super::USER_SCHEMA, BufReader::new(file))?;
+
+        Ok(reader.map(|value| Ok(apache_avro::from_value::<User>(&value?)?)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(id: i64, snapshot: i64) -> User {
+        User {
+            id,
+            id_str: id.to_string(),
+            screen_name: format!("user_{}", id),
+            snapshot,
+            ..User::default()
+        }
+    }
+
+    fn write_all(base: &Path, limits: ChunkLimits, users: &[User]) -> Manifest {
+        let mut writer = ChunkedAvroWriter::new(base, limits).unwrap();
+
+        for user in users {
+            writer.write(user).unwrap();
+        }
+
+        writer.finish().unwrap()
+    }
+
+    fn keys(users: &[User]) -> Vec<(u64, i64)> {
+        users
+            .iter()
+            .map(|user| (user.id(), user.snapshot))
+            .collect()
+    }
+
+    #[test]
+    fn users_are_not_split_at_rotation() {
+        let dir = tempfile::tempdir().unwrap();
+        let limits = ChunkLimits {
+            max_bytes: u64::MAX,
+            max_records: 2,
+        };
+        let users = vec![
+            user(1, 10),
+            user(1, 20),
+            user(1, 30),
+            user(2, 10),
+            user(3, 10),
+            user(3, 20),
+            user(4, 10),
+        ];
+
+        let manifest = write_all(dir.path(), limits, &users);
+        let ranges = manifest
+            .parts
+            .iter()
+            .map(|part| (part.first_user_id, part.last_user_id, part.record_count))
+            .collect::<Vec<_>>();
+
+        // The first part runs past the limit rather than splitting user 1.
+        assert_eq!(ranges, vec![(1, 1, 3), (2, 3, 3), (4, 4, 1)]);
+        assert_eq!(manifest.record_count(), users.len());
+
+        let reader = ChunkedAvroReader::open(dir.path()).unwrap();
+
+        assert_eq!(reader.manifest(), &manifest);
+        assert_eq!(
+            keys(&reader.lookup(1).unwrap()),
+            vec![(1, 10), (1, 20), (1, 30)]
+        );
+        assert_eq!(keys(&reader.lookup(3).unwrap()), vec![(3, 10), (3, 20)]);
+        assert!(reader.lookup(5).unwrap().is_empty());
+
+        let all = reader.iter().collect::<Result<Vec<_>, _>>().unwrap();
+
+        assert_eq!(all, users);
+        assert!(reader.verify().is_ok());
+    }
+
+    #[test]
+    fn rotation_by_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let limits = ChunkLimits {
+            max_bytes: 1,
+            max_records: usize::MAX,
+        };
+        let users = (1..=4)
+            .flat_map(|id| (0..300).map(move |snapshot| user(id, snapshot)))
+            .collect::<Vec<_>>();
+
+        let manifest = write_all(dir.path(), limits, &users);
+
+        assert!(manifest.parts.len() > 1);
+
+        for part in &manifest.parts {
+            assert_eq!(part.record_count % 300, 0);
+        }
+
+        let reader = ChunkedAvroReader::open(dir.path()).unwrap();
+
+        for id in 1..=4 {
+            assert_eq!(reader.lookup(id).unwrap().len(), 300);
+        }
+    }
+
+    #[test]
+    fn repeated_keys_are_allowed() {
+        let dir = tempfile::tempdir().unwrap();
+        let users = vec![user(1, 10), user(1, 10), user(2, 5)];
+
+        let manifest = write_all(dir.path(), ChunkLimits::default(), &users);
+
+        assert_eq!(manifest.record_count(), 3);
+    }
+
+    #[test]
+    fn unsorted_input_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut writer = ChunkedAvroWriter::new(dir.path(), ChunkLimits::default()).unwrap();
+
+        writer.write(&user(2, 10)).unwrap();
+
+        assert!(matches!(
+            writer.write(&user(2, 9)),
+            Err(Error::Unsorted {
+                previous: (2, 10),
+                next: (2, 9)
+            })
+        ));
+        assert!(matches!(
+            writer.write(&user(1, 20)),
+            Err(Error::Unsorted { .. })
+        ));
+    }
+
+    #[test]
+    fn empty_archive() {
+        let dir = tempfile::tempdir().unwrap();
+        let manifest = write_all(dir.path(), ChunkLimits::default(), &[]);
+        let reader = ChunkedAvroReader::open(dir.path()).unwrap();
+
+        assert!(manifest.parts.is_empty());
+        assert!(reader.lookup(1).unwrap().is_empty());
+        assert_eq!(reader.iter().count(), 0);
+    }
+
+    #[test]
+    fn verify_detects_changed_parts() {
+        let dir = tempfile::tempdir().unwrap();
+        let manifest = write_all(
+            dir.path(),
+            ChunkLimits::default(),
+            &[user(1, 10), user(2, 10)],
+        );
+
+        let part_path = dir.path().join(&manifest.parts[0].file_name);
+        let mut bytes = std::fs::read(&part_path).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+        std::fs::write(&part_path, bytes).unwrap();
+
+        let reader = ChunkedAvroReader::open(dir.path()).unwrap();
+
+        assert!(matches!(reader.verify(), Err(Error::ChecksumMismatch(_))));
+    }
+
+    #[test]
+    fn part_for_gaps() {
+        let part = |first_user_id, last_user_id| PartInfo {
+            file_name: String::new(),
+            first_user_id,
+            last_user_id,
+            record_count: 1,
+            byte_count: 1,
+            sha256: String::new(),
+        };
+        let manifest = Manifest {
+            parts: vec![part(1, 5), part(10, 20)],
+        };
+
+        assert_eq!(manifest.part_for(0), None);
+        assert_eq!(manifest.part_for(1), Some(&manifest.parts[0]));
+        assert_eq!(manifest.part_for(5), Some(&manifest.parts[0]));
+        assert_eq!(manifest.part_for(7), None);
+        assert_eq!(manifest.part_for(20), Some(&manifest.parts[1]));
+        assert_eq!(manifest.part_for(21), None);
+    }
+}
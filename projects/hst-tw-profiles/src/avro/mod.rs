@@ -0,0 +1,480 @@
+use super::model::{Entities, Entity, Url, User};
+use apache_avro::{
+    from_avro_datum, from_value,
+    schema::{Schema, SchemaKind},
+    to_avro_datum, to_value, Codec, Reader, Writer,
+};
+use serde_json::Value;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt::Formatter;
+use std::io::{Cursor, Read, Write};
+
+pub mod chunked;
+pub mod schema;
+
+pub use chunked::{ChunkedAvroReader, ChunkedAvroWriter};
+pub use schema::{USER_SCHEMA, USER_SCHEMA_V1, USER_SCHEMA_V2, USER_SCHEMA_V3};
+
+pub fn writer<W: Write>(writer: W) -> Writer<'static, W> {
+    Writer::with_codec(&USER_SCHEMA, writer, Codec::Snappy)
+}
+
+pub fn reader<R: Read>(reader: R) -> Result<Reader<'static, R>, Error> {
+    Ok(Reader::with_schema(&USER_SCHEMA, reader)?)
+}
+
+pub fn validate<R: Read>(reader: Reader<'static, R>) -> Result<usize, ValidationError> {
+    let mut count = 0;
+    let mut last_snapshot = 0;
+    let mut last_user_id = 0;
+    let mut misordered_line_numbers = vec![];
+    let mut duplicate_line_numbers = vec![];
+
+    for (line_number, value) in reader.enumerate() {
+        let user = apache_avro::from_value::<User>(&value?)?;
+
+        match user.snapshot.cmp(&last_snapshot) {
+            Ordering::Greater => {}
+            Ordering::Less => {
+                misordered_line_numbers.push(line_number);
+            }
+            Ordering::Equal => match user.id.cmp(&last_user_id) {
+                Ordering::Greater => {}
+                Ordering::Less => {
+                    misordered_line_numbers.push(line_number);
+                }
+                Ordering::Equal => {
+                    duplicate_line_numbers.push(line_number);
+                }
+            },
+        }
+
+        last_snapshot = user.snapshot;
+        last_user_id = user.id;
+        count += 1;
+    }
+
+    if misordered_line_numbers.is_empty() && duplicate_line_numbers.is_empty() {
+        Ok(count)
+    } else {
+        Err(ValidationError::InvalidContents {
+            misordered_line_numbers,
+            duplicate_line_numbers,
+        })
+    }
+}
+
+pub fn count_users<R: Read>(
+    reader: Reader<'static, R>,
+) -> Result<HashMap<(u64, String), usize>, Error> {
+    let mut counts = HashMap::new();
+
+    for value in reader {
+        let user = apache_avro::from_value::<User>(&value?)?;
+
+        let count = counts.entry((user.id(), user.screen_name)).or_default();
+        *count += 1;
+    }
+
+    Ok(counts)
+}
+
+#[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
+pub enum Error {
+    #[error("I/O error")]
+    Io(#[from] std::io::Error),
+    #[error("Avro error")]
+    Avro(#[from] apache_avro::Error),
+}
+
+#[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
+pub enum ValidationError {
+    #[error("Avro error")]
+    Avro(#[from] apache_avro::Error),
+    #[error("Unsorted lines")]
+    InvalidContents {
+        misordered_line_numbers: Vec<usize>,
+        duplicate_line_numbers: Vec<usize>,
+    },
+}
+
+#[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
+pub enum SchemaMismatch {
+    #[error("Avro error")]
+    Avro(#[from] apache_avro::Error),
+    #[error("JSON error")]
+    Json(#[from] serde_json::Error),
+    #[error("Schema and model fields differ: {}", format_field_mismatches(.0))]
+    Fields(Vec<FieldMismatch>),
+    #[error("User changed during Avro round-trip")]
+    RoundTrip {
+        original: Box<User>,
+        decoded: Box<User>,
+    },
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum FieldMismatch {
+    MissingFromSchema(String),
+    MissingFromModel(String),
+    IncompatibleType {
+        path: String,
+        schema_type: String,
+        model_type: String,
+    },
+}
+
+impl std::fmt::Display for FieldMismatch {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::MissingFromSchema(path) => write!(f, "{} (missing from schema)", path),
+            Self::MissingFromModel(path) => write!(f, "{} (missing from model)", path),
+            Self::IncompatibleType {
+                path,
+                schema_type,
+                model_type,
+            } => write!(
+                f,
+                "{} (schema type {}, model type {})",
+                path, schema_type, model_type
+            ),
+        }
+    }
+}
+
+fn format_field_mismatches(mismatches: &[FieldMismatch]) -> String {
+    mismatches
+        .iter()
+        .map(|mismatch| mismatch.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Check that `USER_SCHEMA` and `model::User` agree.
+///
+/// This is cheap enough to call at application startup.
+pub fn verify_schema_compatibility() -> Result<(), SchemaMismatch> {
+    verify_schema_compatibility_with(&USER_SCHEMA)
+}
+
+/// Check that the given schema and `model::User` agree.
+///
+/// We first compare the schema's fields against the fields of a fully-populated user (with
+/// every optional value present and every list non-empty), and then confirm that this user
+/// survives a round-trip through the schema.
+pub fn verify_schema_compatibility_with(schema: &Schema) -> Result<(), SchemaMismatch> {
+    let user = populated_user();
+
+    let mut mismatches = vec![];
+    compare_schema_fields(
+        "",
+        schema,
+        &serde_json::to_value(&user)?,
+        &mut HashMap::new(),
+        &mut mismatches,
+    );
+
+    if !mismatches.is_empty() {
+        return Err(SchemaMismatch::Fields(mismatches));
+    }
+
+    let bytes = to_avro_datum(schema, to_value(&user)?)?;
+    let decoded = from_value::<User>(&from_avro_datum(schema, &mut Cursor::new(bytes), None)?)?;
+
+    if decoded == user {
+        Ok(())
+    } else {
+        Err(SchemaMismatch::RoundTrip {
+            original: Box::new(user),
+            decoded: Box::new(decoded),
+        })
+    }
+}
+
+fn compare_schema_fields<'a>(
+    path: &str,
+    schema: &'a Schema,
+    value: &Value,
+    names: &mut HashMap<String, &'a Schema>,
+    mismatches: &mut Vec<FieldMismatch>,
+) {
+    // For unions we compare against the first variant that could hold the value.
+    let schema = match schema {
+        Schema::Union(union) => {
+            let variant = union
+                .variants()
+                .iter()
+                .find(|variant| !matches!(variant, Schema::Null) || value.is_null());
+
+            match variant {
+                Some(variant) => variant,
+                None => return,
+            }
+        }
+        other => other,
+    };
+
+    let schema = match schema {
+        Schema::Ref { name } => match names.get(&name.fullname(None)) {
+            Some(schema) => *schema,
+            None => return,
+        },
+        other => other,
+    };
+
+    let compatible = match (schema, value) {
+        (Schema::Record { name, fields, .. }, Value::Object(model_fields)) => {
+            names.insert(name.fullname(None), schema);
+
+            for field in fields {
+                let field_path = child_path(path, &field.name);
+                match model_fields.get(&field.name) {
+                    Some(field_value) => compare_schema_fields(
+                        &field_path,
+                        &field.schema,
+                        field_value,
+                        names,
+                        mismatches,
+                    ),
+                    None => mismatches.push(FieldMismatch::MissingFromModel(field_path)),
+                }
+            }
+
+            for model_field_name in model_fields.keys() {
+                if !fields.iter().any(|field| &field.name == model_field_name) {
+                    mismatches.push(FieldMismatch::MissingFromSchema(child_path(
+                        path,
+                        model_field_name,
+                    )));
+                }
+            }
+
+            true
+        }
+        (Schema::Array(item_schema), Value::Array(items)) => {
+            if let Some(item) = items.first() {
+                compare_schema_fields(&format!("{}[]", path), item_schema, item, names, mismatches);
+            }
+            true
+        }
+        (Schema::Map(value_schema), Value::Object(entries)) => {
+            for (key, entry) in entries {
+                compare_schema_fields(
+                    &child_path(path, key),
+                    value_schema,
+                    entry,
+                    names,
+                    mismatches,
+                );
+            }
+            true
+        }
+        (Schema::Null, Value::Null) => true,
+        (Schema::Boolean, Value::Bool(_)) => true,
+        (Schema::Int | Schema::Long, Value::Number(number)) => number.is_i64() || number.is_u64(),
+        (Schema::Float | Schema::Double, Value::Number(_)) => true,
+        (Schema::String | Schema::Uuid, Value::String(_)) => true,
+        _ => false,
+    };
+
+    if !compatible {
+        mismatches.push(FieldMismatch::IncompatibleType {
+            path: path.to_string(),
+            schema_type: format!("{:?}", SchemaKind::from(schema)),
+            model_type: json_type_name(value).to_string(),
+        });
+    }
+}
+
+fn child_path(path: &str, name: &str) -> String {
+    if path.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}.{}", path, name)
+    }
+}
+
+fn json_type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+fn populated_user() -> User {
+    let url = Url {
+        url: "https://t.co/abc".to_string(),
+        expanded_url: Some("https://example.com/".to_string()),
+        display_url: Some("example.com".to_string()),
+        indices: vec![0, 23],
+    };
+
+    User {
+        id: 1,
+        id_str: "1".to_string(),
+        name: "Name".to_string(),
+        screen_name: "screen_name".to_string(),
+        location: Some("Location".to_string()),
+        description: Some("Description".to_string()),
+        url: Some("https://t.co/abc".to_string()),
+        entities: Some(Entities {
+            url: Some(Entity {
+                urls: vec![url.clone()],
+            }),
+            description: Some(Entity { urls: vec![url] }),
+        }),
+        protected: true,
+        followers_count: 2,
+        friends_count: 3,
+        listed_count: 4,
+        created_at: "Wed Mar 21 20:50:14 +0000 2006".to_string(),
+        favourites_count: 5,
+        utc_offset: Some(6),
+        time_zone: Some("Time zone".to_string()),
+        geo_enabled: Some(true),
+        verified: true,
+        statuses_count: 7,
+        lang: Some("en".to_string()),
+        profile_background_color: Some("000000".to_string()),
+        profile_background_image_url_https: Some("https://example.com/bg.png".to_string()),
+        profile_background_tile: Some(true),
+        profile_image_url_https: "https://example.com/image.png".to_string(),
+        profile_banner_url: Some("https://example.com/banner".to_string()),
+        profile_link_color: Some("111111".to_string()),
+        profile_sidebar_border_color: Some("222222".to_string()),
+        profile_sidebar_fill_color: Some("333333".to_string()),
+        profile_text_color: Some("444444".to_string()),
+        profile_use_background_image: Some(true),
+        has_extended_profile: Some(true),
+        default_profile: true,
+        default_profile_image: true,
+        withheld_scope: Some("user".to_string()),
+        withheld_in_countries: vec!["DE".to_string()],
+        snapshot: 8,
+        ext_is_blue_verified: Some(true),
+        ext_verified_type: Some("Business".to_string()),
+        ext_snapshot_adjusted: Some(true),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The current schema with the given edit applied to its top-level fields.
+    fn edited_schema<F: FnOnce(&mut Vec<Value>)>(edit: F) -> Schema {
+        let mut schema =
+            serde_json::from_str::<Value>(include_str!("../../schemas/avro/user-v3.avsc")).unwrap();
+
+        if let Some(Value::Array(fields)) = schema.get_mut("fields") {
+            edit(fields);
+        }
+
+        Schema::parse_str(&schema.to_string()).unwrap()
+    }
+
+    fn mismatches(schema: &Schema) -> Vec<FieldMismatch> {
+        match verify_schema_compatibility_with(schema) {
+            Err(SchemaMismatch::Fields(mismatches)) => mismatches,
+            other => panic!("Expected field mismatches, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn current_schema_is_compatible() {
+        assert!(verify_schema_compatibility().is_ok());
+    }
+
+    #[test]
+    fn v1_schema_is_missing_fields() {
+        assert_eq!(
+            mismatches(&USER_SCHEMA_V1),
+            vec![
+                FieldMismatch::MissingFromSchema("ext_is_blue_verified".to_string()),
+                FieldMismatch::MissingFromSchema("ext_verified_type".to_string()),
+                FieldMismatch::MissingFromSchema("ext_snapshot_adjusted".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn v2_schema_is_missing_fields() {
+        assert_eq!(
+            mismatches(&USER_SCHEMA_V2),
+            vec![FieldMismatch::MissingFromSchema(
+                "ext_snapshot_adjusted".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn extra_schema_field() {
+        let schema = edited_schema(|fields| {
+            fields.push(serde_json::json!({ "name": "ext_extra", "type": ["null", "string"] }))
+        });
+
+        assert_eq!(
+            mismatches(&schema),
+            vec![FieldMismatch::MissingFromModel("ext_extra".to_string())]
+        );
+    }
+
+    #[test]
+    fn incompatible_types() {
+        let schema = edited_schema(|fields| {
+            for field in fields {
+                if field["name"] == "followers_count" {
+                    field["type"] = serde_json::json!("string");
+                }
+            }
+        });
+        let mismatches = mismatches(&schema);
+
+        assert_eq!(
+            mismatches,
+            vec![FieldMismatch::IncompatibleType {
+                path: "followers_count".to_string(),
+                schema_type: "String".to_string(),
+                model_type: "number".to_string(),
+            }]
+        );
+        assert_eq!(
+            SchemaMismatch::Fields(mismatches).to_string(),
+            "Schema and model fields differ: followers_count (schema type String, model type number)"
+        );
+    }
+
+    #[test]
+    fn nested_field_paths() {
+        let mut schema =
+            serde_json::from_str::<Value>(include_str!("../../schemas/avro/user-v3.avsc")).unwrap();
+        // Rename `entities.url.urls[].display_url` (the description entity refers to the same
+        // record type, so it is also affected).
+        let pointer = "/fields/7/type/1/fields/0/type/1/fields/0/type/items/fields/2/name";
+
+        assert_eq!(schema.pointer(pointer), Some(&Value::from("display_url")));
+        *schema.pointer_mut(pointer).unwrap() = Value::from("display");
+
+        let schema = Schema::parse_str(&schema.to_string()).unwrap();
+
+        assert_eq!(
+            mismatches(&schema),
+            vec![
+                FieldMismatch::MissingFromModel("entities.url.urls[].display".to_string()),
+                FieldMismatch::MissingFromSchema("entities.url.urls[].display_url".to_string()),
+                FieldMismatch::MissingFromModel("entities.description.urls[].display".to_string()),
+                FieldMismatch::MissingFromSchema(
+                    "entities.description.urls[].display_url".to_string()
+                ),
+            ]
+        );
+    }
+}
@@ -0,0 +1,90 @@
+//! Versioned user schemas.
+//!
+//! New fields are only ever appended (as nullable unions with a `null` default), so files and
+//! datums written with an earlier schema can be read with the current one using Avro schema
+//! resolution, with the missing fields decoded as `None`.
+//!
+//! ```rust
+//! use apache_avro::{from_value, to_avro_datum, to_value, types::Value, Writer};
+//! use hst_tw_profiles::avro::{reader, schema::{read_user_datum, USER_SCHEMA_V1}};
+//! use hst_tw_profiles::model::User;
+//!
+//! // The model has the new fields, so we drop them to get a V1 user.
+//! let mut value = to_value(User::default())?;
+//! if let Value::Record(fields) = &mut value {
+//!     fields.retain(|(name, _)| !name.starts_with("ext_"));
+//! }
+//! let value = value.resolve(&USER_SCHEMA_V1)?;
+//!
+//! let mut writer = Writer::new(&USER_SCHEMA_V1, vec![]);
+//! writer.append(value.clone())?;
+//! let bytes = writer.into_inner()?;
+//!
+//! let users = reader(&bytes[..])?
+//!     .map(|value| Ok(from_value::<User>(&value?)?))
+//!     .collect::<Result<Vec<_>, Box<dyn std::error::Error>>>()?;
+//! assert_eq!(users, vec![User::default()]);
+//! assert_eq!(users[0].ext_is_blue_verified, None);
+//! assert_eq!(users[0].ext_verified_type, None);
+//! assert_eq!(users[0].ext_snapshot_adjusted, None);
+//!
+//! let datum = to_avro_datum(&USER_SCHEMA_V1, value)?;
+//! let user = from_value::<User>(&read_user_datum(&datum)?)?;
+//! assert_eq!(user, User::default());
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use apache_avro::{from_avro_datum, schema::Schema, types::Value};
+use std::io::Cursor;
+
+lazy_static::lazy_static! {
+    /// The original schema, without the `ext_` verification fields.
+    pub static ref USER_SCHEMA_V1: Schema = parse(include_str!("../../schemas/avro/user.avsc"));
+    /// Adds `ext_is_blue_verified` and `ext_verified_type`.
+    pub static ref USER_SCHEMA_V2: Schema =
+        parse(include_str!("../../schemas/avro/user-v2.avsc"));
+    /// Adds `ext_snapshot_adjusted`.
+    pub static ref USER_SCHEMA_V3: Schema =
+        parse(include_str!("../../schemas/avro/user-v3.avsc"));
+}
+
+/// The schema used for writing (and as the reader schema for resolution).
+pub use USER_SCHEMA_V3 as USER_SCHEMA;
+
+/// Decode a single datum (with no header) that may have been written with any schema version.
+///
+/// Datums don't carry their schema, but since fields are only appended as nullable unions, and
+/// the decoder reads a union at the end of its input as `null`, a datum written with an earlier
+/// schema decodes with the current one.
+pub fn read_user_datum(bytes: &[u8]) -> Result<Value, apache_avro::Error> {
+    from_avro_datum(&USER_SCHEMA, &mut Cursor::new(bytes), None)
+}
+
+/// Identify the user schema version that a writer schema corresponds to (by its field names).
+///
+/// The record name is the same for every version, so it can't be used to distinguish them.
+pub fn user_schema_version(schema: &Schema) -> Option<u32> {
+    let names = field_names(schema)?;
+
+    [
+        (1, &*USER_SCHEMA_V1),
+        (2, &*USER_SCHEMA_V2),
+        (3, &*USER_SCHEMA_V3),
+    ]
+    .into_iter()
+    .find(|(_, candidate)| field_names(candidate).as_ref() == Some(&names))
+    .map(|(version, _)| version)
+}
+
+fn field_names(schema: &Schema) -> Option<Vec<&str>> {
+    match schema {
+        Schema::Record { fields, .. } => {
+            Some(fields.iter().map(|field| field.name.as_str()).collect())
+        }
+        _ => None,
+    }
+}
+
+fn parse(source: &str) -> Schema {
+    Schema::parse_str(source).unwrap()
+}
@@ -0,0 +1,419 @@
+//! Per-day collection coverage, for distinguishing quiet days from days we weren't collecting.
+//!
+//! Coverage is tracked at minute resolution, so gaps shorter than a minute are not visible.
+
+use chrono::{DateTime, Duration, NaiveDate, Timelike, Utc};
+use std::collections::{BTreeMap, HashSet};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Conventional file name for coverage stored alongside an archive.
+pub const COVERAGE_FILE_NAME: &str = "coverage.json";
+
+/// The conventional coverage path for an archive (in the same directory).
+pub fn coverage_path<P: AsRef<Path>>(archive: P) -> PathBuf {
+    archive
+        .as_ref()
+        .parent()
+        .unwrap_or_else(|| Path::new(""))
+        .join(COVERAGE_FILE_NAME)
+}
+
+const MINUTES_PER_DAY: usize = 24 * 60;
+
+#[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
+pub enum Error {
+    #[error("I/O error")]
+    Io(#[from] std::io::Error),
+    #[error("JSON error")]
+    Json(#[from] serde_json::Error),
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct DayCoverage {
+    pub date: NaiveDate,
+    pub record_count: usize,
+    pub distinct_users: usize,
+    pub first_snapshot: DateTime<Utc>,
+    pub last_snapshot: DateTime<Utc>,
+    /// Longest period in the day without records (including before the first and after the last
+    /// record of the day), in seconds.
+    pub largest_gap: i64,
+    pub hourly_counts: Vec<usize>,
+}
+
+impl DayCoverage {
+    pub fn largest_gap(&self) -> Duration {
+        Duration::seconds(self.largest_gap)
+    }
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct Coverage {
+    pub days: Vec<DayCoverage>,
+}
+
+impl Coverage {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Ok(serde_json::from_reader(BufReader::new(File::open(path)?))?)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+
+        Ok(writer.flush()?)
+    }
+
+    /// Return the coverage for each day in the inclusive range (days without any records are
+    /// omitted).
+    pub fn coverage_between(&self, start: NaiveDate, end: NaiveDate) -> &[DayCoverage] {
+        let from = self.days.partition_point(|day| day.date < start);
+        let to = self.days.partition_point(|day| day.date <= end);
+
+        &self.days[from..to.max(from)]
+    }
+
+    /// Return the days in the inclusive range that have no records at all.
+    pub fn missing_days(&self, start: NaiveDate, end: NaiveDate) -> Vec<NaiveDate> {
+        let covered = self
+            .coverage_between(start, end)
+            .iter()
+            .map(|day| day.date)
+            .collect::<HashSet<_>>();
+
+        start
+            .iter_days()
+            .take_while(|date| *date <= end)
+            .filter(|date| !covered.contains(date))
+            .collect()
+    }
+
+    /// Indicates whether the hour containing the given time has at least the given number of
+    /// records.
+    pub fn is_covered(&self, at: DateTime<Utc>, min_records_per_hour: usize) -> bool {
+        let date = at.date_naive();
+
+        self.days
+            .binary_search_by_key(&date, |day| day.date)
+            .ok()
+            .and_then(|index| self.days[index].hourly_counts.get(at.hour() as usize))
+            .is_some_and(|count| *count >= min_records_per_hour)
+    }
+}
+
+struct DayState {
+    record_count: usize,
+    users: HashSet<u64>,
+    first_snapshot: DateTime<Utc>,
+    last_snapshot: DateTime<Utc>,
+    minute_counts: Vec<usize>,
+}
+
+/// Builds coverage from records in a single pass (in any order).
+#[derive(Default)]
+pub struct CoverageBuilder {
+    days: BTreeMap<NaiveDate, DayState>,
+}
+
+impl CoverageBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, snapshot: DateTime<Utc>, user_id: u64) {
+        let state = self
+            .days
+            .entry(snapshot.date_naive())
+            .or_insert_with(|| DayState {
+                record_count: 0,
+                users: HashSet::new(),
+                first_snapshot: snapshot,
+                last_snapshot: snapshot,
+                minute_counts: vec![0; MINUTES_PER_DAY],
+            });
+
+        state.record_count += 1;
+        state.users.insert(user_id);
+        state.first_snapshot = state.first_snapshot.min(snapshot);
+        state.last_snapshot = state.last_snapshot.max(snapshot);
+        state.minute_counts[(snapshot.hour() * 60 + snapshot.minute()) as usize] += 1;
+    }
+
+    /// Add every user in a snapshot extracted from a stream.
+    pub fn add_user_info(&mut self, user_info: &crate::stream::UserInfo) {
+        for user in &user_info.users {
+            self.add(user_info.snapshot, user.id as u64);
+        }
+    }
+
+    pub fn build(self) -> Coverage {
+        Coverage {
+            days: self
+                .days
+                .into_iter()
+                .map(|(date, state)| DayCoverage {
+                    date,
+                    record_count: state.record_count,
+                    distinct_users: state.users.len(),
+                    first_snapshot: state.first_snapshot,
+                    last_snapshot: state.last_snapshot,
+                    largest_gap: largest_gap(&state.minute_counts) * 60,
+                    hourly_counts: state
+                        .minute_counts
+                        .chunks(60)
+                        .map(|hour| hour.iter().sum())
+                        .collect(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Compute coverage for the contents of tar or ZIP profile archives.
+pub fn compute_coverage<P: AsRef<Path>, I: IntoIterator<Item = P>>(
+    paths: I,
+) -> Result<Coverage, crate::archive::Error> {
+    let mut builder = CoverageBuilder::new();
+
+    for path in paths {
+        let path = path.as_ref();
+        let policy = crate::ndjson::ErrorPolicy::Abort;
+        let f = |user_info: Option<crate::stream::UserInfo>| {
+            if let Some(user_info) = user_info {
+                builder.add_user_info(&user_info);
+            }
+            Ok(())
+        };
+
+        if path.extension().is_some_and(|extension| extension == "zip") {
+            crate::archive::extract_zip(path, policy, f)?;
+        } else {
+            crate::archive::extract_tar(path, policy, f)?;
+        }
+    }
+
+    Ok(builder.build())
+}
+
+/// The longest run of empty minutes (including runs at the start or end of the day).
+fn largest_gap(minute_counts: &[usize]) -> i64 {
+    let mut largest = 0;
+    let mut current = 0;
+
+    for count in minute_counts {
+        if *count == 0 {
+            current += 1;
+            largest = largest.max(current);
+        } else {
+            current = 0;
+        }
+    }
+
+    largest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn timestamp(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(year, month, day, hour, minute, 0)
+            .single()
+            .unwrap()
+    }
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    fn coverage() -> Coverage {
+        let mut builder = CoverageBuilder::new();
+
+        // Out of order, with a repeated user.
+        builder.add(timestamp(2022, 1, 3, 12, 0), 2);
+        builder.add(timestamp(2022, 1, 1, 0, 0), 1);
+        builder.add(timestamp(2022, 1, 1, 0, 30), 1);
+        builder.add(timestamp(2022, 1, 1, 23, 59), 2);
+        builder.add(timestamp(2022, 1, 3, 6, 15), 3);
+
+        builder.build()
+    }
+
+    #[test]
+    fn build_days() {
+        let coverage = coverage();
+
+        assert_eq!(coverage.days.len(), 2);
+
+        let first = &coverage.days[0];
+
+        assert_eq!(first.date, date(2022, 1, 1));
+        assert_eq!(first.record_count, 3);
+        assert_eq!(first.distinct_users, 2);
+        assert_eq!(first.first_snapshot, timestamp(2022, 1, 1, 0, 0));
+        assert_eq!(first.last_snapshot, timestamp(2022, 1, 1, 23, 59));
+        assert_eq!(first.hourly_counts.len(), 24);
+        assert_eq!(first.hourly_counts[0], 2);
+        assert_eq!(first.hourly_counts[23], 1);
+        assert_eq!(first.hourly_counts.iter().sum::<usize>(), 3);
+        // Between 00:31 and 23:58.
+        assert_eq!(first.largest_gap(), Duration::minutes(23 * 60 + 28));
+
+        let second = &coverage.days[1];
+
+        assert_eq!(second.date, date(2022, 1, 3));
+        // From 12:01 to the end of the day.
+        assert_eq!(second.largest_gap(), Duration::minutes(12 * 60 - 1));
+    }
+
+    #[test]
+    fn missing_days_and_ranges() {
+        let coverage = coverage();
+
+        assert_eq!(
+            coverage.missing_days(date(2021, 12, 31), date(2022, 1, 4)),
+            vec![date(2021, 12, 31), date(2022, 1, 2), date(2022, 1, 4)]
+        );
+        assert_eq!(
+            coverage.coverage_between(date(2022, 1, 2), date(2022, 1, 3)),
+            &coverage.days[1..]
+        );
+        assert!(coverage
+            .coverage_between(date(2022, 1, 4), date(2022, 1, 1))
+            .is_empty());
+    }
+
+    #[test]
+    fn is_covered() {
+        let coverage = coverage();
+
+        assert!(coverage.is_covered(timestamp(2022, 1, 1, 0, 45), 2));
+        assert!(!coverage.is_covered(timestamp(2022, 1, 1, 0, 45), 3));
+        assert!(!coverage.is_covered(timestamp(2022, 1, 1, 1, 0), 1));
+        assert!(!coverage.is_covered(timestamp(2022, 1, 2, 12, 0), 0));
+    }
+
+    #[test]
+    fn save_and_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(COVERAGE_FILE_NAME);
+        let coverage = coverage();
+
+        coverage.save(&path).unwrap();
+
+        assert_eq!(Coverage::load(&path).unwrap(), coverage);
+    }
+
+    #[test]
+    fn path_next_to_archive() {
+        assert_eq!(
+            coverage_path("data/2022-01.tar"),
+            Path::new("data").join(COVERAGE_FILE_NAME)
+        );
+        assert_eq!(coverage_path("2022-01.tar"), Path::new(COVERAGE_FILE_NAME));
+    }
+
+    /// Bzip2-compressed stream lines with a single user each.
+    fn compressed_lines(records: &[(DateTime<Utc>, i64)]) -> Vec<u8> {
+        use bzip2::{write::BzEncoder, Compression};
+
+        let mut encoder = BzEncoder::new(vec![], Compression::default());
+
+        for (snapshot, id) in records {
+            let user = crate::model::User {
+                id: *id,
+                id_str: id.to_string(),
+                screen_name: format!("user_{}", id),
+                ..crate::model::User::default()
+            };
+            let status = serde_json::json!({
+                "timestamp_ms": snapshot.timestamp_millis().to_string(),
+                "user": serde_json::to_value(user).unwrap(),
+            });
+
+            serde_json::to_writer(&mut encoder, &status).unwrap();
+            encoder.write_all(b"\n").unwrap();
+        }
+
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn compute_from_archives() {
+        let dir = tempfile::tempdir().unwrap();
+        let tar_path = dir.path().join("2022-01-01.tar");
+        let zip_path = dir.path().join("2022-01-03.zip");
+
+        // A full day, followed by an outage day and a day on which collection stopped after 01:00.
+        let full_day = compressed_lines(&[
+            (timestamp(2022, 1, 1, 0, 0), 1),
+            (timestamp(2022, 1, 1, 6, 0), 2),
+            (timestamp(2022, 1, 1, 12, 0), 1),
+            (timestamp(2022, 1, 1, 18, 0), 3),
+            (timestamp(2022, 1, 1, 23, 59), 2),
+        ]);
+        let partial_day = compressed_lines(&[
+            (timestamp(2022, 1, 3, 0, 10), 1),
+            (timestamp(2022, 1, 3, 0, 20), 2),
+            (timestamp(2022, 1, 3, 1, 0), 1),
+        ]);
+
+        let mut builder = tar::Builder::new(File::create(&tar_path).unwrap());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(full_day.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, "2022-01-01.json.bz2", &full_day[..])
+            .unwrap();
+        builder.finish().unwrap();
+
+        let mut writer = zip::ZipWriter::new(File::create(&zip_path).unwrap());
+        writer
+            .start_file(
+                "2022-01-03.json.bz2",
+                zip::write::FileOptions::default()
+                    .compression_method(zip::CompressionMethod::Stored),
+            )
+            .unwrap();
+        writer.write_all(&partial_day).unwrap();
+        writer.finish().unwrap();
+
+        let coverage = compute_coverage([&tar_path, &zip_path]).unwrap();
+
+        assert_eq!(
+            coverage.missing_days(date(2022, 1, 1), date(2022, 1, 3)),
+            vec![date(2022, 1, 2)]
+        );
+        assert_eq!(coverage.days.len(), 2);
+
+        let full = &coverage.days[0];
+
+        assert_eq!(full.record_count, 5);
+        assert_eq!(full.distinct_users, 3);
+        assert_eq!(full.largest_gap(), Duration::minutes(6 * 60 - 1));
+        assert!(coverage.is_covered(timestamp(2022, 1, 1, 18, 30), 1));
+
+        let partial = &coverage.days[1];
+
+        assert_eq!(partial.date, date(2022, 1, 3));
+        assert_eq!(partial.record_count, 3);
+        assert_eq!(partial.distinct_users, 2);
+        assert_eq!(partial.last_snapshot, timestamp(2022, 1, 3, 1, 0));
+        assert_eq!(&partial.hourly_counts[..3], &[2, 1, 0]);
+        // From 01:01 to the end of the day.
+        assert_eq!(partial.largest_gap(), Duration::minutes(23 * 60 - 1));
+        assert!(!coverage.is_covered(timestamp(2022, 1, 3, 12, 0), 1));
+
+        coverage.save(coverage_path(&tar_path)).unwrap();
+
+        assert_eq!(
+            Coverage::load(dir.path().join(COVERAGE_FILE_NAME)).unwrap(),
+            coverage
+        );
+    }
+}
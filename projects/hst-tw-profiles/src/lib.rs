@@ -1,6 +1,48 @@
 //! Library for working with user profiles from the Twitter API.
+//!
+//! The [`prelude`] module re-exports the types and functions needed for reading and writing
+//! profile data.
 
 pub mod archive;
 pub mod avro;
+pub mod coverage;
 pub mod model;
+
+/// Structured comparisons between profile snapshots.
+pub mod diff {
+    pub use super::model::{diff, FieldChange, ProfileDiff};
+}
+
+pub mod names;
+pub mod ndjson;
+pub mod similarity;
 pub mod stream;
+pub mod text_diff;
+
+/// Commonly used types and functions (with error types renamed to avoid collisions).
+///
+/// ```rust
+/// use hst_tw_profiles::prelude::*;
+///
+/// let mut writer = avro_writer(vec![]);
+/// writer.append_ser(User::default())?;
+/// let bytes = writer.into_inner()?;
+///
+/// let users = avro_reader(&bytes[..])?.count();
+/// assert_eq!(users, 1);
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub mod prelude {
+    pub use super::archive::{extract_tar, extract_zip, Error as ArchiveError};
+    pub use super::avro::{
+        reader as avro_reader, writer as avro_writer, ChunkedAvroReader, ChunkedAvroWriter,
+        Error as AvroError, USER_SCHEMA,
+    };
+    pub use super::model::User;
+    pub use super::ndjson::{ErrorPolicy, ErrorSummary};
+    pub use super::stream::compliance::{
+        apply_compliance, extract_compliance_event, ComplianceEvent,
+    };
+    pub use super::stream::withheld::{extract_withheld_info, WithheldEvent, WithheldInfo};
+    pub use super::stream::{extract_user_info, Error as StreamError, PartialUser, UserInfo};
+}
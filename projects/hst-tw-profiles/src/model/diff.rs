@@ -0,0 +1,500 @@
+//! Field-by-field differences between two snapshots of a profile.
+
+use super::User;
+use crate::text_diff::TextDiff;
+use std::fmt::Write;
+
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum FieldChange {
+    /// A name or description change (with a token-level summary).
+    Text {
+        field: &'static str,
+        old: Option<String>,
+        new: Option<String>,
+        summary: TextDiff,
+    },
+    Count {
+        field: &'static str,
+        old: i64,
+        new: i64,
+        delta: i64,
+    },
+    /// Any other scalar field (rendered as a string).
+    Value {
+        field: &'static str,
+        old: Option<String>,
+        new: Option<String>,
+    },
+    /// A list field compared as a set.
+    Set {
+        field: &'static str,
+        added: Vec<String>,
+        removed: Vec<String>,
+    },
+}
+
+impl FieldChange {
+    pub fn field(&self) -> &'static str {
+        match self {
+            Self::Text { field, .. }
+            | Self::Count { field, .. }
+            | Self::Value { field, .. }
+            | Self::Set { field, .. } => field,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize)]
+pub struct ProfileDiff {
+    pub user_id: u64,
+    pub from_snapshot: i64,
+    pub to_snapshot: i64,
+    /// Only changed fields are included.
+    pub changes: Vec<FieldChange>,
+}
+
+impl ProfileDiff {
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// Render the changes as a compact Markdown table.
+    pub fn to_markdown(&self) -> String {
+        if self.changes.is_empty() {
+            return "No changes.\n".to_string();
+        }
+
+        let mut output = String::from("| Field | Old | New | Change |\n|---|---|---|---|\n");
+
+        for change in &self.changes {
+            let (old, new, summary) = match change {
+                FieldChange::Text {
+                    old, new, summary, ..
+                } => (
+                    old.clone().unwrap_or_default(),
+                    new.clone().unwrap_or_default(),
+                    text_summary(summary),
+                ),
+                FieldChange::Count {
+                    old, new, delta, ..
+                } => (old.to_string(), new.to_string(), format!("{:+}", delta)),
+                FieldChange::Value { old, new, .. } => (
+                    old.clone().unwrap_or_default(),
+                    new.clone().unwrap_or_default(),
+                    String::new(),
+                ),
+                FieldChange::Set { added, removed, .. } => (
+                    String::new(),
+                    String::new(),
+                    added
+                        .iter()
+                        .map(|value| format!("+{}", value))
+                        .chain(removed.iter().map(|value| format!("-{}", value)))
+                        .collect::<Vec<_>>()
+                        .join(" "),
+                ),
+            };
+
+            // Writing to a string can't fail.
+            let _ = writeln!(
+                output,
+                "| {} | {} | {} | {} |",
+                change.field(),
+                escape(&old),
+                escape(&new),
+                escape(&summary)
+            );
+        }
+
+        output
+    }
+}
+
+/// Compute the changes from `a` to `b` (the user ID and snapshot fields are not compared).
+pub fn diff(a: &User, b: &User) -> ProfileDiff {
+    let mut changes = vec![];
+
+    text(&mut changes, "name", Some(&a.name), Some(&b.name));
+    value(
+        &mut changes,
+        "screen_name",
+        Some(&a.screen_name),
+        Some(&b.screen_name),
+    );
+    text(
+        &mut changes,
+        "description",
+        a.description.as_ref(),
+        b.description.as_ref(),
+    );
+    value(
+        &mut changes,
+        "location",
+        a.location.as_ref(),
+        b.location.as_ref(),
+    );
+    value(&mut changes, "url", a.expanded_url(), b.expanded_url());
+    value(
+        &mut changes,
+        "protected",
+        Some(a.protected),
+        Some(b.protected),
+    );
+    value(&mut changes, "verified", Some(a.verified), Some(b.verified));
+    value(
+        &mut changes,
+        "ext_is_blue_verified",
+        a.ext_is_blue_verified,
+        b.ext_is_blue_verified,
+    );
+    value(
+        &mut changes,
+        "ext_verified_type",
+        a.ext_verified_type.as_ref(),
+        b.ext_verified_type.as_ref(),
+    );
+    count(
+        &mut changes,
+        "followers_count",
+        a.followers_count,
+        b.followers_count,
+    );
+    count(
+        &mut changes,
+        "friends_count",
+        a.friends_count,
+        b.friends_count,
+    );
+    count(&mut changes, "listed_count", a.listed_count, b.listed_count);
+    count(
+        &mut changes,
+        "favourites_count",
+        a.favourites_count,
+        b.favourites_count,
+    );
+    count(
+        &mut changes,
+        "statuses_count",
+        a.statuses_count,
+        b.statuses_count,
+    );
+    value(
+        &mut changes,
+        "profile_image_url_https",
+        Some(&a.profile_image_url_https),
+        Some(&b.profile_image_url_https),
+    );
+    value(
+        &mut changes,
+        "profile_banner_url",
+        a.profile_banner_url.as_ref(),
+        b.profile_banner_url.as_ref(),
+    );
+    value(
+        &mut changes,
+        "default_profile_image",
+        Some(a.default_profile_image),
+        Some(b.default_profile_image),
+    );
+    value(&mut changes, "lang", a.lang.as_ref(), b.lang.as_ref());
+    value(
+        &mut changes,
+        "time_zone",
+        a.time_zone.as_ref(),
+        b.time_zone.as_ref(),
+    );
+    value(
+        &mut changes,
+        "withheld_scope",
+        a.withheld_scope.as_ref(),
+        b.withheld_scope.as_ref(),
+    );
+    set(
+        &mut changes,
+        "withheld_in_countries",
+        &a.withheld_in_countries,
+        &b.withheld_in_countries,
+    );
+
+    ProfileDiff {
+        user_id: b.id(),
+        from_snapshot: a.snapshot,
+        to_snapshot: b.snapshot,
+        changes,
+    }
+}
+
+fn text<T: AsRef<str>>(
+    changes: &mut Vec<FieldChange>,
+    field: &'static str,
+    old: Option<T>,
+    new: Option<T>,
+) {
+    let old = old.map(|value| value.as_ref().to_string());
+    let new = new.map(|value| value.as_ref().to_string());
+
+    if old != new {
+        let summary = crate::text_diff::diff(
+            old.as_deref().unwrap_or_default(),
+            new.as_deref().unwrap_or_default(),
+        );
+
+        changes.push(FieldChange::Text {
+            field,
+            old,
+            new,
+            summary,
+        });
+    }
+}
+
+fn value<T: ToString>(
+    changes: &mut Vec<FieldChange>,
+    field: &'static str,
+    old: Option<T>,
+    new: Option<T>,
+) {
+    let old = old.map(|value| value.to_string());
+    let new = new.map(|value| value.to_string());
+
+    if old != new {
+        changes.push(FieldChange::Value { field, old, new });
+    }
+}
+
+fn count(changes: &mut Vec<FieldChange>, field: &'static str, old: i64, new: i64) {
+    if old != new {
+        changes.push(FieldChange::Count {
+            field,
+            old,
+            new,
+            delta: new - old,
+        });
+    }
+}
+
+fn set(changes: &mut Vec<FieldChange>, field: &'static str, old: &[String], new: &[String]) {
+    let mut added = new
+        .iter()
+        .filter(|value| !old.contains(value))
+        .cloned()
+        .collect::<Vec<_>>();
+    let mut removed = old
+        .iter()
+        .filter(|value| !new.contains(value))
+        .cloned()
+        .collect::<Vec<_>>();
+
+    added.sort();
+    added.dedup();
+    removed.sort();
+    removed.dedup();
+
+    if !added.is_empty() || !removed.is_empty() {
+        changes.push(FieldChange::Set {
+            field,
+            added,
+            removed,
+        });
+    }
+}
+
+fn text_summary(summary: &TextDiff) -> String {
+    if summary.formatting_only {
+        return "formatting only".to_string();
+    }
+
+    let mut parts = vec![];
+
+    for (label, values) in [
+        ("added", &summary.added),
+        ("removed", &summary.removed),
+        ("added emoji", &summary.added_emoji),
+        ("removed emoji", &summary.removed_emoji),
+    ] {
+        if !values.is_empty() {
+            parts.push(format!("{}: {}", label, values.join(" ")));
+        }
+    }
+
+    parts.join("; ")
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('|', "\\|")
+        .replace("\r\n", "<br>")
+        .replace('\n', "<br>")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user() -> User {
+        User {
+            id: 1,
+            id_str: "1".to_string(),
+            name: "Jane Doe".to_string(),
+            screen_name: "jane".to_string(),
+            description: Some("Writer and gardener".to_string()),
+            followers_count: 100,
+            withheld_in_countries: vec!["DE".to_string(), "FR".to_string()],
+            snapshot: 100,
+            ..User::default()
+        }
+    }
+
+    #[test]
+    fn identical_snapshots() {
+        let mut b = user();
+        b.snapshot = 200;
+
+        let diff = diff(&user(), &b);
+
+        assert!(diff.is_empty());
+        assert_eq!(diff.user_id, 1);
+        assert_eq!((diff.from_snapshot, diff.to_snapshot), (100, 200));
+        assert_eq!(diff.to_markdown(), "No changes.\n");
+    }
+
+    #[test]
+    fn changed_fields() {
+        let a = user();
+        let mut b = user();
+        b.name = "Jane  Doe!".to_string();
+        b.screen_name = "jane_doe".to_string();
+        b.description = Some("Writer and | baker".to_string());
+        b.followers_count = 90;
+        b.verified = true;
+        b.ext_verified_type = Some("Business".to_string());
+        b.withheld_in_countries = vec!["FR".to_string(), "IT".to_string(), "IT".to_string()];
+
+        let diff = diff(&a, &b);
+        let fields = diff
+            .changes
+            .iter()
+            .map(|change| change.field())
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            fields,
+            vec![
+                "name",
+                "screen_name",
+                "description",
+                "verified",
+                "ext_verified_type",
+                "followers_count",
+                "withheld_in_countries"
+            ]
+        );
+        assert!(matches!(
+            &diff.changes[0],
+            FieldChange::Text { summary, .. } if summary.formatting_only
+        ));
+        assert_eq!(
+            diff.changes[3],
+            FieldChange::Value {
+                field: "verified",
+                old: Some("false".to_string()),
+                new: Some("true".to_string()),
+            }
+        );
+        assert_eq!(
+            diff.changes[4],
+            FieldChange::Value {
+                field: "ext_verified_type",
+                old: None,
+                new: Some("Business".to_string()),
+            }
+        );
+        assert_eq!(
+            diff.changes[5],
+            FieldChange::Count {
+                field: "followers_count",
+                old: 100,
+                new: 90,
+                delta: -10,
+            }
+        );
+        assert_eq!(
+            diff.changes[6],
+            FieldChange::Set {
+                field: "withheld_in_countries",
+                added: vec!["IT".to_string()],
+                removed: vec!["DE".to_string()],
+            }
+        );
+
+        assert_eq!(
+            diff.to_markdown(),
+            "| Field | Old | New | Change |\n\
+             |---|---|---|---|\n\
+             | name | Jane Doe | Jane  Doe! | formatting only |\n\
+             | screen_name | jane | jane_doe |  |\n\
+             | description | Writer and gardener | Writer and \\| baker | added: baker; removed: gardener |\n\
+             | verified | false | true |  |\n\
+             | ext_verified_type |  | Business |  |\n\
+             | followers_count | 100 | 90 | -10 |\n\
+             | withheld_in_countries |  |  | +IT -DE |\n"
+        );
+    }
+
+    #[test]
+    fn counts_only() {
+        let a = user();
+        let mut b = user();
+        b.followers_count = 95;
+        b.friends_count = 5;
+        b.statuses_count = 1_000;
+
+        assert_eq!(
+            diff(&a, &b).to_markdown(),
+            "| Field | Old | New | Change |\n\
+             |---|---|---|---|\n\
+             | followers_count | 100 | 95 | -5 |\n\
+             | friends_count | 0 | 5 | +5 |\n\
+             | statuses_count | 0 | 1000 | +1000 |\n"
+        );
+    }
+
+    #[test]
+    fn removed_description() {
+        let a = user();
+        let mut b = user();
+        b.description = None;
+
+        let diff = diff(&a, &b);
+
+        assert_eq!(diff.changes.len(), 1);
+        assert!(matches!(
+            &diff.changes[0],
+            FieldChange::Text { old: Some(_), new: None, summary, .. }
+                if summary.removed == vec!["Writer", "and", "gardener"]
+        ));
+        assert!(diff
+            .to_markdown()
+            .contains("| description | Writer and gardener |  |"));
+    }
+
+    #[test]
+    fn serialization() {
+        let mut b = user();
+        b.followers_count = 101;
+
+        let json = serde_json::to_value(diff(&user(), &b)).unwrap();
+
+        assert_eq!(
+            json["changes"],
+            serde_json::json!([{
+                "kind": "count",
+                "field": "followers_count",
+                "old": 100,
+                "new": 101,
+                "delta": 1
+            }])
+        );
+    }
+}
@@ -1,5 +1,9 @@
 use chrono::{DateTime, Utc};
 
+mod diff;
+
+pub use diff::{diff, FieldChange, ProfileDiff};
+
 #[derive(Debug, Default, Eq, PartialEq, Clone, serde::Deserialize, serde::Serialize)]
 #[serde(default)]
 pub struct Url {
@@ -66,6 +70,11 @@ pub struct User {
     pub withheld_scope: Option<String>,
     pub withheld_in_countries: Vec<String>,
     pub snapshot: i64,
+    pub ext_is_blue_verified: Option<bool>,
+    pub ext_verified_type: Option<String>,
+    /// Set when the snapshot was moved forward to avoid a collision with a different version
+    /// observed in the same second.
+    pub ext_snapshot_adjusted: Option<bool>,
 }
 
 impl User {
@@ -0,0 +1,146 @@
+//! Normalization of screen names and display names for matching.
+//!
+//! Normalized forms are only intended for comparison and indexing, and the original value should
+//! be kept for display. We normalize encoding (NFC, and invisible formatting characters), but we
+//! don't map visually confusable characters (e.g. Cyrillic `а` and Latin `a` stay distinct).
+
+use crate::text_diff::is_emoji_char;
+use unicode_normalization::UnicodeNormalization;
+
+/// Normalize a screen name for matching.
+///
+/// Screen names are case-insensitive, so they're also lowercased.
+///
+/// ```rust
+/// use hst_tw_profiles::names::normalize_screen_name;
+///
+/// assert_eq!(normalize_screen_name("Foo\u{200b}Bar"), "foobar");
+/// ```
+pub fn normalize_screen_name(input: &str) -> String {
+    normalize_display_name(input).to_lowercase()
+}
+
+/// Normalize a display name (or other free text) for matching.
+///
+/// Zero-width and bidirectional control characters are removed, except that zero-width joiners
+/// between emoji are kept, so that emoji sequences remain intact.
+///
+/// ```rust
+/// use hst_tw_profiles::names::normalize_display_name;
+///
+/// // The decomposed form of "é" and a right-to-left override.
+/// assert_eq!(normalize_display_name("Jose\u{301} \u{202e}Smith"), "José Smith");
+/// ```
+pub fn normalize_display_name(input: &str) -> String {
+    let chars = input.nfc().collect::<Vec<_>>();
+
+    chars
+        .iter()
+        .enumerate()
+        .filter(|(index, c)| {
+            if **c == '\u{200d}' {
+                index
+                    .checked_sub(1)
+                    .and_then(|previous| chars.get(previous))
+                    .is_some_and(|c| is_emoji_char(*c))
+                    && chars.get(index + 1).is_some_and(|c| is_emoji_char(*c))
+            } else {
+                !is_invisible_control(**c)
+            }
+        })
+        .map(|(_, c)| *c)
+        .collect()
+}
+
+/// Zero-width formatting characters and bidirectional controls.
+fn is_invisible_control(c: char) -> bool {
+    matches!(
+        c,
+        '\u{00ad}'
+            | '\u{061c}'
+            | '\u{180e}'
+            | '\u{200b}'..='\u{200f}'
+            | '\u{202a}'..='\u{202e}'
+            | '\u{2060}'..='\u{2064}'
+            | '\u{2066}'..='\u{2069}'
+            | '\u{feff}'
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Input, normalized display name, and normalized screen name.
+    const CASES: &[(&str, &str, &str)] = &[
+        ("plain", "plain", "plain"),
+        ("MixedCase", "MixedCase", "mixedcase"),
+        // NFD and NFC forms of the same name.
+        ("Jose\u{301}", "Jos\u{e9}", "jos\u{e9}"),
+        ("Jos\u{e9}", "Jos\u{e9}", "jos\u{e9}"),
+        (
+            "A\u{30a}ngstro\u{308}m",
+            "\u{c5}ngstr\u{f6}m",
+            "\u{e5}ngstr\u{f6}m",
+        ),
+        // Zero-width characters.
+        ("foo\u{200b}bar", "foobar", "foobar"),
+        ("foo\u{200c}bar", "foobar", "foobar"),
+        ("\u{feff}foo", "foo", "foo"),
+        ("foo\u{2060}bar\u{ad}", "foobar", "foobar"),
+        // Joiners are only kept between emoji.
+        ("foo\u{200d}bar", "foobar", "foobar"),
+        ("\u{200d}\u{1F525}", "\u{1F525}", "\u{1F525}"),
+        (
+            "\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}",
+            "\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}",
+            "\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}",
+        ),
+        (
+            "\u{1F3F3}\u{FE0F}\u{200D}\u{1F308}",
+            "\u{1F3F3}\u{FE0F}\u{200D}\u{1F308}",
+            "\u{1F3F3}\u{FE0F}\u{200D}\u{1F308}",
+        ),
+        // Bidirectional controls.
+        ("\u{202e}evil\u{202c}", "evil", "evil"),
+        ("\u{2067}name\u{2069}", "name", "name"),
+        ("a\u{200f}b\u{061c}c", "abc", "abc"),
+        // Confusable homoglyphs are left alone (Cyrillic "а" and Greek "Ο").
+        ("p\u{430}ypal", "p\u{430}ypal", "p\u{430}ypal"),
+        (
+            "G\u{39f}\u{39f}GLE",
+            "G\u{39f}\u{39f}GLE",
+            "g\u{3bf}\u{3bf}gle",
+        ),
+        ("", "", ""),
+    ];
+
+    #[test]
+    fn normalizes_adversarial_inputs() {
+        for (input, display_name, screen_name) in CASES {
+            assert_eq!(
+                normalize_display_name(input),
+                *display_name,
+                "display name for {:?}",
+                input
+            );
+            assert_eq!(
+                normalize_screen_name(input),
+                *screen_name,
+                "screen name for {:?}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn normalization_is_idempotent() {
+        for (input, _, _) in CASES {
+            let display_name = normalize_display_name(input);
+            let screen_name = normalize_screen_name(input);
+
+            assert_eq!(normalize_display_name(&display_name), display_name);
+            assert_eq!(normalize_screen_name(&screen_name), screen_name);
+        }
+    }
+}
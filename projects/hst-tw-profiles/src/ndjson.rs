@@ -0,0 +1,505 @@
+//! Line-oriented reading with positional context for parse failures.
+
+use std::collections::BTreeMap;
+use std::fmt::Formatter;
+use std::io::BufRead;
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+
+/// Maximum number of characters of the offending line included in a context.
+pub const SNIPPET_LENGTH: usize = 200;
+
+/// Number of lines sent to a worker at once by [`parse_parallel`].
+const PARALLEL_BATCH_SIZE: usize = 1024;
+
+/// The location and (truncated) contents of a line that failed to parse.
+#[derive(Clone, Debug, Eq, PartialEq, serde::Serialize)]
+pub struct LineContext {
+    pub path: String,
+    /// 1-based line number.
+    pub line_number: usize,
+    /// Offset of the start of the line in the (decompressed) input.
+    pub byte_offset: u64,
+    pub snippet: String,
+}
+
+impl std::fmt::Display for LineContext {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}:{} (byte {}): {}",
+            self.path, self.line_number, self.byte_offset, self.snippet
+        )
+    }
+}
+
+/// A single line (without its terminator) and its position in the input.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Line {
+    pub line_number: usize,
+    pub byte_offset: u64,
+    pub bytes: Vec<u8>,
+}
+
+impl Line {
+    pub fn as_str(&self) -> Result<&str, std::str::Utf8Error> {
+        std::str::from_utf8(&self.bytes)
+    }
+
+    /// Build the context for this line (this is only intended to be done on failure).
+    pub fn context<P: Into<String>>(&self, path: P) -> LineContext {
+        let snippet = String::from_utf8_lossy(&self.bytes)
+            .chars()
+            .take(SNIPPET_LENGTH)
+            .collect();
+
+        LineContext {
+            path: path.into(),
+            line_number: self.line_number,
+            byte_offset: self.byte_offset,
+            snippet,
+        }
+    }
+}
+
+/// Iterator over the lines of a reader that tracks line numbers and byte offsets.
+///
+/// Unlike `BufRead::lines`, invalid UTF-8 doesn't end iteration, since the line is returned as
+/// bytes.
+pub struct Lines<R> {
+    reader: R,
+    line_number: usize,
+    byte_offset: u64,
+}
+
+impl<R: BufRead> Lines<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            line_number: 0,
+            byte_offset: 0,
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for Lines<R> {
+    type Item = Result<Line, std::io::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut bytes = vec![];
+
+        match self.reader.read_until(b'\n', &mut bytes) {
+            Ok(0) => None,
+            Ok(count) => {
+                let byte_offset = self.byte_offset;
+
+                self.line_number += 1;
+                self.byte_offset += count as u64;
+
+                if bytes.last() == Some(&b'\n') {
+                    bytes.pop();
+
+                    if bytes.last() == Some(&b'\r') {
+                        bytes.pop();
+                    }
+                }
+
+                Some(Ok(Line {
+                    line_number: self.line_number,
+                    byte_offset,
+                    bytes,
+                }))
+            }
+            Err(error) => Some(Err(error)),
+        }
+    }
+}
+
+type Batch<T, E> = (usize, Vec<Result<T, E>>);
+
+/// Parse lines on the given number of worker threads, yielding the results in input order.
+///
+/// Lines are read on a separate thread, and at most a few batches per worker are buffered, so
+/// memory use doesn't depend on the size of the input. A failure to parse a line is returned as
+/// an error for that line, but a read error ends the iteration.
+///
+/// ```rust
+/// use hst_tw_profiles::ndjson;
+///
+/// let input = "{\"id\":1,\"screen_name\":\"a\"}\nnot json\n{\"id\":2,\"screen_name\":\"b\"}\n";
+/// let results = ndjson::parse_parallel(ndjson::Lines::new(input.as_bytes()), 4, |line| {
+///     serde_json::from_slice::<serde_json::Value>(&line.bytes)
+///         .map(|value| value["id"].as_u64())
+///         .map_err(std::io::Error::other)
+/// })
+/// .collect::<Vec<_>>();
+///
+/// assert_eq!(results.len(), 3);
+/// assert_eq!(results[0].as_ref().ok(), Some(&Some(1)));
+/// assert!(results[1].is_err());
+/// assert_eq!(results[2].as_ref().ok(), Some(&Some(2)));
+/// ```
+pub fn parse_parallel<R, T, E, F>(lines: Lines<R>, threads: usize, parse: F) -> ParallelParse<T, E>
+where
+    R: BufRead + Send + 'static,
+    T: Send + 'static,
+    E: From<std::io::Error> + Send + 'static,
+    F: Fn(&Line) -> Result<T, E> + Send + Sync + 'static,
+{
+    let threads = threads.max(1);
+    let (line_sender, line_receiver) = sync_channel::<(usize, Vec<Line>)>(threads * 2);
+    let (result_sender, result_receiver) = sync_channel(threads * 2);
+    let line_receiver = Arc::new(Mutex::new(line_receiver));
+    let parse = Arc::new(parse);
+
+    for _ in 0..threads {
+        let line_receiver = line_receiver.clone();
+        let result_sender = result_sender.clone();
+        let parse = parse.clone();
+
+        std::thread::spawn(move || loop {
+            // The lock is only held while waiting for the next batch.
+            let next = line_receiver
+                .lock()
+                .ok()
+                .and_then(|receiver| receiver.recv().ok());
+
+            match next {
+                Some((index, batch)) => {
+                    let results = batch.iter().map(|line| parse(line)).collect();
+
+                    // The consumer has been dropped.
+                    if result_sender.send((index, results)).is_err() {
+                        break;
+                    }
+                }
+                None => break,
+            }
+        });
+    }
+
+    std::thread::spawn(move || read_batches(lines, line_sender, result_sender));
+
+    ParallelParse {
+        receiver: result_receiver,
+        pending: BTreeMap::new(),
+        next_index: 0,
+        current: vec![].into_iter(),
+    }
+}
+
+/// Send batches of lines to the workers, or a read error directly to the consumer.
+fn read_batches<R: BufRead, T, E: From<std::io::Error>>(
+    lines: Lines<R>,
+    line_sender: SyncSender<(usize, Vec<Line>)>,
+    result_sender: SyncSender<Batch<T, E>>,
+) {
+    let mut index = 0;
+    let mut batch = Vec::with_capacity(PARALLEL_BATCH_SIZE);
+
+    for line in lines {
+        match line {
+            Ok(line) => {
+                batch.push(line);
+
+                if batch.len() == PARALLEL_BATCH_SIZE {
+                    let full_batch =
+                        std::mem::replace(&mut batch, Vec::with_capacity(PARALLEL_BATCH_SIZE));
+
+                    if line_sender.send((index, full_batch)).is_err() {
+                        return;
+                    }
+                    index += 1;
+                }
+            }
+            Err(error) => {
+                if !batch.is_empty() {
+                    if line_sender.send((index, batch)).is_err() {
+                        return;
+                    }
+                    index += 1;
+                }
+
+                let _ = result_sender.send((index, vec![Err(E::from(error))]));
+                return;
+            }
+        }
+    }
+
+    if !batch.is_empty() {
+        let _ = line_sender.send((index, batch));
+    }
+}
+
+/// Results of [`parse_parallel`] (the worker threads stop when this is dropped).
+pub struct ParallelParse<T, E> {
+    receiver: Receiver<Batch<T, E>>,
+    /// Batches that have been received before their predecessors.
+    pending: BTreeMap<usize, Vec<Result<T, E>>>,
+    next_index: usize,
+    current: std::vec::IntoIter<Result<T, E>>,
+}
+
+impl<T, E> Iterator for ParallelParse<T, E> {
+    type Item = Result<T, E>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(result) = self.current.next() {
+                return Some(result);
+            }
+
+            let batch = match self.pending.remove(&self.next_index) {
+                Some(batch) => batch,
+                None => loop {
+                    // This fails when the reader and all workers are done.
+                    let (index, batch) = self.receiver.recv().ok()?;
+
+                    if index == self.next_index {
+                        break batch;
+                    }
+                    self.pending.insert(index, batch);
+                },
+            };
+
+            self.next_index += 1;
+            self.current = batch.into_iter();
+        }
+    }
+}
+
+/// How lines that fail to parse are handled (e.g. by [`crate::archive::extract_tar`]).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ErrorPolicy {
+    /// Stop at the first failure.
+    Abort,
+    /// Continue past failures, keeping the contexts of at most the given number.
+    Collect(usize),
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, serde::Serialize)]
+pub struct LineFailure {
+    pub context: LineContext,
+    pub message: String,
+}
+
+/// Summary of the failures seen during a run, for inclusion in run logs.
+#[derive(Clone, Debug, Eq, PartialEq, serde::Serialize)]
+pub struct ErrorSummary {
+    pub error_count: usize,
+    pub failures: Vec<LineFailure>,
+    #[serde(skip)]
+    policy: ErrorPolicy,
+}
+
+impl ErrorSummary {
+    pub fn new(policy: ErrorPolicy) -> Self {
+        Self {
+            error_count: 0,
+            failures: vec![],
+            policy,
+        }
+    }
+
+    /// Record a failure, returning `false` if processing should stop.
+    pub fn record<E: std::error::Error>(&mut self, context: LineContext, error: &E) -> bool {
+        self.error_count += 1;
+
+        match self.policy {
+            ErrorPolicy::Abort => false,
+            ErrorPolicy::Collect(max_failures) => {
+                if self.failures.len() < max_failures {
+                    let mut message = error.to_string();
+                    let mut source = error.source();
+
+                    while let Some(error) = source {
+                        message.push_str(": ");
+                        message.push_str(&error.to_string());
+                        source = error.source();
+                    }
+
+                    self.failures.push(LineFailure { context, message });
+                }
+
+                true
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(input: &[u8]) -> Vec<Line> {
+        Lines::new(input).collect::<Result<Vec<_>, _>>().unwrap()
+    }
+
+    #[test]
+    fn lines_track_positions() {
+        let lines = lines(b"abc\r\n\nd\xffe\nlast");
+
+        let positions = lines
+            .iter()
+            .map(|line| (line.line_number, line.byte_offset, line.bytes.clone()))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            positions,
+            vec![
+                (1, 0, b"abc".to_vec()),
+                (2, 5, vec![]),
+                (3, 6, b"d\xffe".to_vec()),
+                (4, 10, b"last".to_vec()),
+            ]
+        );
+        assert_eq!(lines[0].as_str(), Ok("abc"));
+        assert!(lines[2].as_str().is_err());
+    }
+
+    #[test]
+    fn context_truncates_snippet() {
+        let input = format!("ok\n{}\n", "é".repeat(SNIPPET_LENGTH + 10));
+        let lines = lines(input.as_bytes());
+        let context = lines[1].context("input.ndjson");
+
+        assert_eq!(context.line_number, 2);
+        assert_eq!(context.byte_offset, 3);
+        assert_eq!(context.snippet.chars().count(), SNIPPET_LENGTH);
+        assert_eq!(
+            context.to_string(),
+            format!("input.ndjson:2 (byte 3): {}", context.snippet)
+        );
+    }
+
+    #[derive(thiserror::Error, Debug)]
+    #[error("outer")]
+    struct Outer(#[source] std::io::Error);
+
+    #[test]
+    fn summary_collects_failures() {
+        let lines = lines(b"a\nb\nc\n");
+        let mut summary = ErrorSummary::new(ErrorPolicy::Collect(2));
+        let error = Outer(std::io::Error::other("inner"));
+
+        for line in &lines {
+            assert!(summary.record(line.context("input"), &error));
+        }
+
+        assert_eq!(summary.error_count, 3);
+        assert_eq!(summary.failures.len(), 2);
+        assert_eq!(summary.failures[1].context.line_number, 2);
+        assert_eq!(summary.failures[1].message, "outer: inner");
+
+        let json = serde_json::to_value(&summary).unwrap();
+
+        assert_eq!(json["error_count"], 3);
+        assert!(json.get("policy").is_none());
+    }
+
+    #[test]
+    fn summary_aborts() {
+        let lines = lines(b"a\n");
+        let mut summary = ErrorSummary::new(ErrorPolicy::Abort);
+        let error = std::io::Error::other("failure");
+
+        assert!(!summary.record(lines[0].context("input"), &error));
+        assert_eq!(summary.error_count, 1);
+        assert!(summary.failures.is_empty());
+    }
+
+    #[test]
+    fn parse_parallel_preserves_order() {
+        let input = (0..5000)
+            .map(|value| {
+                if value % 1000 == 7 {
+                    "invalid".to_string()
+                } else {
+                    value.to_string()
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let results = parse_parallel(Lines::new(std::io::Cursor::new(input)), 3, |line| {
+            line.as_str()
+                .map_err(std::io::Error::other)?
+                .parse::<usize>()
+                .map_err(|_| std::io::Error::other(format!("line {}", line.line_number)))
+        })
+        .collect::<Vec<_>>();
+
+        assert_eq!(results.len(), 5000);
+
+        for (index, result) in results.iter().enumerate() {
+            match result {
+                Ok(value) => assert_eq!(*value, index),
+                Err(error) => {
+                    assert_eq!(index % 1000, 7);
+                    assert_eq!(error.to_string(), format!("line {}", index + 1));
+                }
+            }
+        }
+    }
+
+    /// Fails once after the given number of bytes (e.g. like a truncated compressed stream), and
+    /// then continues with the rest of the input.
+    struct FailingReader {
+        input: std::io::Cursor<Vec<u8>>,
+        fail_at: Option<u64>,
+    }
+
+    impl std::io::Read for FailingReader {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            match self.fail_at {
+                Some(fail_at) if self.input.position() >= fail_at => {
+                    self.fail_at = None;
+                    Err(std::io::Error::new(
+                        std::io::ErrorKind::UnexpectedEof,
+                        "truncated",
+                    ))
+                }
+                Some(fail_at) => {
+                    let remaining = (fail_at - self.input.position()) as usize;
+                    let length = buf.len().min(remaining);
+                    self.input.read(&mut buf[..length])
+                }
+                None => self.input.read(buf),
+            }
+        }
+    }
+
+    #[test]
+    fn parse_parallel_stops_on_read_error() {
+        let line_count = PARALLEL_BATCH_SIZE + 10;
+        let valid = (0..line_count)
+            .map(|value| format!("{}\n", value))
+            .collect::<String>();
+        // The lines after the failure are never read.
+        let input = format!("{}12\n{}", valid, valid);
+        let reader = FailingReader {
+            input: std::io::Cursor::new(input.into_bytes()),
+            // Partway through the first line after the valid ones.
+            fail_at: Some(valid.len() as u64 + 1),
+        };
+
+        let results = parse_parallel(Lines::new(std::io::BufReader::new(reader)), 2, |line| {
+            line.as_str()
+                .map_err(std::io::Error::other)?
+                .parse::<usize>()
+                .map_err(std::io::Error::other)
+        })
+        .collect::<Vec<_>>();
+
+        assert_eq!(results.len(), line_count + 1);
+
+        for (index, result) in results[..line_count].iter().enumerate() {
+            assert_eq!(result.as_ref().ok(), Some(&index));
+        }
+
+        assert!(matches!(
+            &results[line_count],
+            Err(error) if error.kind() == std::io::ErrorKind::UnexpectedEof
+        ));
+    }
+}
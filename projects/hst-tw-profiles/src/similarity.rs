@@ -0,0 +1,441 @@
+//! Candidate near-duplicate accounts based on shared bio text and creation time.
+//!
+//! Bios are normalized, split into character shingles, and summarized with MinHash signatures,
+//! which are bucketed with locality-sensitive hashing so that only accounts sharing at least one
+//! band are compared. The result is a list of candidate pairs for human review, not a verdict.
+
+use crate::model::User;
+use chrono::{DateTime, Duration, Utc};
+use hst_tw_utils::splitmix::{mix, splitmix64};
+use std::collections::{HashMap, HashSet};
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+const MAX_EXAMPLES: usize = 3;
+
+/// Relative importance of the signals combined into a pair score.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SimilarityWeights {
+    pub bio: f64,
+    pub creation_time: f64,
+    /// Creation times further apart than this contribute nothing to the score.
+    pub creation_window: Duration,
+}
+
+impl Default for SimilarityWeights {
+    fn default() -> Self {
+        Self {
+            bio: 0.7,
+            creation_time: 0.3,
+            creation_window: Duration::days(1),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct CandidatePair {
+    pub user_id_a: u64,
+    pub screen_name_a: String,
+    pub user_id_b: u64,
+    pub screen_name_b: String,
+    pub score: f64,
+    /// Estimated Jaccard similarity of the bio shingle sets.
+    pub bio_similarity: f64,
+    pub creation_delta: Option<Duration>,
+    /// A few of the shingles the bios have in common.
+    pub examples: Vec<String>,
+}
+
+/// MinHash signature generator for character shingles.
+#[derive(Clone, Debug)]
+pub struct MinHasher {
+    shingle_size: usize,
+    seeds: Vec<u64>,
+}
+
+impl MinHasher {
+    pub fn new(shingle_size: usize, hash_count: usize, seed: u64) -> Self {
+        let mut state = seed;
+        let seeds = (0..hash_count).map(|_| splitmix64(&mut state)).collect();
+
+        Self {
+            shingle_size: shingle_size.max(1),
+            seeds,
+        }
+    }
+
+    pub fn hash_count(&self) -> usize {
+        self.seeds.len()
+    }
+
+    /// Compute the signature of already-normalized text (`None` if the text is empty).
+    pub fn signature(&self, text: &str) -> Option<Vec<u64>> {
+        let shingles = shingles(text, self.shingle_size)
+            .into_iter()
+            .map(|shingle| fnv1a(shingle.as_bytes()))
+            .collect::<HashSet<_>>();
+
+        if shingles.is_empty() {
+            None
+        } else {
+            Some(
+                self.seeds
+                    .iter()
+                    .map(|seed| {
+                        shingles
+                            .iter()
+                            .map(|shingle| mix(shingle ^ seed))
+                            .min()
+                            .unwrap_or(u64::MAX)
+                    })
+                    .collect(),
+            )
+        }
+    }
+}
+
+struct Entry {
+    user_id: u64,
+    screen_name: String,
+    created_at: Option<DateTime<Utc>>,
+    bio: String,
+    signature: Vec<u64>,
+}
+
+/// Locality-sensitive hashing index over bio signatures.
+pub struct SimilarityIndex {
+    hasher: MinHasher,
+    band_count: usize,
+    entries: Vec<Entry>,
+    buckets: HashMap<(usize, u64), Vec<usize>>,
+}
+
+impl SimilarityIndex {
+    /// Create an index splitting signatures into the given number of bands.
+    ///
+    /// The band count should divide the hasher's hash count (any remainder is ignored).
+    pub fn new(hasher: MinHasher, band_count: usize) -> Self {
+        Self {
+            band_count: band_count.clamp(1, hasher.hash_count().max(1)),
+            hasher,
+            entries: vec![],
+            buckets: HashMap::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Add a user (typically the latest snapshot), returning `false` if the bio is empty.
+    pub fn add(&mut self, user: &User) -> bool {
+        let bio = normalize(user.description.as_deref().unwrap_or_default());
+
+        match self.hasher.signature(&bio) {
+            Some(signature) => {
+                let index = self.entries.len();
+                let rows = self.rows();
+
+                for (band, chunk) in signature.chunks_exact(rows).enumerate() {
+                    let key = chunk.iter().fold(FNV_OFFSET_BASIS, |acc, value| {
+                        (acc ^ value).wrapping_mul(FNV_PRIME)
+                    });
+                    self.buckets.entry((band, key)).or_default().push(index);
+                }
+
+                let user_id = user.id as u64;
+
+                self.entries.push(Entry {
+                    user_id,
+                    screen_name: user.screen_name.clone(),
+                    created_at: hst_tw_utils::snowflake_to_date_time(user_id)
+                        .or_else(|| hst_tw_utils::parse_date_time(&user.created_at).ok()),
+                    bio,
+                    signature,
+                });
+
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Score all pairs sharing at least one band, returning those at or above the given score
+    /// in descending order of score.
+    pub fn pairs(&self, weights: &SimilarityWeights, min_score: f64) -> Vec<CandidatePair> {
+        let mut seen = HashSet::new();
+        let mut pairs = vec![];
+
+        for indices in self.buckets.values() {
+            for (i, a) in indices.iter().enumerate() {
+                for b in &indices[i + 1..] {
+                    if seen.insert((*a, *b)) {
+                        let pair = self.score(&self.entries[*a], &self.entries[*b], weights);
+
+                        if pair.score >= min_score {
+                            pairs.push(pair);
+                        }
+                    }
+                }
+            }
+        }
+
+        pairs.sort_by(|a, b| {
+            b.score
+                .total_cmp(&a.score)
+                .then_with(|| (a.user_id_a, a.user_id_b).cmp(&(b.user_id_a, b.user_id_b)))
+        });
+        pairs
+    }
+
+    fn rows(&self) -> usize {
+        (self.hasher.hash_count() / self.band_count).max(1)
+    }
+
+    fn score(&self, a: &Entry, b: &Entry, weights: &SimilarityWeights) -> CandidatePair {
+        let (a, b) = if a.user_id <= b.user_id {
+            (a, b)
+        } else {
+            (b, a)
+        };
+
+        let matches = a
+            .signature
+            .iter()
+            .zip(&b.signature)
+            .filter(|(x, y)| x == y)
+            .count();
+        let bio_similarity = matches as f64 / a.signature.len().max(1) as f64;
+
+        let creation_delta = a
+            .created_at
+            .zip(b.created_at)
+            .map(|(x, y)| if x >= y { x - y } else { y - x });
+
+        let creation_similarity = match creation_delta {
+            Some(delta) if delta <= weights.creation_window => {
+                let window = weights.creation_window.num_seconds().max(1) as f64;
+                1.0 - delta.num_seconds() as f64 / window
+            }
+            _ => 0.0,
+        };
+
+        CandidatePair {
+            user_id_a: a.user_id,
+            screen_name_a: a.screen_name.clone(),
+            user_id_b: b.user_id,
+            screen_name_b: b.screen_name.clone(),
+            score: weights.bio * bio_similarity + weights.creation_time * creation_similarity,
+            bio_similarity,
+            creation_delta,
+            examples: shared_shingles(&a.bio, &b.bio, self.hasher.shingle_size),
+        }
+    }
+}
+
+/// Lowercase the text and replace runs of non-alphanumeric characters with a single space.
+///
+/// The text is first normalized with `names::normalize_display_name`.
+pub fn normalize(text: &str) -> String {
+    crate::names::normalize_display_name(text)
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Character shingles of the given size (text shorter than the size is a single shingle).
+pub fn shingles(text: &str, size: usize) -> Vec<&str> {
+    let boundaries = text
+        .char_indices()
+        .map(|(index, _)| index)
+        .chain(std::iter::once(text.len()))
+        .collect::<Vec<_>>();
+
+    if text.is_empty() {
+        vec![]
+    } else if boundaries.len() <= size {
+        vec![text]
+    } else {
+        boundaries
+            .windows(size + 1)
+            .map(|window| &text[window[0]..window[size]])
+            .collect()
+    }
+}
+
+fn shared_shingles(a: &str, b: &str, size: usize) -> Vec<String> {
+    let b_shingles = shingles(b, size).into_iter().collect::<HashSet<_>>();
+    let mut examples = vec![];
+    let mut next_position = 0;
+
+    // Skip shingles overlapping the previous example so that the examples are informative.
+    for (position, shingle) in shingles(a, size).into_iter().enumerate() {
+        if examples.len() >= MAX_EXAMPLES {
+            break;
+        }
+
+        if position >= next_position && b_shingles.contains(shingle) {
+            examples.push(shingle.to_string());
+            next_position = position + size;
+        }
+    }
+
+    examples
+}
+
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(FNV_OFFSET_BASIS, |acc, byte| {
+        (acc ^ *byte as u64).wrapping_mul(FNV_PRIME)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BIO: &str = "Writer, gardener, and amateur astronomer. Opinions are my own!";
+
+    fn user(id: i64, created_at: &str, description: &str) -> User {
+        User {
+            id,
+            id_str: id.to_string(),
+            screen_name: format!("user_{}", id),
+            created_at: created_at.to_string(),
+            description: Some(description.to_string()),
+            ..User::default()
+        }
+    }
+
+    fn index() -> SimilarityIndex {
+        SimilarityIndex::new(MinHasher::new(4, 64, 0), 16)
+    }
+
+    fn jaccard(a: &str, b: &str, size: usize) -> f64 {
+        let a = shingles(a, size).into_iter().collect::<HashSet<_>>();
+        let b = shingles(b, size).into_iter().collect::<HashSet<_>>();
+
+        a.intersection(&b).count() as f64 / a.union(&b).count() as f64
+    }
+
+    #[test]
+    fn normalize_examples() {
+        assert_eq!(normalize("  Hello,   WORLD!! "), "hello world");
+        assert_eq!(normalize("a_b-c"), "a b c");
+        assert_eq!(normalize("!!!"), "");
+    }
+
+    #[test]
+    fn normalize_matches() {
+        // Case, composed and decomposed accents, and invisible characters.
+        assert_eq!(normalize("José SMITH"), normalize("jose\u{301} smith"));
+        assert_eq!(normalize("Jo\u{200b}sé\u{202e} Smith"), "josé smith");
+        assert_eq!(normalize("ÉCOLE"), "école");
+        // Emoji are separators, like punctuation.
+        assert_eq!(normalize("Hi \u{1f469}\u{200d}\u{1f52c}there"), "hi there");
+        // Homoglyphs from other scripts aren't folded (this is a Cyrillic "а").
+        assert_ne!(normalize("p\u{430}ypal"), normalize("paypal"));
+    }
+
+    #[test]
+    fn shingle_examples() {
+        assert_eq!(shingles("abcd", 3), vec!["abc", "bcd"]);
+        assert_eq!(shingles("abc", 3), vec!["abc"]);
+        assert_eq!(shingles("ab", 3), vec!["ab"]);
+        assert_eq!(shingles("héllo", 2), vec!["hé", "él", "ll", "lo"]);
+        assert!(shingles("", 3).is_empty());
+    }
+
+    #[test]
+    fn signatures() {
+        let hasher = MinHasher::new(4, 128, 0);
+        let a = normalize(BIO);
+        let b = normalize("Writer, gardener, and amateur astronomer. Views are my own.");
+
+        assert_eq!(hasher.hash_count(), 128);
+        assert_eq!(hasher.signature(""), None);
+        assert_eq!(
+            hasher.signature(&a),
+            MinHasher::new(4, 128, 0).signature(&a)
+        );
+        assert_ne!(
+            hasher.signature(&a),
+            MinHasher::new(4, 128, 1).signature(&a)
+        );
+
+        let estimate = hasher
+            .signature(&a)
+            .unwrap()
+            .iter()
+            .zip(hasher.signature(&b).unwrap())
+            .filter(|(x, y)| **x == *y)
+            .count() as f64
+            / 128.0;
+
+        assert!((estimate - jaccard(&a, &b, 4)).abs() < 0.15);
+    }
+
+    #[test]
+    fn candidate_pairs() {
+        let mut index = index();
+
+        assert!(index.add(&user(3, "Wed Mar 21 20:50:14 +0000 2012", BIO)));
+        assert!(index.add(&user(
+            1,
+            "Wed Mar 21 08:50:14 +0000 2012",
+            &BIO.to_uppercase()
+        )));
+        assert!(index.add(&user(
+            2,
+            "Wed Mar 21 20:50:14 +0000 2012",
+            "Completely unrelated text about football and cooking"
+        )));
+        assert!(!index.add(&user(4, "Wed Mar 21 20:50:14 +0000 2012", "!!!")));
+        assert_eq!(index.len(), 3);
+
+        let pairs = index.pairs(&SimilarityWeights::default(), 0.5);
+
+        assert_eq!(pairs.len(), 1);
+
+        let pair = &pairs[0];
+
+        assert_eq!((pair.user_id_a, pair.user_id_b), (1, 3));
+        assert_eq!(pair.screen_name_a, "user_1");
+        assert_eq!(pair.bio_similarity, 1.0);
+        assert_eq!(pair.creation_delta, Some(Duration::hours(12)));
+        assert!((pair.score - (0.7 + 0.3 * 0.5)).abs() < 1e-9);
+        // Examples don't overlap.
+        assert_eq!(pair.examples, vec!["writ", "er g", "arde"]);
+    }
+
+    #[test]
+    fn creation_window() {
+        let mut index = index();
+
+        index.add(&user(1, "Wed Mar 21 20:50:14 +0000 2012", BIO));
+        index.add(&user(2, "Fri Mar 23 20:50:14 +0000 2012", BIO));
+        // A snowflake ID takes precedence over `created_at`.
+        index.add(&user(
+            1212092628029698048,
+            "Wed Mar 21 20:50:14 +0000 2012",
+            BIO,
+        ));
+
+        let pairs = index.pairs(&SimilarityWeights::default(), 0.0);
+
+        assert_eq!(pairs.len(), 3);
+        assert!(pairs.iter().all(|pair| pair.score == 0.7));
+        assert_eq!(pairs[0].creation_delta, Some(Duration::days(2)));
+        assert_eq!(
+            (pairs[1].user_id_a, pairs[1].user_id_b),
+            (1, 1212092628029698048)
+        );
+        assert!(pairs[1].creation_delta.unwrap() > Duration::days(365 * 7));
+        assert!(index.pairs(&SimilarityWeights::default(), 0.71).is_empty());
+    }
+}
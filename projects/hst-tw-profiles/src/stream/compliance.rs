@@ -0,0 +1,327 @@
+//! Delete and withhold events from the streaming API.
+//!
+//! These arrive as separate messages (possibly before the status they refer to, since stream
+//! files are often merged), so compliance is applied to a dataset as a whole after extraction.
+
+use super::{get_timestamp_ms, Error};
+use crate::ndjson::Lines;
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+use std::collections::HashSet;
+use std::io::{BufRead, Write};
+
+#[derive(Clone, Debug, Eq, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ComplianceEvent {
+    Delete {
+        status_id: u64,
+        user_id: u64,
+        timestamp: Option<DateTime<Utc>>,
+    },
+    StatusWithheld {
+        status_id: u64,
+        user_id: u64,
+        countries: Vec<String>,
+    },
+    UserWithheld {
+        user_id: u64,
+        countries: Vec<String>,
+    },
+}
+
+impl ComplianceEvent {
+    /// The status this event applies to (if it is not an account-level event).
+    pub fn status_id(&self) -> Option<u64> {
+        match self {
+            Self::Delete { status_id, .. } | Self::StatusWithheld { status_id, .. } => {
+                Some(*status_id)
+            }
+            Self::UserWithheld { .. } => None,
+        }
+    }
+}
+
+/// Parse a delete or withhold message (returning `None` for any other kind of message).
+pub fn extract_compliance_event(value: &Value) -> Option<ComplianceEvent> {
+    if let Some(delete_value) = value.get("delete") {
+        let status_value = delete_value.get("status")?;
+
+        Some(ComplianceEvent::Delete {
+            status_id: get_id(status_value, "id")?,
+            user_id: get_id(status_value, "user_id")?,
+            timestamp: get_timestamp_ms(delete_value),
+        })
+    } else if let Some(withheld_value) = value.get("status_withheld") {
+        Some(ComplianceEvent::StatusWithheld {
+            status_id: get_id(withheld_value, "id")?,
+            user_id: get_id(withheld_value, "user_id")?,
+            countries: get_countries(withheld_value),
+        })
+    } else {
+        let withheld_value = value.get("user_withheld")?;
+
+        Some(ComplianceEvent::UserWithheld {
+            user_id: get_id(withheld_value, "id")?,
+            countries: get_countries(withheld_value),
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ComplianceStats {
+    pub kept: usize,
+    pub deleted: usize,
+    pub withheld: usize,
+}
+
+/// Copy line-delimited statuses from `tweets` to `out`, dropping those affected by the events.
+///
+/// The compliance input contains one serialized [`ComplianceEvent`] per line. Statuses are
+/// matched by their `id_str` (or `id`) field, so applying the same events again changes nothing.
+pub fn apply_compliance<T: BufRead, C: BufRead, W: Write>(
+    tweets: T,
+    compliance: C,
+    mut out: W,
+) -> Result<ComplianceStats, Error> {
+    let mut deleted = HashSet::new();
+    let mut withheld = HashSet::new();
+
+    for line in Lines::new(compliance) {
+        let line = line?;
+
+        if !line.bytes.is_empty() {
+            match serde_json::from_slice(&line.bytes).map_err(Error::InvalidJson)? {
+                ComplianceEvent::Delete { status_id, .. } => {
+                    deleted.insert(status_id);
+                }
+                ComplianceEvent::StatusWithheld { status_id, .. } => {
+                    withheld.insert(status_id);
+                }
+                ComplianceEvent::UserWithheld { .. } => {}
+            }
+        }
+    }
+
+    let mut stats = ComplianceStats::default();
+
+    for line in Lines::new(tweets) {
+        let line = line?;
+
+        if line.bytes.is_empty() {
+            continue;
+        }
+
+        let value: Value = serde_json::from_slice(&line.bytes).map_err(Error::InvalidJson)?;
+        let status_id = get_id(&value, "id");
+
+        if status_id.is_some_and(|id| deleted.contains(&id)) {
+            stats.deleted += 1;
+        } else if status_id.is_some_and(|id| withheld.contains(&id)) {
+            stats.withheld += 1;
+        } else {
+            out.write_all(&line.bytes)?;
+            out.write_all(b"\n")?;
+            stats.kept += 1;
+        }
+    }
+
+    out.flush()?;
+
+    Ok(stats)
+}
+
+/// Read an ID from the string form of the field if available (since JSON numbers may be lossy).
+pub(super) fn get_id(value: &Value, field_name: &str) -> Option<u64> {
+    value
+        .get(format!("{}_str", field_name))
+        .and_then(|id_str_value| id_str_value.as_str()?.parse().ok())
+        .or_else(|| value.get(field_name)?.as_u64())
+}
+
+pub(super) fn get_countries(value: &Value) -> Vec<String> {
+    value
+        .get("withheld_in_countries")
+        .and_then(|countries_value| countries_value.as_array())
+        .map(|countries| {
+            countries
+                .iter()
+                .filter_map(|country| country.as_str().map(|country| country.to_string()))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use serde_json::json;
+
+    #[test]
+    fn extract_delete() {
+        let value = json!({
+            "delete": {
+                // The string forms take precedence.
+                "status": { "id": 1, "id_str": "1600000000000000001", "user_id": 2, "user_id_str": "2" },
+                "timestamp_ms": "1600000000123"
+            }
+        });
+        let event = extract_compliance_event(&value).unwrap();
+
+        assert_eq!(
+            event,
+            ComplianceEvent::Delete {
+                status_id: 1600000000000000001,
+                user_id: 2,
+                timestamp: Utc.timestamp_millis_opt(1600000000123).single(),
+            }
+        );
+        assert_eq!(event.status_id(), Some(1600000000000000001));
+
+        let value = json!({ "delete": { "status": { "id": 1, "user_id": 2 } } });
+
+        assert_eq!(
+            extract_compliance_event(&value),
+            Some(ComplianceEvent::Delete {
+                status_id: 1,
+                user_id: 2,
+                timestamp: None,
+            })
+        );
+    }
+
+    #[test]
+    fn extract_withheld() {
+        let value = json!({
+            "status_withheld": {
+                "id_str": "10",
+                "user_id_str": "2",
+                "withheld_in_countries": ["DE", 1, "FR"]
+            }
+        });
+
+        assert_eq!(
+            extract_compliance_event(&value),
+            Some(ComplianceEvent::StatusWithheld {
+                status_id: 10,
+                user_id: 2,
+                countries: vec!["DE".to_string(), "FR".to_string()],
+            })
+        );
+
+        let value = json!({ "user_withheld": { "id": 2, "withheld_in_countries": ["TR"] } });
+        let event = extract_compliance_event(&value).unwrap();
+
+        assert_eq!(
+            event,
+            ComplianceEvent::UserWithheld {
+                user_id: 2,
+                countries: vec!["TR".to_string()],
+            }
+        );
+        assert_eq!(event.status_id(), None);
+    }
+
+    #[test]
+    fn extract_other_messages() {
+        assert_eq!(
+            extract_compliance_event(&json!({ "id_str": "1", "text": "hi" })),
+            None
+        );
+        assert_eq!(
+            extract_compliance_event(&json!({ "delete": { "status": { "user_id": 2 } } })),
+            None
+        );
+        assert_eq!(
+            extract_compliance_event(&json!({ "user_withheld": { "withheld_in_countries": [] } })),
+            None
+        );
+    }
+
+    #[test]
+    fn serialization_round_trip() {
+        let event = ComplianceEvent::StatusWithheld {
+            status_id: 10,
+            user_id: 2,
+            countries: vec!["DE".to_string()],
+        };
+        let line = serde_json::to_string(&event).unwrap();
+
+        assert!(line.contains(r#""kind":"status_withheld""#));
+        assert_eq!(
+            serde_json::from_str::<ComplianceEvent>(&line).unwrap(),
+            event
+        );
+    }
+
+    #[test]
+    fn apply() {
+        let events = [
+            ComplianceEvent::Delete {
+                status_id: 1,
+                user_id: 100,
+                timestamp: None,
+            },
+            ComplianceEvent::StatusWithheld {
+                status_id: 3,
+                user_id: 100,
+                countries: vec!["DE".to_string()],
+            },
+            ComplianceEvent::UserWithheld {
+                user_id: 100,
+                countries: vec!["DE".to_string()],
+            },
+        ]
+        .iter()
+        .map(|event| serde_json::to_string(event).unwrap())
+        .collect::<Vec<_>>()
+        .join("\n\n");
+        let tweets = r#"{"id_str":"1","text":"a"}
+{"id":2,"text":"b"}
+
+{"id_str":"3","text":"c"}
+{"id_str":"4","id":1,"text":"d"}
+"#;
+
+        let mut output = vec![];
+        let stats = apply_compliance(tweets.as_bytes(), events.as_bytes(), &mut output).unwrap();
+
+        assert_eq!(
+            stats,
+            ComplianceStats {
+                kept: 2,
+                deleted: 1,
+                withheld: 1,
+            }
+        );
+        assert_eq!(
+            String::from_utf8(output.clone()).unwrap(),
+            "{\"id\":2,\"text\":\"b\"}\n{\"id_str\":\"4\",\"id\":1,\"text\":\"d\"}\n"
+        );
+
+        // Applying the events again changes nothing.
+        let mut again = vec![];
+        let stats = apply_compliance(&output[..], events.as_bytes(), &mut again).unwrap();
+
+        assert_eq!(stats.kept, 2);
+        assert_eq!(again, output);
+    }
+
+    #[test]
+    fn apply_rejects_invalid_json() {
+        let mut output = vec![];
+
+        assert!(matches!(
+            apply_compliance("not json\n".as_bytes(), "".as_bytes(), &mut output),
+            Err(Error::InvalidJson(_))
+        ));
+        assert!(matches!(
+            apply_compliance(
+                "".as_bytes(),
+                "{\"kind\":\"other\"}\n".as_bytes(),
+                &mut output
+            ),
+            Err(Error::InvalidJson(_))
+        ));
+    }
+}
@@ -3,9 +3,14 @@ use chrono::{DateTime, TimeZone, Utc};
 use serde_json::Value;
 use std::collections::{HashMap, HashSet};
 
+pub mod compliance;
+mod v2;
+pub mod withheld;
+
 const TIMESTAMP_FIELD_NAME: &str = "snapshot";
 
 #[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
 pub enum Error {
     #[error("I/O error")]
     Io(#[from] std::io::Error),
@@ -15,6 +20,8 @@ pub enum Error {
     MissingUser(Value),
     #[error("Invalid user object")]
     InvalidUser(serde_json::error::Error),
+    #[error("Invalid JSON")]
+    InvalidJson(serde_json::error::Error),
 }
 
 #[derive(Debug, Default, Eq, PartialEq, Clone, serde::Deserialize, serde::Serialize)]
@@ -41,10 +48,18 @@ pub struct UserInfo {
     pub partial_users: Vec<PartialUser>,
 }
 
+/// Extract users from a status (delete messages are skipped, see [`compliance`]).
+///
+/// Both v1.1 statuses and v2 filtered stream payloads (with the tweet under `data`) are
+/// supported. For v2 the snapshot is always the tweet's `created_at`.
 pub fn extract_user_info(
     value: &Value,
     created_at_fallback: bool,
 ) -> Result<Option<UserInfo>, Error> {
+    if v2::is_v2(value) {
+        return v2::extract_user_info(value).map(Some);
+    }
+
     if value.get("delete").is_none() {
         // We try to determine the snapshot timestamp by checking for a `timestamp_ms` field,
         // and then (if specified) by parsing `created_at` (since `timestamp_ms` isn't available
@@ -0,0 +1,441 @@
+//! Twitter API v2 filtered stream payloads.
+//!
+//! These have the tweet under `data` and expanded users and tweets under `includes`, and there's
+//! no `timestamp_ms`, so the snapshot is always the tweet's `created_at`. Users are converted to
+//! the v1.1 shape before being deserialized into the model.
+
+use super::{withheld::WithheldEvent, Error, PartialUser, UserInfo, TIMESTAMP_FIELD_NAME};
+use crate::model::User;
+use chrono::{DateTime, Utc};
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+
+pub(super) fn is_v2(value: &Value) -> bool {
+    value
+        .get("data")
+        .and_then(|data| data.get("id"))
+        .is_some_and(Value::is_string)
+}
+
+pub(super) fn extract_user_info(value: &Value) -> Result<UserInfo, Error> {
+    let data = &value["data"];
+    let snapshot = get_snapshot(value).ok_or_else(|| Error::MissingTimestamp(value.clone()))?;
+    let user_values = get_includes(value, "users");
+
+    // The author comes first, as it does for v1.1 statuses.
+    let author = data
+        .get("author_id")
+        .and_then(Value::as_str)
+        .and_then(|author_id| {
+            user_values
+                .iter()
+                .find(|user| user.get("id").and_then(Value::as_str) == Some(author_id))
+        })
+        .ok_or_else(|| Error::MissingUser(value.clone()))?;
+
+    let mut seen = HashSet::new();
+    let mut users = vec![];
+
+    for user_value in std::iter::once(author).chain(user_values) {
+        let user = get_user(user_value, snapshot)?;
+
+        if seen.insert(user.id) {
+            users.push(user);
+        }
+    }
+
+    let mut partial_user_map = HashMap::new();
+
+    for tweet in std::iter::once(data).chain(get_includes(value, "tweets")) {
+        for partial_user in get_mentions(tweet) {
+            if !seen.contains(&(partial_user.id as i64)) {
+                partial_user_map
+                    .entry(partial_user.id)
+                    .or_insert(partial_user);
+            }
+        }
+    }
+
+    Ok(UserInfo {
+        snapshot,
+        users,
+        partial_users: partial_user_map.into_values().collect(),
+    })
+}
+
+pub(super) fn get_snapshot(value: &Value) -> Option<DateTime<Utc>> {
+    value
+        .get("data")?
+        .get("created_at")?
+        .as_str()
+        .and_then(parse_timestamp)
+}
+
+/// Withholding notices for the tweet, any included tweets, and any included users.
+pub(super) fn get_withheld_events(value: &Value) -> Vec<WithheldEvent> {
+    let tweet_events = std::iter::once(&value["data"])
+        .chain(get_includes(value, "tweets"))
+        .filter_map(|tweet| {
+            let user_id = get_id(tweet, "author_id")?;
+            let tweet_id = get_id(tweet, "id")?;
+
+            WithheldEvent::new(user_id, Some(tweet_id), get_country_codes(tweet))
+        });
+
+    let user_events = get_includes(value, "users")
+        .iter()
+        .filter_map(|user| WithheldEvent::new(get_id(user, "id")?, None, get_country_codes(user)));
+
+    tweet_events.chain(user_events).collect()
+}
+
+fn get_includes<'a>(value: &'a Value, name: &str) -> &'a [Value] {
+    value
+        .get("includes")
+        .and_then(|includes| includes.get(name))
+        .and_then(Value::as_array)
+        .map(Vec::as_slice)
+        .unwrap_or_default()
+}
+
+/// IDs are always strings in v2 payloads.
+fn get_id(value: &Value, field_name: &str) -> Option<u64> {
+    value.get(field_name)?.as_str()?.parse().ok()
+}
+
+fn get_country_codes(value: &Value) -> Vec<String> {
+    value
+        .get("withheld")
+        .and_then(|withheld| withheld.get("country_codes"))
+        .and_then(Value::as_array)
+        .map(|countries| {
+            countries
+                .iter()
+                .filter_map(|country| country.as_str().map(|country| country.to_string()))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn get_mentions(tweet: &Value) -> Vec<PartialUser> {
+    tweet
+        .get("entities")
+        .and_then(|entities| entities.get("mentions"))
+        .and_then(Value::as_array)
+        .map(|mentions| {
+            mentions
+                .iter()
+                .filter_map(|mention| {
+                    let id = mention.get("id")?.as_str()?.parse::<u64>().ok()?;
+                    let username = mention.get("username")?.as_str()?;
+
+                    Some(PartialUser::new(id, username.to_string(), None))
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Map the v2 field names onto the v1.1 ones used by the model.
+fn get_user(value: &Value, snapshot: DateTime<Utc>) -> Result<User, Error> {
+    let id = value
+        .get("id")
+        .and_then(Value::as_str)
+        .and_then(|id| id.parse::<i64>().ok())
+        .ok_or_else(|| Error::MissingUser(value.clone()))?;
+    let field = |name: &str| value.get(name).cloned().unwrap_or(Value::Null);
+    let metric = |name: &str| {
+        value
+            .get("public_metrics")
+            .and_then(|metrics| metrics.get(name))
+            .and_then(Value::as_i64)
+            .unwrap_or_default()
+    };
+
+    let created_at = value
+        .get("created_at")
+        .and_then(Value::as_str)
+        .and_then(parse_timestamp)
+        .map(hst_tw_utils::format_date_time);
+
+    // The v2 API uses an empty string for a missing URL.
+    let url = value
+        .get("url")
+        .and_then(Value::as_str)
+        .filter(|url| !url.is_empty());
+
+    let verified_type = value.get("verified_type").and_then(Value::as_str);
+    let withheld = value.get("withheld");
+
+    let mut user_value = json!({
+        "id": id,
+        "id_str": id.to_string(),
+        "name": field("name"),
+        "screen_name": field("username"),
+        "location": field("location"),
+        "description": field("description"),
+        "url": url,
+        "entities": value.get("entities").map(|entities| json!({
+            "url": get_entity(entities.get("url")),
+            "description": get_entity(entities.get("description")),
+        })),
+        "protected": value.get("protected").and_then(Value::as_bool).unwrap_or_default(),
+        "followers_count": metric("followers_count"),
+        "friends_count": metric("following_count"),
+        "listed_count": metric("listed_count"),
+        "favourites_count": metric("like_count"),
+        "statuses_count": metric("tweet_count"),
+        "created_at": created_at.unwrap_or_default(),
+        "verified": value.get("verified").and_then(Value::as_bool).unwrap_or_default(),
+        "profile_image_url_https": field("profile_image_url"),
+        "withheld_scope": withheld.and_then(|withheld| withheld.get("scope")),
+        "withheld_in_countries": withheld
+            .and_then(|withheld| withheld.get("country_codes"))
+            .cloned()
+            .unwrap_or_else(|| json!([])),
+        "ext_is_blue_verified": verified_type.map(|verified_type| verified_type == "blue"),
+        "ext_verified_type": verified_type.and_then(|verified_type| match verified_type {
+            "business" => Some("Business"),
+            "government" => Some("Government"),
+            _ => None,
+        }),
+    });
+
+    // Missing fields are left out so that they get the model's defaults.
+    if let Some(fields) = user_value.as_object_mut() {
+        fields.retain(|_, value| !value.is_null());
+        fields.insert(
+            TIMESTAMP_FIELD_NAME.to_string(),
+            json!(snapshot.timestamp()),
+        );
+    }
+
+    serde_json::from_value(user_value).map_err(Error::InvalidUser)
+}
+
+/// The v2 API gives URL positions as `start` and `end` fields instead of `indices`.
+fn get_entity(entity: Option<&Value>) -> Value {
+    match entity
+        .and_then(|entity| entity.get("urls"))
+        .and_then(Value::as_array)
+    {
+        Some(urls) => json!({ "urls": urls.iter().map(get_url).collect::<Vec<_>>() }),
+        None => Value::Null,
+    }
+}
+
+fn get_url(url: &Value) -> Value {
+    let indices = [url.get("start"), url.get("end")]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+
+    json!({
+        "url": url.get("url"),
+        "expanded_url": url.get("expanded_url"),
+        "display_url": url.get("display_url"),
+        "indices": indices,
+    })
+}
+
+fn parse_timestamp(value: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(value)
+        .ok()
+        .map(|timestamp| timestamp.with_timezone(&Utc))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn payload() -> Value {
+        json!({
+            "data": {
+                "id": "1000",
+                "author_id": "1",
+                "created_at": "2020-09-13T12:26:40.000Z",
+                "text": "@bar @baz hi",
+                "entities": {
+                    "mentions": [
+                        { "start": 0, "end": 4, "id": "2", "username": "bar" },
+                        { "start": 5, "end": 9, "id": "3", "username": "baz" }
+                    ]
+                },
+                "withheld": { "country_codes": ["FR", "DE", "FR"] }
+            },
+            "includes": {
+                "users": [
+                    {
+                        "id": "2",
+                        "username": "bar",
+                        "name": "Bar",
+                        "created_at": "2010-01-01T00:00:00.000Z",
+                        "url": "",
+                        "verified_type": "government",
+                        "withheld": { "scope": "user", "country_codes": ["TR"] }
+                    },
+                    {
+                        "id": "1",
+                        "username": "foo",
+                        "name": "Foo",
+                        "description": "See https://t.co/abc",
+                        "url": "https://t.co/xyz",
+                        "protected": false,
+                        "verified": false,
+                        "verified_type": "blue",
+                        "profile_image_url": "https://pbs.twimg.com/foo.jpg",
+                        "entities": {
+                            "url": {
+                                "urls": [{
+                                    "start": 0,
+                                    "end": 15,
+                                    "url": "https://t.co/xyz",
+                                    "expanded_url": "https://example.com/",
+                                    "display_url": "example.com"
+                                }]
+                            }
+                        },
+                        "public_metrics": {
+                            "followers_count": 10,
+                            "following_count": 20,
+                            "listed_count": 3,
+                            "tweet_count": 40,
+                            "like_count": 50
+                        }
+                    }
+                ],
+                "tweets": [{
+                    "id": "999",
+                    "author_id": "2",
+                    "entities": { "mentions": [{ "id": "4", "username": "qux" }] }
+                }]
+            }
+        })
+    }
+
+    #[test]
+    fn detect_v2() {
+        assert!(is_v2(&payload()));
+        assert_eq!(
+            super::super::extract_user_info(&payload(), false)
+                .unwrap()
+                .map(|info| info.users.len()),
+            Some(2)
+        );
+        assert!(!is_v2(&json!({ "data": { "id": 1000 } })));
+        assert!(!is_v2(&json!({ "id_str": "1000", "user": {} })));
+    }
+
+    #[test]
+    fn extract_users() {
+        let info = extract_user_info(&payload()).unwrap();
+        let snapshot = Utc.timestamp_opt(1_600_000_000, 0).single().unwrap();
+
+        assert_eq!(info.snapshot, snapshot);
+        assert_eq!(
+            info.users
+                .iter()
+                .map(|user| user.screen_name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["foo", "bar"]
+        );
+
+        let author = &info.users[0];
+
+        assert_eq!(author.id, 1);
+        assert_eq!(author.id_str, "1");
+        assert_eq!(author.name, "Foo");
+        assert_eq!(author.snapshot, 1_600_000_000);
+        assert_eq!(author.url.as_deref(), Some("https://t.co/xyz"));
+        assert_eq!(author.expanded_url(), Some("https://example.com/"));
+        assert_eq!(
+            author.entities.as_ref().unwrap().url.as_ref().unwrap().urls[0].indices,
+            vec![0, 15]
+        );
+        assert_eq!(author.followers_count, 10);
+        assert_eq!(author.friends_count, 20);
+        assert_eq!(author.listed_count, 3);
+        assert_eq!(author.statuses_count, 40);
+        assert_eq!(author.favourites_count, 50);
+        assert_eq!(
+            author.profile_image_url_https,
+            "https://pbs.twimg.com/foo.jpg"
+        );
+        assert_eq!(author.ext_is_blue_verified, Some(true));
+        assert_eq!(author.ext_verified_type, None);
+        assert_eq!(author.created_at, "");
+
+        let mentioned = &info.users[1];
+
+        assert_eq!(mentioned.url, None);
+        assert_eq!(mentioned.description, None);
+        assert_eq!(mentioned.followers_count, 0);
+        assert_eq!(
+            mentioned.created_at().unwrap(),
+            Utc.with_ymd_and_hms(2010, 1, 1, 0, 0, 0).single().unwrap()
+        );
+        assert_eq!(mentioned.ext_is_blue_verified, Some(false));
+        assert_eq!(mentioned.ext_verified_type.as_deref(), Some("Government"));
+        assert_eq!(mentioned.withheld_scope.as_deref(), Some("user"));
+        assert_eq!(mentioned.withheld_in_countries, vec!["TR".to_string()]);
+
+        // Mentions of included users are omitted.
+        let mut partial_users = info.partial_users;
+        partial_users.sort_by_key(|partial_user| partial_user.id);
+
+        assert_eq!(
+            partial_users,
+            vec![
+                PartialUser::new(3, "baz".to_string(), None),
+                PartialUser::new(4, "qux".to_string(), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn extract_users_errors() {
+        let mut value = payload();
+        value["data"]["created_at"] = json!("Sun Sep 13 12:26:40 +0000 2020");
+
+        assert!(matches!(
+            extract_user_info(&value),
+            Err(Error::MissingTimestamp(_))
+        ));
+
+        let mut value = payload();
+        value["data"]["author_id"] = json!("5");
+
+        assert!(matches!(
+            extract_user_info(&value),
+            Err(Error::MissingUser(_))
+        ));
+
+        let mut value = payload();
+        value["includes"]["users"][0]["name"] = json!(1);
+
+        assert!(matches!(
+            extract_user_info(&value),
+            Err(Error::InvalidUser(_))
+        ));
+    }
+
+    #[test]
+    fn withheld_events() {
+        assert_eq!(
+            get_withheld_events(&payload()),
+            vec![
+                WithheldEvent {
+                    user_id: 1,
+                    tweet_id: Some(1000),
+                    countries: vec!["DE".to_string(), "FR".to_string()],
+                },
+                WithheldEvent {
+                    user_id: 2,
+                    tweet_id: None,
+                    countries: vec!["TR".to_string()],
+                },
+            ]
+        );
+    }
+}
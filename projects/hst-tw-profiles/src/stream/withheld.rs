@@ -0,0 +1,227 @@
+//! Withholding notices carried by statuses and users.
+//!
+//! Withheld statuses and accounts have their country codes in `withheld_in_countries` (or
+//! `withheld.country_codes` for v2 payloads). Separate `status_withheld` and `user_withheld`
+//! messages are handled by [`super::compliance`].
+
+use super::compliance::{get_countries, get_id};
+use super::{get_created_at, get_timestamp_ms, v2, Error};
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+use std::collections::HashSet;
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq, serde::Serialize)]
+pub struct WithheldEvent {
+    pub user_id: u64,
+    /// Missing for account-level withholding.
+    pub tweet_id: Option<u64>,
+    /// Sorted and deduplicated.
+    pub countries: Vec<String>,
+}
+
+impl WithheldEvent {
+    pub(super) fn new(
+        user_id: u64,
+        tweet_id: Option<u64>,
+        mut countries: Vec<String>,
+    ) -> Option<Self> {
+        countries.sort();
+        countries.dedup();
+
+        if countries.is_empty() {
+            None
+        } else {
+            Some(Self {
+                user_id,
+                tweet_id,
+                countries,
+            })
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct WithheldInfo {
+    pub snapshot: DateTime<Utc>,
+    pub events: Vec<WithheldEvent>,
+}
+
+/// Extract withholding notices from a status, including any retweeted or quoted status.
+///
+/// Returns `None` if nothing in the status is withheld. The snapshot timestamp is determined as
+/// for [`super::extract_user_info`], always falling back to `created_at`.
+pub fn extract_withheld_info(value: &Value) -> Result<Option<WithheldInfo>, Error> {
+    let (snapshot, candidates) = if v2::is_v2(value) {
+        (v2::get_snapshot(value), v2::get_withheld_events(value))
+    } else {
+        let status_values = [
+            Some(value),
+            value.get("retweeted_status"),
+            value.get("quoted_status"),
+        ];
+        let mut events = vec![];
+
+        for status_value in status_values.into_iter().flatten() {
+            add_status_events(status_value, &mut events);
+        }
+
+        (
+            get_timestamp_ms(value).or_else(|| get_created_at(value)),
+            events,
+        )
+    };
+
+    // The same notice often appears on both a retweet and the original.
+    let mut seen = HashSet::new();
+    let events = candidates
+        .into_iter()
+        .filter(|event| seen.insert(event.clone()))
+        .collect::<Vec<_>>();
+
+    if events.is_empty() {
+        Ok(None)
+    } else {
+        let snapshot = snapshot.ok_or_else(|| Error::MissingTimestamp(value.clone()))?;
+
+        Ok(Some(WithheldInfo { snapshot, events }))
+    }
+}
+
+fn add_status_events(status_value: &Value, acc: &mut Vec<WithheldEvent>) {
+    let user_value = match status_value.get("user") {
+        Some(user_value) => user_value,
+        None => return,
+    };
+
+    if let Some(user_id) = get_id(user_value, "id") {
+        if let Some(event) = get_id(status_value, "id")
+            .and_then(|id| WithheldEvent::new(user_id, Some(id), get_countries(status_value)))
+        {
+            acc.push(event);
+        }
+
+        if let Some(event) = WithheldEvent::new(user_id, None, get_countries(user_value)) {
+            acc.push(event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use serde_json::json;
+
+    fn event(user_id: u64, tweet_id: Option<u64>, countries: &[&str]) -> WithheldEvent {
+        WithheldEvent {
+            user_id,
+            tweet_id,
+            countries: countries
+                .iter()
+                .map(|country| country.to_string())
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn new_event() {
+        let countries = ["FR", "DE", "FR"].map(String::from).to_vec();
+
+        assert_eq!(
+            WithheldEvent::new(1, Some(2), countries),
+            Some(event(1, Some(2), &["DE", "FR"]))
+        );
+        assert_eq!(WithheldEvent::new(1, None, vec![]), None);
+    }
+
+    #[test]
+    fn extract_from_status() {
+        let original = json!({
+            "id_str": "100",
+            "user": { "id_str": "2", "withheld_in_countries": ["TR"] },
+            "withheld_in_countries": ["DE"]
+        });
+        let value = json!({
+            "id_str": "101",
+            "timestamp_ms": "1600000000123",
+            "created_at": "Sun Sep 13 12:00:00 +0000 2020",
+            "user": { "id": 1, "withheld_in_countries": [] },
+            "withheld_in_countries": ["RU", "IN"],
+            "retweeted_status": original,
+            // The same notices can appear more than once.
+            "quoted_status": original
+        });
+
+        assert_eq!(
+            extract_withheld_info(&value).unwrap(),
+            Some(WithheldInfo {
+                snapshot: Utc
+                    .timestamp_millis_opt(1_600_000_000_123)
+                    .single()
+                    .unwrap(),
+                events: vec![
+                    event(1, Some(101), &["IN", "RU"]),
+                    event(2, Some(100), &["DE"]),
+                    event(2, None, &["TR"]),
+                ],
+            })
+        );
+    }
+
+    #[test]
+    fn extract_falls_back_to_created_at() {
+        let value = json!({
+            "id": 101,
+            "created_at": "Sun Sep 13 12:26:40 +0000 2020",
+            "user": { "id": 1, "withheld_in_countries": ["DE"] }
+        });
+        let info = extract_withheld_info(&value).unwrap().unwrap();
+
+        assert_eq!(info.snapshot.timestamp(), 1_600_000_000);
+        assert_eq!(info.events, vec![event(1, None, &["DE"])]);
+    }
+
+    #[test]
+    fn extract_nothing_withheld() {
+        let value = json!({
+            "id_str": "101",
+            "user": { "id": 1, "withheld_in_countries": [] }
+        });
+
+        // The missing timestamp only matters if there are notices.
+        assert_eq!(extract_withheld_info(&value).unwrap(), None);
+
+        let value = json!({
+            "id_str": "101",
+            "user": { "id": 1 },
+            "withheld_in_countries": ["DE"]
+        });
+
+        assert!(matches!(
+            extract_withheld_info(&value),
+            Err(Error::MissingTimestamp(_))
+        ));
+    }
+
+    #[test]
+    fn extract_from_v2_payload() {
+        let value = json!({
+            "data": {
+                "id": "101",
+                "author_id": "1",
+                "created_at": "2020-09-13T12:26:40.000Z",
+                "withheld": { "country_codes": ["DE"] }
+            },
+            "includes": {
+                "users": [{ "id": "1", "withheld": { "country_codes": ["TR"] } }]
+            }
+        });
+        let info = extract_withheld_info(&value).unwrap().unwrap();
+
+        assert_eq!(info.snapshot.timestamp(), 1_600_000_000);
+        assert_eq!(
+            info.events,
+            vec![event(1, Some(101), &["DE"]), event(1, None, &["TR"])]
+        );
+    }
+}
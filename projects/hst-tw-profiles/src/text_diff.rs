@@ -0,0 +1,239 @@
+//! Compact change summaries for display names and profile descriptions.
+//!
+//! Segmentation follows the Unicode word boundary rules, so emoji ZWJ sequences, flags, and
+//! characters with combining marks are kept together as single tokens.
+
+use std::collections::HashMap;
+use unicode_segmentation::UnicodeSegmentation;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TokenKind {
+    Word,
+    Emoji,
+    Punctuation,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Token<'a> {
+    pub kind: TokenKind,
+    pub value: &'a str,
+}
+
+#[derive(Debug, Default, Eq, PartialEq, Clone, serde::Deserialize, serde::Serialize)]
+pub struct TextDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub added_emoji: Vec<String>,
+    pub removed_emoji: Vec<String>,
+    /// Indicates that the texts differ only in whitespace or punctuation.
+    pub formatting_only: bool,
+}
+
+impl TextDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.removed.is_empty()
+            && self.added_emoji.is_empty()
+            && self.removed_emoji.is_empty()
+            && !self.formatting_only
+    }
+}
+
+/// Split the input into word, emoji, and punctuation tokens (whitespace is dropped).
+pub fn tokenize(input: &str) -> Vec<Token<'_>> {
+    input
+        .split_word_bounds()
+        .filter(|segment| !segment.chars().all(char::is_whitespace))
+        .map(|segment| {
+            let kind = if segment.chars().any(is_emoji_char) {
+                TokenKind::Emoji
+            } else if segment.chars().any(char::is_alphanumeric) {
+                TokenKind::Word
+            } else {
+                TokenKind::Punctuation
+            };
+
+            Token {
+                kind,
+                value: segment,
+            }
+        })
+        .collect()
+}
+
+/// Summarize the changes between two versions of a name or description.
+///
+/// Added and removed tokens are computed as multiset differences, and are listed in the order
+/// in which they appear in the new and old text respectively.
+pub fn diff(old: &str, new: &str) -> TextDiff {
+    let old_tokens = tokenize(old);
+    let new_tokens = tokenize(new);
+
+    let (added, removed) = multiset_difference(&old_tokens, &new_tokens, TokenKind::Word);
+    let (added_emoji, removed_emoji) =
+        multiset_difference(&old_tokens, &new_tokens, TokenKind::Emoji);
+
+    let formatting_only = old != new
+        && old_tokens
+            .iter()
+            .filter(|token| token.kind != TokenKind::Punctuation)
+            .eq(new_tokens
+                .iter()
+                .filter(|token| token.kind != TokenKind::Punctuation));
+
+    TextDiff {
+        added,
+        removed,
+        added_emoji,
+        removed_emoji,
+        formatting_only,
+    }
+}
+
+fn multiset_difference(
+    old: &[Token],
+    new: &[Token],
+    kind: TokenKind,
+) -> (Vec<String>, Vec<String>) {
+    let mut counts: HashMap<&str, i64> = HashMap::new();
+
+    for token in old.iter().filter(|token| token.kind == kind) {
+        *counts.entry(token.value).or_default() -= 1;
+    }
+
+    for token in new.iter().filter(|token| token.kind == kind) {
+        *counts.entry(token.value).or_default() += 1;
+    }
+
+    let added = select_by_count(new, kind, &mut counts.clone(), 1);
+    let removed = select_by_count(old, kind, &mut counts, -1);
+
+    (added, removed)
+}
+
+fn select_by_count(
+    tokens: &[Token],
+    kind: TokenKind,
+    counts: &mut HashMap<&str, i64>,
+    sign: i64,
+) -> Vec<String> {
+    tokens
+        .iter()
+        .filter(|token| token.kind == kind)
+        .filter_map(|token| {
+            let count = counts.get_mut(token.value)?;
+
+            if *count * sign > 0 {
+                *count -= sign;
+                Some(token.value.to_string())
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// Approximate test for characters that only occur in emoji sequences.
+///
+/// This covers the pictographic blocks (which include the regional indicators used for flags),
+/// the emoji presentation selector, and the keycap combining mark. The zero-width joiner is not
+/// included, since it also appears in ordinary text in some scripts (name normalization uses
+/// this to decide which joiners to keep).
+pub(crate) fn is_emoji_char(c: char) -> bool {
+    matches!(
+        c as u32,
+        0x1F000..=0x1FAFF | 0x2600..=0x27BF | 0x2B00..=0x2BFF | 0xFE0F | 0x20E3
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(input: &str, kind: TokenKind) -> Vec<&str> {
+        tokenize(input)
+            .into_iter()
+            .filter(|token| token.kind == kind)
+            .map(|token| token.value)
+            .collect()
+    }
+
+    #[test]
+    fn tokenize_keeps_zwj_sequences_together() {
+        let family = "\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}";
+        let input = format!("My {} account", family);
+
+        assert_eq!(values(&input, TokenKind::Emoji), vec![family]);
+        assert_eq!(values(&input, TokenKind::Word), vec!["My", "account"]);
+    }
+
+    #[test]
+    fn tokenize_keeps_flags_modifiers_and_keycaps_together() {
+        let flag = "\u{1F1E9}\u{1F1EA}";
+        let thumbs_up = "\u{1F44D}\u{1F3FD}";
+        let keycap = "1\u{FE0F}\u{20E3}";
+        let input = format!("{} {} {}", flag, thumbs_up, keycap);
+
+        assert_eq!(
+            values(&input, TokenKind::Emoji),
+            vec![flag, thumbs_up, keycap]
+        );
+    }
+
+    #[test]
+    fn tokenize_keeps_combining_marks_with_their_base() {
+        let decomposed = "Jose\u{301}";
+        let input = format!("{} Garci\u{301}a", decomposed);
+
+        assert_eq!(
+            values(&input, TokenKind::Word),
+            vec![decomposed, "Garci\u{301}a"]
+        );
+        assert!(values(&input, TokenKind::Punctuation).is_empty());
+    }
+
+    #[test]
+    fn diff_separates_words_and_emoji() {
+        let rainbow_flag = "\u{1F3F3}\u{FE0F}\u{200D}\u{1F308}";
+        let result = diff(
+            "Jane Doe \u{1F1FA}\u{1F1F8}",
+            &format!("Jane Doe {}", rainbow_flag),
+        );
+
+        assert!(result.added.is_empty());
+        assert!(result.removed.is_empty());
+        assert_eq!(result.added_emoji, vec![rainbow_flag]);
+        assert_eq!(result.removed_emoji, vec!["\u{1F1FA}\u{1F1F8}"]);
+        assert!(!result.formatting_only);
+    }
+
+    #[test]
+    fn diff_treats_zwj_variants_as_different_emoji() {
+        let woman = "\u{1F469}";
+        let woman_technologist = "\u{1F469}\u{200D}\u{1F4BB}";
+        let result = diff(
+            &format!("Dev {}", woman),
+            &format!("Dev {}", woman_technologist),
+        );
+
+        assert_eq!(result.added_emoji, vec![woman_technologist]);
+        assert_eq!(result.removed_emoji, vec![woman]);
+    }
+
+    #[test]
+    fn diff_counts_repeated_tokens() {
+        let result = diff("very very good", "very good good");
+
+        assert_eq!(result.added, vec!["good"]);
+        assert_eq!(result.removed, vec!["very"]);
+    }
+
+    #[test]
+    fn diff_detects_formatting_only_changes() {
+        let result = diff("Hello, world", "Hello   world!");
+
+        assert!(result.formatting_only);
+        assert!(!result.is_empty());
+        assert!(diff("Hello", "Hello").is_empty());
+    }
+}
@@ -0,0 +1,562 @@
+//! External merge sort for inputs that don't fit in memory.
+//!
+//! Input is collected into sorted runs of at most the configured memory budget, which are
+//! spilled to temporary files and then merged with a loser tree. If the whole input fits in a
+//! single run, nothing is written to disk.
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Default memory budget for buffered records (in bytes).
+pub const DEFAULT_MEMORY_BUDGET: usize = 256 * 1024 * 1024;
+
+/// Maximum number of runs merged at once (larger numbers of runs are merged in several passes).
+const MAX_FAN_IN: usize = 128;
+const RUN_DIR_PREFIX: &str = "hst-extsort";
+
+static RUN_DIR_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
+pub enum Error {
+    #[error("I/O error")]
+    Io(#[from] std::io::Error),
+}
+
+/// A record with a fixed-size binary encoding.
+pub trait Record: Ord + Sized {
+    const SIZE: usize;
+
+    fn encode(&self, output: &mut [u8]);
+    fn decode(input: &[u8]) -> Self;
+}
+
+impl Record for u32 {
+    const SIZE: usize = 4;
+
+    fn encode(&self, output: &mut [u8]) {
+        output.copy_from_slice(&self.to_be_bytes());
+    }
+
+    fn decode(input: &[u8]) -> Self {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(input);
+        Self::from_be_bytes(bytes)
+    }
+}
+
+impl Record for u64 {
+    const SIZE: usize = 8;
+
+    fn encode(&self, output: &mut [u8]) {
+        output.copy_from_slice(&self.to_be_bytes());
+    }
+
+    fn decode(input: &[u8]) -> Self {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(input);
+        Self::from_be_bytes(bytes)
+    }
+}
+
+impl<A: Record, B: Record> Record for (A, B) {
+    const SIZE: usize = A::SIZE + B::SIZE;
+
+    fn encode(&self, output: &mut [u8]) {
+        self.0.encode(&mut output[..A::SIZE]);
+        self.1.encode(&mut output[A::SIZE..]);
+    }
+
+    fn decode(input: &[u8]) -> Self {
+        (A::decode(&input[..A::SIZE]), B::decode(&input[A::SIZE..]))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ExternalSorter {
+    memory_budget: usize,
+    temp_dir: PathBuf,
+    dedup: bool,
+}
+
+impl Default for ExternalSorter {
+    fn default() -> Self {
+        Self::new(DEFAULT_MEMORY_BUDGET)
+    }
+}
+
+impl ExternalSorter {
+    /// Create a sorter that buffers approximately `memory_budget` bytes of records per run.
+    pub fn new(memory_budget: usize) -> Self {
+        Self {
+            memory_budget,
+            temp_dir: std::env::temp_dir(),
+            dedup: false,
+        }
+    }
+
+    /// Set the directory in which spill files are created.
+    pub fn temp_dir<P: AsRef<Path>>(mut self, temp_dir: P) -> Self {
+        self.temp_dir = temp_dir.as_ref().to_path_buf();
+        self
+    }
+
+    /// Remove duplicate records from the output.
+    pub fn dedup(mut self, dedup: bool) -> Self {
+        self.dedup = dedup;
+        self
+    }
+
+    pub fn sort<T: Record, I: IntoIterator<Item = T>>(&self, input: I) -> Result<Sorted<T>, Error> {
+        let capacity = (self.memory_budget / std::mem::size_of::<T>().max(1)).max(1);
+        let mut buffer = Vec::with_capacity(capacity.min(1 << 20));
+        let mut runs = RunDir::new(&self.temp_dir);
+
+        for record in input {
+            buffer.push(record);
+
+            if buffer.len() >= capacity {
+                self.sort_buffer(&mut buffer);
+                runs.spill(&buffer)?;
+                buffer.clear();
+            }
+        }
+
+        self.sort_buffer(&mut buffer);
+
+        let (source, runs) = if runs.runs.is_empty() {
+            (Source::Memory(buffer.into_iter()), None)
+        } else {
+            if !buffer.is_empty() {
+                runs.spill(&buffer)?;
+            }
+            drop(buffer);
+
+            runs.reduce::<T>()?;
+
+            (
+                Source::Merge(LoserTree::new(runs.open(&runs.runs)?)?),
+                Some(runs),
+            )
+        };
+
+        Ok(Sorted {
+            source,
+            _runs: runs,
+            dedup: self.dedup,
+            last: None,
+        })
+    }
+
+    fn sort_buffer<T: Ord>(&self, buffer: &mut Vec<T>) {
+        buffer.sort_unstable();
+
+        if self.dedup {
+            buffer.dedup();
+        }
+    }
+}
+
+/// Sort and deduplicate user IDs (or any other `u64` values).
+pub fn sort_dedup_u64s<I: IntoIterator<Item = u64>>(
+    input: I,
+    memory_budget: usize,
+) -> Result<Sorted<u64>, Error> {
+    ExternalSorter::new(memory_budget).dedup(true).sort(input)
+}
+
+/// Sorted output (any spill files are removed when this is dropped).
+pub struct Sorted<T> {
+    source: Source<T>,
+    // Declared after the source so that the directory is removed after the readers are closed.
+    _runs: Option<RunDir>,
+    dedup: bool,
+    last: Option<T>,
+}
+
+enum Source<T> {
+    Memory(std::vec::IntoIter<T>),
+    Merge(LoserTree<T>),
+}
+
+impl<T: Record + Clone> Iterator for Sorted<T> {
+    type Item = Result<T, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let next = match &mut self.source {
+                Source::Memory(records) => return records.next().map(Ok),
+                Source::Merge(tree) => tree.next_record()?,
+            };
+
+            match next {
+                Ok(record) => {
+                    if self.dedup {
+                        if self.last.as_ref() == Some(&record) {
+                            continue;
+                        }
+                        self.last = Some(record.clone());
+                    }
+
+                    return Some(Ok(record));
+                }
+                Err(error) => return Some(Err(error)),
+            }
+        }
+    }
+}
+
+struct RunDir {
+    base: PathBuf,
+    path: Option<PathBuf>,
+    /// File paths and record counts.
+    runs: Vec<(PathBuf, usize)>,
+    next_run: usize,
+}
+
+impl RunDir {
+    fn new(base: &Path) -> Self {
+        Self {
+            base: base.to_path_buf(),
+            path: None,
+            runs: vec![],
+            next_run: 0,
+        }
+    }
+
+    fn spill<T: Record>(&mut self, records: &[T]) -> Result<(), Error> {
+        self.write_run::<T, _, _>(records.iter().map(Ok))
+    }
+
+    /// Merge the oldest runs until there are few enough to merge at once.
+    fn reduce<T: Record>(&mut self) -> Result<(), Error> {
+        while self.runs.len() > MAX_FAN_IN {
+            let group = self.runs.drain(..MAX_FAN_IN).collect::<Vec<_>>();
+            let mut tree = LoserTree::<T>::new(self.open(&group)?)?;
+
+            self.write_run::<T, _, _>(std::iter::from_fn(|| tree.next_record()))?;
+
+            for (path, _) in group {
+                std::fs::remove_file(path)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write_run<T: Record, R: std::borrow::Borrow<T>, I: Iterator<Item = Result<R, Error>>>(
+        &mut self,
+        records: I,
+    ) -> Result<(), Error> {
+        let path = match &self.path {
+            Some(path) => path.clone(),
+            None => {
+                let path = self.base.join(format!(
+                    "{}-{}-{}",
+                    RUN_DIR_PREFIX,
+                    std::process::id(),
+                    RUN_DIR_COUNTER.fetch_add(1, Ordering::Relaxed)
+                ));
+                std::fs::create_dir_all(&path)?;
+                self.path.insert(path).clone()
+            }
+        };
+
+        let run_path = path.join(format!("run-{:06}", self.next_run));
+        let mut writer = BufWriter::new(File::create(&run_path)?);
+        let mut bytes = vec![0; T::SIZE];
+        let mut count = 0;
+
+        for record in records {
+            record?.borrow().encode(&mut bytes);
+            writer.write_all(&bytes)?;
+            count += 1;
+        }
+
+        writer.flush()?;
+        self.runs.push((run_path, count));
+        self.next_run += 1;
+
+        Ok(())
+    }
+
+    fn open<T: Record>(&self, runs: &[(PathBuf, usize)]) -> Result<Vec<RunReader<T>>, Error> {
+        runs.iter()
+            .map(|(path, count)| {
+                Ok(RunReader {
+                    reader: BufReader::new(File::open(path)?),
+                    remaining: *count,
+                    bytes: vec![0; T::SIZE],
+                    _record: std::marker::PhantomData,
+                })
+            })
+            .collect()
+    }
+}
+
+impl Drop for RunDir {
+    fn drop(&mut self) {
+        if let Some(path) = &self.path {
+            // Failing to clean up temporary files isn't worth failing the sort for.
+            let _ = std::fs::remove_dir_all(path);
+        }
+    }
+}
+
+struct RunReader<T> {
+    reader: BufReader<File>,
+    remaining: usize,
+    bytes: Vec<u8>,
+    _record: std::marker::PhantomData<T>,
+}
+
+impl<T: Record> RunReader<T> {
+    fn read(&mut self) -> Result<Option<T>, Error> {
+        if self.remaining == 0 {
+            Ok(None)
+        } else {
+            self.reader.read_exact(&mut self.bytes)?;
+            self.remaining -= 1;
+            Ok(Some(T::decode(&self.bytes)))
+        }
+    }
+}
+
+/// Tournament tree for k-way merging, where each internal node holds the losing run index.
+struct LoserTree<T> {
+    runs: Vec<RunReader<T>>,
+    heads: Vec<Option<T>>,
+    /// The winner is at index zero.
+    tree: Vec<usize>,
+}
+
+impl<T: Record> LoserTree<T> {
+    /// Placeholder that beats every run (used only while building the tree).
+    const UNSET: usize = usize::MAX;
+
+    fn new(mut runs: Vec<RunReader<T>>) -> Result<Self, Error> {
+        let heads = runs
+            .iter_mut()
+            .map(|run| run.read())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut tree = Self {
+            tree: vec![Self::UNSET; runs.len().max(1)],
+            runs,
+            heads,
+        };
+
+        for index in (0..tree.runs.len()).rev() {
+            tree.adjust(index);
+        }
+
+        Ok(tree)
+    }
+
+    /// Indicates whether run `a` should be output before run `b` (exhausted runs always lose).
+    fn beats(&self, a: usize, b: usize) -> bool {
+        if a == Self::UNSET {
+            return true;
+        } else if b == Self::UNSET {
+            return false;
+        }
+
+        match (&self.heads[a], &self.heads[b]) {
+            (Some(value_a), Some(value_b)) => (value_a, a) < (value_b, b),
+            (Some(_), None) => true,
+            (None, _) => false,
+        }
+    }
+
+    /// Replay the matches from the given leaf to the root.
+    fn adjust(&mut self, index: usize) {
+        let mut winner = index;
+        let mut node = (index + self.runs.len()) / 2;
+
+        while node > 0 {
+            if self.beats(self.tree[node], winner) {
+                std::mem::swap(&mut self.tree[node], &mut winner);
+            }
+            node /= 2;
+        }
+
+        self.tree[0] = winner;
+    }
+
+    fn next_record(&mut self) -> Option<Result<T, Error>> {
+        let winner = *self.tree.first()?;
+        let record = self.heads.get_mut(winner)?.take()?;
+
+        match self.runs[winner].read() {
+            Ok(next) => {
+                self.heads[winner] = next;
+                self.adjust(winner);
+                Some(Ok(record))
+            }
+            Err(error) => Some(Err(error)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const U64_SIZE: usize = std::mem::size_of::<u64>();
+
+    /// Deterministic values with plenty of duplicates in `0..modulus`.
+    fn values(len: usize, modulus: u64) -> Vec<u64> {
+        (0..len as u64)
+            .map(|i| (i.wrapping_mul(0x9e37_79b9_7f4a_7c15) >> 17) % modulus)
+            .collect()
+    }
+
+    fn sort_with(budget_records: usize, input: &[u64], dedup: bool) -> (Vec<u64>, usize) {
+        let dir = tempfile::tempdir().unwrap();
+        let sorter = ExternalSorter::new(budget_records * U64_SIZE)
+            .temp_dir(dir.path())
+            .dedup(dedup);
+
+        let sorted = sorter.sort(input.iter().copied()).unwrap();
+        let run_count = sorted._runs.as_ref().map_or(0, |runs| runs.runs.len());
+        let result = sorted.collect::<Result<Vec<_>, _>>().unwrap();
+
+        // Spill files are removed once the output is dropped.
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+
+        (result, run_count)
+    }
+
+    fn expected(input: &[u64], dedup: bool) -> Vec<u64> {
+        let mut expected = input.to_vec();
+        expected.sort_unstable();
+        if dedup {
+            expected.dedup();
+        }
+        expected
+    }
+
+    #[test]
+    fn sort_empty_input() {
+        assert_eq!(sort_with(4, &[], false), (vec![], 0));
+        assert_eq!(sort_with(4, &[], true), (vec![], 0));
+    }
+
+    #[test]
+    fn sort_in_memory() {
+        let input = values(100, 30);
+        let (result, run_count) = sort_with(1000, &input, false);
+
+        assert_eq!(run_count, 0);
+        assert_eq!(result, expected(&input, false));
+    }
+
+    #[test]
+    fn sort_single_spilled_run() {
+        // The buffer fills exactly once, so the only run is on disk.
+        let input = values(16, 1000);
+        let (result, run_count) = sort_with(16, &input, false);
+
+        assert_eq!(run_count, 1);
+        assert_eq!(result, expected(&input, false));
+    }
+
+    #[test]
+    fn sort_non_power_of_two_run_counts() {
+        for run_count in [2, 3, 5, 6, 7, 9, 31, 33, 127] {
+            let input = values(run_count * 10 - 3, 50);
+            let (result, actual_run_count) = sort_with(10, &input, false);
+
+            assert_eq!(actual_run_count, run_count);
+            assert_eq!(result, expected(&input, false), "{} runs", run_count);
+        }
+    }
+
+    #[test]
+    fn sort_uneven_runs() {
+        // Already sorted, reversed, and constant input all produce runs that are exhausted at
+        // different times.
+        let ascending = (0..250).collect::<Vec<u64>>();
+        let descending = (0..250).rev().collect::<Vec<u64>>();
+        let constant = vec![7; 250];
+
+        for input in [ascending, descending, constant] {
+            let (result, run_count) = sort_with(7, &input, false);
+
+            assert_eq!(run_count, 36);
+            assert_eq!(result, expected(&input, false));
+        }
+    }
+
+    #[test]
+    fn sort_multiple_passes() {
+        // One record per run forces several passes through `reduce`.
+        for len in [MAX_FAN_IN + 1, 2 * MAX_FAN_IN + 5, 1000] {
+            let input = values(len, 200);
+            let (result, run_count) = sort_with(1, &input, false);
+
+            assert!(run_count <= MAX_FAN_IN);
+            assert_eq!(result, expected(&input, false), "{} records", len);
+        }
+    }
+
+    #[test]
+    fn sort_multiple_passes_dedup() {
+        let input = values(1000, 37);
+        let (result, _) = sort_with(3, &input, true);
+
+        assert_eq!(result, (0..37).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn sort_dedup_across_runs() {
+        for budget_records in [1, 4, 10, 1000] {
+            let input = values(500, 60);
+            let (result, _) = sort_with(budget_records, &input, true);
+
+            assert_eq!(result, expected(&input, true));
+        }
+    }
+
+    #[test]
+    fn sort_dedup_u64s_all_duplicates() {
+        for budget_records in [1, 2, 5, 300, 10_000] {
+            let result = sort_dedup_u64s(std::iter::repeat_n(42, 1000), budget_records * U64_SIZE)
+                .unwrap()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+
+            assert_eq!(result, vec![42]);
+        }
+    }
+
+    #[test]
+    fn sort_pairs() {
+        let input = values(300, 20)
+            .into_iter()
+            .zip(values(300, 3).into_iter().map(|value| value as u32))
+            .collect::<Vec<(u64, u32)>>();
+        let dir = tempfile::tempdir().unwrap();
+
+        let result = ExternalSorter::new(16 * std::mem::size_of::<(u64, u32)>())
+            .temp_dir(dir.path())
+            .sort(input.clone())
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        let mut expected = input;
+        expected.sort_unstable();
+
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn loser_tree_without_runs() {
+        let mut tree = LoserTree::<u64>::new(vec![]).unwrap();
+
+        assert!(tree.next_record().is_none());
+    }
+}
@@ -1,8 +1,75 @@
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, TimeZone, Utc};
+
+pub mod extsort;
+pub mod preflight;
+pub mod splitmix;
+pub mod testkit;
 
 const TWITTER_DATE_TIME_FMT: &str = "%a %b %d %H:%M:%S %z %Y";
+const TWITTER_EPOCH_MS: i64 = 1288834974657;
+/// Smallest ID treated as a snowflake (earlier sequential IDs are all far below this).
+const FIRST_SNOWFLAKE_ID: u64 = 1 << 40;
 
 /// Parse the time format used in Twitter API responses.
 pub fn parse_date_time(input: &str) -> Result<DateTime<Utc>, chrono::ParseError> {
     Ok(DateTime::parse_from_str(input, TWITTER_DATE_TIME_FMT)?.into())
 }
+
+/// Format a time in the format used in Twitter API responses.
+pub fn format_date_time(value: DateTime<Utc>) -> String {
+    value.format(TWITTER_DATE_TIME_FMT).to_string()
+}
+
+/// Format bytes (e.g. a digest) as lowercase hexadecimal.
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Extract the creation time from a snowflake ID.
+///
+/// Returns `None` for IDs that predate snowflakes (e.g. user IDs assigned before 2013).
+pub fn snowflake_to_date_time(id: u64) -> Option<DateTime<Utc>> {
+    if id < FIRST_SNOWFLAKE_ID {
+        None
+    } else {
+        Utc.timestamp_millis_opt((id >> 22) as i64 + TWITTER_EPOCH_MS)
+            .single()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snowflake_to_date_time_examples() {
+        assert_eq!(
+            snowflake_to_date_time(1212092628029698048),
+            Utc.timestamp_millis_opt(1577820376771).single()
+        );
+        assert_eq!(
+            snowflake_to_date_time(FIRST_SNOWFLAKE_ID),
+            Utc.timestamp_millis_opt(TWITTER_EPOCH_MS + (1 << 18))
+                .single()
+        );
+        assert_eq!(snowflake_to_date_time(FIRST_SNOWFLAKE_ID - 1), None);
+        assert_eq!(snowflake_to_date_time(12), None);
+    }
+
+    #[test]
+    fn hex_examples() {
+        assert_eq!(hex(&[]), "");
+        assert_eq!(hex(&[0, 9, 10, 255]), "00090aff");
+    }
+
+    #[test]
+    fn date_time_round_trip() {
+        let value = parse_date_time("Tue Mar 21 20:50:14 +0000 2006").unwrap();
+
+        assert_eq!(value, Utc.timestamp_opt(1142974214, 0).single().unwrap());
+        assert_eq!(format_date_time(value), "Tue Mar 21 20:50:14 +0000 2006");
+        // The day of the week is checked.
+        assert!(parse_date_time("Wed Mar 21 20:50:14 +0000 2006").is_err());
+        assert!(parse_date_time("2006-03-21T20:50:14Z").is_err());
+    }
+}
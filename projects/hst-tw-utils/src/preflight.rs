@@ -0,0 +1,265 @@
+//! Disk space checks for operations that write large files.
+//!
+//! These checks are only advisory (other processes may use space after the check), but they
+//! let long-running operations fail before writing anything instead of halfway through.
+
+use std::path::{Path, PathBuf};
+
+#[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
+pub enum Error {
+    #[error("I/O error")]
+    Io(#[from] std::io::Error),
+    #[error("Insufficient disk space at {path} (required: {required} bytes, available: {available} bytes)")]
+    InsufficientSpace {
+        path: PathBuf,
+        required: u64,
+        available: u64,
+    },
+    #[error("Insufficient inodes at {path} (required: {required}, available: {available})")]
+    InsufficientInodes {
+        path: PathBuf,
+        required: u64,
+        available: u64,
+    },
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct SpaceInfo {
+    pub available_bytes: u64,
+    /// May be unknown on some platforms or file systems.
+    pub available_inodes: Option<u64>,
+}
+
+/// Source of file system usage information.
+pub trait FsProbe {
+    /// Return usage information for the file system containing the given (existing) path.
+    ///
+    /// Returns `None` if the information is not available on this platform.
+    fn space(&self, path: &Path) -> std::io::Result<Option<SpaceInfo>>;
+}
+
+/// Probe that queries the operating system.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemProbe;
+
+impl FsProbe for SystemProbe {
+    #[cfg(unix)]
+    // The widths of these fields vary across platforms.
+    #[allow(clippy::unnecessary_cast)]
+    fn space(&self, path: &Path) -> std::io::Result<Option<SpaceInfo>> {
+        let stats = nix::sys::statvfs::statvfs(path)?;
+        let available_bytes =
+            (stats.blocks_available() as u64).saturating_mul(stats.fragment_size() as u64);
+        let available_inodes = stats.files_available() as u64;
+
+        Ok(Some(SpaceInfo {
+            available_bytes,
+            // Some file systems (e.g. Btrfs) don't have a fixed inode count and report zero.
+            available_inodes: if stats.files() == 0 {
+                None
+            } else {
+                Some(available_inodes)
+            },
+        }))
+    }
+
+    #[cfg(not(unix))]
+    fn space(&self, _path: &Path) -> std::io::Result<Option<SpaceInfo>> {
+        Ok(None)
+    }
+}
+
+/// Check that the file system containing the path has at least the required number of bytes.
+///
+/// The path does not need to exist yet.
+pub fn check_space<P: AsRef<Path>>(path: P, required_bytes: u64) -> Result<(), Error> {
+    check_space_with(&SystemProbe, path, required_bytes, 0)
+}
+
+/// Check for both available space and inodes using the given probe.
+pub fn check_space_with<F: FsProbe, P: AsRef<Path>>(
+    probe: &F,
+    path: P,
+    required_bytes: u64,
+    required_inodes: u64,
+) -> Result<(), Error> {
+    let path = existing_ancestor(path.as_ref())?;
+
+    if let Some(info) = probe.space(&path)? {
+        if info.available_bytes < required_bytes {
+            return Err(Error::InsufficientSpace {
+                path,
+                required: required_bytes,
+                available: info.available_bytes,
+            });
+        }
+
+        if let Some(available_inodes) = info.available_inodes {
+            if available_inodes < required_inodes {
+                return Err(Error::InsufficientInodes {
+                    path,
+                    required: required_inodes,
+                    available: available_inodes,
+                });
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Estimate the space needed to process an input file, given an expected output-to-input ratio.
+pub fn estimate_from_input<P: AsRef<Path>>(input: P, ratio: f64) -> Result<u64, Error> {
+    let size = std::fs::metadata(input)?.len();
+
+    Ok(scale(size, ratio))
+}
+
+/// Estimate the space needed to write a number of records with a known average size, given an
+/// expected output-to-input ratio.
+pub fn estimate_from_records(count: u64, average_size: u64, ratio: f64) -> u64 {
+    scale(count.saturating_mul(average_size), ratio)
+}
+
+/// Scale a byte count by a ratio, rounding up.
+pub fn scale(size: u64, ratio: f64) -> u64 {
+    (size as f64 * ratio).ceil() as u64
+}
+
+fn existing_ancestor(path: &Path) -> Result<PathBuf, Error> {
+    let path = if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        std::env::current_dir()?.join(path)
+    };
+
+    Ok(path
+        .ancestors()
+        .find(|ancestor| ancestor.exists())
+        .unwrap_or(&path)
+        .to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    /// Probe that returns fixed information and records the paths it is asked about.
+    struct FakeProbe {
+        info: Option<SpaceInfo>,
+        paths: RefCell<Vec<PathBuf>>,
+    }
+
+    impl FakeProbe {
+        fn new(available_bytes: u64, available_inodes: Option<u64>) -> Self {
+            Self {
+                info: Some(SpaceInfo {
+                    available_bytes,
+                    available_inodes,
+                }),
+                paths: RefCell::new(vec![]),
+            }
+        }
+    }
+
+    impl FsProbe for FakeProbe {
+        fn space(&self, path: &Path) -> std::io::Result<Option<SpaceInfo>> {
+            self.paths.borrow_mut().push(path.to_path_buf());
+
+            Ok(self.info)
+        }
+    }
+
+    #[test]
+    fn sufficient_space() {
+        let dir = tempfile::tempdir().unwrap();
+        let probe = FakeProbe::new(100, Some(10));
+
+        assert!(check_space_with(&probe, dir.path(), 100, 10).is_ok());
+        assert_eq!(*probe.paths.borrow(), vec![dir.path().to_path_buf()]);
+    }
+
+    #[test]
+    fn insufficient_space() {
+        let dir = tempfile::tempdir().unwrap();
+        let probe = FakeProbe::new(100, Some(10));
+
+        assert!(matches!(
+            check_space_with(&probe, dir.path(), 101, 0),
+            Err(Error::InsufficientSpace {
+                required: 101,
+                available: 100,
+                ..
+            })
+        ));
+        assert!(matches!(
+            check_space_with(&probe, dir.path(), 0, 11),
+            Err(Error::InsufficientInodes {
+                required: 11,
+                available: 10,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn unknown_information_passes() {
+        let dir = tempfile::tempdir().unwrap();
+
+        assert!(check_space_with(&FakeProbe::new(100, None), dir.path(), 0, u64::MAX).is_ok());
+
+        let probe = FakeProbe {
+            info: None,
+            paths: RefCell::new(vec![]),
+        };
+
+        assert!(check_space_with(&probe, dir.path(), u64::MAX, u64::MAX).is_ok());
+    }
+
+    #[test]
+    fn missing_paths_use_an_existing_ancestor() {
+        let dir = tempfile::tempdir().unwrap();
+        let probe = FakeProbe::new(100, None);
+
+        check_space_with(&probe, dir.path().join("a/b/c"), 0, 0).unwrap();
+        check_space_with(&probe, "relative/missing", 0, 0).unwrap();
+
+        let paths = probe.paths.borrow();
+
+        assert_eq!(paths[0], dir.path());
+        assert!(paths[1].is_absolute());
+        assert!(std::env::current_dir().unwrap().starts_with(&paths[1]));
+    }
+
+    #[test]
+    fn estimates() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("input");
+        std::fs::write(&path, [0; 1000]).unwrap();
+
+        assert_eq!(estimate_from_input(&path, 2.0).unwrap(), 2000);
+        assert_eq!(estimate_from_input(&path, 0.0015).unwrap(), 2);
+        assert!(estimate_from_input(dir.path().join("missing"), 1.0).is_err());
+        assert_eq!(estimate_from_records(1000, 300, 1.5), 450_000);
+        assert_eq!(estimate_from_records(0, 300, 1.5), 0);
+        assert_eq!(estimate_from_records(u64::MAX, 2, 1.0), u64::MAX);
+        assert_eq!(scale(3, 0.5), 2);
+        assert_eq!(scale(0, 10.0), 0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn system_probe() {
+        let dir = tempfile::tempdir().unwrap();
+        let info = SystemProbe.space(dir.path()).unwrap();
+
+        assert!(info.is_some());
+        assert!(check_space(dir.path(), 0).is_ok());
+        assert!(matches!(
+            check_space(dir.path(), u64::MAX),
+            Err(Error::InsufficientSpace { .. })
+        ));
+    }
+}
@@ -0,0 +1,12 @@
+//! Helpers for generating reproducible synthetic datasets in benchmarks.
+
+/// Seed used for all generated benchmark data, so that results are comparable across runs.
+pub const SEED: u64 = 0x5eed;
+
+/// Number of records in generated datasets (override with `HST_BENCH_RECORDS`).
+pub fn record_count(default: usize) -> usize {
+    std::env::var("HST_BENCH_RECORDS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}